@@ -0,0 +1,71 @@
+/*
+ * C interface of usdx_parser, built with the `ffi` feature.
+ *
+ * Songs are opaque handles released with usdx_song_free. Strings returned by
+ * the library are copies released with usdx_string_free. Failing calls return
+ * NULL or 0 and leave a message for usdx_last_error.
+ */
+#ifndef USDX_PARSER_H
+#define USDX_PARSER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct UsdxSong UsdxSong;
+
+#define USDX_FIELD_ARTIST 0
+#define USDX_FIELD_TITLE 1
+#define USDX_FIELD_MP3 2
+#define USDX_FIELD_VIDEO 3
+#define USDX_FIELD_EDITION 4
+#define USDX_FIELD_GENRE 5
+#define USDX_FIELD_YEAR 6
+#define USDX_FIELD_LANGUAGE 7
+#define USDX_FIELD_COVER 8
+#define USDX_FIELD_BACKGROUND 9
+#define USDX_FIELD_SINGER_P1 10
+#define USDX_FIELD_SINGER_P2 11
+
+typedef struct UsdxNote {
+    /* ':', '*', 'F' or '-' */
+    char note_type;
+    /* 0 for solo songs, otherwise 1 or 2, or 3 for both singers */
+    uint8_t voice;
+    uint8_t has_length;
+    uint8_t has_tone;
+    uint32_t beat;
+    uint32_t length;
+    int32_t tone;
+} UsdxNote;
+
+/* Message of the last failed call on this thread, or NULL */
+const char *usdx_last_error(void);
+
+/* Parse `len` bytes of chart text, returning NULL on failure */
+UsdxSong *usdx_song_parse(const uint8_t *data, size_t len);
+void usdx_song_free(UsdxSong *song);
+
+/* Copy of a text header, NULL when it's unset */
+char *usdx_song_field(const UsdxSong *song, int field);
+float usdx_song_bpm(const UsdxSong *song);
+uint32_t usdx_song_gap(const UsdxSong *song);
+
+size_t usdx_song_note_count(const UsdxSong *song);
+/* Fill `out` with note `index`, returning 0 when there is no such note */
+int usdx_song_note(const UsdxSong *song, size_t index, UsdxNote *out);
+/* Copy of the lyric of note `index`, NULL for line breaks */
+char *usdx_song_note_lyric(const UsdxSong *song, size_t index);
+
+/* The song as chart text */
+char *usdx_song_serialize(const UsdxSong *song);
+void usdx_string_free(char *text);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
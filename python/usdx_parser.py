@@ -0,0 +1,176 @@
+"""Python bindings of usdx_parser
+
+Wraps the C interface of the Rust crate, so scripts parse charts exactly like
+the desktop tools do. Build the library first:
+
+    cargo rustc --release --features python --crate-type cdylib
+
+The library is looked up in the USDX_PARSER_LIB environment variable, next to
+this file and in ../target/release.
+
+    song = Song.from_file("tests/duet.txt")
+    print(song.title, len(song.notes))
+"""
+import ctypes
+import os
+import sys
+
+__all__ = ["Note", "Song", "UsdxError"]
+
+_FIELDS = [
+    "artist",
+    "title",
+    "mp3",
+    "video",
+    "edition",
+    "genre",
+    "year",
+    "language",
+    "cover",
+    "background",
+    "singer_p1",
+    "singer_p2",
+]
+
+
+class UsdxError(Exception):
+    """A chart failed to parse"""
+
+
+class _Note(ctypes.Structure):
+    _fields_ = [
+        ("note_type", ctypes.c_char),
+        ("voice", ctypes.c_uint8),
+        ("has_length", ctypes.c_uint8),
+        ("has_tone", ctypes.c_uint8),
+        ("beat", ctypes.c_uint32),
+        ("length", ctypes.c_uint32),
+        ("tone", ctypes.c_int32),
+    ]
+
+
+def _library_path():
+    if "USDX_PARSER_LIB" in os.environ:
+        return os.environ["USDX_PARSER_LIB"]
+    name = {"win32": "usdx_parser.dll", "darwin": "libusdx_parser.dylib"}.get(
+        sys.platform, "libusdx_parser.so"
+    )
+    here = os.path.dirname(os.path.abspath(__file__))
+    for path in [os.path.join(here, name), os.path.join(here, "..", "target", "release", name)]:
+        if os.path.exists(path):
+            return path
+    return name
+
+
+def _load():
+    lib = ctypes.CDLL(_library_path())
+    song = ctypes.c_void_p
+    text = ctypes.c_void_p
+    signatures = {
+        "usdx_last_error": ([], ctypes.c_char_p),
+        "usdx_song_parse": ([ctypes.c_char_p, ctypes.c_size_t], song),
+        "usdx_song_free": ([song], None),
+        "usdx_song_field": ([song, ctypes.c_int], text),
+        "usdx_song_bpm": ([song], ctypes.c_float),
+        "usdx_song_gap": ([song], ctypes.c_uint32),
+        "usdx_song_note_count": ([song], ctypes.c_size_t),
+        "usdx_song_note": ([song, ctypes.c_size_t, ctypes.POINTER(_Note)], ctypes.c_int),
+        "usdx_song_note_lyric": ([song, ctypes.c_size_t], text),
+        "usdx_song_serialize": ([song], text),
+        "usdx_string_free": ([text], None),
+    }
+    for name, (args, ret) in signatures.items():
+        function = getattr(lib, name)
+        function.argtypes = args
+        function.restype = ret
+    return lib
+
+
+_lib = _load()
+
+
+def _take_string(pointer):
+    """Copy a string returned by the library and release it"""
+    if not pointer:
+        return None
+    try:
+        return ctypes.string_at(pointer).decode("utf-8")
+    finally:
+        _lib.usdx_string_free(pointer)
+
+
+class Note:
+    """One note of a song"""
+
+    __slots__ = ["note_type", "beat_number", "note_length", "note_tone", "lyric", "voice"]
+
+    def __init__(self, note_type, beat_number, note_length, note_tone, lyric, voice):
+        self.note_type = note_type
+        self.beat_number = beat_number
+        self.note_length = note_length
+        self.note_tone = note_tone
+        self.lyric = lyric
+        self.voice = voice
+
+    def __repr__(self):
+        return "Note({!r}, {}, {}, {}, {!r}, {!r})".format(
+            self.note_type,
+            self.beat_number,
+            self.note_length,
+            self.note_tone,
+            self.lyric,
+            self.voice,
+        )
+
+
+class Song:
+    """A parsed chart; its headers and notes are copied to Python on parsing"""
+
+    def __init__(self, handle):
+        self._handle = handle
+        for i, field in enumerate(_FIELDS):
+            setattr(self, field, _take_string(_lib.usdx_song_field(handle, i)))
+        self.bpm = _lib.usdx_song_bpm(handle)
+        self.gap = _lib.usdx_song_gap(handle)
+        self.notes = []
+        raw = _Note()
+        for i in range(_lib.usdx_song_note_count(handle)):
+            _lib.usdx_song_note(handle, i, ctypes.byref(raw))
+            self.notes.append(
+                Note(
+                    raw.note_type.decode(),
+                    raw.beat,
+                    raw.length if raw.has_length else None,
+                    raw.tone if raw.has_tone else None,
+                    _take_string(_lib.usdx_song_note_lyric(handle, i)),
+                    "P{}".format(raw.voice) if raw.voice else None,
+                )
+            )
+
+    @classmethod
+    def parse(cls, text):
+        """Parse chart text given as str or bytes"""
+        data = text.encode("utf-8") if isinstance(text, str) else bytes(text)
+        handle = _lib.usdx_song_parse(data, len(data))
+        if not handle:
+            raise UsdxError((_lib.usdx_last_error() or b"Parsing failed").decode())
+        return cls(handle)
+
+    @classmethod
+    def from_file(cls, path):
+        with open(path, "rb") as f:
+            return cls.parse(f.read())
+
+    def __str__(self):
+        """The chart text as the parser read it"""
+        return _take_string(_lib.usdx_song_serialize(self._handle))
+
+    def __repr__(self):
+        return "Song(artist={!r}, title={!r}, notes={})".format(
+            self.artist, self.title, len(self.notes)
+        )
+
+    def __del__(self):
+        if getattr(self, "_handle", None):
+            _lib.usdx_song_free(self._handle)
+            self._handle = None
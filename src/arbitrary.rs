@@ -0,0 +1,183 @@
+//! Random valid charts for property testing
+//!
+//! [`Arbitrary`] builds values from a seeded [`Gen`], and every generated
+//! [`Song`] is a chart the parser reads back unchanged. [`check`] runs a
+//! property against many of them and reports the seed of the first failure,
+//! so a failing case can be replayed with [`Gen::new`].
+use crate::{Note, NoteType, Song, Voice};
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt::Debug;
+
+/// Small deterministic random number generator (SplitMix64)
+#[derive(Debug, Clone)]
+pub struct Gen {
+    state: u64,
+}
+
+/// Types that can be generated at random
+pub trait Arbitrary: Sized {
+    fn arbitrary(g: &mut Gen) -> Self;
+}
+
+/// Characters of generated text, including non-ASCII letters and symbols
+const CHARS: &[char] = &[
+    'a', 'e', 'i', 'o', 'u', 'n', 'r', 's', 't', 'L', 'M', 'Z', '0', '7', ' ', '\'', ',', '?', '!',
+    '-', ':', '#', 'ä', 'é', 'ñ', 'ß', 'ø', 'Ж', 'λ', 'あ', '愛', '♪',
+];
+
+impl Gen {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Number in `0..n`, `n` must not be 0
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// True with a chance of one in `n`
+    pub fn one_in(&mut self, n: u64) -> bool {
+        self.below(n) == 0
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+
+    /// Text of up to `max_len` characters that is valid in a header or lyric
+    pub fn text(&mut self, max_len: u64) -> String {
+        let len = self.below(max_len + 1);
+        (0..len).map(|_| *self.choose(CHARS)).collect()
+    }
+}
+
+impl Arbitrary for NoteType {
+    fn arbitrary(g: &mut Gen) -> Self {
+        g.choose(&[Self::Normal, Self::Golden, Self::Freestyle, Self::LineBreak])
+            .clone()
+    }
+}
+
+impl Arbitrary for Voice {
+    fn arbitrary(g: &mut Gen) -> Self {
+        *g.choose(&[Self::P1, Self::P2])
+    }
+}
+
+impl Arbitrary for Note {
+    /// Any note, at a beat below 10000
+    fn arbitrary(g: &mut Gen) -> Self {
+        let beat = g.below(10_000) as u32;
+        match NoteType::arbitrary(g) {
+            NoteType::LineBreak => Note {
+                line_start: g.one_in(4).then(|| beat + g.below(8) as u32),
+                ..Note::line_break(beat)
+            },
+            note_type => Note::new(
+                note_type,
+                beat,
+                1 + g.below(16) as u32,
+                g.below(48) as i32 - 24,
+                &g.text(8),
+            ),
+        }
+    }
+}
+
+impl Arbitrary for Song {
+    /// A chart with ascending beats, solo or duet, that survives a round trip
+    fn arbitrary(g: &mut Gen) -> Self {
+        // Header values are read back trimmed, and blank ones as absent
+        let header = |g: &mut Gen| {
+            (!g.one_in(3))
+                .then(|| g.text(20).trim().to_string())
+                .filter(|a| !a.is_empty())
+        };
+        // Multiples of 0.25 are exact in both f32 and the written form
+        let bpm = (1 + g.below(2400)) as f32 / 4.0;
+        // Charts need a title
+        let title = format!("T{}", g.text(20).trim_end());
+        let mut song = Song::new(&title, bpm, g.below(60_000) as u32);
+        song.artist = header(g);
+        song.mp3 = header(g);
+        song.video = header(g);
+        song.edition = header(g);
+        song.genre = header(g);
+        song.year = (!g.one_in(3)).then(|| (1950 + g.below(80)).to_string());
+        song.language = header(g);
+        song.video_gap = (!g.one_in(3)).then(|| g.below(10_000) as f32 / 4.0);
+        song.cover = header(g);
+        song.background = header(g);
+        let voices: &[Option<Voice>] = if g.one_in(4) {
+            song.singer_p1 = header(g);
+            song.singer_p2 = header(g);
+            if g.one_in(2) {
+                &[Some(Voice::P1), Some(Voice::Both), Some(Voice::P2)]
+            } else {
+                &[Some(Voice::P1), Some(Voice::P2)]
+            }
+        } else {
+            &[None]
+        };
+        for &voice in voices {
+            let mut beat = 0;
+            for i in 0..g.below(40) {
+                let mut note = Note::arbitrary(g);
+                // Voices can't start with a line break
+                if i == 0 && note.note_type == NoteType::LineBreak {
+                    note = Note::new(NoteType::Normal, 0, 1, 0, "");
+                }
+                beat += g.below(8) as u32;
+                let line_gap = note.line_start.map(|a| a - note.beat_number);
+                note.beat_number = beat;
+                note.line_start = line_gap.map(|a| beat + a);
+                beat += note.note_length.unwrap_or_default();
+                note.voice = voice;
+                song.notes.push(note);
+            }
+        }
+        song
+    }
+}
+
+/// Check `property` on `cases` generated values, panicking with the seed
+/// and value of the first one it fails for
+/// ```rust
+/// use usdx_parser::arbitrary::check;
+/// use usdx_parser::Song;
+///
+/// check(50, |song: &Song| song.to_string().parse::<Song>().is_ok());
+/// ```
+pub fn check<T: Arbitrary + Debug>(cases: u64, mut property: impl FnMut(&T) -> bool) {
+    for seed in 0..cases {
+        let value = T::arbitrary(&mut Gen::new(seed));
+        if !property(&value) {
+            panic!("Property failed for seed {}: {:?}", seed, value);
+        }
+    }
+}
+
+#[test]
+pub fn test_round_trip_property() {
+    use crate::NoteRef;
+
+    check(300, |song: &Song| {
+        let text = song.to_string();
+        text.parse::<Song>().is_ok_and(|a| a.to_string() == text)
+    });
+    check(300, |note: &Note| {
+        let text = note.to_string();
+        NoteRef::try_from(text.as_str()).is_ok_and(|a| a.to_note().to_string() == text)
+    });
+    let mut songs = (0..20).map(|seed| Song::arbitrary(&mut Gen::new(seed)));
+    assert!(songs.any(|s| s.notes.iter().any(|n| n.voice.is_some())));
+}
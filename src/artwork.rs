@@ -0,0 +1,356 @@
+//! Checks of the cover and background images a song references
+//!
+//! PNG, JPEG, GIF, BMP and WebP files are recognized by their content. The
+//! container structure is walked and checksums are verified where the format
+//! has them, which catches truncated downloads and misnamed files; pixel
+//! data itself is not decompressed.
+use crate::Song;
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+
+/// Covers smaller than this in either direction look blurry in song selection
+const MIN_COVER_SIZE: u32 = 200;
+/// Backgrounds smaller than this in either direction look blurry fullscreen
+const MIN_BACKGROUND_SIZE: u32 = 480;
+/// Covers whose sides differ more than this ratio get stretched
+const SQUARE_TOLERANCE: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Bmp,
+    WebP,
+}
+
+/// Format and dimensions of an image file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Which header the image is referenced by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Artwork {
+    Cover,
+    Background,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArtworkIssue {
+    /// The referenced file doesn't exist
+    Missing,
+    /// The file is no image or is damaged
+    Undecodable(String),
+    TooSmall {
+        width: u32,
+        height: u32,
+    },
+    /// A cover that isn't roughly square
+    NotSquare {
+        width: u32,
+        height: u32,
+    },
+    /// A background in portrait orientation
+    Portrait {
+        width: u32,
+        height: u32,
+    },
+}
+
+/// Problem with one of the images of a song
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtworkFinding {
+    pub artwork: Artwork,
+    pub path: PathBuf,
+    pub issue: ArtworkIssue,
+}
+
+fn be16(data: &[u8], at: usize) -> u32 {
+    u16::from_be_bytes([data[at], data[at + 1]]) as u32
+}
+
+fn be32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn le16(data: &[u8], at: usize) -> u32 {
+    u16::from_le_bytes([data[at], data[at + 1]]) as u32
+}
+
+fn le32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+/// CRC-32 as used by PNG and zlib
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn png_info(data: &[u8]) -> Result<(u32, u32)> {
+    let mut pos = 8;
+    let mut size = None;
+    let mut has_data = false;
+    loop {
+        if data.len() < pos + 12 {
+            bail!("PNG is truncated");
+        }
+        let len = be32(data, pos) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let Some(end) = (pos + 8).checked_add(len).filter(|a| a + 4 <= data.len()) else {
+            bail!("PNG chunk {} is truncated", String::from_utf8_lossy(kind));
+        };
+        if crc32(&data[pos + 4..end]) != be32(data, end) {
+            bail!("PNG chunk {} is corrupt", String::from_utf8_lossy(kind));
+        }
+        match kind {
+            b"IHDR" if len >= 8 => size = Some((be32(data, pos + 8), be32(data, pos + 12))),
+            b"IDAT" => has_data = true,
+            b"IEND" => break,
+            _ => (),
+        }
+        if size.is_none() {
+            bail!("PNG doesn't start with a header chunk");
+        }
+        pos = end + 4;
+    }
+    if !has_data {
+        bail!("PNG has no image data");
+    }
+    Ok(size.unwrap_or_default())
+}
+
+fn jpeg_info(data: &[u8]) -> Result<(u32, u32)> {
+    let mut pos = 2;
+    let mut size = None;
+    loop {
+        while data.get(pos) == Some(&0xff) {
+            pos += 1;
+        }
+        let Some(&marker) = data.get(pos) else {
+            bail!("JPEG is truncated");
+        };
+        if data[pos - 1] != 0xff {
+            bail!("Invalid JPEG segment at byte {}", pos);
+        }
+        pos += 1;
+        match marker {
+            // Markers without a payload
+            0x01 | 0xd0..=0xd7 => continue,
+            0xd9 => bail!("JPEG ends before its image data"),
+            _ => (),
+        }
+        if data.len() < pos + 2 {
+            bail!("JPEG is truncated");
+        }
+        let len = be16(data, pos) as usize;
+        if len < 2 || data.len() < pos + len {
+            bail!("JPEG segment {:02X} is truncated", marker);
+        }
+        let is_frame = matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc);
+        if is_frame && len >= 7 {
+            size = Some((be16(data, pos + 5), be16(data, pos + 3)));
+        }
+        pos += len;
+        if marker == 0xda {
+            break;
+        }
+    }
+    let Some(size) = size else {
+        bail!("JPEG has no frame header");
+    };
+    // Some encoders pad the file after the end marker
+    let end = data.iter().rposition(|a| *a != 0).unwrap_or(0);
+    if end < pos || data[end - 1..=end] != [0xff, 0xd9] {
+        bail!("JPEG is truncated");
+    }
+    Ok(size)
+}
+
+fn gif_info(data: &[u8]) -> Result<(u32, u32)> {
+    if data.len() < 14 {
+        bail!("GIF is truncated");
+    }
+    if data.last() != Some(&0x3b) {
+        bail!("GIF is truncated");
+    }
+    Ok((le16(data, 6), le16(data, 8)))
+}
+
+fn bmp_info(data: &[u8]) -> Result<(u32, u32)> {
+    if data.len() < 26 {
+        bail!("BMP is truncated");
+    }
+    if (le32(data, 2) as usize) > data.len() {
+        bail!("BMP is truncated");
+    }
+    // Negative heights mark top-down bitmaps
+    let height = (le32(data, 22) as i32).unsigned_abs();
+    Ok((le32(data, 18), height))
+}
+
+fn webp_info(data: &[u8]) -> Result<(u32, u32)> {
+    if data.len() < 30 || (le32(data, 4) as usize + 8) > data.len() {
+        bail!("WebP is truncated");
+    }
+    let size = match &data[12..16] {
+        b"VP8 " => (le16(data, 26) & 0x3fff, le16(data, 28) & 0x3fff),
+        b"VP8L" => {
+            let bits = le32(data, 21);
+            ((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1)
+        }
+        b"VP8X" => (
+            (le32(data, 24) & 0xff_ffff) + 1,
+            (le32(data, 27) & 0xff_ffff) + 1,
+        ),
+        _ => bail!("Unknown WebP chunk"),
+    };
+    Ok(size)
+}
+
+/// Format and dimensions of an image, failing if it is damaged
+/// ```rust
+/// use usdx_parser::artwork::{image_info, ImageFormat};
+///
+/// let data = std::fs::read("tests/artwork/background.jpg").unwrap();
+/// let info = image_info(&data).unwrap();
+/// assert_eq!((info.format, info.width, info.height), (ImageFormat::Jpeg, 1280, 720));
+/// assert!(image_info(&data[..data.len() / 2]).is_err());
+/// ```
+pub fn image_info(data: &[u8]) -> Result<ImageInfo> {
+    let (format, (width, height)) = if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        (ImageFormat::Png, png_info(data)?)
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        (ImageFormat::Jpeg, jpeg_info(data)?)
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        (ImageFormat::Gif, gif_info(data)?)
+    } else if data.starts_with(b"BM") {
+        (ImageFormat::Bmp, bmp_info(data)?)
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        (ImageFormat::WebP, webp_info(data)?)
+    } else {
+        bail!("Unknown image format");
+    };
+    if width == 0 || height == 0 {
+        bail!("Image has no pixels");
+    }
+    Ok(ImageInfo {
+        format,
+        width,
+        height,
+    })
+}
+
+fn check(artwork: Artwork, path: PathBuf) -> Option<ArtworkFinding> {
+    let finding = |issue| {
+        Some(ArtworkFinding {
+            artwork,
+            path: path.clone(),
+            issue,
+        })
+    };
+    let data = match std::fs::read(&path) {
+        Ok(a) => a,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return finding(ArtworkIssue::Missing)
+        }
+        Err(e) => return finding(ArtworkIssue::Undecodable(e.to_string())),
+    };
+    let (width, height) = match image_info(&data) {
+        Ok(a) => (a.width, a.height),
+        Err(e) => return finding(ArtworkIssue::Undecodable(e.to_string())),
+    };
+    let min = match artwork {
+        Artwork::Cover => MIN_COVER_SIZE,
+        Artwork::Background => MIN_BACKGROUND_SIZE,
+    };
+    if width.min(height) < min {
+        return finding(ArtworkIssue::TooSmall { width, height });
+    }
+    let ratio = width as f32 / height as f32;
+    match artwork {
+        Artwork::Cover if (ratio - 1.0).abs() > SQUARE_TOLERANCE => {
+            finding(ArtworkIssue::NotSquare { width, height })
+        }
+        Artwork::Background if height > width => finding(ArtworkIssue::Portrait { width, height }),
+        _ => None,
+    }
+}
+
+impl Song {
+    /// Check `#COVER` and `#BACKGROUND`, resolved relative to `song_dir`
+    /// ```rust
+    /// use usdx_parser::artwork::ArtworkIssue;
+    /// use usdx_parser::Song;
+    /// use std::path::Path;
+    ///
+    /// let song = Song::from_file("tests/artwork/Artwork Test.txt").unwrap();
+    /// let findings = song.check_artwork(Path::new("tests/artwork"));
+    /// assert_eq!(findings[0].issue, ArtworkIssue::TooSmall { width: 128, height: 128 });
+    /// ```
+    pub fn check_artwork(&self, song_dir: &Path) -> Vec<ArtworkFinding> {
+        [
+            (Artwork::Cover, &self.cover),
+            (Artwork::Background, &self.background),
+        ]
+        .into_iter()
+        .filter_map(|(artwork, file)| check(artwork, song_dir.join(file.as_deref()?)))
+        .collect()
+    }
+}
+
+#[test]
+pub fn test_artwork_checks() {
+    let dir = Path::new("tests/artwork");
+    let png = std::fs::read(dir.join("cover.png")).unwrap();
+    assert_eq!(
+        image_info(&png).unwrap(),
+        ImageInfo {
+            format: ImageFormat::Png,
+            width: 128,
+            height: 128
+        }
+    );
+    let mut corrupt = png.clone();
+    corrupt[20] ^= 1;
+    assert!(image_info(&corrupt).is_err());
+    assert!(image_info(b"not an image").is_err());
+
+    let mut song = Song::from_file("tests/artwork/Artwork Test.txt").unwrap();
+    assert_eq!(song.check_artwork(dir).len(), 1);
+    song.cover = Some("background.jpg".to_string());
+    song.background = Some("missing.jpg".to_string());
+    assert_eq!(
+        song.check_artwork(dir),
+        [
+            ArtworkFinding {
+                artwork: Artwork::Cover,
+                path: dir.join("background.jpg"),
+                issue: ArtworkIssue::NotSquare {
+                    width: 1280,
+                    height: 720
+                }
+            },
+            ArtworkFinding {
+                artwork: Artwork::Background,
+                path: dir.join("missing.jpg"),
+                issue: ArtworkIssue::Missing
+            }
+        ]
+    );
+    song.background = Some("Artwork Test.txt".to_string());
+    assert!(matches!(
+        song.check_artwork(dir)[1].issue,
+        ArtworkIssue::Undecodable(_)
+    ));
+}
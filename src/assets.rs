@@ -0,0 +1,214 @@
+//! Media problems across a whole library
+//!
+//! [`SongLibrary::check_assets`] looks up the media every chart names and
+//! collects what's wrong: audio or covers that aren't set, files that don't
+//! exist and names whose case differs from the file on disk. Windows finds
+//! those anyway, other systems don't. The JSON form lists one finding per
+//! object, with the file name to put in the header where it is known.
+use crate::json::Value;
+use crate::library::SongLibrary;
+use crate::Song;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Header naming a media file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetKind {
+    Audio,
+    Video,
+    Cover,
+    Background,
+}
+
+/// What's wrong with a media file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetIssue {
+    /// The header isn't set; only reported for audio and covers
+    NotSet,
+    /// No file has the name
+    Missing,
+    /// Only a file differing in case exists, named `actual`
+    WrongCase { actual: String },
+}
+
+/// Media problem of one chart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetFinding {
+    pub chart: PathBuf,
+    pub kind: AssetKind,
+    /// File name the header gives
+    pub name: Option<String>,
+    pub issue: AssetIssue,
+}
+
+/// Media problems of a library, in chart order
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AssetReport {
+    pub findings: Vec<AssetFinding>,
+}
+
+impl fmt::Display for AssetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Audio => "audio",
+            Self::Video => "video",
+            Self::Cover => "cover",
+            Self::Background => "background",
+        })
+    }
+}
+
+impl AssetReport {
+    /// Charts with no cover, or one that can't be found
+    pub fn missing_covers(&self) -> impl Iterator<Item = &AssetFinding> {
+        self.findings.iter().filter(|f| {
+            f.kind == AssetKind::Cover
+                && matches!(f.issue, AssetIssue::NotSet | AssetIssue::Missing)
+        })
+    }
+
+    /// Videos named by a chart that don't exist
+    pub fn dead_videos(&self) -> impl Iterator<Item = &AssetFinding> {
+        self.findings
+            .iter()
+            .filter(|f| f.kind == AssetKind::Video && f.issue == AssetIssue::Missing)
+    }
+
+    /// Media names whose case differs from the file on disk
+    pub fn wrong_case(&self) -> impl Iterator<Item = &AssetFinding> {
+        self.findings
+            .iter()
+            .filter(|f| matches!(f.issue, AssetIssue::WrongCase { .. }))
+    }
+
+    /// Findings as a JSON array of `{"chart", "asset", "name", "issue", "fix"}`
+    /// objects, `issue` being `not set`, `missing` or `wrong case` and `fix`
+    /// the file name to use instead, or `null`
+    pub fn to_json(&self) -> String {
+        let member = |key: &str, value| (key.to_string(), value);
+        Value::Array(
+            self.findings
+                .iter()
+                .map(|f| {
+                    let (issue, fix) = match &f.issue {
+                        AssetIssue::NotSet => ("not set", None),
+                        AssetIssue::Missing => ("missing", None),
+                        AssetIssue::WrongCase { actual } => ("wrong case", Some(actual.as_str())),
+                    };
+                    Value::Object(vec![
+                        member("chart", Value::String(f.chart.display().to_string())),
+                        member("asset", Value::String(f.kind.to_string())),
+                        member("name", Value::from(f.name.as_deref())),
+                        member("issue", Value::from(issue)),
+                        member("fix", Value::from(fix)),
+                    ])
+                })
+                .collect(),
+        )
+        .to_string()
+    }
+}
+
+/// Problem with the file `name` in `dir`, `None` when it's there as named
+fn check(dir: &Path, name: &str) -> Option<AssetIssue> {
+    if name.contains(['/', '\\']) {
+        return match dir.join(name).is_file() {
+            true => None,
+            false => Some(AssetIssue::Missing),
+        };
+    }
+    // Listed rather than opened, as case-insensitive file systems open any case
+    let files: Vec<String> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|a| a.ok())
+        .filter(|a| a.path().is_file())
+        .filter_map(|a| a.file_name().into_string().ok())
+        .collect();
+    if files.iter().any(|a| a == name) {
+        return None;
+    }
+    match files.iter().find(|a| a.eq_ignore_ascii_case(name)) {
+        Some(actual) => Some(AssetIssue::WrongCase {
+            actual: actual.clone(),
+        }),
+        None => Some(AssetIssue::Missing),
+    }
+}
+
+/// Media problems of `song`, whose chart is `chart`
+fn song_findings(chart: &Path, song: &Song) -> Vec<AssetFinding> {
+    let dir = chart.parent().unwrap_or(Path::new(""));
+    let mut ret = vec![];
+    for (kind, name) in [
+        (AssetKind::Audio, &song.mp3),
+        (AssetKind::Video, &song.video),
+        (AssetKind::Cover, &song.cover),
+        (AssetKind::Background, &song.background),
+    ] {
+        let issue = match name.as_deref() {
+            None if matches!(kind, AssetKind::Audio | AssetKind::Cover) => Some(AssetIssue::NotSet),
+            None => None,
+            Some(name) => check(dir, name),
+        };
+        if let Some(issue) = issue {
+            ret.push(AssetFinding {
+                chart: chart.to_path_buf(),
+                kind,
+                name: name.clone(),
+                issue,
+            });
+        }
+    }
+    ret
+}
+
+impl SongLibrary {
+    /// Media problems of every song in the library
+    /// ```rust
+    /// use usdx_parser::library::SongLibrary;
+    ///
+    /// let report = SongLibrary::scan("tests/song_folder").unwrap().check_assets();
+    /// // Both charts name "cover.jpg" for Cover.JPG
+    /// assert_eq!(report.wrong_case().count(), 2);
+    /// assert_eq!(report.dead_videos().count(), 1);
+    /// ```
+    pub fn check_assets(&self) -> AssetReport {
+        AssetReport {
+            findings: self
+                .songs
+                .iter()
+                .flat_map(|e| song_findings(&e.path, &e.song))
+                .collect(),
+        }
+    }
+}
+
+#[test]
+pub fn test_check_assets() {
+    let library = SongLibrary::scan("tests/library").unwrap();
+    let report = library.check_assets();
+    // Neither chart has its audio or a cover next to it
+    assert_eq!(report.missing_covers().count(), library.songs.len());
+    assert!(report
+        .findings
+        .iter()
+        .any(|f| f.kind == AssetKind::Audio && f.issue == AssetIssue::Missing));
+
+    let report = SongLibrary::scan("tests/song_folder")
+        .unwrap()
+        .check_assets();
+    let wrong: Vec<_> = report.wrong_case().collect();
+    assert_eq!(
+        wrong[0].issue,
+        AssetIssue::WrongCase {
+            actual: "Cover.JPG".to_string()
+        }
+    );
+    assert!(report.findings.iter().all(|f| f.kind != AssetKind::Audio));
+    let json = Value::parse(&report.to_json()).unwrap();
+    assert_eq!(json.items().len(), report.findings.len());
+    assert!(report
+        .to_json()
+        .contains(r#""asset":"cover","name":"cover.jpg","issue":"wrong case","fix":"Cover.JPG"}"#));
+}
@@ -0,0 +1,239 @@
+//! Audacity label track import and export
+//!
+//! Labels are stored one per line as `start<TAB>end<TAB>text` with times in
+//! seconds. Audacity optionally follows a label with a `\<TAB>low<TAB>high`
+//! line holding the selected frequency range, which is used for note pitch.
+//! A label with the text `-` stands for a line break, which lasts until the
+//! next line starts if the chart says when that is.
+//!
+//! Golden notes are written with a `*:` prefix and freestyle notes with
+//! `F:`. Normal notes whose lyric would read as a prefix get `::` in front.
+use crate::{Note, NoteType, Song};
+use anyhow::{bail, Result};
+
+/// A single Audacity label
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    /// Start of the label in seconds
+    pub start: f64,
+    /// End of the label in seconds
+    pub end: f64,
+    pub text: String,
+    /// Selected frequency range in Hz, if the label was made on a spectrogram
+    pub frequency: Option<(f64, f64)>,
+}
+
+/// Parse an exported Audacity label track
+/// ```rust
+/// use usdx_parser::audacity::parse_labels;
+///
+/// let labels = parse_labels("1.000000\t1.500000\tHel\n1.500000\t2.000000\tlo\n").unwrap();
+/// assert_eq!(labels.len(), 2);
+/// assert_eq!(labels[1].text, "lo");
+/// ```
+pub fn parse_labels(input: &str) -> Result<Vec<Label>> {
+    let mut labels: Vec<Label> = vec![];
+    for line in input.lines().filter(|l| !l.trim().is_empty()) {
+        let mut splot = line.splitn(3, '\t');
+        let first = splot.next().unwrap();
+        if first == "\\" {
+            let low = splot.next().map(|a| a.trim().parse::<f64>());
+            let high = splot.next().map(|a| a.trim().parse::<f64>());
+            let (Some(label), Some(Ok(low)), Some(Ok(high))) = (labels.last_mut(), low, high)
+            else {
+                bail!("Invalid frequency line: {}", line);
+            };
+            label.frequency = Some((low, high));
+            continue;
+        }
+        let start = first.trim().parse::<f64>()?;
+        let end = match splot.next() {
+            Some(a) => a.trim().parse::<f64>()?,
+            None => bail!("Label is missing an end time: {}", line),
+        };
+        let text = splot.next().unwrap_or_default().to_string();
+        labels.push(Label {
+            start,
+            end,
+            text,
+            frequency: None,
+        });
+    }
+    Ok(labels)
+}
+
+/// Write labels in the format Audacity imports
+pub fn write_labels(labels: &[Label]) -> String {
+    let mut ret = String::new();
+    for label in labels {
+        ret.push_str(&format!(
+            "{:.6}\t{:.6}\t{}\n",
+            label.start, label.end, label.text
+        ));
+        if let Some((low, high)) = label.frequency {
+            ret.push_str(&format!("\\\t{:.6}\t{:.6}\n", low, high));
+        }
+    }
+    ret
+}
+
+/// Frequency in Hz of a USDX tone, where tone 0 is C4
+fn tone_to_frequency(tone: i32) -> f64 {
+    440.0 * 2f64.powf((tone - 9) as f64 / 12.0)
+}
+
+/// Prefixes marking the note type in label text
+const TYPE_PREFIXES: [(&str, NoteType); 3] = [
+    ("*:", NoteType::Golden),
+    ("F:", NoteType::Freestyle),
+    ("::", NoteType::Normal),
+];
+
+/// Label text of a sung note
+fn label_text(note_type: &NoteType, lyric: &str) -> String {
+    let prefix = match note_type {
+        NoteType::Golden => "*:",
+        NoteType::Freestyle => "F:",
+        _ if lyric == "-" || TYPE_PREFIXES.iter().any(|a| lyric.starts_with(a.0)) => "::",
+        _ => "",
+    };
+    format!("{}{}", prefix, lyric)
+}
+
+/// Note type and lyric of a label's text
+fn parse_label_text(text: &str) -> (NoteType, &str) {
+    for (prefix, note_type) in TYPE_PREFIXES {
+        if let Some(lyric) = text.strip_prefix(prefix) {
+            return (note_type, lyric);
+        }
+    }
+    (NoteType::Normal, text)
+}
+
+/// Nearest USDX tone for a frequency in Hz
+fn frequency_to_tone(frequency: f64) -> i32 {
+    (12.0 * (frequency / 440.0).log2()).round() as i32 + 9
+}
+
+impl Song {
+    /// Convert all notes into Audacity labels, using `#BPM` and `#GAP` for timing
+    pub fn to_audacity_labels(&self) -> Vec<Label> {
+        self.notes
+            .iter()
+            .map(|note| {
+                let start = self.beat_to_ms(note.beat_number as f64) / 1000.0;
+                match note.note_type {
+                    NoteType::LineBreak => Label {
+                        start,
+                        end: note
+                            .line_start
+                            .map_or(start, |a| self.beat_to_ms(a as f64) / 1000.0),
+                        text: "-".to_string(),
+                        frequency: None,
+                    },
+                    _ => {
+                        let end = self
+                            .beat_to_ms((note.beat_number + note.note_length.unwrap_or(0)) as f64)
+                            / 1000.0;
+                        let frequency = note.note_tone.map(tone_to_frequency);
+                        Label {
+                            start,
+                            end,
+                            text: label_text(
+                                &note.note_type,
+                                note.lyric.as_deref().unwrap_or_default(),
+                            ),
+                            frequency: frequency.map(|f| (f, f)),
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Replace the notes with the ones described by an Audacity label track
+    ///
+    /// Timing is converted using the song's `#BPM` and `#GAP`, so set those first.
+    /// Labels without a frequency range get tone 0.
+    ///
+    /// Labels don't say who sings them, so each note keeps the duet singer of
+    /// the note it replaces. Duets therefore need one label per existing note.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let mut song = Song::from_file("tests/i_hate_everything_about_you.txt").unwrap();
+    /// let labels = song.to_audacity_labels();
+    /// song.import_audacity_labels(&labels).unwrap();
+    /// assert_eq!(song.notes[0].lyric.as_deref(), Some("Ev"));
+    /// ```
+    pub fn import_audacity_labels(&mut self, labels: &[Label]) -> Result<()> {
+        let mut notes = vec![];
+        for label in labels {
+            let start = self.ms_to_beat(label.start * 1000.0).round();
+            if start < 0.0 {
+                bail!("Label '{}' starts before #GAP", label.text);
+            }
+            let end = self.ms_to_beat(label.end * 1000.0).round();
+            if label.text == "-" {
+                let mut note = Note::line_break(start as u32);
+                note.line_start = (end > start).then_some(end as u32);
+                notes.push(note);
+                continue;
+            }
+            let length = (end - start).max(1.0) as u32;
+            let tone = label
+                .frequency
+                .map(|(low, high)| frequency_to_tone((low * high).sqrt()))
+                .unwrap_or(0);
+            let (note_type, lyric) = parse_label_text(&label.text);
+            notes.push(Note::new(note_type, start as u32, length, tone, lyric));
+        }
+        if self.is_duet() && notes.len() != self.notes.len() {
+            bail!(
+                "Duet has {} notes but {} labels, so their singers are unknown",
+                self.notes.len(),
+                notes.len()
+            );
+        }
+        for (note, old) in notes.iter_mut().zip(&self.notes) {
+            note.voice = old.voice;
+        }
+        self.notes = notes;
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_audacity_round_trip() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let text = write_labels(&song.to_audacity_labels());
+    let labels = parse_labels(&text).unwrap();
+    let mut imported = song.clone();
+    imported.import_audacity_labels(&labels).unwrap();
+    assert_eq!(imported.notes, song.notes);
+
+    let mut song = Song::new("T", 120.0, 0);
+    song.notes = vec![
+        Note::new(NoteType::Golden, 0, 2, 3, "Gold"),
+        Note::new(NoteType::Freestyle, 4, 2, 0, "Free"),
+        Note::new(NoteType::Normal, 8, 2, 0, "*:star"),
+        Note::new(NoteType::Normal, 12, 2, 0, "-"),
+        Note::line_break(16),
+        Note::new(NoteType::Normal, 16, 2, 0, "Fly"),
+    ];
+    let labels = song.to_audacity_labels();
+    assert_eq!(labels[0].text, "*:Gold");
+    assert_eq!(labels[2].text, "::*:star");
+    assert_eq!(labels[5].text, "Fly");
+    let mut imported = song.clone();
+    imported.import_audacity_labels(&labels).unwrap();
+    assert_eq!(imported.notes, song.notes);
+
+    let song = Song::from_file("tests/duet.txt").unwrap();
+    let mut labels = song.to_audacity_labels();
+    let mut imported = song.clone();
+    imported.import_audacity_labels(&labels).unwrap();
+    assert_eq!(imported.notes, song.notes);
+    labels.pop();
+    assert!(imported.import_audacity_labels(&labels).is_err());
+}
@@ -0,0 +1,264 @@
+//! `#GAP` suggestions from the audio
+//!
+//! Finding where the singing starts is the most tedious part of charting.
+//! [`Song::suggest_gap`] looks for the first sustained rise of energy in the
+//! vocal frequency range and moves `#GAP` so the first note lands on it.
+//! Only uncompressed WAV files are decoded here; other formats have to be
+//! decoded by the caller into [`Audio`] samples.
+use crate::{NoteType, Song};
+use anyhow::{bail, Result};
+use std::path::Path;
+
+/// Window the energy is measured over, in milliseconds
+const FRAME_MS: f64 = 10.0;
+/// How long the energy has to stay up for an onset, in frames
+const SUSTAIN_FRAMES: usize = 20;
+
+/// Mono audio samples between -1 and 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audio {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+/// Where the singing starts, with how sure the analysis is about it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Onset {
+    /// Milliseconds from the start of the audio
+    pub ms: f64,
+    /// From 0 for a guess to 1 for a clear start out of silence
+    pub confidence: f32,
+}
+
+/// Suggested `#GAP` for a chart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GapSuggestion {
+    pub gap: u32,
+    /// Milliseconds to add to the current `#GAP`
+    pub correction: i64,
+    /// From 0 for a guess to 1 for a clear start out of silence
+    pub confidence: f32,
+}
+
+impl Audio {
+    /// Read a WAV file
+    pub fn from_file(path: &str) -> Result<Audio> {
+        Audio::from_wav(&std::fs::read(path)?)
+    }
+
+    /// Decode an integer or float PCM WAV file, mixing its channels down to mono
+    pub fn from_wav(data: &[u8]) -> Result<Audio> {
+        if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            bail!("Only WAV audio can be analysed");
+        }
+        let u16_at = |d: &[u8], i: usize| u16::from_le_bytes([d[i], d[i + 1]]);
+        let mut format = None;
+        let mut pos = 12;
+        while pos + 8 <= data.len() {
+            let id = &data[pos..pos + 4];
+            let len =
+                u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]])
+                    as usize;
+            let body = &data[pos + 8..data.len().min(pos + 8 + len)];
+            if id == b"fmt " {
+                if body.len() < 16 {
+                    bail!("WAV format chunk is too short");
+                }
+                let mut tag = u16_at(body, 0);
+                // WAVE_FORMAT_EXTENSIBLE keeps the real format in its sub format GUID
+                if tag == 0xfffe && body.len() >= 26 {
+                    tag = u16_at(body, 24);
+                }
+                let channels = u16_at(body, 2) as usize;
+                let rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                format = Some((tag, channels, rate, u16_at(body, 14)));
+            } else if id == b"data" {
+                let Some((tag, channels, sample_rate, bits)) = format else {
+                    bail!("WAV data comes before its format");
+                };
+                if channels == 0 || sample_rate == 0 {
+                    bail!("WAV file has no channels or no sample rate");
+                }
+                let sample: fn(&[u8]) -> f32 = match (tag, bits) {
+                    (1, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+                    (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+                    (1, 24) => |b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0,
+                    (1, 32) => {
+                        |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0
+                    }
+                    (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                    _ => bail!("Unsupported WAV encoding {} with {} bits", tag, bits),
+                };
+                let frame = channels * bits as usize / 8;
+                let samples = body
+                    .chunks_exact(frame)
+                    .map(|f| {
+                        let sum: f32 = f.chunks_exact(bits as usize / 8).map(sample).sum();
+                        sum / channels as f32
+                    })
+                    .collect();
+                return Ok(Audio {
+                    sample_rate,
+                    samples,
+                });
+            }
+            // Chunks are padded to an even length
+            pos += 8 + len + len % 2;
+        }
+        bail!("WAV file has no audio data");
+    }
+
+    /// Find the first sustained rise of energy in the vocal range
+    ///
+    /// `None` when the audio is too short or too even to tell.
+    pub fn onset(&self) -> Option<Onset> {
+        let energies = self.frame_energies();
+        if energies.len() < SUSTAIN_FRAMES {
+            return None;
+        }
+        let mut sorted = energies.clone();
+        sorted.sort_by(f64::total_cmp);
+        let floor = sorted[sorted.len() / 10];
+        let loud = sorted[sorted.len() * 9 / 10];
+        let range = loud - floor;
+        if range < 6.0 {
+            return None;
+        }
+        let threshold = floor + 0.3 * range;
+        let above = |from: usize| {
+            let window = &energies[from..energies.len().min(from + SUSTAIN_FRAMES)];
+            window.iter().filter(|&&e| e > threshold).count() as f32 / SUSTAIN_FRAMES as f32
+        };
+        let mut frame =
+            (0..energies.len()).find(|&i| energies[i] > threshold && above(i) >= 0.7)?;
+        let sustain = above(frame);
+        // Back to where the attack leaves the noise floor
+        let start = floor + 0.1 * range;
+        for _ in 0..5 {
+            if frame == 0 || energies[frame - 1] <= start {
+                break;
+            }
+            frame -= 1;
+        }
+        Some(Onset {
+            ms: frame as f64 * FRAME_MS,
+            confidence: (range / 30.0).min(1.0) as f32 * sustain,
+        })
+    }
+
+    /// Energy in dB of every frame, band limited to where voices are loudest
+    fn frame_energies(&self) -> Vec<f64> {
+        let rate = self.sample_rate as f64;
+        let frame = ((rate * FRAME_MS / 1000.0) as usize).max(1);
+        // One pole high pass at 150 Hz and low pass at 3.5 kHz
+        let high = 1.0 / (1.0 + 2.0 * std::f64::consts::PI * 150.0 / rate);
+        let low = {
+            let rc = 1.0 / (2.0 * std::f64::consts::PI * 3500.0);
+            (1.0 / rate) / (rc + 1.0 / rate)
+        };
+        let (mut last_in, mut high_out, mut low_out) = (0.0, 0.0, 0.0);
+        self.samples
+            .chunks(frame)
+            .map(|chunk| {
+                let mut sum = 0.0;
+                for &s in chunk {
+                    let s = s as f64;
+                    high_out = high * (high_out + s - last_in);
+                    last_in = s;
+                    low_out += low * (high_out - low_out);
+                    sum += low_out * low_out;
+                }
+                10.0 * (sum / chunk.len() as f64 + 1e-12).log10()
+            })
+            .collect()
+    }
+}
+
+impl Song {
+    /// Suggest a `#GAP` that puts the first sung note where the singing starts in `audio`
+    ///
+    /// `None` when the chart has no notes or no onset is found.
+    pub fn suggest_gap(&self, audio: &Audio) -> Option<GapSuggestion> {
+        let first = self
+            .notes
+            .iter()
+            .filter(|n| n.note_type != NoteType::LineBreak)
+            .map(|n| n.beat_number)
+            .min()?;
+        let onset = audio.onset()?;
+        let lead = self.beat_to_ms(first as f64) - self.gap as f64;
+        let gap = (onset.ms - lead).round().max(0.0) as u32;
+        Some(GapSuggestion {
+            gap,
+            correction: gap as i64 - self.gap as i64,
+            confidence: onset.confidence,
+        })
+    }
+
+    /// Like [`Song::suggest_gap`], reading the `#MP3` file, which has to be a WAV file
+    ///
+    /// `song_dir` is the folder containing the song's txt, which the audio path is relative to.
+    pub fn suggest_gap_from_audio(&self, song_dir: &str) -> Result<GapSuggestion> {
+        let Some(mp3) = self.mp3.as_ref() else {
+            bail!("No audio file specified!");
+        };
+        let audio = Audio::from_wav(&std::fs::read(Path::new(song_dir).join(mp3))?)?;
+        let Some(suggestion) = self.suggest_gap(&audio) else {
+            bail!("Couldn't find where the singing starts");
+        };
+        Ok(suggestion)
+    }
+}
+
+#[test]
+pub fn test_suggest_gap() {
+    // 16 bit stereo: 1.2 s of faint noise, then a sung 440 Hz tone
+    let rate = 8000;
+    let mut pcm = Vec::new();
+    let mut noise = 1u32;
+    for i in 0..rate * 3 {
+        let t = i as f64 / rate as f64;
+        noise = noise.wrapping_mul(1103515245).wrapping_add(12345);
+        let mut s = ((noise >> 16) as f64 / 32768.0 - 1.0) * 0.001;
+        if t >= 1.2 {
+            s += 0.5 * (2.0 * std::f64::consts::PI * 440.0 * t).sin();
+        }
+        let s = (s * 32767.0) as i16;
+        pcm.extend(s.to_le_bytes());
+        pcm.extend(s.to_le_bytes());
+    }
+    let mut wav = Vec::new();
+    wav.extend(b"RIFF");
+    wav.extend((36 + pcm.len() as u32).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    wav.extend([1, 0, 2, 0]);
+    wav.extend((rate as u32).to_le_bytes());
+    wav.extend((rate as u32 * 4).to_le_bytes());
+    wav.extend([4, 0, 16, 0]);
+    wav.extend(b"data");
+    wav.extend((pcm.len() as u32).to_le_bytes());
+    wav.extend(pcm);
+    let audio = Audio::from_wav(&wav).unwrap();
+    assert_eq!(audio.samples.len(), rate * 3);
+
+    let onset = audio.onset().unwrap();
+    assert!((onset.ms - 1200.0).abs() <= 20.0, "{}", onset.ms);
+    assert!(onset.confidence > 0.8);
+
+    // First note at beat 4 is 192 ms after the GAP at 312.5 BPM
+    let mut song = Song::from_file("tests/duet.txt").unwrap();
+    for note in &mut song.notes {
+        note.beat_number += 4;
+    }
+    let suggestion = song.suggest_gap(&audio).unwrap();
+    assert!((suggestion.gap as f64 - 1008.0).abs() <= 20.0);
+    assert_eq!(suggestion.correction, suggestion.gap as i64 - 1200);
+
+    let silence = Audio {
+        sample_rate: rate as u32,
+        samples: vec![0.0; rate],
+    };
+    assert_eq!(silence.onset(), None);
+    assert!(Audio::from_wav(b"ID3 not a wav").is_err());
+}
@@ -0,0 +1,154 @@
+//! Command line tools for UltraStar charts
+use anyhow::{bail, Result};
+use std::process::ExitCode;
+use usdx_parser::convert::{convert_path, ConvertOptions};
+use usdx_parser::lint::{lint_path, reports_to_json, FileReport, FormatVersion, Severity};
+
+const USAGE: &str = "\
+Usage:
+    usdx lint [--json] <file|dir>...
+    usdx validate [--version <1.0.0|1.1.0|1.2.0>] [--json] <file|dir>...
+    usdx convert --out <dir> [--to <chart|lrc|srt|json>] [--profile <game>]
+                 [--relative|--absolute] [--encoding <utf8|utf8-bom|latin1>]
+                 <file|dir>...
+
+lint checks charts for mistakes, validate also checks them against a
+version of the format specification (1.2.0 unless given). convert writes
+charts for a game (usdx, worldparty, vocaluxe or performous) or as lyrics
+files into the output directory. Directories are searched for charts
+recursively. Exits with 1 when any chart has errors or failed to convert.";
+
+/// Options shared by the checking commands
+struct Check {
+    json: bool,
+    version: Option<FormatVersion>,
+    paths: Vec<String>,
+}
+
+fn parse_check(args: &[String], validate: bool) -> Result<Check> {
+    let mut check = Check {
+        json: false,
+        version: validate.then_some(FormatVersion::V1_2_0),
+        paths: vec![],
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => check.json = true,
+            "--version" if validate => {
+                let Some(version) = args.next() else {
+                    bail!("--version needs a value");
+                };
+                check.version = Some(version.parse()?);
+            }
+            a if a.starts_with("--") => bail!("Unknown option {}", a),
+            a => check.paths.push(a.to_string()),
+        }
+    }
+    if check.paths.is_empty() {
+        bail!("No charts given");
+    }
+    Ok(check)
+}
+
+fn run_check(check: Check) -> Result<bool> {
+    let mut reports: Vec<FileReport> = vec![];
+    for path in &check.paths {
+        reports.extend(lint_path(path, check.version)?);
+    }
+    let count = |severity| {
+        reports
+            .iter()
+            .flat_map(|r| &r.lints)
+            .filter(|l| l.severity == severity)
+            .count()
+    };
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+    if check.json {
+        println!("{}", reports_to_json(&reports));
+    } else {
+        for report in &reports {
+            print!("{}", report);
+        }
+        println!(
+            "{} charts, {} errors, {} warnings",
+            reports.len(),
+            errors,
+            warnings
+        );
+    }
+    Ok(errors == 0)
+}
+
+fn run_convert(args: &[String]) -> Result<bool> {
+    let mut options = ConvertOptions::default();
+    let mut out = None;
+    let mut paths = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            let Some(value) = args.next() else {
+                bail!("{} needs a value", arg);
+            };
+            Ok(value)
+        };
+        match arg.as_str() {
+            "--out" => out = Some(value()?),
+            "--to" => options.format = value()?.parse()?,
+            "--profile" => options.profile = value()?.parse()?,
+            "--encoding" => options.encoding = value()?.parse()?,
+            "--relative" => options.relative = true,
+            "--absolute" => options.relative = false,
+            a if a.starts_with("--") => bail!("Unknown option {}", a),
+            a => paths.push(a),
+        }
+    }
+    let Some(out) = out else {
+        bail!("No output directory given");
+    };
+    if paths.is_empty() {
+        bail!("No charts given");
+    }
+    let (mut written, mut failed) = (0, 0);
+    for path in paths {
+        for (input, result) in convert_path(path, out, &options)? {
+            match result {
+                Ok(_) => written += 1,
+                Err(e) => {
+                    failed += 1;
+                    println!("{}: error: {}", input.display(), e);
+                }
+            }
+        }
+    }
+    println!("{} charts converted, {} failed", written, failed);
+    Ok(failed == 0)
+}
+
+fn run(args: &[String]) -> Result<bool> {
+    let Some((command, rest)) = args.split_first() else {
+        bail!("No command given");
+    };
+    match command.as_str() {
+        "lint" => run_check(parse_check(rest, false)?),
+        "validate" => run_check(parse_check(rest, true)?),
+        "convert" => run_convert(rest),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(true)
+        }
+        a => bail!("Unknown command {}", a),
+    }
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("usdx: {}\n\n{}", e, USAGE);
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Compact binary encoding of songs
+//!
+//! Integers are LEB128 varints (signed ones zigzag encoded), strings are a
+//! length followed by UTF-8 bytes and options a presence byte followed by the
+//! value. Used by the on-disk library index and the song cache.
+use crate::{Header, NoteRef, NoteType, Song, SongRef, Voice};
+use anyhow::{bail, Result};
+
+#[derive(Default)]
+pub(crate) struct Writer {
+    pub data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn varint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.data.push(byte);
+                return;
+            }
+            self.data.push(byte | 0x80);
+        }
+    }
+
+    pub fn signed(&mut self, value: i64) {
+        self.varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.data.extend_from_slice(value.as_bytes());
+    }
+
+    pub fn option<T>(&mut self, value: Option<T>, f: impl FnOnce(&mut Self, T)) {
+        match value {
+            Some(a) => {
+                self.u8(1);
+                f(self, a);
+            }
+            None => self.u8(0),
+        }
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    pub data: &'a [u8],
+    pub pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.data.len() - self.pos {
+            bail!("Unexpected end of binary data");
+        }
+        self.pos += n;
+        Ok(&self.data[self.pos - n..self.pos])
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn varint(&mut self) -> Result<u64> {
+        let mut ret = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            ret |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(ret);
+            }
+        }
+        bail!("Varint is too long");
+    }
+
+    pub fn signed(&mut self) -> Result<i64> {
+        let value = self.varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    pub fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    pub fn str(&mut self) -> Result<&'a str> {
+        let len = self.varint()? as usize;
+        Ok(std::str::from_utf8(self.take(len)?)?)
+    }
+
+    pub fn string(&mut self) -> Result<String> {
+        Ok(self.str()?.to_string())
+    }
+
+    pub fn option<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<Option<T>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(f(self)?)),
+            a => bail!("Invalid option marker {}", a),
+        }
+    }
+}
+
+fn optional_str(w: &mut Writer, value: &Option<String>) {
+    w.option(value.as_deref(), Writer::str);
+}
+
+pub(crate) fn write_song(w: &mut Writer, song: &Song) {
+    optional_str(w, &song.artist);
+    w.str(&song.title);
+    optional_str(w, &song.mp3);
+    optional_str(w, &song.video);
+    optional_str(w, &song.edition);
+    optional_str(w, &song.genre);
+    optional_str(w, &song.year);
+    optional_str(w, &song.language);
+    w.f32(song.bpm);
+    w.varint(song.gap as u64);
+    w.option(song.video_gap, Writer::f32);
+    w.option(song.start, Writer::f32);
+    w.option(song.preview_start, Writer::f32);
+    optional_str(w, &song.cover);
+    optional_str(w, &song.background);
+    optional_str(w, &song.singer_p1);
+    optional_str(w, &song.singer_p2);
+    w.varint(song.header_order.len() as u64);
+    for &header in &song.header_order {
+        w.u8(header as u8);
+    }
+    w.varint(song.other_headers.len() as u64);
+    for (i, tag, value) in &song.other_headers {
+        w.varint(*i as u64);
+        w.str(tag);
+        w.str(value);
+    }
+    w.u8(song.relative as u8);
+    w.varint(song.line_offsets.len() as u64);
+    for &offset in &song.line_offsets {
+        w.varint(offset as u64);
+    }
+    w.varint(song.notes.len() as u64);
+    for note in song.notes.iter() {
+        w.u8(match note.note_type {
+            NoteType::Normal => 0,
+            NoteType::Golden => 1,
+            NoteType::Freestyle => 2,
+            NoteType::LineBreak => 3,
+        });
+        w.varint(note.beat_number as u64);
+        w.option(note.note_length, |w, a| w.varint(a as u64));
+        w.option(note.line_start, |w, a| w.varint(a as u64));
+        w.option(note.note_tone, |w, a| w.signed(a as i64));
+        w.option(note.lyric.as_deref(), Writer::str);
+        w.option(note.second_lyric.as_deref(), Writer::str);
+        w.u8(match note.voice {
+            None => 0,
+            Some(Voice::P1) => 1,
+            Some(Voice::P2) => 2,
+            Some(Voice::Both) => 3,
+        });
+    }
+}
+
+fn optional<'a>(r: &mut Reader<'a>) -> Result<Option<&'a str>> {
+    r.option(Reader::str)
+}
+
+pub(crate) fn read_song(r: &mut Reader) -> Result<Song> {
+    Ok(read_song_ref(r)?.to_song())
+}
+
+/// Decodes a song whose text borrows from the encoded data
+pub(crate) fn read_song_ref<'a>(r: &mut Reader<'a>) -> Result<SongRef<'a>> {
+    let artist = optional(r)?;
+    let title = r.str()?;
+    let mp3 = optional(r)?;
+    let video = optional(r)?;
+    let edition = optional(r)?;
+    let genre = optional(r)?;
+    let year = optional(r)?;
+    let language = optional(r)?;
+    let bpm = r.f32()?;
+    let gap = r.varint()? as u32;
+    let video_gap = r.option(Reader::f32)?;
+    let start = r.option(Reader::f32)?;
+    let preview_start = r.option(Reader::f32)?;
+    let cover = optional(r)?;
+    let background = optional(r)?;
+    let singer_p1 = optional(r)?;
+    let singer_p2 = optional(r)?;
+    let count = r.varint()? as usize;
+    let mut header_order = Vec::with_capacity(count.min(Header::ALL.len()));
+    for _ in 0..count {
+        let i = r.u8()?;
+        let Some(&header) = Header::ALL.get(i as usize) else {
+            bail!("Invalid header {}", i);
+        };
+        header_order.push(header);
+    }
+    let count = r.varint()? as usize;
+    let mut other_headers = Vec::with_capacity(count.min(r.data.len() / 3));
+    for _ in 0..count {
+        other_headers.push((r.varint()? as usize, r.str()?, r.str()?));
+    }
+    let relative = match r.u8()? {
+        0 => false,
+        1 => true,
+        a => bail!("Invalid relative flag {}", a),
+    };
+    let count = r.varint()? as usize;
+    let mut line_offsets = Vec::with_capacity(count.min(r.data.len()));
+    for _ in 0..count {
+        line_offsets.push(r.varint()? as u32);
+    }
+    let count = r.varint()? as usize;
+    // Every note takes at least six bytes, which bounds bogus counts
+    let mut notes = Vec::with_capacity(count.min(r.data.len() / 6));
+    for _ in 0..count {
+        let note_type = match r.u8()? {
+            0 => NoteType::Normal,
+            1 => NoteType::Golden,
+            2 => NoteType::Freestyle,
+            3 => NoteType::LineBreak,
+            a => bail!("Invalid note type {}", a),
+        };
+        let beat_number = r.varint()? as u32;
+        let note_length = r.option(|r| Ok(r.varint()? as u32))?;
+        let line_start = r.option(|r| Ok(r.varint()? as u32))?;
+        let note_tone = r.option(|r| Ok(r.signed()? as i32))?;
+        let lyric = optional(r)?;
+        let second_lyric = optional(r)?;
+        let voice = match r.u8()? {
+            0 => None,
+            1 => Some(Voice::P1),
+            2 => Some(Voice::P2),
+            3 => Some(Voice::Both),
+            a => bail!("Invalid voice {}", a),
+        };
+        notes.push(NoteRef {
+            note_type,
+            beat_number,
+            note_length,
+            note_tone,
+            lyric,
+            second_lyric,
+            voice,
+            line_start,
+        });
+    }
+    Ok(SongRef {
+        artist,
+        title,
+        mp3,
+        video,
+        edition,
+        genre,
+        year,
+        language,
+        bpm,
+        gap,
+        video_gap,
+        start,
+        preview_start,
+        cover,
+        background,
+        singer_p1,
+        singer_p2,
+        notes,
+        header_order,
+        other_headers,
+        relative,
+        line_offsets,
+    })
+}
+
+#[test]
+pub fn test_song_encoding() {
+    let files = [
+        "tests/duet.txt",
+        "tests/queen_bohemian_rhapsody.txt",
+        "tests/please_tell_rosie.txt",
+    ];
+    for file in files {
+        let song = Song::from_file(file).unwrap();
+        let mut w = Writer::default();
+        write_song(&mut w, &song);
+        let decoded = read_song(&mut Reader::new(&w.data)).unwrap();
+        assert_eq!(decoded.to_string(), song.to_string());
+        assert_eq!(decoded.other_headers, song.other_headers);
+        assert!(read_song(&mut Reader::new(&w.data[..w.data.len() - 1])).is_err());
+    }
+}
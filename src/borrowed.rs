@@ -0,0 +1,653 @@
+//! Parsing without copying
+//!
+//! [`SongRef`] and [`NoteRef`] borrow their headers and lyrics from the chart
+//! text, which saves thousands of small allocations per file for read-only
+//! work like indexing. [`Song`] parsing goes through them as well.
+use crate::{
+    CompatProfile, EmptyHeaders, Header, Note, NoteType, ParseLimits, ParseOptions, Song, Voice,
+};
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
+
+/// Header tags the parser reads, in the spelling USDX writes them
+const HEADER_TAGS: &[&str] = &[
+    "ARTIST",
+    "TITLE",
+    "MP3",
+    "AUDIO",
+    "VIDEO",
+    "EDITION",
+    "GENRE",
+    "YEAR",
+    "LANGUAGE",
+    "BPM",
+    "GAP",
+    "VIDEOGAP",
+    "START",
+    "PREVIEWSTART",
+    "COVER",
+    "BACKGROUND",
+    "P1",
+    "DUETSINGERP1",
+    "P2",
+    "DUETSINGERP2",
+    "RELATIVE",
+    "LYRICS2",
+];
+
+fn parse_yes_no(input: &str) -> Result<bool> {
+    let is = |a: &[&str]| a.iter().any(|b| input.trim().eq_ignore_ascii_case(b));
+    if is(&["yes", "true"]) {
+        Ok(true)
+    } else if is(&["no", "false"]) {
+        Ok(false)
+    } else {
+        bail!("Expected yes or no, found {}", input)
+    }
+}
+
+/// Number in a header value, with either `.` or `,` as decimal separator
+pub(crate) fn parse_decimal(tag: &str, input: &str) -> Result<f64> {
+    match input.trim().replace(',', ".").parse::<f64>() {
+        Ok(a) if a.is_finite() => Ok(a),
+        _ => bail!("{} specified failed to be parsed!", tag),
+    }
+}
+
+/// Millisecond header value, rounded to whole milliseconds
+pub(crate) fn parse_ms(tag: &str, input: &str) -> Result<u32> {
+    match parse_decimal(tag, input)? {
+        a if (0.0..=u32::MAX as f64).contains(&a) => Ok((a + 0.5) as u32),
+        _ => bail!("{} must not be negative!", tag),
+    }
+}
+
+/// Header value in seconds, which keeps its fraction
+pub(crate) fn parse_seconds(tag: &str, input: &str) -> Result<f32> {
+    Ok(parse_decimal(tag, input)? as f32)
+}
+
+/// Number of the sentence of every sung note, counted like
+/// [`Song::sentences`] does, and `None` for line breaks
+pub(crate) fn sentence_numbers<'n>(
+    notes: impl IntoIterator<Item = (&'n NoteType, Option<Voice>)>,
+) -> Vec<Option<usize>> {
+    let mut ret = vec![];
+    let mut count = 0;
+    let mut fresh = true;
+    let mut voice = None;
+    for (note_type, v) in notes {
+        if *note_type == NoteType::LineBreak {
+            fresh = true;
+            ret.push(None);
+            continue;
+        }
+        if v != voice {
+            fresh = true;
+            voice = v;
+        }
+        if fresh {
+            count += 1;
+            fresh = false;
+        }
+        ret.push(Some(count - 1));
+    }
+    ret
+}
+
+/// Song whose text fields borrow from the parsed chart
+#[derive(Debug, Clone)]
+pub struct SongRef<'a> {
+    pub artist: Option<&'a str>,
+    pub title: &'a str,
+    /// Path to the audio file, from `#MP3` or else `#AUDIO`
+    pub mp3: Option<&'a str>,
+    pub video: Option<&'a str>,
+    pub edition: Option<&'a str>,
+    pub genre: Option<&'a str>,
+    pub year: Option<&'a str>,
+    pub language: Option<&'a str>,
+    /// Beats per minute
+    pub bpm: f32,
+    /// Delay in ms before the lyrics start after song
+    pub gap: u32,
+    /// Seconds into the video at which the audio starts
+    pub video_gap: Option<f32>,
+    pub start: Option<f32>,
+    pub preview_start: Option<f32>,
+    /// Path to the cover image
+    pub cover: Option<&'a str>,
+    /// Path to the background image
+    pub background: Option<&'a str>,
+    /// Name of the first duet singer
+    pub singer_p1: Option<&'a str>,
+    /// Name of the second duet singer
+    pub singer_p2: Option<&'a str>,
+    /// All notes with lyrics
+    pub notes: Vec<NoteRef<'a>>,
+    /// Order the headers were read in
+    pub header_order: Vec<Header>,
+    /// Headers without a field of their own, as written with the number of
+    /// `header_order` entries before them
+    pub other_headers: Vec<(usize, &'a str, &'a str)>,
+    /// Whether the chart counted beats from each line break
+    pub relative: bool,
+    /// For relative charts, the beat each line of notes counted from
+    pub line_offsets: Vec<u32>,
+}
+
+/// Note whose lyric borrows from the parsed chart
+#[derive(Debug, Clone)]
+pub struct NoteRef<'a> {
+    pub note_type: NoteType,
+    /// Number of beats after start of the song when this note happens
+    pub beat_number: u32,
+    /// Number of beats this note lasts
+    pub note_length: Option<u32>,
+    pub note_tone: Option<i32>,
+    /// String content for this note
+    pub lyric: Option<&'a str>,
+    /// Lyric of the second layer, from the `#LYRICS2` headers
+    pub second_lyric: Option<&'a str>,
+    /// Duet singer of this note, `None` for solo songs
+    pub voice: Option<Voice>,
+    /// For line breaks written as `- <end> <start>`, the beat the next line starts at
+    pub line_start: Option<u32>,
+}
+
+impl<'a> SongRef<'a> {
+    /// Parse a chart without copying its text
+    /// ```rust
+    /// use usdx_parser::SongRef;
+    ///
+    /// let text = std::fs::read_to_string("tests/i_hate_everything_about_you.txt").unwrap();
+    /// let song = SongRef::parse(&text).unwrap();
+    /// assert_eq!(song.artist, Some("Three Days Grace"));
+    /// assert_eq!(song.to_song().title, song.title);
+    /// ```
+    pub fn parse(value: &'a str) -> Result<SongRef<'a>> {
+        Self::parse_with(value, CompatProfile::default())
+    }
+
+    /// Parse a chart without copying its text, using the tolerance rules of a specific game
+    pub fn parse_with(value: &'a str, profile: CompatProfile) -> Result<SongRef<'a>> {
+        Self::parse_lines(
+            value,
+            profile,
+            &ParseLimits::unlimited(),
+            &Default::default(),
+            true,
+        )
+    }
+
+    /// Parse a chart from an untrusted source, failing once it exceeds `limits`
+    /// ```rust
+    /// use usdx_parser::{CompatProfile, ParseLimits, SongRef};
+    ///
+    /// let text = format!("#TITLE:{}\n#BPM:100\n#GAP:0\n", "a".repeat(10_000));
+    /// let limits = ParseLimits::untrusted();
+    /// assert!(SongRef::parse_limited(&text, CompatProfile::default(), &limits).is_err());
+    /// ```
+    pub fn parse_limited(
+        value: &'a str,
+        profile: CompatProfile,
+        limits: &ParseLimits,
+    ) -> Result<SongRef<'a>> {
+        Self::parse_lines(value, profile, limits, &Default::default(), true)
+    }
+
+    /// Parse a chart within `limits`, reading it as `options` say
+    ///
+    /// [`ParseOptions::normalize_nfc`] needs owned text and is left to
+    /// [`Song::from_str_with_options`].
+    pub fn parse_with_options(
+        value: &'a str,
+        profile: CompatProfile,
+        limits: &ParseLimits,
+        options: &ParseOptions,
+    ) -> Result<SongRef<'a>> {
+        Self::parse_lines(value, profile, limits, options, true)
+    }
+
+    /// Parse only the headers, leaving `notes` empty
+    #[cfg(feature = "std")]
+    pub(crate) fn parse_headers(value: &'a str, profile: CompatProfile) -> Result<SongRef<'a>> {
+        Self::parse_lines(
+            value,
+            profile,
+            &ParseLimits::unlimited(),
+            &Default::default(),
+            false,
+        )
+    }
+
+    fn parse_lines(
+        value: &'a str,
+        profile: CompatProfile,
+        limits: &ParseLimits,
+        options: &ParseOptions,
+        with_notes: bool,
+    ) -> Result<SongRef<'a>> {
+        limits.check_input(value.len())?;
+        let mut artist = None;
+        let mut title = None;
+        let mut mp3 = None;
+        let mut audio = None;
+        let mut video = None;
+        let mut edition = None;
+        let mut genre = None;
+        let mut year = None;
+        let mut language = None;
+        let mut bpm = None;
+        let mut gap = None;
+        let mut video_gap = None;
+        let mut start = None;
+        let mut preview_start = None;
+        let mut cover = None;
+        let mut background = None;
+        let mut singer_p1 = None;
+        let mut duet_singer_p1 = None;
+        let mut singer_p2 = None;
+        let mut duet_singer_p2 = None;
+        let mut relative = None;
+        let mut voice = None;
+        let mut notes = vec![];
+        // One `#LYRICS2` value per sentence
+        let mut second_lyrics = vec![];
+        let mut header_order = vec![];
+        let mut other_headers = vec![];
+        // Indices of the notes that start a voice, where relative beats restart
+        let mut voice_starts = vec![];
+        // Set from the first note or voice marker on, where headers don't belong
+        let mut in_notes = false;
+        for (number, line) in value.lines().enumerate() {
+            let number = number + 1;
+            limits.check_line(number, line)?;
+            let line = line.trim_start();
+            // Whatever follows the end of the chart is ignored
+            if line.trim_end() == "E" {
+                break;
+            }
+            if line.starts_with('#') {
+                if in_notes {
+                    if options.strict {
+                        bail!("Header on line {} comes after the notes", number);
+                    }
+                    tracing::warn!("Header on line {} comes after the notes", number);
+                }
+                let Some((tag, value)) = profile.split_tag(line, HEADER_TAGS) else {
+                    // Kept as written, so that saving the song doesn't lose it
+                    if let Some((tag, value)) =
+                        line.strip_prefix('#').and_then(|a| a.split_once(':'))
+                    {
+                        limits.check_header(number, tag, value)?;
+                        other_headers.push((header_order.len(), tag, value));
+                    }
+                    continue;
+                };
+                // Unknown to games that don't read it, and kept like other unknown headers
+                if tag == "AUDIO" && !profile.accepts_audio_tag() {
+                    limits.check_header(number, tag, value)?;
+                    other_headers.push((header_order.len(), &line[1..1 + tag.len()], value));
+                    continue;
+                }
+                // Repeated and never trimmed, as spaces separate the words
+                if tag == "LYRICS2" {
+                    limits.check_header(number, tag, value)?;
+                    second_lyrics.push(value);
+                    continue;
+                }
+                let value = match options.keep_header_whitespace {
+                    true => value,
+                    false => value.trim(),
+                };
+                limits.check_header(number, tag, value)?;
+                let slot = match tag {
+                    "ARTIST" => &mut artist,
+                    "TITLE" => &mut title,
+                    "MP3" => &mut mp3,
+                    "AUDIO" => &mut audio,
+                    "VIDEO" => &mut video,
+                    "EDITION" => &mut edition,
+                    "GENRE" => &mut genre,
+                    "YEAR" => &mut year,
+                    "LANGUAGE" => &mut language,
+                    "BPM" => &mut bpm,
+                    "GAP" => &mut gap,
+                    "VIDEOGAP" => &mut video_gap,
+                    "START" => &mut start,
+                    "PREVIEWSTART" => &mut preview_start,
+                    "COVER" => &mut cover,
+                    "BACKGROUND" => &mut background,
+                    "P1" => &mut singer_p1,
+                    "DUETSINGERP1" => &mut duet_singer_p1,
+                    "P2" => &mut singer_p2,
+                    "DUETSINGERP2" => &mut duet_singer_p2,
+                    "RELATIVE" => {
+                        other_headers.push((header_order.len(), &line[1..1 + tag.len()], value));
+                        &mut relative
+                    }
+                    _ => continue,
+                };
+                // The first occurrence of a tag wins
+                if slot.is_some() {
+                    continue;
+                }
+                // Required headers are checked below, timing fails to parse when empty
+                if value.trim().is_empty() && !matches!(tag, "TITLE" | "BPM" | "GAP" | "RELATIVE") {
+                    match options.empty_headers {
+                        EmptyHeaders::Absent => continue,
+                        EmptyHeaders::Keep => (),
+                        EmptyHeaders::Warn => {
+                            tracing::warn!("#{} on line {} has no value", tag, number)
+                        }
+                    }
+                }
+                *slot = Some(value);
+                let header = match tag {
+                    "ARTIST" => Header::Artist,
+                    "TITLE" => Header::Title,
+                    "MP3" => Header::Mp3,
+                    "AUDIO" => Header::Mp3,
+                    "VIDEO" => Header::Video,
+                    "EDITION" => Header::Edition,
+                    "GENRE" => Header::Genre,
+                    "YEAR" => Header::Year,
+                    "LANGUAGE" => Header::Language,
+                    "BPM" => Header::Bpm,
+                    "GAP" => Header::Gap,
+                    "VIDEOGAP" => Header::VideoGap,
+                    "START" => Header::Start,
+                    "PREVIEWSTART" => Header::PreviewStart,
+                    "COVER" => Header::Cover,
+                    "BACKGROUND" => Header::Background,
+                    "P1" | "DUETSINGERP1" => Header::SingerP1,
+                    "P2" | "DUETSINGERP2" => Header::SingerP2,
+                    _ => continue,
+                };
+                if !header_order.contains(&header) {
+                    header_order.push(header);
+                }
+                continue;
+            }
+            let marker = Voice::from_marker(line);
+            if marker.is_some() || line.starts_with([':', '*', 'F', '-', 'R', 'G']) {
+                in_notes = true;
+            }
+            if !with_notes || line.is_empty() {
+                continue;
+            }
+            if let Some(v) = marker {
+                voice = Some(v);
+                voice_starts.push(notes.len());
+                continue;
+            }
+            let Ok(mut note) = NoteRef::try_from(line) else {
+                continue;
+            };
+            note.voice = voice;
+            notes.push(note);
+            limits.check_notes(number, notes.len())?;
+        }
+        if !second_lyrics.is_empty() {
+            let mut lines: Vec<_> = second_lyrics.iter().map(|a| a.split('|')).collect();
+            let numbers = sentence_numbers(notes.iter().map(|n| (&n.note_type, n.voice)));
+            for (note, number) in notes.iter_mut().zip(numbers) {
+                note.second_lyric = number
+                    .and_then(|i| lines.get_mut(i)?.next())
+                    .filter(|a| !a.is_empty());
+            }
+        }
+        // #MP3 wins when both are set, so games that only know it play the same file
+        let mp3 = mp3.or(audio);
+        let singer_p1 = singer_p1.or(duet_singer_p1);
+        let singer_p2 = singer_p2.or(duet_singer_p2);
+        let relative = parse_yes_no(relative.unwrap_or("no"))?;
+        let mut line_offsets = vec![];
+        if relative {
+            let mut counter = 0;
+            let mut line_start = true;
+            let mut voice_starts = voice_starts.into_iter().peekable();
+            for (i, note) in notes.iter_mut().enumerate() {
+                while voice_starts.next_if_eq(&i).is_some() {
+                    counter = 0;
+                    line_start = true;
+                }
+                if let Some(offset) = note.update_offset() {
+                    note.offset(counter);
+                    counter = counter.saturating_add(offset);
+                    line_start = true;
+                } else {
+                    if line_start {
+                        line_offsets.push(counter);
+                        line_start = false;
+                    }
+                    note.offset(counter);
+                }
+            }
+        }
+
+        let title = match title {
+            Some(a) if !a.trim().is_empty() => a,
+            Some(_) => bail!("Title must not be empty!"),
+            None => bail!("No title specified!"),
+        };
+
+        let bpm = if let Some(a) = bpm {
+            match parse_decimal("BPM", a)? as f32 {
+                a if a.is_finite() && a > 0.0 => a,
+                _ => bail!("BPM must be a positive number!"),
+            }
+        } else {
+            bail!("No bpm specified!");
+        };
+
+        // USDX reads a missing gap as 0
+        let gap = match gap {
+            Some(a) => parse_ms("GAP", a)?,
+            None if options.strict => bail!("No gap specified!"),
+            None => {
+                tracing::warn!("No gap specified, using 0");
+                0
+            }
+        };
+
+        let video_gap = video_gap
+            .map(|a| parse_seconds("VIDEOGAP", a))
+            .transpose()?;
+        let start = start.map(|a| parse_seconds("START", a)).transpose()?;
+        let preview_start = preview_start
+            .map(|a| parse_seconds("PREVIEWSTART", a))
+            .transpose()?;
+
+        Ok(Self {
+            artist,
+            title,
+            mp3,
+            video,
+            edition,
+            genre,
+            year,
+            language,
+            bpm,
+            gap,
+            video_gap,
+            start,
+            preview_start,
+            cover,
+            background,
+            singer_p1,
+            singer_p2,
+            notes,
+            header_order,
+            other_headers,
+            relative,
+            line_offsets,
+        })
+    }
+
+    /// Copy into an owned [`Song`]
+    pub fn to_song(&self) -> Song {
+        let owned = |a: Option<&str>| a.map(str::to_string);
+        Song {
+            artist: owned(self.artist),
+            title: self.title.to_string(),
+            mp3: owned(self.mp3),
+            video: owned(self.video),
+            edition: owned(self.edition),
+            genre: owned(self.genre),
+            year: owned(self.year),
+            language: owned(self.language),
+            bpm: self.bpm,
+            gap: self.gap,
+            video_gap: self.video_gap,
+            start: self.start,
+            preview_start: self.preview_start,
+            cover: owned(self.cover),
+            background: owned(self.background),
+            singer_p1: owned(self.singer_p1),
+            singer_p2: owned(self.singer_p2),
+            notes: self.notes.iter().map(NoteRef::to_note).collect(),
+            header_order: self.header_order.clone(),
+            other_headers: self
+                .other_headers
+                .iter()
+                .map(|&(i, tag, value)| (i, tag.to_string(), value.to_string()))
+                .collect(),
+            relative: self.relative,
+            line_offsets: self.line_offsets.clone(),
+        }
+    }
+}
+
+impl NoteRef<'_> {
+    // Updates the offset if the note is LineBreak
+    fn update_offset(&self) -> Option<u32> {
+        if self.note_type == NoteType::LineBreak {
+            Some(self.line_start.unwrap_or(self.beat_number))
+        } else {
+            None
+        }
+    }
+
+    // Offsets the note by `n` beats.
+    // Used for relative lyrics
+    fn offset(&mut self, n: u32) {
+        self.beat_number = self.beat_number.saturating_add(n);
+        self.line_start = self.line_start.map(|a| a.saturating_add(n));
+    }
+
+    /// Copy into an owned [`Note`]
+    pub fn to_note(&self) -> Note {
+        Note {
+            note_type: self.note_type.clone(),
+            beat_number: self.beat_number,
+            note_length: self.note_length,
+            note_tone: self.note_tone,
+            lyric: self.lyric.map(Arc::from),
+            second_lyric: self.second_lyric.map(Arc::from),
+            voice: self.voice,
+            line_start: self.line_start,
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for NoteRef<'a> {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        // Fields are separated by any run of spaces or tabs
+        let mut rest = value;
+        let mut field = |name| {
+            let start = rest.trim_start_matches([' ', '\t']);
+            let end = start.find([' ', '\t']).unwrap_or(start.len());
+            if end == 0 {
+                bail!("Note is missing its {}: {}", name, value);
+            }
+            rest = &start[end..];
+            Ok(&start[..end])
+        };
+        let note_type = field("type")?.try_into()?;
+        let beat_number = field("beat")?.parse::<u32>()?;
+        let mut line_start = None;
+        let (note_length, note_tone, lyric) = if note_type == NoteType::LineBreak {
+            // Converted charts give the start of the next line as well
+            if let Ok(start) = field("line start") {
+                line_start = Some(start.parse::<u32>()?);
+            }
+            (None, None, None)
+        } else {
+            let note_length = field("length")?.parse::<u32>()?;
+            let note_tone = field("tone")?.parse::<i32>()?;
+            // The lyric is the rest of the line after one separator, so its
+            // own spaces are kept
+            let mut chars = rest.chars();
+            chars.next();
+            (Some(note_length), Some(note_tone), Some(chars.as_str()))
+        };
+        Ok(Self {
+            note_type,
+            beat_number,
+            note_length,
+            note_tone,
+            lyric,
+            second_lyric: None,
+            voice: None,
+            line_start,
+        })
+    }
+}
+
+#[test]
+pub fn test_borrowed_parsing() {
+    let text = std::fs::read_to_string("tests/duet.txt").unwrap();
+    let song = SongRef::parse(&text).unwrap();
+    // Borrowed fields point into the parsed text
+    let range = text.as_bytes().as_ptr_range();
+    assert!(range.contains(&song.title.as_ptr()));
+    assert!(range.contains(&song.notes[0].lyric.unwrap().as_ptr()));
+    assert_eq!(
+        song.to_song().to_string(),
+        Song::from_str_with(&text, CompatProfile::default())
+            .unwrap()
+            .to_string()
+    );
+    let note = NoteRef::try_from(": 4 2 0  two  spaces ").unwrap();
+    assert_eq!(note.lyric, Some(" two  spaces "));
+    let note = NoteRef::try_from("*\t12  3\t\t-2\tta b").unwrap();
+    assert_eq!(
+        (note.beat_number, note.note_length, note.note_tone),
+        (12, Some(3), Some(-2))
+    );
+    assert_eq!(note.lyric, Some("ta b"));
+    assert_eq!(NoteRef::try_from("-  40   44").unwrap().beat_number, 40);
+    assert_eq!(NoteRef::try_from(": 0 4 2").unwrap().lyric, Some(""));
+    assert!(NoteRef::try_from(": 0 4").is_err());
+
+    let song =
+        SongRef::parse("#TITLE:A\n#BPM: 120,5 \n#GAP:1200,5\n#VIDEOGAP: 15.25\nE\n").unwrap();
+    assert_eq!(
+        (song.bpm, song.gap, song.video_gap),
+        (120.5, 1201, Some(15.25))
+    );
+    let text = "#TITLE:A\n#BPM:100\n#GAP:0\n#VIDEOGAP:0,5\n#START: 12,5\n#PREVIEWSTART:30.75\nE\n";
+    let song = SongRef::parse(text).unwrap();
+    assert_eq!(
+        (song.video_gap, song.start, song.preview_start),
+        (Some(0.5), Some(12.5), Some(30.75))
+    );
+    assert_eq!(
+        song.to_song().to_string(),
+        "#TITLE:A\n#BPM:100\n#GAP:0\n#VIDEOGAP:0,5\n#START:12,5\n#PREVIEWSTART:30,75\nE\n"
+    );
+    assert!(SongRef::parse("#TITLE:A\n#BPM:100\n#GAP:-5\nE\n").is_err());
+    let error = SongRef::parse("#TITLE: \n#BPM:100\n#GAP:0\nE\n").unwrap_err();
+    assert_eq!(error.to_string(), "Title must not be empty!");
+    assert!(SongRef::parse("#TITLE:A\n#BPM:100\n#GAP:1.2.3\nE\n").is_err());
+    let text = "#TITLE:A\n#BPM:100\n#GAP:0\n#RELATIVE:YES \n: 0 1 0 a\n- 4\n: 0 1 0 b\nE\n";
+    let song = SongRef::parse(text).unwrap();
+    assert_eq!(song.notes[2].beat_number, 4);
+}
@@ -0,0 +1,113 @@
+//! Precompiled song caches
+//!
+//! Games can parse their songs once and store them in a cache file that
+//! loads without parsing any text. A cache starts with a table of song
+//! offsets, so single songs are decoded on demand, and decoded songs borrow
+//! their text from the cache data instead of copying it.
+use crate::binary::{read_song_ref, write_song, Reader, Writer};
+use crate::{Song, SongRef};
+use anyhow::{bail, Result};
+
+const CACHE_MAGIC: &[u8] = b"USDXCACH";
+const CACHE_VERSION: u64 = 7;
+
+/// Encode `songs` as a cache
+pub fn write_cache<'a>(songs: impl IntoIterator<Item = &'a Song>) -> Vec<u8> {
+    let mut body = Writer::default();
+    let mut offsets = vec![];
+    for song in songs {
+        offsets.push(body.data.len() as u64);
+        write_song(&mut body, song);
+    }
+    let mut w = Writer::default();
+    w.data.extend_from_slice(CACHE_MAGIC);
+    w.varint(CACHE_VERSION);
+    w.varint(offsets.len() as u64);
+    for offset in offsets {
+        w.data.extend_from_slice(&offset.to_le_bytes());
+    }
+    w.data.extend_from_slice(&body.data);
+    w.data
+}
+
+/// Songs stored in cache data, decoded when accessed
+#[derive(Debug, Clone, Copy)]
+pub struct SongCache<'a> {
+    offsets: &'a [u8],
+    body: &'a [u8],
+}
+
+impl<'a> SongCache<'a> {
+    /// Open cache data written by [`write_cache`]
+    /// ```rust
+    /// use usdx_parser::cache::{write_cache, SongCache};
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    /// let data = write_cache([&song]);
+    /// let cache = SongCache::new(&data).unwrap();
+    /// assert_eq!(cache.get(0).unwrap().title, "Bohemian Rhapsody");
+    /// ```
+    pub fn new(data: &'a [u8]) -> Result<SongCache<'a>> {
+        let Some(rest) = data.strip_prefix(CACHE_MAGIC) else {
+            bail!("Not a song cache");
+        };
+        let mut r = Reader::new(rest);
+        let version = r.varint()?;
+        if version != CACHE_VERSION {
+            bail!("Unsupported song cache version {}", version);
+        }
+        let count = r.varint()? as usize;
+        let Some(table) = count.checked_mul(8) else {
+            bail!("Song cache is truncated");
+        };
+        let offsets = r.take(table)?;
+        Ok(SongCache {
+            offsets,
+            body: &rest[r.pos..],
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len() / 8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Decode song `i`; it borrows its text from the cache data
+    pub fn get(&self, i: usize) -> Result<SongRef<'a>> {
+        let Some(offset) = self.offsets.get(i * 8..i * 8 + 8) else {
+            bail!("Song {} is not in the cache", i);
+        };
+        let offset = u64::from_le_bytes(offset.try_into()?) as usize;
+        let Some(data) = self.body.get(offset..) else {
+            bail!("Song {} lies outside the cache", i);
+        };
+        read_song_ref(&mut Reader::new(data))
+    }
+
+    /// Decode every song in order
+    pub fn iter(&self) -> impl Iterator<Item = Result<SongRef<'a>>> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+}
+
+#[test]
+pub fn test_song_cache() {
+    let songs =
+        ["tests/duet.txt", "tests/please_tell_rosie.txt"].map(|a| Song::from_file(a).unwrap());
+    let data = write_cache(&songs);
+    let cache = SongCache::new(&data).unwrap();
+    assert_eq!(cache.len(), 2);
+    for (cached, song) in cache.iter().zip(songs.iter()) {
+        let cached = cached.unwrap();
+        assert!(data.as_ptr_range().contains(&cached.title.as_ptr()));
+        assert_eq!(cached.to_song().to_string(), song.to_string());
+    }
+    assert!(cache.get(2).is_err());
+    assert!(SongCache::new(&data[..12]).is_err());
+    assert!(SongCache::new(b"USDXIDX\0").is_err());
+    assert!(SongCache::new(&write_cache([])).unwrap().is_empty());
+}
@@ -0,0 +1,175 @@
+//! Song lists for sharing and website generation
+//!
+//! [`SongLibrary::export_catalog`] lists every song with its metadata, how
+//! long and how hard it is and whether the media it names exists, as CSV
+//! with a header row or as a JSON array of objects with the same keys.
+use crate::folder::resolve;
+use crate::json::Value;
+use crate::library::{SongEntry, SongLibrary};
+use crate::NoteType;
+use anyhow::bail;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Format of a catalog
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CatalogFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl FromStr for CatalogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "csv" => Self::Csv,
+            "json" => Self::Json,
+            _ => bail!("Unknown catalog format: {}", s),
+        })
+    }
+}
+
+/// Columns of the catalog, in order
+const COLUMNS: [&str; 15] = [
+    "path",
+    "artist",
+    "title",
+    "edition",
+    "genre",
+    "year",
+    "language",
+    "bpm",
+    "duet",
+    "durationMs",
+    "difficulty",
+    "audio",
+    "video",
+    "cover",
+    "background",
+];
+
+impl SongLibrary {
+    /// Every song of the library as one row
+    ///
+    /// Paths are relative to the library root. The media columns are `ok`
+    /// when the file exists, `missing` when it doesn't and empty (`null`)
+    /// when the header isn't set. The duration runs to the end of the last note.
+    /// ```rust
+    /// use usdx_parser::catalog::CatalogFormat;
+    /// use usdx_parser::library::SongLibrary;
+    ///
+    /// let library = SongLibrary::scan("tests/library").unwrap();
+    /// let csv = library.export_catalog(CatalogFormat::Csv);
+    /// assert!(csv.starts_with("path,artist,title,"));
+    /// assert_eq!(csv.lines().count(), 1 + library.songs.len());
+    /// ```
+    pub fn export_catalog(&self, format: CatalogFormat) -> String {
+        let rows = self.songs.iter().map(|e| catalog_row(&self.root, e));
+        match format {
+            CatalogFormat::Csv => {
+                let mut ret = COLUMNS.join(",");
+                ret.push('\n');
+                for row in rows {
+                    let fields: Vec<String> = row.iter().map(csv_field).collect();
+                    ret.push_str(&fields.join(","));
+                    ret.push('\n');
+                }
+                ret
+            }
+            CatalogFormat::Json => Value::Array(
+                rows.map(|row| {
+                    Value::Object(COLUMNS.iter().map(|c| c.to_string()).zip(row).collect())
+                })
+                .collect(),
+            )
+            .to_string(),
+        }
+    }
+}
+
+/// Values of one song in [`COLUMNS`] order
+fn catalog_row(root: &Path, entry: &SongEntry) -> Vec<Value> {
+    let song = &entry.song;
+    let text = |a: &Option<String>| Value::from(a.as_deref());
+    let dir = entry.path.parent().unwrap_or(Path::new(""));
+    let asset = |a: &Option<String>| match a.as_deref() {
+        None => Value::Null,
+        Some(name) if resolve(dir, name).is_some() => Value::from("ok"),
+        Some(_) => Value::from("missing"),
+    };
+    let end = song
+        .notes
+        .iter()
+        .filter(|n| n.note_type != NoteType::LineBreak)
+        .map(|n| {
+            n.beat_number
+                .saturating_add(n.note_length.unwrap_or_default())
+        })
+        .max();
+    let duration = end.map_or(0.0, |a| song.beat_to_ms(a as f64).round());
+    let path = entry.path.strip_prefix(root).unwrap_or(&entry.path);
+    vec![
+        Value::String(path.display().to_string()),
+        text(&song.artist),
+        Value::from(song.title.as_str()),
+        text(&song.edition),
+        text(&song.genre),
+        text(&song.year),
+        text(&song.language),
+        Value::Number(song.bpm.into()),
+        Value::Bool(song.is_duet()),
+        Value::Number(duration),
+        Value::Number((song.difficulty() as f64 * 100.0).round() / 100.0),
+        asset(&song.mp3),
+        asset(&song.video),
+        asset(&song.cover),
+        asset(&song.background),
+    ]
+}
+
+/// `value` as a CSV field, quoted when it has to be
+fn csv_field(value: &Value) -> String {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::String(a) => a.clone(),
+        other => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+#[test]
+pub fn test_export_catalog() {
+    use crate::Song;
+
+    let mut library = SongLibrary::scan("tests/library").unwrap();
+    let mut song = Song::new("Comma, \"Quoted\"", 100.0, 0);
+    song.cover = Some("nowhere.jpg".to_string());
+    library.songs.push(SongEntry {
+        path: library.root.join("Extra").join("song.txt"),
+        song,
+    });
+    let csv = library.export_catalog(CatalogFormat::Csv);
+    let last = csv.lines().last().unwrap();
+    let expected = format!(
+        "Extra{}song.txt,,\"Comma, \"\"Quoted\"\"\",,,,,100,false,0,0,,,missing,",
+        std::path::MAIN_SEPARATOR
+    );
+    assert_eq!(last, expected);
+
+    let json = Value::parse(&library.export_catalog(CatalogFormat::Json)).unwrap();
+    let duet = json
+        .items()
+        .iter()
+        .find(|a| a.get("title").and_then(Value::as_str) == Some("Duet Test"))
+        .unwrap();
+    assert_eq!(duet.get("duet"), Some(&Value::Bool(true)));
+    assert_eq!(duet.get("durationMs").and_then(Value::as_f64), Some(3312.0));
+    assert_eq!(duet.get("audio").and_then(Value::as_str), Some("missing"));
+    assert!("xml".parse::<CatalogFormat>().is_err());
+}
@@ -0,0 +1,200 @@
+//! Compact storage for the notes of a song
+//!
+//! A [`Note`] keeps its lyric in an allocation of its own, which adds up over
+//! the thousands of notes of a long song. [`CompactNotes`] stores the numbers of every
+//! note in one array and all lyrics back to back in a single string, so a song
+//! with thousands of notes needs exactly two allocations.
+use crate::{Note, NoteRef, NoteType, Song, Voice};
+
+const TYPE_MASK: u16 = 0b11;
+const VOICE_SHIFT: u16 = 2;
+const HAS_LENGTH: u16 = 1 << 4;
+const HAS_TONE: u16 = 1 << 5;
+const HAS_LYRIC: u16 = 1 << 6;
+const HAS_LINE_START: u16 = 1 << 7;
+const HAS_SECOND_LYRIC: u16 = 1 << 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PackedNote {
+    beat_number: u32,
+    note_length: u32,
+    note_tone: i32,
+    line_start: u32,
+    /// End of the lyric in the arena; it starts where the previous note ends
+    lyric_end: u32,
+    /// End of the second lyric, which follows the lyric
+    second_end: u32,
+    /// Note type, voice and which options are set
+    flags: u16,
+}
+
+/// Notes of a song with their lyrics in a shared arena
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactNotes {
+    notes: Vec<PackedNote>,
+    lyrics: String,
+}
+
+impl CompactNotes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    pub fn push(&mut self, note: &Note) {
+        let mut flags = match note.note_type {
+            NoteType::Normal => 0,
+            NoteType::Golden => 1,
+            NoteType::Freestyle => 2,
+            NoteType::LineBreak => 3,
+        };
+        flags |= match note.voice {
+            None => 0,
+            Some(Voice::P1) => 1,
+            Some(Voice::P2) => 2,
+            Some(Voice::Both) => 3,
+        } << VOICE_SHIFT;
+        if note.note_length.is_some() {
+            flags |= HAS_LENGTH;
+        }
+        if note.note_tone.is_some() {
+            flags |= HAS_TONE;
+        }
+        if note.line_start.is_some() {
+            flags |= HAS_LINE_START;
+        }
+        if let Some(lyric) = note.lyric.as_deref() {
+            flags |= HAS_LYRIC;
+            self.lyrics.push_str(lyric);
+        }
+        let lyric_end = self.lyrics.len() as u32;
+        if let Some(lyric) = note.second_lyric.as_deref() {
+            flags |= HAS_SECOND_LYRIC;
+            self.lyrics.push_str(lyric);
+        }
+        self.notes.push(PackedNote {
+            beat_number: note.beat_number,
+            note_length: note.note_length.unwrap_or_default(),
+            note_tone: note.note_tone.unwrap_or_default(),
+            line_start: note.line_start.unwrap_or_default(),
+            lyric_end,
+            second_end: self.lyrics.len() as u32,
+            flags,
+        });
+    }
+
+    /// Note `i`, with its lyric borrowed from the arena
+    pub fn get(&self, i: usize) -> Option<NoteRef<'_>> {
+        let note = self.notes.get(i)?;
+        let start = match i {
+            0 => 0,
+            i => self.notes[i - 1].second_end as usize,
+        };
+        let flag = |bit| note.flags & bit != 0;
+        Some(NoteRef {
+            note_type: match note.flags & TYPE_MASK {
+                0 => NoteType::Normal,
+                1 => NoteType::Golden,
+                2 => NoteType::Freestyle,
+                _ => NoteType::LineBreak,
+            },
+            beat_number: note.beat_number,
+            note_length: flag(HAS_LENGTH).then_some(note.note_length),
+            note_tone: flag(HAS_TONE).then_some(note.note_tone),
+            lyric: flag(HAS_LYRIC).then(|| &self.lyrics[start..note.lyric_end as usize]),
+            second_lyric: flag(HAS_SECOND_LYRIC)
+                .then(|| &self.lyrics[note.lyric_end as usize..note.second_end as usize]),
+            voice: match (note.flags >> VOICE_SHIFT) & 0b11 {
+                1 => Some(Voice::P1),
+                2 => Some(Voice::P2),
+                3 => Some(Voice::Both),
+                _ => None,
+            },
+            line_start: flag(HAS_LINE_START).then_some(note.line_start),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = NoteRef<'_>> + '_ {
+        (0..self.len()).filter_map(|i| self.get(i))
+    }
+
+    /// Owned copies of every note
+    pub fn to_notes(&self) -> Vec<Note> {
+        self.iter().map(|n| n.to_note()).collect()
+    }
+
+    /// Bytes allocated on the heap
+    pub fn heap_size(&self) -> usize {
+        self.notes.capacity() * std::mem::size_of::<PackedNote>() + self.lyrics.capacity()
+    }
+
+    /// Release unused capacity after the last [`CompactNotes::push`]
+    pub fn shrink_to_fit(&mut self) {
+        self.notes.shrink_to_fit();
+        self.lyrics.shrink_to_fit();
+    }
+}
+
+impl<'a> FromIterator<&'a Note> for CompactNotes {
+    fn from_iter<T: IntoIterator<Item = &'a Note>>(iter: T) -> Self {
+        let mut ret = CompactNotes::new();
+        for note in iter {
+            ret.push(note);
+        }
+        ret.shrink_to_fit();
+        ret
+    }
+}
+
+impl Song {
+    /// Notes of the song in [`CompactNotes`] form
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    /// let notes = song.compact_notes();
+    /// assert_eq!(notes.len(), song.notes.len());
+    /// assert_eq!(notes.get(0).unwrap().lyric, song.notes[0].lyric.as_deref());
+    /// ```
+    pub fn compact_notes(&self) -> CompactNotes {
+        self.notes.iter().collect()
+    }
+}
+
+#[test]
+pub fn test_compact_notes() {
+    for file in ["tests/duet.txt", "tests/i_hate_everything_about_you.txt"] {
+        let song = Song::from_file(file).unwrap();
+        let compact = song.compact_notes();
+        let mut copy = song.clone();
+        copy.notes = compact.to_notes();
+        assert_eq!(copy.to_string(), song.to_string());
+        // One allocation per lyric plus the vector itself, against two in total
+        let owned = song.notes.capacity() * std::mem::size_of::<Note>()
+            + song
+                .notes
+                .iter()
+                .filter_map(|n| n.lyric.as_ref())
+                .map(|l| l.len())
+                .sum::<usize>();
+        assert!(compact.heap_size() < owned);
+    }
+    assert!(CompactNotes::new().get(0).is_none());
+
+    let mut song = Song::from_file("tests/duet.txt").unwrap();
+    song.notes[1].second_lyric = Some("lou".into());
+    let compact = song.compact_notes();
+    assert_eq!(compact.get(0).unwrap().second_lyric, None);
+    assert_eq!(compact.get(1).unwrap().second_lyric, Some("lou"));
+    assert_eq!(
+        compact.get(2).unwrap().lyric,
+        song.notes[2].lyric.as_deref()
+    );
+}
@@ -0,0 +1,149 @@
+//! Compatibility profiles for the different UltraStar implementations
+//!
+//! The games agree on the core format but differ in how forgiving their
+//! parsers are and in a few details of what they write out.
+use crate::Header;
+use anyhow::bail;
+use core::fmt;
+use core::str::FromStr;
+
+/// Target game whose parsing tolerance and output quirks should be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompatProfile {
+    /// UltraStar Deluxe
+    #[default]
+    Usdx,
+    /// UltraStar WorldParty
+    WorldParty,
+    Vocaluxe,
+    Performous,
+}
+
+impl CompatProfile {
+    /// Whether header tags are matched regardless of case (`#artist:`)
+    pub fn case_insensitive_tags(self) -> bool {
+        !matches!(self, Self::WorldParty)
+    }
+
+    /// Whether `#AUDIO` is accepted in place of `#MP3`
+    pub fn accepts_audio_tag(self) -> bool {
+        matches!(self, Self::Usdx | Self::Performous)
+    }
+
+    /// Decimal separator written for fractional header values such as `#BPM`
+    pub fn decimal_separator(self) -> char {
+        match self {
+            Self::Performous => '.',
+            _ => ',',
+        }
+    }
+
+    /// Header tags written for the names of the two duet singers
+    pub fn duet_singer_tags(self) -> [&'static str; 2] {
+        match self {
+            Self::Usdx => ["P1", "P2"],
+            Self::WorldParty | Self::Vocaluxe | Self::Performous => {
+                ["DUETSINGERP1", "DUETSINGERP2"]
+            }
+        }
+    }
+
+    /// Whether lines of both duet singers can be on screen at once; `aligned`
+    /// when they start on the same beat
+    pub fn shows_duet_overlap(self, aligned: bool) -> bool {
+        aligned || !matches!(self, Self::Performous)
+    }
+
+    /// Headers a chart needs for the game to list it
+    pub fn required_headers(self) -> &'static [Header] {
+        match self {
+            Self::Usdx | Self::WorldParty => &[
+                Header::Title,
+                Header::Artist,
+                Header::Mp3,
+                Header::Bpm,
+                Header::Gap,
+            ],
+            Self::Vocaluxe | Self::Performous => {
+                &[Header::Title, Header::Artist, Header::Mp3, Header::Bpm]
+            }
+        }
+    }
+
+    /// Headers the game reads without needing them, which charts are expected to set
+    pub fn recommended_headers(self) -> &'static [Header] {
+        match self {
+            Self::Usdx | Self::WorldParty => &[Header::Cover],
+            Self::Vocaluxe | Self::Performous => &[Header::Gap, Header::Cover],
+        }
+    }
+
+    /// Splits a `#<tag>:<value>` line into the tag out of `tags` it names and
+    /// its value, honouring the tag case rules
+    pub(crate) fn split_tag<'a>(
+        self,
+        line: &'a str,
+        tags: &[&'static str],
+    ) -> Option<(&'static str, &'a str)> {
+        let (name, value) = line.strip_prefix('#')?.split_once(':')?;
+        let tag = if self.case_insensitive_tags() {
+            tags.iter().find(|t| name.eq_ignore_ascii_case(t))
+        } else {
+            tags.iter().find(|t| name == **t)
+        };
+        Some((tag?, value))
+    }
+}
+
+impl fmt::Display for CompatProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Usdx => "USDX",
+            Self::WorldParty => "WorldParty",
+            Self::Vocaluxe => "Vocaluxe",
+            Self::Performous => "Performous",
+        })
+    }
+}
+
+impl FromStr for CompatProfile {
+    type Err = anyhow::Error;
+
+    /// Game name in any case, like `usdx` or `Performous`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "usdx" => Self::Usdx,
+            "worldparty" => Self::WorldParty,
+            "vocaluxe" => Self::Vocaluxe,
+            "performous" => Self::Performous,
+            _ => bail!("Unknown game: {}", s),
+        })
+    }
+}
+
+#[test]
+pub fn test_profile_quirks() {
+    use crate::{Song, Voice};
+
+    let text = std::fs::read_to_string("tests/duet.txt").unwrap();
+    let song = Song::from_str_with(&text, CompatProfile::Usdx).unwrap();
+    assert_eq!(song.singer_p1.as_deref(), Some("Singer One"));
+    assert_eq!(song.singer_p2.as_deref(), Some("Singer Two"));
+    assert!(song.notes.iter().any(|n| n.voice == Some(Voice::P2)));
+    assert_eq!(text.replace("\r\n", "\n"), song.to_string());
+
+    let performous = song.to_string_with(CompatProfile::Performous);
+    assert!(performous.contains("#DUETSINGERP1:Singer One\n"));
+    assert!(performous.contains("#BPM:312.5\n"));
+    let reparsed = Song::from_str_with(&performous, CompatProfile::Performous).unwrap();
+    assert_eq!(reparsed.singer_p2, song.singer_p2);
+    assert_eq!(reparsed.notes.len(), song.notes.len());
+
+    let lowercase = text.replace("#TITLE:", "#title:");
+    assert!(Song::from_str_with(&lowercase, CompatProfile::Vocaluxe).is_ok());
+    assert!(Song::from_str_with(&lowercase, CompatProfile::WorldParty).is_err());
+    assert_eq!(
+        "WorldParty".parse::<CompatProfile>().unwrap(),
+        CompatProfile::WorldParty
+    );
+}
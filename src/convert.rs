@@ -0,0 +1,295 @@
+//! Batch conversion of charts
+//!
+//! Rewrites charts for a target game, switches between absolute and relative
+//! beats, changes the text encoding or turns them into LRC, SRT or JSON
+//! lyrics files.
+use crate::library::{chart_candidates, decode_chart, is_chart};
+use crate::{CompatProfile, NoteType, Song};
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// File format written by a conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// UltraStar chart text
+    #[default]
+    Chart,
+    Lrc,
+    Srt,
+    /// The form of [`Song::to_json`]
+    Json,
+}
+
+/// Text encoding of the written file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// UTF-8 with a byte order mark, which some Windows tools expect
+    Utf8Bom,
+    /// Single byte encoding of older charts; other characters become `?`
+    Latin1,
+}
+
+/// How to convert a chart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ConvertOptions {
+    pub format: OutputFormat,
+    /// Game whose output quirks charts are written with
+    pub profile: CompatProfile,
+    /// Write charts with `#RELATIVE:yes` beats
+    pub relative: bool,
+    pub encoding: Encoding,
+}
+
+/// Lyrics line with its time span in ms
+struct Sentence {
+    start: f64,
+    end: f64,
+    text: String,
+}
+
+impl OutputFormat {
+    /// File extension, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Chart => "txt",
+            Self::Lrc => "lrc",
+            Self::Srt => "srt",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "chart" | "txt" => Self::Chart,
+            "lrc" => Self::Lrc,
+            "srt" => Self::Srt,
+            "json" => Self::Json,
+            _ => bail!("Unknown output format: {}", s),
+        })
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" => Self::Utf8,
+            "utf8-bom" | "utf-8-bom" => Self::Utf8Bom,
+            "latin1" | "latin-1" | "iso-8859-1" => Self::Latin1,
+            _ => bail!("Unknown encoding: {}", s),
+        })
+    }
+}
+
+impl Encoding {
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            Self::Utf8 => text.as_bytes().to_vec(),
+            Self::Utf8Bom => [b"\xef\xbb\xbf", text.as_bytes()].concat(),
+            Self::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(c).unwrap_or(b'?'))
+                .collect(),
+        }
+    }
+}
+
+/// Lyrics lines of every voice, ordered by start time
+fn sentences(song: &Song) -> Vec<Sentence> {
+    let mut ret: Vec<Sentence> = song
+        .sentences()
+        .iter()
+        .map(|s| Sentence {
+            start: song.beat_to_ms(s.start_beat() as f64),
+            end: song.beat_to_ms(s.end_beat() as f64),
+            text: s.text(),
+        })
+        .filter(|s| !s.text.is_empty())
+        .collect();
+    ret.sort_by(|a, b| a.start.total_cmp(&b.start));
+    ret
+}
+
+/// `ms` split into hours, minutes, seconds and milliseconds
+fn clock(ms: f64) -> (u64, u64, u64, u64) {
+    let ms = ms.max(0.0).round() as u64;
+    (ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+impl Song {
+    /// Lyrics as an LRC file with one timed line per sentence
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song: Song = "#TITLE:T\n#BPM:150\n#GAP:1000\n: 0 4 0 Hel\n: 4 4 0 lo\n- 10\n: 12 4 0 you\n"
+    ///     .parse()
+    ///     .unwrap();
+    /// assert_eq!(song.to_lrc(), "[ti:T]\n[00:01.00]Hello\n[00:02.20]you\n");
+    /// ```
+    pub fn to_lrc(&self) -> String {
+        let mut ret = String::new();
+        if let Some(artist) = &self.artist {
+            ret.push_str(&format!("[ar:{}]\n", artist));
+        }
+        ret.push_str(&format!("[ti:{}]\n", self.title));
+        for sentence in sentences(self) {
+            let (h, m, s, ms) = clock(sentence.start);
+            ret.push_str(&format!(
+                "[{:02}:{:02}.{:02}]{}\n",
+                h * 60 + m,
+                s,
+                ms / 10,
+                sentence.text
+            ));
+        }
+        ret
+    }
+
+    /// Lyrics as SRT subtitles with one entry per sentence
+    pub fn to_srt(&self) -> String {
+        let time = |ms| {
+            let (h, m, s, ms) = clock(ms);
+            format!("{:02}:{:02}:{:02},{:03}", h, m, s, ms)
+        };
+        let mut ret = String::new();
+        for (i, sentence) in sentences(self).iter().enumerate() {
+            ret.push_str(&format!(
+                "{}\n{} --> {}\n{}\n\n",
+                i + 1,
+                time(sentence.start),
+                time(sentence.end),
+                sentence.text
+            ));
+        }
+        ret
+    }
+
+    /// Serialize with `#RELATIVE:yes`, counting beats from the last line break
+    pub fn to_relative_string_with(&self, profile: CompatProfile) -> String {
+        let mut song = self.clone();
+        let mut voice = None;
+        let mut counter = 0;
+        for note in &mut song.notes {
+            if note.voice != voice {
+                voice = note.voice;
+                counter = 0;
+            }
+            let beat = note.beat_number;
+            note.beat_number = beat.saturating_sub(counter);
+            if note.note_type == NoteType::LineBreak {
+                let start = note.line_start.unwrap_or(beat);
+                note.line_start = note.line_start.map(|a| a.saturating_sub(counter));
+                counter = start;
+            }
+        }
+        song.chart_text(profile, true)
+    }
+
+    /// The song written as `options` say
+    pub fn convert(&self, options: &ConvertOptions) -> Vec<u8> {
+        let text = match options.format {
+            OutputFormat::Chart if options.relative => {
+                self.to_relative_string_with(options.profile)
+            }
+            OutputFormat::Chart => self.to_string_with(options.profile),
+            OutputFormat::Lrc => self.to_lrc(),
+            OutputFormat::Srt => self.to_srt(),
+            OutputFormat::Json => self.to_json(),
+        };
+        options.encoding.encode(&text)
+    }
+}
+
+/// Convert the chart at `input`, or every chart below it for a directory,
+/// into `output_dir`
+///
+/// Directory structure below `input` is kept. Returns every chart with the
+/// file written for it or why it failed.
+pub fn convert_path(
+    input: &str,
+    output_dir: &str,
+    options: &ConvertOptions,
+) -> Result<Vec<(PathBuf, Result<PathBuf>)>> {
+    let input = Path::new(input);
+    let dir = input.is_dir();
+    let (root, files) = if dir {
+        (input, chart_candidates(input)?)
+    } else {
+        (
+            input.parent().unwrap_or(Path::new("")),
+            vec![input.to_path_buf()],
+        )
+    };
+    let mut ret = vec![];
+    for path in files {
+        let text = match std::fs::read(&path) {
+            Ok(data) => decode_chart(&data),
+            Err(e) => {
+                ret.push((path, Err(e.into())));
+                continue;
+            }
+        };
+        // Readmes and the like in a songs directory aren't charts
+        if dir && !is_chart(&text) {
+            continue;
+        }
+        let converted = text.parse::<Song>().and_then(|song| {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let target = Path::new(output_dir)
+                .join(relative)
+                .with_extension(options.format.extension());
+            if let Some(dir) = target.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(&target, song.convert(options))?;
+            Ok(target)
+        });
+        ret.push((path, converted));
+    }
+    Ok(ret)
+}
+
+#[test]
+pub fn test_convert() {
+    let text = std::fs::read_to_string("tests/please_tell_rosie.txt").unwrap();
+    let song: Song = text.parse().unwrap();
+    let relative = song.to_relative_string_with(CompatProfile::Usdx);
+    assert!(relative.contains("#RELATIVE:yes\n"));
+    let reparsed: Song = relative.parse().unwrap();
+    assert_eq!(reparsed.to_string(), song.to_string());
+
+    let duet = Song::from_file("tests/duet.txt").unwrap();
+    let srt = duet.to_srt();
+    assert!(srt.starts_with("1\n00:00:01,200 --> "));
+    assert_eq!(srt.matches(" --> ").count(), 4);
+
+    let options = ConvertOptions {
+        encoding: Encoding::Latin1,
+        ..Default::default()
+    };
+    let mut umlaut = duet.clone();
+    umlaut.title = "Für €".to_string();
+    assert!(umlaut
+        .convert(&options)
+        .windows(5)
+        .any(|a| a == b"F\xfcr ?"));
+
+    let dir = std::env::temp_dir().join("usdx_parser_convert_test");
+    let options = ConvertOptions {
+        format: OutputFormat::Lrc,
+        ..Default::default()
+    };
+    let converted = convert_path("tests/library", dir.to_str().unwrap(), &options).unwrap();
+    assert_eq!(converted.len(), 3);
+    assert_eq!(converted.iter().filter(|a| a.1.is_ok()).count(), 2);
+    assert!(dir.join("Nested/Solo - Short/Solo - Short.lrc").exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,171 @@
+//! Playback position tracking for renderers
+//!
+//! A [`Cursor`] is asked for the state of the lyrics at the current playback
+//! time once per frame. It remembers where it was, so moving forward costs
+//! next to nothing; seeking backwards falls back to a binary search.
+use crate::lyrics::Sentence;
+use crate::{Note, Song, Voice};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// Walks the sung notes of one voice along the playback time
+#[derive(Debug, Clone)]
+pub struct Cursor<'a> {
+    notes: Vec<&'a Note>,
+    /// Start and end of every note in ms
+    times: Vec<(f64, f64)>,
+    /// Notes of every sentence, as ranges of `notes`
+    sentences: Vec<Range<usize>>,
+    /// End of every sentence in ms
+    sentence_ends: Vec<f64>,
+    /// First note and sentence that haven't ended at `time`
+    note: usize,
+    sentence: usize,
+    time: f64,
+}
+
+/// What is happening at one point of playback
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'c, 'a> {
+    /// Sentence being sung, or the next one when between sentences;
+    /// `None` after the last one
+    pub sentence: Option<&'c [&'a Note]>,
+    /// Note being sung right now
+    pub note: Option<&'a Note>,
+    /// How far into `note` playback is, from 0 to 1
+    pub progress: f32,
+    /// Every note starting after now, in order
+    pub upcoming: &'c [&'a Note],
+}
+
+impl<'a> Cursor<'a> {
+    fn new(song: &'a Song, voice: Option<Voice>) -> Self {
+        // Sentences sung by both duet singers are part of each singer's
+        let mut lines = song.sentences();
+        lines.retain(|s| s.voice == voice || s.voice == Some(Voice::Both));
+        lines.sort_by_key(Sentence::start_beat);
+        let mut notes = Vec::new();
+        let mut times = Vec::new();
+        let mut sentences = Vec::new();
+        for line in lines {
+            let start = notes.len();
+            for note in line.notes {
+                let beat = note.beat_number as f64;
+                let length = note.note_length.unwrap_or_default() as f64;
+                notes.push(note);
+                times.push((song.beat_to_ms(beat), song.beat_to_ms(beat + length)));
+            }
+            sentences.push(start..notes.len());
+        }
+        let sentence_ends = sentences
+            .iter()
+            .map(|r: &Range<usize>| times[r.end - 1].1)
+            .collect();
+        Self {
+            notes,
+            times,
+            sentences,
+            sentence_ends,
+            note: 0,
+            sentence: 0,
+            time: f64::NEG_INFINITY,
+        }
+    }
+
+    /// State of the lyrics at `ms` from the start of the audio
+    pub fn at(&mut self, ms: f64) -> Frame<'_, 'a> {
+        if ms < self.time {
+            self.note = self.times.partition_point(|t| t.1 <= ms);
+            self.sentence = self.sentence_ends.partition_point(|&end| end <= ms);
+        } else {
+            while self.times.get(self.note).is_some_and(|t| t.1 <= ms) {
+                self.note += 1;
+            }
+            while self
+                .sentence_ends
+                .get(self.sentence)
+                .is_some_and(|&end| end <= ms)
+            {
+                self.sentence += 1;
+            }
+        }
+        self.time = ms;
+        let current = self.times.get(self.note).filter(|t| t.0 <= ms);
+        let upcoming = self.note + usize::from(current.is_some());
+        Frame {
+            sentence: self
+                .sentences
+                .get(self.sentence)
+                .map(|r| &self.notes[r.clone()]),
+            note: current.map(|_| self.notes[self.note]),
+            progress: current.map_or(0.0, |t| ((ms - t.0) / (t.1 - t.0)) as f32),
+            upcoming: &self.notes[upcoming..],
+        }
+    }
+}
+
+impl Song {
+    /// Cursor over the notes of a solo song, or of the first singer of a duet
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// let mut cursor = song.cursor();
+    /// let frame = cursor.at(1300.0);
+    /// assert_eq!(frame.note.unwrap().lyric.as_deref(), Some("Hel"));
+    /// assert_eq!(frame.sentence.unwrap().len(), 2);
+    /// ```
+    pub fn cursor(&self) -> Cursor<'_> {
+        let voice = self.notes.iter().find_map(|n| n.voice).map(|_| Voice::P1);
+        Cursor::new(self, voice)
+    }
+
+    /// Cursor over the notes of one duet singer
+    pub fn voice_cursor(&self, voice: Voice) -> Cursor<'_> {
+        Cursor::new(self, Some(voice))
+    }
+}
+
+#[test]
+pub fn test_playback_cursor() {
+    let song = Song::from_file("tests/duet.txt").unwrap();
+    let mut cursor = song.voice_cursor(Voice::P2);
+    // P2 starts at beat 20, 20 * 48 ms after the gap
+    let before = cursor.at(0.0);
+    assert!(before.note.is_none());
+    assert_eq!(before.sentence.unwrap()[0].lyric.as_deref(), Some("Hi"));
+    assert_eq!(before.upcoming.len(), 3);
+    let frame = cursor.at(1200.0 + 22.0 * 48.0);
+    assert_eq!(frame.note.unwrap().lyric.as_deref(), Some("Hi"));
+    assert!((frame.progress - 0.5).abs() < 1e-6);
+    assert_eq!(frame.upcoming.len(), 2);
+    // Between the notes of a sentence
+    let gap = cursor.at(1200.0 + 25.0 * 48.0);
+    assert!(gap.note.is_none());
+    assert_eq!(gap.upcoming[0].lyric.as_deref(), Some(" back"));
+    // The next sentence once the first one ended
+    let next = cursor.at(1200.0 + 38.0 * 48.0);
+    assert_eq!(next.sentence.unwrap()[0].lyric.as_deref(), Some("yeah"));
+    assert!(cursor.at(100_000.0).sentence.is_none());
+    // Seeking back
+    assert_eq!(
+        cursor
+            .at(1200.0 + 21.0 * 48.0)
+            .note
+            .unwrap()
+            .lyric
+            .as_deref(),
+        Some("Hi")
+    );
+
+    // Both singers get the notes of P3
+    let text = "#TITLE:T\n#BPM:150\n#GAP:0\nP1\n: 0 2 0 one\nP3\n: 4 2 0 all\nP2\n\
+        : 0 2 0 two\nE\n";
+    let song: Song = text.parse().unwrap();
+    for (voice, first) in [(Voice::P1, "one"), (Voice::P2, "two")] {
+        let mut cursor = song.voice_cursor(voice);
+        assert_eq!(cursor.at(0.0).note.unwrap().lyric.as_deref(), Some(first));
+        let frame = cursor.at(450.0);
+        assert_eq!(frame.note.unwrap().lyric.as_deref(), Some("all"));
+    }
+}
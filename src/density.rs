@@ -0,0 +1,52 @@
+//! How many notes a chart packs into each stretch of the song
+//!
+//! [`Song::density_profile`] cuts the song into windows of equal length,
+//! starting with the audio, and counts the notes starting in each. Plotted,
+//! the counts show where a chart gets busy.
+use crate::{NoteType, Song};
+use alloc::vec;
+use alloc::vec::Vec;
+
+impl Song {
+    /// Notes starting in each `window_ms` long window from the start of the
+    /// audio, up to the window of the last note
+    ///
+    /// Both singers' notes count. Windows that aren't positive give no counts.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// // The first note is sung 1.2 s into the audio, the last at 3.12 s
+    /// assert_eq!(song.density_profile(1000.0), [0, 3, 2, 1]);
+    /// ```
+    pub fn density_profile(&self, window_ms: f64) -> Vec<usize> {
+        if window_ms.is_nan() || window_ms <= 0.0 {
+            return vec![];
+        }
+        let windows: Vec<usize> = self
+            .notes
+            .iter()
+            .filter(|n| n.note_type != NoteType::LineBreak)
+            .map(|n| (self.beat_to_ms(n.beat_number as f64) / window_ms) as usize)
+            .collect();
+        let mut ret = vec![0; windows.iter().max().map_or(0, |&a| a + 1)];
+        for window in windows {
+            ret[window] += 1;
+        }
+        ret
+    }
+}
+
+#[test]
+pub fn test_density_profile() {
+    let text = "#TITLE:T\n#BPM:150\n#GAP:50\n: 0 1 0 a\n: 1 1 0 b\n- 3\n: 3 1 0 c\n: 25 1 0 d\nE\n";
+    let song: Song = text.parse().unwrap();
+    // 100 ms per beat, so the notes start at 50, 150, 350 and 2550 ms
+    assert_eq!(song.density_profile(1000.0), [3, 0, 1]);
+    assert_eq!(song.density_profile(200.0)[..2], [2, 1]);
+    assert!(song.density_profile(-1.0).is_empty());
+    assert!(song.density_profile(f64::NAN).is_empty());
+    assert!(Song::new("Empty", 100.0, 0)
+        .density_profile(1000.0)
+        .is_empty());
+}
@@ -0,0 +1,155 @@
+//! Where the singers of a duet take turns and where they sing together
+//!
+//! [`Song::duet_sections`] splits a duet into stretches sung by one singer
+//! or by both. [`Song::duet_overlaps`] lists the pairs of lines that are on
+//! screen at the same time in a way the target game can't show.
+use crate::compat::CompatProfile;
+use crate::lyrics::Sentence;
+use crate::{Song, Voice};
+use alloc::vec::Vec;
+
+/// Who sings a [`Section`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    Solo(Voice),
+    Both,
+}
+
+/// Stretch of a duet with the same singers, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Section {
+    pub part: Part,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Lines of both singers overlapping in time, as indices into [`Song::sentences`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overlap {
+    pub p1: usize,
+    pub p2: usize,
+}
+
+impl Song {
+    /// Stretches sung by one singer or both, from the first line to the last
+    ///
+    /// Empty for solo songs. A singer counts as singing from the start of
+    /// each of their lines to its end, and both of them during `P3` lines.
+    /// Pauses in which nobody sings belong to no section, and a section goes
+    /// on across them until the part changes.
+    /// ```rust
+    /// use usdx_parser::duet::Part;
+    /// use usdx_parser::{Song, Voice};
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// let parts: Vec<_> = song.duet_sections().iter().map(|s| s.part).collect();
+    /// assert_eq!(parts, [Part::Solo(Voice::P1), Part::Solo(Voice::P2)]);
+    /// ```
+    pub fn duet_sections(&self) -> Vec<Section> {
+        let spans = |voice: Voice| -> Vec<(u32, u32)> {
+            let mut ret: Vec<(u32, u32)> = Vec::new();
+            let sentences = self.sentences();
+            let sung = |s: &&Sentence| s.voice == Some(voice) || s.voice == Some(Voice::Both);
+            for sentence in sentences.iter().filter(sung) {
+                let (start, end) = (sentence.start_beat(), sentence.end_beat());
+                match ret.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => ret.push((start, end)),
+                }
+            }
+            ret
+        };
+        let (p1, p2) = (spans(Voice::P1), spans(Voice::P2));
+        if p1.is_empty() && p2.is_empty() {
+            return Vec::new();
+        }
+        let mut bounds: Vec<u32> = p1.iter().chain(&p2).flat_map(|&(a, b)| [a, b]).collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+        let sings =
+            |spans: &[(u32, u32)], beat: u32| spans.iter().any(|&(a, b)| a <= beat && beat < b);
+        let mut ret: Vec<Section> = Vec::new();
+        for pair in bounds.windows(2) {
+            let part = match (sings(&p1, pair[0]), sings(&p2, pair[0])) {
+                (true, true) => Part::Both,
+                (true, false) => Part::Solo(Voice::P1),
+                (false, true) => Part::Solo(Voice::P2),
+                (false, false) => continue,
+            };
+            let (start, end) = (
+                self.beat_to_ms(pair[0] as f64),
+                self.beat_to_ms(pair[1] as f64),
+            );
+            match ret.last_mut() {
+                Some(last) if last.part == part => last.end = end,
+                _ => ret.push(Section { part, start, end }),
+            }
+        }
+        ret
+    }
+
+    /// Lines of both singers that overlap in time in a way `profile` can't show
+    ///
+    /// Performous only shows lines of both singers together when they start
+    /// on the same beat; the other games show each singer's line on its own.
+    pub fn duet_overlaps(&self, profile: CompatProfile) -> Vec<Overlap> {
+        let sentences = self.sentences();
+        let of = |voice: Voice| {
+            sentences
+                .iter()
+                .enumerate()
+                .filter(move |(_, s)| s.voice == Some(voice))
+        };
+        let mut ret = Vec::new();
+        for (i, a) in of(Voice::P1) {
+            for (j, b) in of(Voice::P2) {
+                let overlaps = a.start_beat() < b.end_beat() && b.start_beat() < a.end_beat();
+                if overlaps && !profile.shows_duet_overlap(a.start_beat() == b.start_beat()) {
+                    ret.push(Overlap { p1: i, p2: j });
+                }
+            }
+        }
+        ret
+    }
+}
+
+#[test]
+pub fn test_duet_sections() {
+    let text = "#TITLE:T\n#BPM:150\n#GAP:0\nP1\n: 0 4 0 a\n- 6\n: 8 8 0 b\nP2\n: 4 4 0 c\n- 10\n: 12 8 0 d\n: 24 4 0 e\nE\n";
+    let song: Song = text.parse().unwrap();
+    let sections: Vec<_> = song
+        .duet_sections()
+        .iter()
+        .map(|s| (s.part, s.start, s.end))
+        .collect();
+    // 100 ms per beat
+    assert_eq!(
+        sections,
+        [
+            (Part::Solo(Voice::P1), 0.0, 400.0),
+            (Part::Solo(Voice::P2), 400.0, 800.0),
+            (Part::Solo(Voice::P1), 800.0, 1200.0),
+            (Part::Both, 1200.0, 1600.0),
+            (Part::Solo(Voice::P2), 1600.0, 2800.0),
+        ]
+    );
+    assert_eq!(song.duet_overlaps(CompatProfile::Usdx), []);
+    assert_eq!(
+        song.duet_overlaps(CompatProfile::Performous),
+        [Overlap { p1: 1, p2: 3 }]
+    );
+
+    let text = "#TITLE:T\n#BPM:150\n#GAP:0\nP1\n: 0 4 0 a\nP2\n: 4 4 0 b\nP 3\n: 8 4 0 c\nE\n";
+    let song: Song = text.parse().unwrap();
+    assert_eq!(song.notes[2].voice, Some(Voice::Both));
+    assert_eq!(song.to_string(), text.replace("P 3", "P3"));
+    let parts: Vec<_> = song.duet_sections().iter().map(|s| s.part).collect();
+    assert_eq!(
+        parts,
+        [Part::Solo(Voice::P1), Part::Solo(Voice::P2), Part::Both]
+    );
+
+    let solo = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    assert_eq!(solo.duet_sections(), []);
+    assert_eq!(solo.duet_overlaps(CompatProfile::Performous), []);
+}
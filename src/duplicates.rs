@@ -0,0 +1,259 @@
+//! Detection of songs charted more than once
+//!
+//! Two songs are duplicates when their normalized artist and title agree, or
+//! when their melodies do. Names are folded, bracketed additions like
+//! "(Duet)" or "[Remastered]" and featured artists are dropped. The melody
+//! fingerprint is the pitch steps between the first sung notes, so charts
+//! that are transposed or use a different BPM still match.
+//!
+//! [`Song::fingerprint`] hashes the whole note sequence instead, for finding
+//! exact copies of a chart across libraries whatever their headers say.
+use crate::library::SongLibrary;
+use crate::text::tokens;
+use crate::{NoteType, Song};
+use std::collections::HashMap;
+
+/// Number of pitch steps that make up a melody fingerprint
+const FINGERPRINT_LEN: usize = 16;
+
+/// Songs a library contains more than once
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    /// Indices into [`SongLibrary::songs`], in library order
+    pub songs: Vec<usize>,
+    /// Some of the songs have the same normalized artist and title
+    pub same_name: bool,
+    /// Some of the songs have the same melody
+    pub same_melody: bool,
+}
+
+/// Text with everything in round or square brackets removed
+fn strip_brackets(text: &str) -> String {
+    let mut depth = 0usize;
+    let mut ret = String::new();
+    for c in text.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            c if depth == 0 => ret.push(c),
+            _ => (),
+        }
+    }
+    ret
+}
+
+/// Normalized "artist title" of a song, `None` without a usable title
+pub(crate) fn name_key(song: &Song) -> Option<String> {
+    let artist = tokens(&strip_brackets(song.artist.as_deref().unwrap_or_default()));
+    let artist = artist
+        .iter()
+        .take_while(|a| !matches!(a.as_str(), "feat" | "ft" | "featuring"))
+        .filter(|a| *a != "the");
+    let title = tokens(&strip_brackets(&song.title));
+    if title.is_empty() {
+        return None;
+    }
+    let words = artist.chain(title.iter()).cloned().collect::<Vec<_>>();
+    Some(words.join(" "))
+}
+
+/// Pitch steps between the first sung notes, `None` for short or unpitched charts
+pub(crate) fn melody_fingerprint(song: &Song) -> Option<Vec<i32>> {
+    let mut voice = None;
+    let tones = song
+        .notes
+        .iter()
+        .filter(|n| matches!(n.note_type, NoteType::Normal | NoteType::Golden))
+        // Only follow one voice, duets are fingerprinted by their first singer
+        .filter(|n| *voice.get_or_insert(n.voice) == n.voice)
+        .filter_map(|n| n.note_tone)
+        .take(FINGERPRINT_LEN * 2 + 1)
+        .collect::<Vec<_>>();
+    // A note repeated at the same pitch is the same melody split differently
+    let mut steps = tones
+        .windows(2)
+        .map(|a| a[1] - a[0])
+        .filter(|a| *a != 0)
+        .collect::<Vec<_>>();
+    if steps.len() < FINGERPRINT_LEN {
+        return None;
+    }
+    steps.truncate(FINGERPRINT_LEN);
+    Some(steps)
+}
+
+/// Greatest common divisor, 0 only if both are
+fn gcd(a: u32, b: u32) -> u32 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+impl Song {
+    /// Hash of the notes alone, equal for copies of a chart whatever their headers
+    ///
+    /// Beats count from the first note and are reduced to their smallest
+    /// step, so a different `#GAP` or a doubled BPM doesn't change it, and
+    /// pitches are hashed as steps between notes, so neither does
+    /// transposing. Lyrics and line breaks aren't part of it. The hash is
+    /// FNV-1a and stays the same across versions and platforms.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// let mut copy = song.clone();
+    /// copy.title = "Renamed".to_string();
+    /// copy.gap += 500;
+    /// copy.double_bpm();
+    /// assert_eq!(copy.fingerprint(), song.fingerprint());
+    /// ```
+    pub fn fingerprint(&self) -> u64 {
+        let notes = || {
+            self.notes
+                .iter()
+                .filter(|n| n.note_type != NoteType::LineBreak)
+        };
+        let first = notes().map(|n| n.beat_number).min().unwrap_or_default();
+        let step = notes()
+            .flat_map(|n| [n.beat_number - first, n.note_length.unwrap_or_default()])
+            .fold(0, gcd)
+            .max(1);
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        let mut feed = |value: u32| {
+            for byte in value.to_le_bytes() {
+                hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        let mut voice = None;
+        let mut tone = None;
+        for note in notes() {
+            if note.voice != voice {
+                voice = note.voice;
+                tone = None;
+                feed(u32::MAX);
+            }
+            let (kind, pitched) = match note.note_type {
+                NoteType::Normal => (0, true),
+                NoteType::Golden => (1, true),
+                _ => (2, false),
+            };
+            let interval = match (pitched, note.note_tone) {
+                (true, Some(a)) => a - tone.replace(a).unwrap_or(a),
+                _ => 0,
+            };
+            feed(kind);
+            feed((note.beat_number - first) / step);
+            feed(note.note_length.unwrap_or_default() / step);
+            feed(interval as u32);
+        }
+        hash
+    }
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+impl SongLibrary {
+    /// Groups of songs that are likely the same song charted several times
+    /// ```rust
+    /// use usdx_parser::library::SongLibrary;
+    ///
+    /// let library = SongLibrary::scan("tests/library").unwrap();
+    /// assert!(library.find_duplicates().is_empty());
+    /// ```
+    pub fn find_duplicates(&self) -> Vec<DuplicateGroup> {
+        let mut parents = (0..self.songs.len()).collect::<Vec<_>>();
+        let mut links = vec![(false, false); self.songs.len()];
+        let mut names = HashMap::new();
+        let mut melodies = HashMap::new();
+        for (i, entry) in self.songs.iter().enumerate() {
+            if let Some(key) = name_key(&entry.song) {
+                let first = *names.entry(key).or_insert(i);
+                if first != i {
+                    let root = find(&mut parents, first);
+                    parents[i] = root;
+                    links[i].0 = true;
+                }
+            }
+            if let Some(key) = melody_fingerprint(&entry.song) {
+                let first = *melodies.entry(key).or_insert(i);
+                if first != i {
+                    let (a, b) = (find(&mut parents, first), find(&mut parents, i));
+                    parents[b.max(a)] = a.min(b);
+                    links[i].1 = true;
+                }
+            }
+        }
+        let mut groups = HashMap::<usize, DuplicateGroup>::new();
+        for (i, (same_name, same_melody)) in links.into_iter().enumerate() {
+            let root = find(&mut parents, i);
+            let group = groups.entry(root).or_insert_with(|| DuplicateGroup {
+                songs: vec![],
+                same_name: false,
+                same_melody: false,
+            });
+            group.songs.push(i);
+            group.same_name |= same_name;
+            group.same_melody |= same_melody;
+        }
+        let mut ret = groups
+            .into_values()
+            .filter(|g| g.songs.len() > 1)
+            .collect::<Vec<_>>();
+        ret.sort_by_key(|g| g.songs[0]);
+        ret
+    }
+}
+
+#[test]
+pub fn test_find_duplicates() {
+    use crate::library::SongEntry;
+
+    let queen = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let mut renamed = queen.clone();
+    renamed.title = "Bohemian Rhapsody (Live)".to_string();
+    // Same melody an octave up, under a misspelled name
+    let mut transposed = queen.clone();
+    transposed.artist = Some("Qeen".to_string());
+    for note in transposed.notes.iter_mut() {
+        note.note_tone = note.note_tone.map(|a| a + 12);
+    }
+    let other = Song::from_file("tests/please_tell_rosie.txt").unwrap();
+    let mut library = SongLibrary::default();
+    for (path, song) in [
+        ("a", queen),
+        ("b", other),
+        ("c", renamed),
+        ("d", transposed),
+    ] {
+        library.songs.push(SongEntry {
+            path: path.into(),
+            song,
+        });
+    }
+    assert_eq!(
+        library.find_duplicates(),
+        [DuplicateGroup {
+            songs: vec![0, 2, 3],
+            same_name: true,
+            same_melody: true,
+        }]
+    );
+    let mut song = Song::new("Bohemian Rhapsody [Remastered 2011]", 100.0, 0);
+    song.artist = Some("The Queen feat. Nobody".to_string());
+    assert_eq!(name_key(&song).unwrap(), "queen bohemian rhapsody");
+
+    let queen = &library.songs[0].song;
+    let transposed = &library.songs[3].song;
+    assert_eq!(transposed.fingerprint(), queen.fingerprint());
+    assert_ne!(library.songs[1].song.fingerprint(), queen.fingerprint());
+    let mut moved = queen.clone();
+    moved.notes[1].beat_number += 1;
+    assert_ne!(moved.fingerprint(), queen.fingerprint());
+}
@@ -0,0 +1,281 @@
+//! Reversible editing with undo and redo
+//!
+//! An [`EditSession`] owns the song being edited and changes it only through
+//! [`Edit`]s. Applying an edit records the edit that reverts it, so editor
+//! frontends get an undo history without keeping full copies of the song.
+use crate::borrowed::{parse_decimal, parse_ms, parse_seconds};
+use crate::{Header, Note, Song};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
+use core::ops::Range;
+
+/// One change to a song
+#[derive(Debug, Clone)]
+pub enum Edit {
+    /// Set a header from its text form, `None` removes it
+    SetHeader(Header, Option<String>),
+    /// Move a note to start at another beat
+    MoveNote {
+        note: usize,
+        beat: u32,
+    },
+    /// Shift the tone of a range of notes by a number of semitones
+    Transpose {
+        notes: Range<usize>,
+        semitones: i32,
+    },
+    /// Replace the lyric of a note
+    SetLyric(usize, Option<Arc<str>>),
+    /// Insert a note before the one at the index
+    InsertNote(usize, Note),
+    RemoveNote(usize),
+}
+
+impl Song {
+    /// Text form of a header, as it appears after the tag in a chart
+    /// ```rust
+    /// use usdx_parser::{Header, Song};
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// assert_eq!(song.header(Header::Bpm).as_deref(), Some("312.5"));
+    /// assert_eq!(song.header(Header::Cover), None);
+    /// ```
+    pub fn header(&self, header: Header) -> Option<String> {
+        match header {
+            Header::Artist => self.artist.clone(),
+            Header::Title => Some(self.title.clone()),
+            Header::Mp3 => self.mp3.clone(),
+            Header::Edition => self.edition.clone(),
+            Header::Genre => self.genre.clone(),
+            Header::Year => self.year.clone(),
+            Header::Language => self.language.clone(),
+            Header::Bpm => Some(self.bpm.to_string()),
+            Header::Gap => Some(self.gap.to_string()),
+            Header::Video => self.video.clone(),
+            Header::VideoGap => self.video_gap.map(|a| a.to_string()),
+            Header::Start => self.start.map(|a| a.to_string()),
+            Header::PreviewStart => self.preview_start.map(|a| a.to_string()),
+            Header::Cover => self.cover.clone(),
+            Header::Background => self.background.clone(),
+            Header::SingerP1 => self.singer_p1.clone(),
+            Header::SingerP2 => self.singer_p2.clone(),
+        }
+    }
+
+    /// Set a header from its text form; `None` removes optional headers
+    pub fn set_header(&mut self, header: Header, value: Option<&str>) -> Result<()> {
+        let text = value.map(str::to_string);
+        match (header, value) {
+            (Header::Title, Some(a)) => self.title = a.to_string(),
+            (Header::Bpm, Some(a)) => match parse_decimal("BPM", a)? as f32 {
+                a if a.is_finite() && a > 0.0 => self.bpm = a,
+                _ => bail!("BPM must be a positive number!"),
+            },
+            (Header::Gap, Some(a)) => self.gap = parse_ms("GAP", a)?,
+            (Header::VideoGap, a) => {
+                self.video_gap = a.map(|a| parse_seconds("VIDEOGAP", a)).transpose()?
+            }
+            (Header::Start, a) => self.start = a.map(|a| parse_seconds("START", a)).transpose()?,
+            (Header::PreviewStart, a) => {
+                self.preview_start = a.map(|a| parse_seconds("PREVIEWSTART", a)).transpose()?
+            }
+            (Header::Title | Header::Bpm | Header::Gap, None) => {
+                bail!("{:?} is required and can't be removed", header)
+            }
+            (Header::Artist, _) => self.artist = text,
+            (Header::Mp3, _) => self.mp3 = text,
+            (Header::Edition, _) => self.edition = text,
+            (Header::Genre, _) => self.genre = text,
+            (Header::Year, _) => self.year = text,
+            (Header::Language, _) => self.language = text,
+            (Header::Video, _) => self.video = text,
+            (Header::Cover, _) => self.cover = text,
+            (Header::Background, _) => self.background = text,
+            (Header::SingerP1, _) => self.singer_p1 = text,
+            (Header::SingerP2, _) => self.singer_p2 = text,
+        }
+        Ok(())
+    }
+
+    /// Apply `edit`, returning the edit that reverts it
+    pub fn apply(&mut self, edit: Edit) -> Result<Edit> {
+        let len = self.notes.len();
+        let check = |i: usize| {
+            if i >= len {
+                bail!("No note at index {}", i);
+            }
+            Ok(())
+        };
+        Ok(match edit {
+            Edit::SetHeader(header, value) => {
+                let old = self.header(header);
+                self.set_header(header, value.as_deref())?;
+                Edit::SetHeader(header, old)
+            }
+            Edit::MoveNote { note, beat } => {
+                check(note)?;
+                let old = core::mem::replace(&mut self.notes[note].beat_number, beat);
+                Edit::MoveNote { note, beat: old }
+            }
+            Edit::Transpose { notes, semitones } => {
+                if notes.end > len {
+                    bail!("No note at index {}", len);
+                }
+                for note in &mut self.notes[notes.clone()] {
+                    if let Some(tone) = &mut note.note_tone {
+                        *tone = tone.wrapping_add(semitones);
+                    }
+                }
+                Edit::Transpose {
+                    notes,
+                    semitones: semitones.wrapping_neg(),
+                }
+            }
+            Edit::SetLyric(note, lyric) => {
+                check(note)?;
+                let old = core::mem::replace(&mut self.notes[note].lyric, lyric);
+                Edit::SetLyric(note, old)
+            }
+            Edit::InsertNote(i, note) => {
+                if i > len {
+                    bail!("Can't insert a note at index {}", i);
+                }
+                self.notes.insert(i, note);
+                Edit::RemoveNote(i)
+            }
+            Edit::RemoveNote(i) => {
+                check(i)?;
+                Edit::InsertNote(i, self.notes.remove(i))
+            }
+        })
+    }
+}
+
+/// Song being edited, with undo and redo history
+#[derive(Debug, Clone)]
+pub struct EditSession {
+    song: Song,
+    /// Edits reverting the applied ones, latest last
+    undo: Vec<Edit>,
+    /// Edits reapplying the undone ones, latest last
+    redo: Vec<Edit>,
+}
+
+impl EditSession {
+    pub fn new(song: Song) -> Self {
+        Self {
+            song,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    /// The song in its current state
+    pub fn song(&self) -> &Song {
+        &self.song
+    }
+
+    pub fn into_song(self) -> Song {
+        self.song
+    }
+
+    /// Apply `edit` and make it the latest step of the history
+    ///
+    /// A failed edit leaves the song and the history unchanged.
+    /// ```rust
+    /// use usdx_parser::edit::{Edit, EditSession};
+    /// use usdx_parser::{Header, Song};
+    ///
+    /// let mut session = EditSession::new(Song::from_file("tests/duet.txt").unwrap());
+    /// session.apply(Edit::SetHeader(Header::Title, Some("Renamed".into()))).unwrap();
+    /// session.apply(Edit::Transpose { notes: 0..3, semitones: 2 }).unwrap();
+    /// assert_eq!(session.song().notes[0].note_tone, Some(7));
+    /// session.undo().unwrap();
+    /// session.undo().unwrap();
+    /// assert_eq!(session.song().title, "Duet Test");
+    /// session.redo().unwrap();
+    /// assert_eq!(session.song().title, "Renamed");
+    /// ```
+    pub fn apply(&mut self, edit: Edit) -> Result<()> {
+        let revert = self.song.apply(edit)?;
+        self.undo.push(revert);
+        self.redo.clear();
+        Ok(())
+    }
+
+    /// Revert the latest edit; `false` if there is nothing to undo
+    pub fn undo(&mut self) -> Result<bool> {
+        let Some(edit) = self.undo.pop() else {
+            return Ok(false);
+        };
+        self.redo.push(self.song.apply(edit)?);
+        Ok(true)
+    }
+
+    /// Reapply the latest undone edit; `false` if there is nothing to redo
+    pub fn redo(&mut self) -> Result<bool> {
+        let Some(edit) = self.redo.pop() else {
+            return Ok(false);
+        };
+        self.undo.push(self.song.apply(edit)?);
+        Ok(true)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[test]
+pub fn test_edit_session() {
+    use crate::NoteType;
+
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let original = song.to_string();
+    let mut session = EditSession::new(song);
+    let edits = [
+        Edit::SetHeader(Header::Bpm, Some("120,5".into())),
+        Edit::SetHeader(Header::Video, None),
+        Edit::SetHeader(Header::VideoGap, Some("15,5".into())),
+        Edit::MoveNote { note: 0, beat: 1 },
+        Edit::Transpose {
+            notes: 0..20,
+            semitones: -3,
+        },
+        Edit::SetLyric(1, Some(" that".into())),
+        Edit::InsertNote(0, Note::new(NoteType::Golden, 0, 1, 0, "Oh")),
+        Edit::RemoveNote(5),
+    ];
+    for edit in edits {
+        session.apply(edit).unwrap();
+    }
+    assert_eq!(session.song().bpm, 120.5);
+    assert_eq!(session.song().video_gap, Some(15.5));
+    assert_eq!(session.song().notes[0].lyric.as_deref(), Some("Oh"));
+    assert_eq!(session.song().notes[1].note_tone, Some(14));
+    let edited = session.song().to_string();
+
+    // Failed edits don't touch the history
+    assert!(session.apply(Edit::RemoveNote(100_000)).is_err());
+    assert!(session.apply(Edit::SetHeader(Header::Title, None)).is_err());
+    assert!(session
+        .apply(Edit::SetHeader(Header::Gap, Some("soon".into())))
+        .is_err());
+
+    while session.undo().unwrap() {}
+    assert_eq!(session.song().to_string(), original);
+    assert!(!session.can_undo());
+    while session.redo().unwrap() {}
+    assert_eq!(session.song().to_string(), edited);
+    session.undo().unwrap();
+    session
+        .apply(Edit::SetHeader(Header::Genre, Some("Rock".into())))
+        .unwrap();
+    assert!(!session.can_redo());
+}
@@ -0,0 +1,167 @@
+//! Event based parsing
+//!
+//! Instead of building a [`Song`](crate::Song), the chart is reported line by
+//! line as [`Event`]s, so huge or concatenated files can be processed in
+//! constant memory. Every `E` line ends a song with [`Event::End`] and the
+//! next song may follow right after it. Relative beats are converted to
+//! absolute ones like the regular parser does; malformed lines are skipped.
+use crate::{NoteRef, NoteType, Voice};
+use core::str::Lines;
+#[cfg(feature = "std")]
+use std::io::BufRead;
+
+/// Piece of a chart
+#[derive(Debug, Clone)]
+pub enum Event<'a> {
+    /// `#TAG:value`, with the tag as written in the file
+    Header(&'a str, &'a str),
+    /// Start of the notes of a duet singer
+    Voice(Voice),
+    /// Sung note
+    Note(NoteRef<'a>),
+    /// Line break at this beat
+    LineBreak(u32),
+    End,
+}
+
+/// Parser state that carries over from line to line
+#[derive(Debug, Default)]
+struct State {
+    relative: bool,
+    counter: u32,
+    /// Events since the last `End`
+    open: bool,
+}
+
+impl State {
+    fn event<'a>(&mut self, line: &'a str) -> Option<Event<'a>> {
+        let line = line.trim_start_matches('\u{feff}').trim_start();
+        if line.is_empty() {
+            return None;
+        }
+        self.open = true;
+        if let Some(header) = line.strip_prefix('#') {
+            let (tag, value) = header.split_once(':')?;
+            if tag.eq_ignore_ascii_case("RELATIVE") {
+                self.relative = matches!(value.trim().to_lowercase().as_str(), "yes" | "true");
+            }
+            return Some(Event::Header(tag, value));
+        }
+        if line.starts_with('E') {
+            *self = State::default();
+            return Some(Event::End);
+        }
+        if let Some(voice) = Voice::from_marker(line) {
+            self.counter = 0;
+            return Some(Event::Voice(voice));
+        }
+        let mut note = NoteRef::try_from(line).ok()?;
+        let raw_start = note.line_start.unwrap_or(note.beat_number);
+        if self.relative {
+            note.beat_number += self.counter;
+        }
+        if note.note_type == NoteType::LineBreak {
+            if self.relative {
+                self.counter += raw_start;
+            }
+            return Some(Event::LineBreak(note.beat_number));
+        }
+        Some(Event::Note(note))
+    }
+}
+
+/// Iterator over the events of a chart text
+pub struct Events<'a> {
+    lines: Lines<'a>,
+    state: State,
+}
+
+impl<'a> Events<'a> {
+    /// ```rust
+    /// use usdx_parser::events::{Event, Events};
+    ///
+    /// let text = "#TITLE:One\n#BPM:100\n#GAP:0\n: 0 4 0 La\nE\n#TITLE:Two\n";
+    /// let titles = Events::new(text)
+    ///     .filter_map(|e| match e {
+    ///         Event::Header("TITLE", title) => Some(title),
+    ///         _ => None,
+    ///     })
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(titles, ["One", "Two"]);
+    /// ```
+    pub fn new(text: &'a str) -> Self {
+        Events {
+            lines: text.lines(),
+            state: State::default(),
+        }
+    }
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in self.lines.by_ref() {
+            if let Some(event) = self.state.event(line) {
+                return Some(event);
+            }
+        }
+        // A missing `E` still ends the song
+        if core::mem::take(&mut self.state.open) {
+            return Some(Event::End);
+        }
+        None
+    }
+}
+
+/// Read events from `reader` one line at a time, passing each to `f`
+#[cfg(feature = "std")]
+pub fn read_events(mut reader: impl BufRead, mut f: impl FnMut(Event)) -> anyhow::Result<()> {
+    let mut state = State::default();
+    let mut line = String::new();
+    while reader.read_line(&mut line)? != 0 {
+        if let Some(event) = state.event(line.trim_end_matches(['\n', '\r'])) {
+            f(event);
+        }
+        line.clear();
+    }
+    if state.open {
+        f(Event::End);
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_event_parsing() {
+    use crate::Song;
+
+    let duet = std::fs::read_to_string("tests/duet.txt").unwrap();
+    let relative = std::fs::read_to_string("tests/please_tell_rosie.txt").unwrap();
+    let text = format!("{}\n{}", duet, relative);
+    let mut beats = vec![];
+    let mut ends = 0;
+    read_events(text.as_bytes(), |event| match event {
+        Event::Note(note) => beats.push(note.beat_number),
+        Event::LineBreak(beat) => beats.push(beat),
+        Event::End => ends += 1,
+        _ => (),
+    })
+    .unwrap();
+    assert_eq!(ends, 2);
+    let expected = [duet, relative]
+        .iter()
+        .flat_map(|a| Song::try_from(a.clone()).unwrap().notes)
+        .map(|n| n.beat_number)
+        .collect::<Vec<_>>();
+    assert_eq!(beats, expected);
+    assert_eq!(Events::new(&text).count(), {
+        let mut count = 0;
+        read_events(text.as_bytes(), |_| count += 1).unwrap();
+        count
+    });
+    assert!(matches!(
+        Events::new("#TITLE:No end").last(),
+        Some(Event::End)
+    ));
+    assert_eq!(Events::new("").count(), 0);
+}
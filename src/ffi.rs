@@ -0,0 +1,258 @@
+//! C interface
+//!
+//! Lets the C, C++ and Pascal karaoke programs reuse this parser. The
+//! declarations are in `include/usdx_parser.h`; build the library with
+//! `cargo rustc --release --features ffi --crate-type cdylib` (or
+//! `staticlib`). Songs are opaque handles released with `usdx_song_free`,
+//! returned strings are released with `usdx_string_free`, and failing calls
+//! leave a message for `usdx_last_error`.
+use crate::{NoteType, Song, Voice};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CString};
+use std::ptr;
+
+pub const USDX_FIELD_ARTIST: c_int = 0;
+pub const USDX_FIELD_TITLE: c_int = 1;
+pub const USDX_FIELD_MP3: c_int = 2;
+pub const USDX_FIELD_VIDEO: c_int = 3;
+pub const USDX_FIELD_EDITION: c_int = 4;
+pub const USDX_FIELD_GENRE: c_int = 5;
+pub const USDX_FIELD_YEAR: c_int = 6;
+pub const USDX_FIELD_LANGUAGE: c_int = 7;
+pub const USDX_FIELD_COVER: c_int = 8;
+pub const USDX_FIELD_BACKGROUND: c_int = 9;
+pub const USDX_FIELD_SINGER_P1: c_int = 10;
+pub const USDX_FIELD_SINGER_P2: c_int = 11;
+
+/// One note as seen from C
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UsdxNote {
+    /// `:`, `*`, `F` or `-`
+    pub note_type: c_char,
+    /// 0 for solo songs, otherwise 1 or 2, or 3 for both singers
+    pub voice: u8,
+    pub has_length: u8,
+    pub has_tone: u8,
+    pub beat: u32,
+    pub length: u32,
+    pub tone: i32,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: impl ToString) {
+    let message = message.to_string().replace('\0', " ");
+    LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(message).ok());
+}
+
+fn to_c_string(text: &str) -> *mut c_char {
+    match CString::new(text) {
+        Ok(a) => a.into_raw(),
+        Err(_) => {
+            set_error("String contains a NUL byte");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Message of the last failed call on this thread, or NULL
+///
+/// The pointer stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn usdx_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |a| a.as_ptr()))
+}
+
+/// Parse `len` bytes of chart text at `data`, returning NULL on failure
+///
+/// # Safety
+/// `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn usdx_song_parse(data: *const u8, len: usize) -> *mut Song {
+    if data.is_null() {
+        set_error("No chart data");
+        return ptr::null_mut();
+    }
+    let bytes = std::slice::from_raw_parts(data, len);
+    let text = String::from_utf8_lossy(bytes);
+    match text.parse::<Song>() {
+        Ok(song) => Box::into_raw(Box::new(song)),
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// `song` must come from `usdx_song_parse` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn usdx_song_free(song: *mut Song) {
+    if !song.is_null() {
+        drop(Box::from_raw(song));
+    }
+}
+
+/// Copy of a text header, NULL when it's unset
+///
+/// # Safety
+/// `song` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn usdx_song_field(song: *const Song, field: c_int) -> *mut c_char {
+    let Some(song) = song.as_ref() else {
+        return ptr::null_mut();
+    };
+    let value = match field {
+        USDX_FIELD_ARTIST => song.artist.as_deref(),
+        USDX_FIELD_TITLE => Some(song.title.as_str()),
+        USDX_FIELD_MP3 => song.mp3.as_deref(),
+        USDX_FIELD_VIDEO => song.video.as_deref(),
+        USDX_FIELD_EDITION => song.edition.as_deref(),
+        USDX_FIELD_GENRE => song.genre.as_deref(),
+        USDX_FIELD_YEAR => song.year.as_deref(),
+        USDX_FIELD_LANGUAGE => song.language.as_deref(),
+        USDX_FIELD_COVER => song.cover.as_deref(),
+        USDX_FIELD_BACKGROUND => song.background.as_deref(),
+        USDX_FIELD_SINGER_P1 => song.singer_p1.as_deref(),
+        USDX_FIELD_SINGER_P2 => song.singer_p2.as_deref(),
+        _ => {
+            set_error(format!("Unknown field {}", field));
+            None
+        }
+    };
+    value.map_or(ptr::null_mut(), to_c_string)
+}
+
+/// # Safety
+/// `song` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn usdx_song_bpm(song: *const Song) -> f32 {
+    song.as_ref().map_or(0.0, |s| s.bpm)
+}
+
+/// # Safety
+/// `song` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn usdx_song_gap(song: *const Song) -> u32 {
+    song.as_ref().map_or(0, |s| s.gap)
+}
+
+/// # Safety
+/// `song` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn usdx_song_note_count(song: *const Song) -> usize {
+    song.as_ref().map_or(0, |s| s.notes.len())
+}
+
+/// Fill `out` with note `index`, returning 0 when there is no such note
+///
+/// # Safety
+/// `song` must be a live handle and `out` writable.
+#[no_mangle]
+pub unsafe extern "C" fn usdx_song_note(
+    song: *const Song,
+    index: usize,
+    out: *mut UsdxNote,
+) -> c_int {
+    let Some(note) = song.as_ref().and_then(|s| s.notes.get(index)) else {
+        set_error(format!("No note {}", index));
+        return 0;
+    };
+    let Some(out) = out.as_mut() else {
+        return 0;
+    };
+    *out = UsdxNote {
+        note_type: match note.note_type {
+            NoteType::Normal => b':',
+            NoteType::Golden => b'*',
+            NoteType::Freestyle => b'F',
+            NoteType::LineBreak => b'-',
+        } as c_char,
+        voice: match note.voice {
+            None => 0,
+            Some(Voice::P1) => 1,
+            Some(Voice::P2) => 2,
+            Some(Voice::Both) => 3,
+        },
+        has_length: note.note_length.is_some().into(),
+        has_tone: note.note_tone.is_some().into(),
+        beat: note.beat_number,
+        length: note.note_length.unwrap_or_default(),
+        tone: note.note_tone.unwrap_or_default(),
+    };
+    1
+}
+
+/// Copy of the lyric of note `index`, NULL for line breaks
+///
+/// # Safety
+/// `song` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn usdx_song_note_lyric(song: *const Song, index: usize) -> *mut c_char {
+    song.as_ref()
+        .and_then(|s| s.notes.get(index))
+        .and_then(|n| n.lyric.as_deref())
+        .map_or(ptr::null_mut(), to_c_string)
+}
+
+/// The song as chart text
+///
+/// # Safety
+/// `song` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn usdx_song_serialize(song: *const Song) -> *mut c_char {
+    song.as_ref()
+        .map_or(ptr::null_mut(), |s| to_c_string(&s.to_string()))
+}
+
+/// # Safety
+/// `text` must come from this library and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn usdx_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+#[test]
+pub fn test_ffi_calls() {
+    use std::ffi::CStr;
+
+    let text = std::fs::read("tests/duet.txt").unwrap();
+    unsafe {
+        let song = usdx_song_parse(text.as_ptr(), text.len());
+        assert!(!song.is_null());
+        let title = usdx_song_field(song, USDX_FIELD_TITLE);
+        assert_eq!(
+            CStr::from_ptr(title).to_str().unwrap(),
+            (*song).title.as_str()
+        );
+        usdx_string_free(title);
+        let mut note = UsdxNote::default();
+        assert_eq!(usdx_song_note(song, 0, &mut note), 1);
+        assert_eq!(note.voice, 1);
+        let count = usdx_song_note_count(song);
+        assert_eq!(usdx_song_note(song, count, &mut note), 0);
+        let chart = usdx_song_serialize(song);
+        assert_eq!(CStr::from_ptr(chart).to_str().unwrap(), (*song).to_string());
+        usdx_string_free(chart);
+        usdx_song_free(song);
+
+        let bad = b"#TITLE:No BPM\n";
+        assert!(usdx_song_parse(bad.as_ptr(), bad.len()).is_null());
+        assert!(!usdx_last_error().is_null());
+    }
+    // Every export is declared in the header
+    let header = std::fs::read_to_string("include/usdx_parser.h").unwrap();
+    let source = std::fs::read_to_string("src/ffi.rs").unwrap();
+    for name in source
+        .split("extern \"C\" fn ")
+        .skip(1)
+        .filter_map(|a| a.split('(').next())
+    {
+        assert!(header.contains(&format!("{}(", name)), "{}", name);
+    }
+}
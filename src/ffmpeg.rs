@@ -0,0 +1,280 @@
+//! Video frames and checks through the FFmpeg command line tools
+//!
+//! Many songs ship a video but no background image, which games show when
+//! videos are turned off. [`Song::background_from_video`] saves a frame of
+//! the `#VIDEO` as the `#BACKGROUND`. [`Song::check_video`] makes sure the
+//! video opens and lasts as long as the chart. The crate decodes no video
+//! itself, it runs the `ffmpeg` and `ffprobe` programs, which have to be
+//! installed.
+use crate::lyrics::Sentence;
+use crate::Song;
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The FFmpeg programs to run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ffmpeg {
+    /// Path of `ffmpeg`, by default looked up in `PATH`
+    pub ffmpeg: PathBuf,
+    /// Path of `ffprobe`, by default looked up in `PATH`
+    pub ffprobe: PathBuf,
+}
+
+/// Container and codec of a video file
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoInfo {
+    /// Formats FFmpeg reads the container as, such as `mov,mp4,m4a,3gp,3g2,mj2`
+    pub container: String,
+    /// Codec of the first video stream, such as `h264`
+    pub codec: String,
+    /// `None` if the container doesn't say
+    pub duration_ms: Option<f64>,
+}
+
+/// The `#VIDEO` of a song with what it has to cover
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoCheck {
+    pub path: PathBuf,
+    pub info: VideoInfo,
+    /// End of the last note in video time, that is plus `#VIDEOGAP`
+    pub required_ms: f64,
+}
+
+impl VideoCheck {
+    /// Whether the video lasts until the last note ends; videos of unknown
+    /// duration are taken to
+    pub fn is_long_enough(&self) -> bool {
+        self.info.duration_ms.is_none_or(|a| a >= self.required_ms)
+    }
+}
+
+impl Default for Ffmpeg {
+    fn default() -> Self {
+        Self {
+            ffmpeg: "ffmpeg".into(),
+            ffprobe: "ffprobe".into(),
+        }
+    }
+}
+
+impl Ffmpeg {
+    /// The programs found in `PATH`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Save the frame of `video` at `at_ms` ms as a JPEG to `output`
+    pub fn grab_frame(&self, video: &Path, at_ms: f64, output: &Path) -> Result<()> {
+        if !at_ms.is_finite() || at_ms < 0.0 {
+            bail!("Frame time must be a positive number of ms!");
+        }
+        let mut command = Command::new(&self.ffmpeg);
+        command.args(frame_args(at_ms)).arg("-i").arg(video);
+        command.args(["-frames:v", "1", "-q:v", "2", "-f", "image2", "-c:v"]);
+        command.args(["mjpeg", "-update", "1"]).arg(output);
+        run(command)?;
+        // Timestamps past the end of the video give no frame, but no error either
+        if !std::fs::metadata(output).is_ok_and(|a| a.len() > 0) {
+            let _ = std::fs::remove_file(output);
+            bail!("Video has no frame at {} ms", at_ms);
+        }
+        Ok(())
+    }
+
+    /// Container, codec and duration of `video`, failing if it doesn't open
+    /// or has no video stream
+    pub fn probe(&self, video: &Path) -> Result<VideoInfo> {
+        let mut command = Command::new(&self.ffprobe);
+        command.args(["-v", "error", "-show_entries"]);
+        command.args(["format=format_name,duration:stream=codec_type,codec_name"]);
+        command.arg(video);
+        parse_probe(&String::from_utf8_lossy(&run(command)?))
+    }
+}
+
+/// Read the `[STREAM]` and `[FORMAT]` sections `ffprobe` prints
+fn parse_probe(output: &str) -> Result<VideoInfo> {
+    let mut container = None;
+    let mut codec = None;
+    let mut duration_ms = None;
+    // Codec of the stream being read and whether it's a video stream
+    let mut stream = (None, false);
+    for line in output.lines().map(str::trim) {
+        match line.split_once('=') {
+            Some(("codec_name", a)) => stream.0 = Some(a),
+            Some(("codec_type", a)) => stream.1 = a == "video",
+            Some(("format_name", a)) => container = Some(a.to_string()),
+            Some(("duration", a)) => duration_ms = a.parse::<f64>().ok().map(|a| a * 1000.0),
+            _ if line == "[/STREAM]" => {
+                if let (Some(name), true) = stream {
+                    codec.get_or_insert(name.to_string());
+                }
+                stream = (None, false);
+            }
+            _ => {}
+        }
+    }
+    let Some(container) = container else {
+        bail!("Not a video file");
+    };
+    let Some(codec) = codec else {
+        bail!("No video stream in {}", container);
+    };
+    Ok(VideoInfo {
+        container,
+        codec,
+        duration_ms,
+    })
+}
+
+/// Arguments seeking to `at_ms`, before the input so that only that part is decoded
+fn frame_args(at_ms: f64) -> [String; 6] {
+    [
+        "-v".into(),
+        "error".into(),
+        "-nostdin".into(),
+        "-y".into(),
+        "-ss".into(),
+        format!("{:.3}", at_ms / 1000.0),
+    ]
+}
+
+/// Run `command`, returning its output or its error message
+pub(crate) fn run(mut command: Command) -> Result<Vec<u8>> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = match command.output() {
+        Ok(a) => a,
+        Err(e) => bail!("Failed to run {}: {}", program, e),
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{} failed: {}", program, stderr.trim());
+    }
+    Ok(output.stdout)
+}
+
+impl Song {
+    /// Save the frame of `#VIDEO` at `at_ms` as `Artist - Title [BG].jpg` next
+    /// to the chart and point `#BACKGROUND` to it
+    ///
+    /// `at_ms` is in ms from the start of the video. Returns the name of the
+    /// written background.
+    pub fn background_from_video(
+        &mut self,
+        song_dir: &str,
+        at_ms: f64,
+        ffmpeg: &Ffmpeg,
+    ) -> Result<String> {
+        let Some(video) = self.video.as_ref() else {
+            bail!("No video file specified!");
+        };
+        let dir = Path::new(song_dir);
+        let video = dir.join(video);
+        if !video.is_file() {
+            bail!("Video file {} doesn't exist", video.display());
+        }
+        let name = format!("{} [BG].jpg", self.folder_name());
+        let tmp = dir.join(format!("{}.tmp", name));
+        ffmpeg.grab_frame(&video, at_ms, &tmp)?;
+        std::fs::rename(tmp, dir.join(&name))?;
+        self.background = Some(name.clone());
+        Ok(name)
+    }
+
+    /// Probe `#VIDEO` and work out how long it has to last
+    /// ```rust,no_run
+    /// use usdx_parser::ffmpeg::Ffmpeg;
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("songs/Artist - Title/Artist - Title.txt").unwrap();
+    /// let check = song.check_video("songs/Artist - Title", &Ffmpeg::new()).unwrap();
+    /// if !check.is_long_enough() {
+    ///     println!("{} ends before the chart", check.path.display());
+    /// }
+    /// ```
+    pub fn check_video(&self, song_dir: &str, ffmpeg: &Ffmpeg) -> Result<VideoCheck> {
+        let Some(video) = self.video.as_ref() else {
+            bail!("No video file specified!");
+        };
+        let path = Path::new(song_dir).join(video);
+        if !path.is_file() {
+            bail!("Video file {} doesn't exist", path.display());
+        }
+        Ok(VideoCheck {
+            info: ffmpeg.probe(&path)?,
+            path,
+            required_ms: self.required_video_ms(),
+        })
+    }
+
+    /// End of the last note in video time, `#VIDEOGAP` being in seconds
+    fn required_video_ms(&self) -> f64 {
+        let end = self.sentences().iter().map(Sentence::end_beat).max();
+        end.map_or(0.0, |a| self.beat_to_ms(a as f64))
+            + self.video_gap.unwrap_or_default() as f64 * 1000.0
+    }
+}
+
+#[test]
+pub fn test_background_from_video() {
+    assert_eq!(frame_args(61234.0)[5], "61.234");
+
+    let mut song = Song::new("Title", 300.0, 0);
+    let ffmpeg = Ffmpeg {
+        ffmpeg: "usdx-missing-ffmpeg".into(),
+        ffprobe: "usdx-missing-ffprobe".into(),
+    };
+    let error = song.background_from_video("tests", 0.0, &ffmpeg);
+    assert_eq!(error.unwrap_err().to_string(), "No video file specified!");
+    song.video = Some("missing.mp4".to_string());
+    assert!(song.background_from_video("tests", 0.0, &ffmpeg).is_err());
+    song.video = Some("duet.txt".to_string());
+    let error = song.background_from_video("tests", 1000.0, &ffmpeg);
+    assert!(error.unwrap_err().to_string().starts_with("Failed to run"));
+    assert!(song.background_from_video("tests", -1.0, &ffmpeg).is_err());
+    assert_eq!(song.background, None);
+}
+
+#[test]
+pub fn test_check_video() {
+    let output = "[STREAM]\ncodec_name=aac\ncodec_type=audio\n[/STREAM]\n[STREAM]\n\
+        codec_name=h264\ncodec_type=video\n[/STREAM]\n[FORMAT]\nformat_name=mov,mp4,m4a\n\
+        duration=12.500000\n[/FORMAT]\n";
+    let info = parse_probe(output).unwrap();
+    assert_eq!(info.container, "mov,mp4,m4a");
+    assert_eq!(info.codec, "h264");
+    assert_eq!(info.duration_ms, Some(12500.0));
+    let audio = "[STREAM]\ncodec_name=mp3\ncodec_type=audio\n[/STREAM]\n[FORMAT]\n\
+        format_name=mp3\nduration=N/A\n[/FORMAT]\n";
+    assert_eq!(
+        parse_probe(audio).unwrap_err().to_string(),
+        "No video stream in mp3"
+    );
+
+    let mut check = VideoCheck {
+        path: "video.mp4".into(),
+        info,
+        required_ms: 13000.0,
+    };
+    assert!(!check.is_long_enough());
+    check.info.duration_ms = None;
+    assert!(check.is_long_enough());
+
+    let text = "#TITLE:T\n#BPM:150\n#GAP:1000\n: 0 4 0 a\n: 8 2 0 b\nE\n";
+    let mut song: Song = text.parse().unwrap();
+    assert_eq!(song.required_video_ms(), 2000.0);
+    song.video_gap = Some(2.5);
+    assert_eq!(song.required_video_ms(), 4500.0);
+
+    let mut song = Song::from_file("tests/duet.txt").unwrap();
+    song.video = Some("duet.txt".to_string());
+    let ffmpeg = Ffmpeg {
+        ffprobe: "usdx-missing-ffprobe".into(),
+        ..Default::default()
+    };
+    let error = song.check_video("tests", &ffmpeg).unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("Failed to run usdx-missing-ffprobe"));
+}
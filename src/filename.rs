@@ -0,0 +1,124 @@
+//! File and folder names that work on every file system
+//!
+//! Windows is the most restrictive target: it forbids `<>:"/\|?*` and control
+//! characters, drops trailing dots and spaces, and reserves device names like
+//! `CON` or `COM1` even with an extension. Most file systems limit a name to
+//! 255 bytes.
+/// Longest name most file systems accept, in bytes
+pub const MAX_NAME_LEN: usize = 255;
+
+const RESERVED: &[&str] = &["CON", "PRN", "AUX", "NUL"];
+
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    let upper = stem.to_ascii_uppercase();
+    if RESERVED.contains(&upper.as_str()) {
+        return true;
+    }
+    // COM1 to COM9 and LPT1 to LPT9
+    let bytes = upper.as_bytes();
+    bytes.len() == 4
+        && (upper.starts_with("COM") || upper.starts_with("LPT"))
+        && (b'1'..=b'9').contains(&bytes[3])
+}
+
+/// Cut `name` to at most `max_len` bytes without splitting a character
+fn truncate(name: &str, max_len: usize) -> &str {
+    let mut end = name.len().min(max_len);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+/// Forbidden characters replaced, trailing dots and spaces and reserved names handled
+fn clean(name: &str) -> String {
+    let mut ret = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    ret = ret.trim().trim_end_matches(['.', ' ']).to_string();
+    if ret.is_empty() {
+        ret = "_".to_string();
+    }
+    if is_reserved(&ret) {
+        ret.insert(0, '_');
+    }
+    ret
+}
+
+/// Safe name of at most `max_len` bytes; an extension is kept when cutting
+/// ```rust
+/// # use usdx_parser::filename::sanitize_with_limit;
+/// assert_eq!(sanitize_with_limit("Very long title.mp3", 12), "Very lon.mp3");
+/// ```
+pub fn sanitize_with_limit(name: &str, max_len: usize) -> String {
+    let ret = clean(name);
+    if ret.len() <= max_len {
+        return ret;
+    }
+    match ret.rsplit_once('.') {
+        // Only treat short suffixes as extensions, titles contain dots too
+        Some((stem, ext)) if !stem.is_empty() && ext.len() <= 5 && ext.len() + 1 < max_len => {
+            let stem = truncate(stem, max_len - ext.len() - 1);
+            format!("{}.{}", stem.trim_end_matches(['.', ' ']), ext)
+        }
+        _ => truncate(&ret, max_len)
+            .trim_end_matches(['.', ' '])
+            .to_string(),
+    }
+}
+
+/// Safe name for a file or folder
+/// ```rust
+/// # use usdx_parser::filename::sanitize;
+/// assert_eq!(sanitize("AC/DC: Live?"), "AC_DC_ Live_");
+/// assert_eq!(sanitize("Etc. ..."), "Etc");
+/// assert_eq!(sanitize("con.txt"), "_con.txt");
+/// ```
+pub fn sanitize(name: &str) -> String {
+    sanitize_with_limit(name, MAX_NAME_LEN)
+}
+
+/// `Artist - Title`, or just the title, as a safe name of at most `max_len` bytes
+/// ```rust
+/// # use usdx_parser::filename::song_base_name;
+/// assert_eq!(song_base_name(Some("AC/DC"), "Back in Black?", 255), "AC_DC - Back in Black_");
+/// ```
+pub fn song_base_name(artist: Option<&str>, title: &str, max_len: usize) -> String {
+    let name = match artist {
+        Some(artist) if !artist.trim().is_empty() => {
+            format!("{} - {}", artist.trim(), title.trim())
+        }
+        _ => title.trim().to_string(),
+    };
+    // Dots in names like "Mr. Big" are not extensions, so cut plainly
+    let ret = clean(&name);
+    truncate(&ret, max_len)
+        .trim_end_matches(['.', ' '])
+        .to_string()
+}
+
+#[test]
+pub fn test_sanitize_names() {
+    assert_eq!(sanitize("  Trailing dots... "), "Trailing dots");
+    assert_eq!(sanitize("tab\there"), "tab_here");
+    assert_eq!(sanitize("..."), "_");
+    assert_eq!(sanitize("LPT1"), "_LPT1");
+    assert_eq!(sanitize("LPT10"), "LPT10");
+    assert_eq!(sanitize("Aux .mp3"), "_Aux .mp3");
+
+    let long = "ä".repeat(200);
+    let cut = sanitize(&format!("{}.txt", long));
+    assert!(cut.len() <= MAX_NAME_LEN);
+    assert!(cut.ends_with("ä.txt"));
+
+    let name = song_base_name(Some("Mr. Big"), &"x".repeat(300), 100);
+    assert_eq!(name.len(), 100);
+    assert!(name.starts_with("Mr. Big - xxx"));
+    assert_eq!(song_base_name(Some(" "), "Solo.", 255), "Solo");
+}
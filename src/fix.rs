@@ -0,0 +1,259 @@
+//! Automatic fixes for common chart problems
+//!
+//! A [`Ruleset`] lists the [`Fix`]es to run. [`Song::apply_fixes`] runs them
+//! on one song and [`SongLibrary::apply_fixes`] on every chart of a library,
+//! writing the changed charts back and reporting what changed in each.
+use crate::library::SongLibrary;
+use crate::typography::Typography;
+use crate::{NoteType, Song, Voice};
+use anyhow::Result;
+use std::fmt;
+use std::path::PathBuf;
+
+/// One kind of automatic fix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fix {
+    /// Put every singer's notes in beat order
+    SortNotes,
+    /// Give notes without length one beat
+    ZeroLengths,
+    /// Remove line breaks that have no notes before or after them
+    EmptyLines,
+    /// Cut whitespace around header values
+    TrimHeaders,
+    /// Compose decomposed letters, see [`Song::normalize_nfc`]
+    Nfc,
+    /// Rewrite quotes and dashes, see [`Song::fix_typography`]
+    Typography(Typography),
+}
+
+/// Fixes to run, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ruleset {
+    pub fixes: Vec<Fix>,
+}
+
+/// What fixing one chart changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChangelog {
+    pub path: PathBuf,
+    /// Every fix that changed something, with how many notes or headers it changed
+    pub changes: Vec<(Fix, usize)>,
+}
+
+impl Default for Ruleset {
+    /// Fixes that only repair charts; typography is a matter of style and left out
+    fn default() -> Self {
+        Self {
+            fixes: vec![
+                Fix::SortNotes,
+                Fix::ZeroLengths,
+                Fix::EmptyLines,
+                Fix::TrimHeaders,
+                Fix::Nfc,
+            ],
+        }
+    }
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::SortNotes => "sort notes",
+            Self::ZeroLengths => "zero lengths",
+            Self::EmptyLines => "empty lines",
+            Self::TrimHeaders => "trim headers",
+            Self::Nfc => "nfc",
+            Self::Typography(_) => "typography",
+        })
+    }
+}
+
+impl Song {
+    /// Run every fix of `ruleset`, returning those that changed something and how much
+    /// ```rust
+    /// use usdx_parser::fix::{Fix, Ruleset};
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:Title\n#BPM:100\n#GAP:0\n- 0\n: 4 2 0 b\n: 0 0 0 a\nE\n";
+    /// let mut song: Song = text.parse().unwrap();
+    /// song.title = " Title ".to_string();
+    /// let changes = song.apply_fixes(&Ruleset::default());
+    /// assert_eq!(
+    ///     changes,
+    ///     [(Fix::SortNotes, 2), (Fix::ZeroLengths, 1), (Fix::EmptyLines, 1), (Fix::TrimHeaders, 1)]
+    /// );
+    /// assert_eq!(song.to_string(), "#TITLE:Title\n#BPM:100\n#GAP:0\n: 0 1 0 a\n: 4 2 0 b\nE\n");
+    /// ```
+    pub fn apply_fixes(&mut self, ruleset: &Ruleset) -> Vec<(Fix, usize)> {
+        ruleset
+            .fixes
+            .iter()
+            .map(|&fix| (fix, self.apply_fix(fix)))
+            .filter(|&(_, count)| count > 0)
+            .collect()
+    }
+
+    fn apply_fix(&mut self, fix: Fix) -> usize {
+        match fix {
+            Fix::SortNotes => {
+                let mut order: Vec<usize> = (0..self.notes.len()).collect();
+                // Line breaks go before notes on the same beat
+                order.sort_by_key(|&i| {
+                    let n = &self.notes[i];
+                    (
+                        n.voice.map(|v| match v {
+                            Voice::P1 => 0,
+                            Voice::P2 => 1,
+                            Voice::Both => 2,
+                        }),
+                        n.beat_number,
+                        n.note_type != NoteType::LineBreak,
+                    )
+                });
+                let moved = order.iter().enumerate().filter(|&(a, &b)| a != b).count();
+                self.notes = order.into_iter().map(|i| self.notes[i].clone()).collect();
+                moved
+            }
+            Fix::ZeroLengths => {
+                let mut count = 0;
+                for note in &mut self.notes {
+                    if note.note_type != NoteType::LineBreak
+                        && matches!(note.note_length, None | Some(0))
+                    {
+                        note.note_length = Some(1);
+                        count += 1;
+                    }
+                }
+                count
+            }
+            Fix::EmptyLines => {
+                let len = self.notes.len();
+                let mut keep = vec![true; len];
+                let mut voice = None;
+                let mut sung = false;
+                for (i, note) in self.notes.iter().enumerate() {
+                    if note.voice != voice {
+                        voice = note.voice;
+                        sung = false;
+                    }
+                    if note.note_type != NoteType::LineBreak {
+                        sung = true;
+                        continue;
+                    }
+                    let last_of_voice = self.notes[i + 1..]
+                        .iter()
+                        .take_while(|n| n.voice == voice)
+                        .all(|n| n.note_type == NoteType::LineBreak);
+                    keep[i] = sung && !last_of_voice;
+                    sung = false;
+                }
+                let mut keep = keep.into_iter();
+                self.notes.retain(|_| keep.next().unwrap_or(true));
+                len - self.notes.len()
+            }
+            Fix::TrimHeaders => {
+                let mut count = 0;
+                let mut trim = |text: &mut String| {
+                    let trimmed = text.trim();
+                    if trimmed.len() != text.len() {
+                        *text = trimmed.to_string();
+                        count += 1;
+                    }
+                };
+                trim(&mut self.title);
+                for header in [
+                    &mut self.artist,
+                    &mut self.mp3,
+                    &mut self.video,
+                    &mut self.edition,
+                    &mut self.genre,
+                    &mut self.year,
+                    &mut self.language,
+                    &mut self.cover,
+                    &mut self.background,
+                    &mut self.singer_p1,
+                    &mut self.singer_p2,
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    trim(header);
+                }
+                count
+            }
+            Fix::Nfc => self.normalize_nfc(),
+            Fix::Typography(typography) => self.fix_typography(&typography),
+        }
+    }
+}
+
+impl SongLibrary {
+    /// Run `ruleset` on every song and write the changed charts back, returning
+    /// a changelog for each of them
+    ///
+    /// Charts are replaced atomically and written as UTF-8. Songs that needed
+    /// no fixes are neither written nor listed.
+    pub fn apply_fixes(&mut self, ruleset: &Ruleset) -> Result<Vec<FileChangelog>> {
+        let mut ret = vec![];
+        for entry in &mut self.songs {
+            let changes = entry.song.apply_fixes(ruleset);
+            if changes.is_empty() {
+                continue;
+            }
+            let tmp = entry.path.with_extension("txt.tmp");
+            std::fs::write(&tmp, entry.song.to_string())?;
+            std::fs::rename(tmp, &entry.path)?;
+            ret.push(FileChangelog {
+                path: entry.path.clone(),
+                changes,
+            });
+        }
+        Ok(ret)
+    }
+}
+
+#[test]
+pub fn test_apply_fixes() {
+    use crate::Note;
+
+    let mut duet = Song::from_file("tests/duet.txt").unwrap();
+    assert_eq!(duet.apply_fixes(&Ruleset::default()), []);
+    let original = duet.to_string();
+    duet.notes.swap(4, 6);
+    duet.notes.push(Note {
+        voice: Some(Voice::P2),
+        ..Note::line_break(50)
+    });
+    assert_eq!(
+        duet.apply_fixes(&Ruleset::default()),
+        [(Fix::SortNotes, 2), (Fix::EmptyLines, 1)]
+    );
+    assert_eq!(duet.to_string(), original);
+
+    let dir = std::env::temp_dir().join(format!("usdx_fix_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("a.txt"),
+        "#TITLE:A\n#BPM:100\n#GAP:0\n: 0 0 0 \"Hi\"\nE\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("b.txt"),
+        "#TITLE:B\n#BPM:100\n#GAP:0\n: 0 4 0 la\nE\n",
+    )
+    .unwrap();
+    let mut library = SongLibrary::scan(dir.to_str().unwrap()).unwrap();
+    let typography = Fix::Typography(Typography::for_language("en"));
+    let mut ruleset = Ruleset::default();
+    ruleset.fixes.push(typography);
+    let log = library.apply_fixes(&ruleset).unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].path, dir.join("a.txt"));
+    assert_eq!(log[0].changes, [(Fix::ZeroLengths, 1), (typography, 1)]);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("a.txt")).unwrap(),
+        "#TITLE:A\n#BPM:100\n#GAP:0\n: 0 1 0 “Hi”\nE\n"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
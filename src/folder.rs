@@ -0,0 +1,284 @@
+//! Writing songs as ready-to-play song folders
+//!
+//! Games expect every song in its own `Artist - Title/` directory holding the
+//! chart and the media it references. Media is copied in under conventional
+//! names: `Artist - Title.mp3` for the audio, the same base name for the
+//! video and `[CO]`/`[BG]` suffixes for the cover and background.
+//! [`SongFolder::load`] reads such a folder back, with its media resolved.
+use crate::filename::{song_base_name, MAX_NAME_LEN};
+use crate::library::{decode_chart, is_chart, ScanError};
+use crate::Song;
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+
+/// Loose media files that belong to a song
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SongAssets {
+    pub audio: Option<PathBuf>,
+    pub video: Option<PathBuf>,
+    pub cover: Option<PathBuf>,
+    pub background: Option<PathBuf>,
+}
+
+/// Chart of a song folder with the media it names
+#[derive(Debug, Clone)]
+pub struct FolderChart {
+    pub path: PathBuf,
+    pub song: Song,
+    /// Absolute paths of the media files that exist
+    pub assets: SongAssets,
+    /// Media files the headers name that aren't in the folder
+    pub missing: Vec<String>,
+}
+
+/// Everything needed to play a song: its charts and their media
+#[derive(Debug)]
+pub struct SongFolder {
+    pub dir: PathBuf,
+    /// Every chart in the folder, like a `[DUET]` variant next to the solo
+    /// chart, sorted by path
+    pub charts: Vec<FolderChart>,
+    /// Charts that failed to parse
+    pub errors: Vec<ScanError>,
+}
+
+/// Absolute path of the file `name` in `dir`
+///
+/// Charts written on Windows often get the case of file names wrong, so a
+/// plain name matches a file differing only in case as well.
+pub(crate) fn resolve(dir: &Path, name: &str) -> Option<PathBuf> {
+    let path = dir.join(name);
+    if path.is_file() {
+        return path.canonicalize().ok();
+    }
+    if name.contains(['/', '\\']) {
+        return None;
+    }
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|a| a.ok())
+        .find(|a| {
+            a.file_name()
+                .to_str()
+                .is_some_and(|a| a.eq_ignore_ascii_case(name))
+                && a.path().is_file()
+        })
+        .and_then(|a| a.path().canonicalize().ok())
+}
+
+impl FolderChart {
+    fn new(dir: &Path, path: PathBuf, song: Song) -> Self {
+        let mut missing = vec![];
+        let mut find = |name: &Option<String>| {
+            let name = name.as_deref()?;
+            let path = resolve(dir, name);
+            if path.is_none() {
+                missing.push(name.to_string());
+            }
+            path
+        };
+        let assets = SongAssets {
+            audio: find(&song.mp3),
+            video: find(&song.video),
+            cover: find(&song.cover),
+            background: find(&song.background),
+        };
+        Self {
+            path,
+            song,
+            assets,
+            missing,
+        }
+    }
+
+    pub fn is_duet(&self) -> bool {
+        self.song.is_duet()
+    }
+}
+
+impl SongFolder {
+    /// Parse every chart in `dir` and resolve the media files they name
+    /// ```rust
+    /// use usdx_parser::folder::SongFolder;
+    ///
+    /// let folder = SongFolder::load("tests/song_folder").unwrap();
+    /// let chart = folder.main_chart().unwrap();
+    /// assert!(chart.assets.audio.as_ref().unwrap().is_absolute());
+    /// assert!(folder.duet().is_some());
+    /// ```
+    pub fn load(dir: &str) -> Result<SongFolder> {
+        let dir = Path::new(dir).canonicalize()?;
+        let mut files = vec![];
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|a| a.eq_ignore_ascii_case("txt"))
+            {
+                files.push(path);
+            }
+        }
+        files.sort();
+        let mut charts = vec![];
+        let mut errors = vec![];
+        for path in files {
+            let text = match std::fs::read(&path) {
+                Ok(data) => decode_chart(&data),
+                Err(e) => {
+                    errors.push(ScanError {
+                        path,
+                        error: e.into(),
+                    });
+                    continue;
+                }
+            };
+            if !is_chart(&text) {
+                continue;
+            }
+            match Song::try_from(text) {
+                Ok(song) => charts.push(FolderChart::new(&dir, path, song)),
+                Err(error) => errors.push(ScanError { path, error }),
+            }
+        }
+        if charts.is_empty() && errors.is_empty() {
+            bail!("No chart in {}", dir.display());
+        }
+        Ok(SongFolder {
+            dir,
+            charts,
+            errors,
+        })
+    }
+
+    /// The chart to play by default: the first solo chart, or any chart
+    pub fn main_chart(&self) -> Option<&FolderChart> {
+        self.charts
+            .iter()
+            .find(|c| !c.is_duet())
+            .or(self.charts.first())
+    }
+
+    /// The first duet chart
+    pub fn duet(&self) -> Option<&FolderChart> {
+        self.charts.iter().find(|c| c.is_duet())
+    }
+}
+
+/// `base` plus the extension of `source`, lowercased
+fn file_name(base: &str, source: &Path) -> String {
+    match source.extension().and_then(|a| a.to_str()) {
+        Some(ext) => format!("{}.{}", base, ext.to_lowercase()),
+        None => base.to_string(),
+    }
+}
+
+impl Song {
+    /// Name of the song folder and its files, `Artist - Title`
+    ///
+    /// Leaves room for the longest suffix the media files get, ` [CO].jpeg`.
+    pub fn folder_name(&self) -> String {
+        song_base_name(self.artist.as_deref(), &self.title, MAX_NAME_LEN - 16)
+    }
+
+    /// Create `target_dir/Artist - Title/` with the chart and copies of the assets
+    ///
+    /// The headers of the written chart point to the copied files; headers
+    /// without an asset are kept as they are. Returns the song folder.
+    /// ```rust
+    /// use usdx_parser::folder::SongAssets;
+    /// use usdx_parser::Song;
+    ///
+    /// let dir = std::env::temp_dir().join(format!("usdx_folder_doc_{}", std::process::id()));
+    /// let song = Song::from_file("tests/artwork/Artwork Test.txt").unwrap();
+    /// let assets = SongAssets {
+    ///     cover: Some("tests/artwork/cover.png".into()),
+    ///     ..Default::default()
+    /// };
+    /// let folder = song.write_folder(dir.to_str().unwrap(), &assets).unwrap();
+    /// assert!(folder.join("Artwork - Test [CO].png").is_file());
+    /// # std::fs::remove_dir_all(dir).unwrap();
+    /// ```
+    pub fn write_folder(&self, target_dir: &str, assets: &SongAssets) -> Result<PathBuf> {
+        let base = self.folder_name();
+        let folder = Path::new(target_dir).join(&base);
+        std::fs::create_dir_all(&folder)?;
+        let mut song = self.clone();
+        let files = [
+            (&assets.audio, &mut song.mp3, base.clone()),
+            (&assets.video, &mut song.video, base.clone()),
+            (&assets.cover, &mut song.cover, format!("{} [CO]", base)),
+            (
+                &assets.background,
+                &mut song.background,
+                format!("{} [BG]", base),
+            ),
+        ];
+        for (source, header, name) in files {
+            let Some(source) = source else {
+                continue;
+            };
+            let name = file_name(&name, source);
+            if let Err(e) = std::fs::copy(source, folder.join(&name)) {
+                bail!("Failed to copy {}: {}", source.display(), e);
+            }
+            *header = Some(name);
+        }
+        std::fs::write(folder.join(format!("{}.txt", base)), song.to_string())?;
+        Ok(folder)
+    }
+}
+
+#[test]
+pub fn test_write_folder() {
+    let dir = std::env::temp_dir().join(format!("usdx_folder_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut song = Song::from_file("tests/artwork/Artwork Test.txt").unwrap();
+    song.artist = Some("AC/DC".to_string());
+    song.title = "What?".to_string();
+    let assets = SongAssets {
+        audio: Some("tests/artwork/background.jpg".into()),
+        background: Some("tests/artwork/background.jpg".into()),
+        ..Default::default()
+    };
+    let folder = song.write_folder(dir.to_str().unwrap(), &assets).unwrap();
+    assert_eq!(folder, dir.join("AC_DC - What_"));
+    let written = Song::from_file(folder.join("AC_DC - What_.txt").to_str().unwrap()).unwrap();
+    assert_eq!(written.mp3.as_deref(), Some("AC_DC - What_.jpg"));
+    assert_eq!(
+        written.background.as_deref(),
+        Some("AC_DC - What_ [BG].jpg")
+    );
+    // Without a cover asset the header still names the original file
+    assert_eq!(written.cover.as_deref(), Some("cover.png"));
+    assert!(folder.join("AC_DC - What_ [BG].jpg").is_file());
+
+    let missing = SongAssets {
+        video: Some("tests/artwork/missing.mp4".into()),
+        ..Default::default()
+    };
+    assert!(song.write_folder(dir.to_str().unwrap(), &missing).is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+pub fn test_load_song_folder() {
+    let folder = SongFolder::load("tests/song_folder").unwrap();
+    assert_eq!(folder.charts.len(), 2);
+    assert!(folder.errors.is_empty());
+    let main = folder.main_chart().unwrap();
+    assert!(main.path.ends_with("Various - Folder.txt"));
+    // The chart names cover.jpg, the file is Cover.JPG
+    assert!(main
+        .assets
+        .cover
+        .as_ref()
+        .unwrap()
+        .ends_with("song_folder/Cover.JPG"));
+    assert_eq!(main.missing, ["missing.mp4"]);
+    let duet = folder.duet().unwrap();
+    assert!(duet.path.ends_with("Various - Folder [DUET].txt"));
+    assert_eq!(duet.assets.audio, main.assets.audio);
+    assert!(SongFolder::load("tests/library").is_err());
+    assert!(SongFolder::load("tests/missing").is_err());
+}
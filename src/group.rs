@@ -0,0 +1,140 @@
+//! Songs of a library grouped for browsing
+//!
+//! Groups are keyed the way they sort: artists ignore leading articles, case
+//! and diacritics, so "The Beatles" and "beatles" share a group listed under
+//! B. Songs without the header a view groups by are left out of it.
+use crate::library::{SongEntry, SongLibrary};
+use crate::query::year;
+use crate::sort::{collation_key, sort_key};
+use crate::text::fold;
+use std::collections::BTreeMap;
+
+/// Songs sharing a header value
+#[derive(Debug, Clone)]
+pub struct Group<'a> {
+    /// Display form of the value, as the first song of the group has it
+    pub name: String,
+    /// Songs in library order
+    pub songs: Vec<&'a SongEntry>,
+}
+
+/// Groups keyed by their sort key
+pub type Groups<'a> = BTreeMap<String, Group<'a>>;
+
+impl SongLibrary {
+    /// Songs by `#ARTIST`, with the article moved to the end of group names
+    /// ```rust
+    /// use usdx_parser::library::SongLibrary;
+    ///
+    /// let library = SongLibrary::scan("tests/library").unwrap();
+    /// let names: Vec<_> = library.by_artist().into_values().map(|g| g.name).collect();
+    /// assert_eq!(names, ["Solo", "Various"]);
+    /// ```
+    pub fn by_artist(&self) -> Groups<'_> {
+        self.group(|entry| {
+            let song = &entry.song;
+            let artist = song.artist.as_deref()?;
+            let language = song.language.as_deref();
+            Some(vec![(
+                collation_key(artist, language),
+                sort_key(artist, language),
+            )])
+        })
+    }
+
+    /// Songs by `#EDITION`
+    pub fn by_edition(&self) -> Groups<'_> {
+        self.group(|entry| {
+            let edition = entry.song.edition.as_deref()?.trim();
+            Some(vec![(fold(edition), edition.to_string())])
+        })
+    }
+
+    /// Songs by language, named in English where the language is known
+    ///
+    /// Songs in several languages are in the group of each of them.
+    pub fn by_language(&self) -> Groups<'_> {
+        self.group(|entry| {
+            let languages = entry.song.languages().into_iter().map(|l| {
+                let name = l.canonical.map_or(l.raw, |a| a.name.to_string());
+                (fold(&name), name)
+            });
+            Some(languages.collect())
+        })
+    }
+
+    /// Songs by the decade of their `#YEAR`, keyed by its first year
+    pub fn by_decade(&self) -> BTreeMap<u32, Vec<&SongEntry>> {
+        let mut ret = BTreeMap::<u32, Vec<_>>::new();
+        for entry in &self.songs {
+            if let Some(year) = year(&entry.song) {
+                ret.entry(year - year % 10).or_default().push(entry);
+            }
+        }
+        ret
+    }
+
+    /// Songs grouped by the `(key, name)` pairs `keys` gives for them
+    fn group<'a>(
+        &'a self,
+        keys: impl Fn(&SongEntry) -> Option<Vec<(String, String)>>,
+    ) -> Groups<'a> {
+        let mut ret = Groups::new();
+        for entry in &self.songs {
+            for (key, name) in keys(entry).unwrap_or_default() {
+                ret.entry(key)
+                    .or_insert_with(|| Group {
+                        name,
+                        songs: vec![],
+                    })
+                    .songs
+                    .push(entry);
+            }
+        }
+        ret
+    }
+}
+
+#[test]
+pub fn test_groups() {
+    use crate::Song;
+
+    let mut library = SongLibrary::default();
+    for (artist, language, year) in [
+        ("The Beatles", "English", "1969"),
+        ("beatles", "eng", "1965"),
+        ("Ärzte", "German, English", "1998"),
+        ("Die Ärzte", "Deutsch", "ca. 2001"),
+    ] {
+        let mut song = Song::new("Title", 100.0, 0);
+        song.artist = Some(artist.to_string());
+        song.language = Some(language.to_string());
+        song.year = Some(year.to_string());
+        library.songs.push(SongEntry {
+            path: artist.into(),
+            song,
+        });
+    }
+    library.songs[0].song.edition = Some("SingStar ".to_string());
+
+    let artists = library.by_artist();
+    let groups: Vec<_> = artists
+        .iter()
+        .map(|(k, g)| (k.as_str(), g.name.as_str(), g.songs.len()))
+        .collect();
+    assert_eq!(
+        groups,
+        [("arzte", "Ärzte", 2), ("beatles", "Beatles, The", 2)]
+    );
+    let languages = library.by_language();
+    assert_eq!(languages["english"].songs.len(), 3);
+    assert_eq!(languages["german"].name, "German");
+    assert_eq!(languages["german"].songs.len(), 2);
+    assert_eq!(library.by_edition()["singstar"].name, "SingStar");
+    let decades: Vec<_> = library
+        .by_decade()
+        .into_iter()
+        .map(|(d, s)| (d, s.len()))
+        .collect();
+    assert_eq!(decades, [(1960, 2), (1990, 1), (2000, 1)]);
+}
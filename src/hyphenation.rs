@@ -0,0 +1,252 @@
+//! Syllable counts checked against dictionary hyphenation
+//!
+//! Every syllable of a word should get a note of its own. Words crammed onto
+//! one note or split over far more notes than they have syllables are hard
+//! to sing. [`Song::check_syllables`] counts the syllables of every word with
+//! the hyphenation patterns for the song's language and reports the words
+//! whose notes don't fit.
+//!
+//! Patterns are TeX's (Liang's), as the `hyph-*.pat.txt` files of hyph-utf8
+//! have them. The crate ships none, callers load those for their languages.
+use crate::{Song, Voice};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, HashMap};
+
+/// Difference between notes and syllables a word may have before it's reported
+pub const MAX_DIFFERENCE: usize = 1;
+
+/// Hyphenation patterns of one language
+#[derive(Debug, Clone, Default)]
+pub struct Patterns {
+    /// Letters of every pattern with the values between them
+    patterns: HashMap<String, Vec<u8>>,
+    /// Words hyphenated by hand, as break positions
+    exceptions: HashMap<String, Vec<usize>>,
+    /// Letters in the longest pattern
+    longest: usize,
+    /// Letters a word keeps before its first break
+    pub left_min: usize,
+    /// Letters a word keeps after its last break
+    pub right_min: usize,
+}
+
+/// Word whose notes don't fit its syllables
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyllableMismatch {
+    /// Text of the word without holds
+    pub word: String,
+    /// Duet singer of the word, `None` for solo songs
+    pub voice: Option<Voice>,
+    /// Start of the word in ms from the start of the audio
+    pub start: f64,
+    /// Notes of the word, not counting holds (`~`)
+    pub notes: usize,
+    /// Syllables the patterns find
+    pub syllables: usize,
+}
+
+impl Patterns {
+    /// Read whitespace separated patterns such as `.ach4` or `n2kl`
+    ///
+    /// Entries with hyphens (`ta-ble`) are exceptions hyphenated by hand, as
+    /// in `hyph-*.hyp.txt`. Lines starting with `%` are comments. Breaks may
+    /// be at any letter, which is what counting syllables needs; set
+    /// `left_min` and `right_min` for the minimums of typesetting.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut ret = Self {
+            left_min: 1,
+            right_min: 1,
+            ..Default::default()
+        };
+        for entry in text
+            .lines()
+            .filter(|l| !l.trim_start().starts_with('%'))
+            .flat_map(str::split_whitespace)
+        {
+            let entry = entry.to_lowercase();
+            if entry.contains('-') {
+                let mut breaks = vec![];
+                let mut letters = String::new();
+                for c in entry.chars() {
+                    match c {
+                        '-' => breaks.push(letters.chars().count()),
+                        _ => letters.push(c),
+                    }
+                }
+                ret.exceptions.insert(letters, breaks);
+                continue;
+            }
+            let mut letters = String::new();
+            let mut values = vec![0];
+            for c in entry.chars() {
+                match c.to_digit(10) {
+                    Some(d) => *values.last_mut().unwrap() = d as u8,
+                    None => {
+                        letters.push(c);
+                        values.push(0);
+                    }
+                }
+            }
+            if letters.is_empty() {
+                bail!("Hyphenation pattern without letters: {}", entry);
+            }
+            ret.longest = ret.longest.max(values.len() - 1);
+            ret.patterns.insert(letters, values);
+        }
+        Ok(ret)
+    }
+
+    /// `word` split where it may be hyphenated
+    /// ```rust
+    /// use usdx_parser::hyphenation::Patterns;
+    ///
+    /// let patterns = Patterns::parse("a1n\nta-ble").unwrap();
+    /// assert_eq!(patterns.hyphenate("Banana"), ["Ba", "na", "na"]);
+    /// assert_eq!(patterns.hyphenate("table"), ["ta", "ble"]);
+    /// ```
+    pub fn hyphenate<'a>(&self, word: &'a str) -> Vec<&'a str> {
+        let lower: String = word.to_lowercase();
+        let chars: Vec<char> = lower.chars().collect();
+        // Lowercasing can change the number of characters, leave those alone
+        if chars.len() != word.chars().count() {
+            return vec![word];
+        }
+        let breaks = match self.exceptions.get(&lower) {
+            Some(breaks) => breaks.clone(),
+            None => self.breaks(&chars),
+        };
+        let mut ret = vec![];
+        let mut rest = word;
+        let mut done = 0;
+        for b in breaks {
+            let at = rest
+                .char_indices()
+                .nth(b - done)
+                .map_or(rest.len(), |(i, _)| i);
+            ret.push(&rest[..at]);
+            rest = &rest[at..];
+            done = b;
+        }
+        ret.push(rest);
+        ret
+    }
+
+    /// Syllables of `word`, one more than its breaks
+    pub fn syllables(&self, word: &str) -> usize {
+        self.hyphenate(word).len()
+    }
+
+    /// Positions of the letters `word` may be broken before
+    fn breaks(&self, word: &[char]) -> Vec<usize> {
+        let text: Vec<char> = [&['.'][..], word, &['.']].concat();
+        // Values between the letters of `text`
+        let mut values = vec![0; text.len() + 1];
+        for start in 0..text.len() {
+            for end in start + 1..=text.len().min(start + self.longest) {
+                let part: String = text[start..end].iter().collect();
+                if let Some(pattern) = self.patterns.get(&part) {
+                    for (i, &v) in pattern.iter().enumerate() {
+                        values[start + i] = values[start + i].max(v);
+                    }
+                }
+            }
+        }
+        // Before word letter `i` is between text letters `i` and `i + 1`
+        (self.left_min.max(1)..=word.len().saturating_sub(self.right_min.max(1)))
+            .filter(|&i| values[i + 1] % 2 == 1)
+            .collect()
+    }
+}
+
+impl Song {
+    /// Words whose notes differ from their syllables by more than [`MAX_DIFFERENCE`]
+    ///
+    /// `dictionaries` maps ISO 639-1 codes to patterns; the first language of
+    /// `#LANGUAGE` with patterns is used, a song with none of them gives no
+    /// mismatches. Holds don't count as notes of their own.
+    /// ```rust
+    /// use std::collections::BTreeMap;
+    /// use usdx_parser::hyphenation::Patterns;
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:T\n#BPM:100\n#GAP:0\n#LANGUAGE:English\n: 0 4 0 Banana\n: 4 4 0  hi\n\
+    ///     : 8 4 0 ~\nE\n";
+    /// let song: Song = text.parse().unwrap();
+    /// let dictionaries = BTreeMap::from([("en".to_string(), Patterns::parse("a1n").unwrap())]);
+    /// let mismatches = song.check_syllables(&dictionaries);
+    /// assert_eq!(mismatches.len(), 1);
+    /// assert_eq!((mismatches[0].notes, mismatches[0].syllables), (1, 3));
+    /// ```
+    pub fn check_syllables(
+        &self,
+        dictionaries: &BTreeMap<String, Patterns>,
+    ) -> Vec<SyllableMismatch> {
+        let Some(patterns) = self
+            .languages()
+            .iter()
+            .filter_map(|l| l.canonical)
+            .find_map(|l| dictionaries.get(l.iso639_1))
+        else {
+            return vec![];
+        };
+        let mut ret = vec![];
+        for word in self.words() {
+            let letters: String = word.text.chars().filter(|c| c.is_alphabetic()).collect();
+            if letters.is_empty() {
+                continue;
+            }
+            let notes = word
+                .notes
+                .iter()
+                .filter(|n| {
+                    !n.lyric
+                        .as_deref()
+                        .unwrap_or_default()
+                        .trim()
+                        .starts_with('~')
+                })
+                .count();
+            let syllables = patterns.syllables(&letters);
+            if notes.abs_diff(syllables) > MAX_DIFFERENCE {
+                ret.push(SyllableMismatch {
+                    word: word.text.clone(),
+                    voice: word.voice,
+                    start: word.start,
+                    notes,
+                    syllables,
+                });
+            }
+        }
+        ret
+    }
+}
+
+#[test]
+pub fn test_check_syllables() {
+    let patterns = Patterns::parse("% comment line\n.ab4c 1ba\n2ca a1n\nta-ble").unwrap();
+    assert_eq!(patterns.hyphenate("abc"), ["abc"]);
+    assert_eq!(patterns.hyphenate("Cabana"), ["Ca", "ba", "na"]);
+    assert_eq!(patterns.hyphenate("TABLE"), ["TA", "BLE"]);
+    let mut strict = patterns.clone();
+    strict.left_min = 2;
+    strict.right_min = 3;
+    assert_eq!(strict.hyphenate("banana"), ["ba", "nana"]);
+    assert!(Patterns::parse("1 2").is_err());
+
+    let text = "#TITLE:T\n#BPM:100\n#GAP:0\n#LANGUAGE:German, English\n\
+        : 0 2 0 ba\n: 2 2 0 na\n: 4 2 0 na \n: 6 2 0 hi\n: 8 2 0 i\n: 10 2 0 i\nE\n";
+    let song: Song = text.parse().unwrap();
+    let dictionaries = BTreeMap::from([("en".to_string(), patterns)]);
+    let mismatches = song.check_syllables(&dictionaries);
+    assert_eq!(
+        mismatches,
+        [SyllableMismatch {
+            word: "hiii".to_string(),
+            voice: None,
+            start: 900.0,
+            notes: 3,
+            syllables: 1,
+        }]
+    );
+    assert!(song.check_syllables(&BTreeMap::new()).is_empty());
+}
@@ -0,0 +1,160 @@
+//! Note lookup by position
+//!
+//! Editors hit-test clicks on a timeline many times per second. A
+//! [`NoteIndex`] sorts the sung notes of a song once, so finding the note
+//! under a beat or the notes inside a selection is a binary search.
+use crate::{Note, NoteType, Song};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// Sung notes of every voice, sorted by start
+#[derive(Debug, Clone)]
+pub struct NoteIndex<'a> {
+    song: &'a Song,
+    notes: Vec<&'a Note>,
+    /// Latest end of any note up to the same position in `notes`, in beats
+    ends: Vec<u32>,
+}
+
+fn end(note: &Note) -> u32 {
+    note.beat_number
+        .saturating_add(note.note_length.unwrap_or_default())
+}
+
+impl<'a> NoteIndex<'a> {
+    fn new(song: &'a Song) -> Self {
+        let mut notes: Vec<&Note> = song
+            .notes
+            .iter()
+            .filter(|n| n.note_type != NoteType::LineBreak)
+            .collect();
+        notes.sort_by_key(|n| n.beat_number);
+        let mut latest = 0;
+        let ends = notes
+            .iter()
+            .map(|n| {
+                latest = latest.max(end(n));
+                latest
+            })
+            .collect();
+        Self { song, notes, ends }
+    }
+
+    /// Number of sung notes
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    /// Note sounding at `beat`; of overlapping notes the one starting last
+    pub fn at_beat(&self, beat: u32) -> Option<&'a Note> {
+        self.find(beat as f64)
+    }
+
+    /// Note sounding at `ms` from the start of the audio
+    pub fn at_ms(&self, ms: f64) -> Option<&'a Note> {
+        self.find(self.song.ms_to_beat(ms))
+    }
+
+    fn find(&self, beat: f64) -> Option<&'a Note> {
+        let started = self.notes.partition_point(|n| n.beat_number as f64 <= beat);
+        // Walk back while some earlier note still lasts past `beat`
+        (0..started)
+            .rev()
+            .take_while(|&i| self.ends[i] as f64 > beat)
+            .map(|i| self.notes[i])
+            .find(|n| end(n) as f64 > beat)
+    }
+
+    /// Notes overlapping `beats`, in order of their start
+    pub fn in_beats(&self, beats: Range<u32>) -> impl Iterator<Item = &'a Note> + '_ {
+        let first = self.ends.partition_point(|&e| e <= beats.start);
+        let last = self.notes.partition_point(|n| n.beat_number < beats.end);
+        self.notes[first..last.max(first)]
+            .iter()
+            .copied()
+            .filter(move |n| end(n) > beats.start)
+    }
+
+    /// Notes overlapping the time span `ms`
+    pub fn in_ms(&self, ms: Range<f64>) -> impl Iterator<Item = &'a Note> + '_ {
+        let beat = |ms: f64| self.song.ms_to_beat(ms).max(0.0);
+        let end = beat(ms.end);
+        // Rounded up by hand, `ceil` needs std
+        let last = end as u32 + u32::from(end > (end as u32) as f64);
+        self.in_beats(beat(ms.start) as u32..last)
+    }
+}
+
+impl Song {
+    /// Index for looking up notes by beat or time
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// let index = song.note_index();
+    /// assert_eq!(index.at_beat(5).unwrap().lyric.as_deref(), Some("lo"));
+    /// assert!(index.at_beat(10).is_none());
+    /// assert_eq!(index.in_beats(0..13).count(), 3);
+    /// ```
+    pub fn note_index(&self) -> NoteIndex<'_> {
+        NoteIndex::new(self)
+    }
+
+    /// Note sounding at `beat`
+    ///
+    /// This builds a [`NoteIndex`] first; keep one for repeated lookups.
+    pub fn note_at_beat(&self, beat: u32) -> Option<&Note> {
+        self.note_index().at_beat(beat)
+    }
+
+    /// Note sounding at `ms` from the start of the audio
+    ///
+    /// This builds a [`NoteIndex`] first; keep one for repeated lookups.
+    pub fn note_at_ms(&self, ms: f64) -> Option<&Note> {
+        self.note_index().at_ms(ms)
+    }
+}
+
+#[test]
+pub fn test_note_index() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let index = song.note_index();
+    for note in song
+        .notes
+        .iter()
+        .filter(|n| n.note_type != NoteType::LineBreak)
+    {
+        let found = index.at_beat(note.beat_number).unwrap();
+        assert!(core::ptr::eq(found, note));
+        let linear: Vec<_> = song
+            .notes
+            .iter()
+            .filter(|n| n.note_type != NoteType::LineBreak)
+            .filter(|n| end(n) > note.beat_number && n.beat_number < note.beat_number + 20)
+            .collect();
+        let indexed: Vec<_> = index
+            .in_beats(note.beat_number..note.beat_number + 20)
+            .collect();
+        assert_eq!(linear.len(), indexed.len());
+    }
+    // Between "life?" and the line break
+    assert!(song.note_at_beat(49).is_none());
+    let ms = song.beat_to_ms(7.5);
+    assert_eq!(song.note_at_ms(ms).unwrap().lyric.as_deref(), Some(" this"));
+    assert!(song.note_at_ms(0.0).is_none());
+
+    // Notes of both duet voices
+    let duet = Song::from_file("tests/duet.txt").unwrap();
+    let index = duet.note_index();
+    assert_eq!(index.len(), 6);
+    assert_eq!(
+        index
+            .in_ms(duet.beat_to_ms(12.0)..duet.beat_to_ms(21.0))
+            .count(),
+        2
+    );
+}
@@ -0,0 +1,109 @@
+//! Shared storage for metadata values that repeat across a library
+//!
+//! Tens of thousands of songs name only a few dozen languages, genres and
+//! editions. Interning them makes every equal value share one allocation.
+use crate::library::SongLibrary;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Hands out one shared copy per distinct string
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shared copy of `value`, allocated on first use
+    /// ```rust
+    /// use usdx_parser::library::Interner;
+    /// use std::sync::Arc;
+    ///
+    /// let mut interner = Interner::new();
+    /// let a = interner.intern("English");
+    /// let b = interner.intern("English");
+    /// assert!(Arc::ptr_eq(&a, &b));
+    /// ```
+    pub fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(a) = self.strings.get(value) {
+            return a.clone();
+        }
+        let a = Arc::<str>::from(value);
+        self.strings.insert(a.clone());
+        a
+    }
+
+    /// Number of distinct strings
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+/// Headers of a song with the repeating values interned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SongMetadata {
+    pub path: PathBuf,
+    pub artist: Option<Arc<str>>,
+    pub title: Arc<str>,
+    pub edition: Option<Arc<str>>,
+    pub genre: Option<Arc<str>>,
+    pub year: Option<Arc<str>>,
+    pub language: Option<Arc<str>>,
+    pub duet: bool,
+}
+
+impl SongLibrary {
+    /// Metadata of every song, sharing equal values through `interner`
+    ///
+    /// Keep the result instead of the library when only the headers are needed.
+    pub fn metadata(&self, interner: &mut Interner) -> Vec<SongMetadata> {
+        let mut intern = |a: Option<&str>| a.map(|a| interner.intern(a));
+        self.songs
+            .iter()
+            .map(|e| SongMetadata {
+                path: e.path.clone(),
+                artist: intern(e.song.artist.as_deref()),
+                title: intern(Some(&e.song.title)).unwrap_or_default(),
+                edition: intern(e.song.edition.as_deref()),
+                genre: intern(e.song.genre.as_deref()),
+                year: intern(e.song.year.as_deref()),
+                language: intern(e.song.language.as_deref()),
+                duet: e.song.is_duet(),
+            })
+            .collect()
+    }
+}
+
+#[test]
+pub fn test_interned_metadata() {
+    use crate::library::SongEntry;
+    use crate::Song;
+
+    let mut library = SongLibrary::default();
+    for title in ["One", "Two", "Three"] {
+        let mut song = Song::new(title, 100.0, 0);
+        song.language = Some("German".to_string());
+        song.edition = Some("SingStar".to_string());
+        library.songs.push(SongEntry {
+            path: title.into(),
+            song,
+        });
+    }
+    let mut interner = Interner::new();
+    let metadata = library.metadata(&mut interner);
+    assert_eq!(interner.len(), 5);
+    let german = metadata[0].language.as_ref().unwrap();
+    assert!(metadata
+        .iter()
+        .all(|m| Arc::ptr_eq(m.language.as_ref().unwrap(), german)));
+    assert_eq!(&*metadata[2].title, "Three");
+    assert!(!metadata[1].duet);
+}
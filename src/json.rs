@@ -0,0 +1,507 @@
+//! Minimal JSON reader and writer
+//!
+//! Parses a complete document into a [`Value`] tree and writes one back out
+//! compactly. Object keys keep their document order. Songs convert to and
+//! from their JSON form here as well.
+use crate::{Note, NoteType, Song, Voice};
+use anyhow::{bail, Result};
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn parse(text: &str) -> Result<Value> {
+        let mut parser = Parser {
+            data: text.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value(0)?;
+        parser.whitespace();
+        if parser.pos != parser.data.len() {
+            bail!("Unexpected data after JSON value at byte {}", parser.pos);
+        }
+        Ok(value)
+    }
+
+    /// Member of an object, `None` for missing keys and non-objects
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(members) => members.iter().find(|a| a.0 == key).map(|a| &a.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(a) => Some(*a),
+            _ => None,
+        }
+    }
+
+    /// Elements of an array, empty for anything else
+    pub fn items(&self) -> &[Value] {
+        match self {
+            Self::Array(a) => a,
+            _ => &[],
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<Option<&str>> for Value {
+    fn from(value: Option<&str>) -> Self {
+        value.map(Value::from).unwrap_or(Value::Null)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(a) => write!(f, "{}", a),
+            Self::Number(a) if a.is_finite() => write!(f, "{}", a),
+            Self::Number(_) => f.write_str("null"),
+            Self::String(a) => write_string(f, a),
+            Self::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Self::Object(members) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+impl Song {
+    /// The song as JSON, with camelCase keys and the notes in an array
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// let json = song.to_json();
+    /// assert!(json.contains(r#""singerP1":"Singer One""#));
+    /// assert_eq!(Song::from_json(&json).unwrap().to_string(), song.to_string());
+    /// ```
+    pub fn to_json(&self) -> String {
+        song_to_json(self).to_string()
+    }
+
+    /// Read a song from the JSON written by [`Song::to_json`]
+    pub fn from_json(json: &str) -> Result<Song> {
+        song_from_json(&Value::parse(json)?)
+    }
+
+    /// The song as timed sentences and syllables for web players
+    ///
+    /// Unlike [`Song::to_json`] this is flattened for JavaScript karaoke
+    /// players, with everything timed in milliseconds from the start of the audio:
+    ///
+    /// ```text
+    /// {
+    ///   "title": "…", "artist": "…" | null, "audio": "…" | null,
+    ///   "sentences": [{
+    ///     "voice": "P1" | "P2" | "P3" | null,
+    ///     "start": 1200, "end": 1584, "text": "Hello",
+    ///     "syllables": [{
+    ///       "text": "Hel", "start": 1200, "end": 1392,
+    ///       "pitch": 5 | null, "type": "normal" | "golden" | "freestyle"
+    ///     }]
+    ///   }]
+    /// }
+    /// ```
+    ///
+    /// `pitch` counts semitones from C4 like the chart does. Syllable texts keep
+    /// their spaces and drop holds (`~`), so joining them gives the sentence.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// let json = song.to_player_json();
+    /// assert!(json.contains(r#"{"voice":"P1","start":1200,"end":1584,"text":"Hello","#));
+    /// assert!(json.contains(r#"{"text":"Hel","start":1200,"end":1392,"pitch":5,"type":"normal"}"#));
+    /// ```
+    pub fn to_player_json(&self) -> String {
+        player_json(self).to_string()
+    }
+}
+
+fn player_json(song: &Song) -> Value {
+    let ms = |beat: u32| Value::Number(song.beat_to_ms(beat as f64).round());
+    let member = |key: &str, value| (key.to_string(), value);
+    let sentences = song
+        .sentences()
+        .iter()
+        .map(|sentence| {
+            let syllables = sentence
+                .notes
+                .iter()
+                .map(|n| {
+                    let text: String = n
+                        .lyric
+                        .iter()
+                        .flat_map(|l| l.chars())
+                        .filter(|&c| c != '~')
+                        .collect();
+                    let kind = match n.note_type {
+                        NoteType::Golden => "golden",
+                        NoteType::Freestyle => "freestyle",
+                        _ => "normal",
+                    };
+                    let end = n
+                        .beat_number
+                        .saturating_add(n.note_length.unwrap_or_default());
+                    Value::Object(vec![
+                        member("text", Value::String(text)),
+                        member("start", ms(n.beat_number)),
+                        member("end", ms(end)),
+                        member(
+                            "pitch",
+                            n.note_tone.map_or(Value::Null, |a| Value::Number(a.into())),
+                        ),
+                        member("type", Value::from(kind)),
+                    ])
+                })
+                .collect();
+            Value::Object(vec![
+                member(
+                    "voice",
+                    sentence
+                        .voice
+                        .map_or(Value::Null, |v| Value::String(v.to_string())),
+                ),
+                member("start", ms(sentence.start_beat())),
+                member("end", ms(sentence.end_beat())),
+                member("text", Value::String(sentence.text())),
+                member("syllables", Value::Array(syllables)),
+            ])
+        })
+        .collect();
+    Value::Object(vec![
+        member("title", Value::from(song.title.as_str())),
+        member("artist", Value::from(song.artist.as_deref())),
+        member("audio", Value::from(song.mp3.as_deref())),
+        member("sentences", Value::Array(sentences)),
+    ])
+}
+
+fn song_to_json(song: &Song) -> Value {
+    let text = |a: &Option<String>| Value::from(a.as_deref());
+    let number = |a: Option<u32>| a.map_or(Value::Null, |a| Value::Number(a.into()));
+    let member = |key: &str, value| (key.to_string(), value);
+    let notes = song
+        .notes
+        .iter()
+        .map(|n| {
+            Value::Object(vec![
+                member("type", Value::String(n.note_type.to_string())),
+                member("beat", Value::Number(n.beat_number.into())),
+                member("length", number(n.note_length)),
+                member(
+                    "tone",
+                    n.note_tone.map_or(Value::Null, |a| Value::Number(a.into())),
+                ),
+                member("lyric", Value::from(n.lyric.as_deref())),
+                member("secondLyric", Value::from(n.second_lyric.as_deref())),
+                member(
+                    "voice",
+                    n.voice
+                        .map_or(Value::Null, |v| Value::String(v.to_string())),
+                ),
+                member("lineStart", number(n.line_start)),
+            ])
+        })
+        .collect();
+    Value::Object(vec![
+        member("artist", text(&song.artist)),
+        member("title", Value::from(song.title.as_str())),
+        member("mp3", text(&song.mp3)),
+        member("video", text(&song.video)),
+        member("edition", text(&song.edition)),
+        member("genre", text(&song.genre)),
+        member("year", text(&song.year)),
+        member("language", text(&song.language)),
+        member("bpm", Value::Number(song.bpm.into())),
+        member("gap", Value::Number(song.gap.into())),
+        member(
+            "videoGap",
+            song.video_gap
+                .map_or(Value::Null, |a| Value::Number(a.into())),
+        ),
+        member("cover", text(&song.cover)),
+        member("background", text(&song.background)),
+        member("singerP1", text(&song.singer_p1)),
+        member("singerP2", text(&song.singer_p2)),
+        member("notes", Value::Array(notes)),
+    ])
+}
+
+fn song_from_json(json: &Value) -> Result<Song> {
+    let text = |value: &Value, key| value.get(key).and_then(Value::as_str).map(str::to_string);
+    let number = |value: &Value, key| value.get(key).and_then(Value::as_f64);
+    let Some(title) = text(json, "title") else {
+        bail!("Song has no title");
+    };
+    let Some(bpm) = number(json, "bpm") else {
+        bail!("Song has no bpm");
+    };
+    let mut song = Song::new(
+        &title,
+        bpm as f32,
+        number(json, "gap").unwrap_or_default() as u32,
+    );
+    song.artist = text(json, "artist");
+    song.mp3 = text(json, "mp3");
+    song.video = text(json, "video");
+    song.edition = text(json, "edition");
+    song.genre = text(json, "genre");
+    song.year = text(json, "year");
+    song.language = text(json, "language");
+    song.video_gap = number(json, "videoGap").map(|a| a as f32);
+    song.cover = text(json, "cover");
+    song.background = text(json, "background");
+    song.singer_p1 = text(json, "singerP1");
+    song.singer_p2 = text(json, "singerP2");
+    for note in json.get("notes").map(Value::items).unwrap_or_default() {
+        let Some(note_type) = note.get("type").and_then(Value::as_str) else {
+            bail!("Note has no type");
+        };
+        song.notes.push(Note {
+            note_type: NoteType::try_from(note_type)?,
+            beat_number: number(note, "beat").unwrap_or_default() as u32,
+            note_length: number(note, "length").map(|a| a as u32),
+            note_tone: number(note, "tone").map(|a| a as i32),
+            lyric: text(note, "lyric").map(Arc::from),
+            second_lyric: text(note, "secondLyric").map(Arc::from),
+            voice: note
+                .get("voice")
+                .and_then(Value::as_str)
+                .and_then(Voice::from_marker),
+            line_start: number(note, "lineStart").map(|a| a as u32),
+        });
+    }
+    Ok(song)
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn whitespace(&mut self) {
+        while self
+            .data
+            .get(self.pos)
+            .is_some_and(|a| a.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<()> {
+        if !self.data[self.pos..].starts_with(literal.as_bytes()) {
+            bail!("Expected '{}' at byte {}", literal, self.pos);
+        }
+        self.pos += literal.len();
+        Ok(())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > 128 {
+            bail!("JSON is nested too deeply");
+        }
+        self.whitespace();
+        let Some(c) = self.data.get(self.pos) else {
+            bail!("Unexpected end of JSON");
+        };
+        Ok(match c {
+            b'n' => {
+                self.expect("null")?;
+                Value::Null
+            }
+            b't' => {
+                self.expect("true")?;
+                Value::Bool(true)
+            }
+            b'f' => {
+                self.expect("false")?;
+                Value::Bool(false)
+            }
+            b'"' => Value::String(self.string()?),
+            b'[' => {
+                self.pos += 1;
+                let mut items = vec![];
+                self.whitespace();
+                if self.data.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.whitespace();
+                    match self.data.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            break;
+                        }
+                        _ => bail!("Expected ',' or ']' at byte {}", self.pos),
+                    }
+                }
+                Value::Array(items)
+            }
+            b'{' => {
+                self.pos += 1;
+                let mut members = vec![];
+                self.whitespace();
+                if self.data.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(members));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(":")?;
+                    members.push((key, self.value(depth + 1)?));
+                    self.whitespace();
+                    match self.data.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            break;
+                        }
+                        _ => bail!("Expected ',' or '}}' at byte {}", self.pos),
+                    }
+                }
+                Value::Object(members)
+            }
+            _ => self.number()?,
+        })
+    }
+
+    fn number(&mut self) -> Result<Value> {
+        let start = self.pos;
+        while self
+            .data
+            .get(self.pos)
+            .is_some_and(|a| matches!(a, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.data[start..self.pos])?;
+        match text.parse() {
+            Ok(a) => Ok(Value::Number(a)),
+            Err(_) => bail!("Invalid JSON value at byte {}", start),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let Some(digits) = self.data.get(self.pos..self.pos + 4) else {
+            bail!("Truncated unicode escape");
+        };
+        self.pos += 4;
+        Ok(u32::from_str_radix(std::str::from_utf8(digits)?, 16)?)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect("\"")?;
+        let mut ret = String::new();
+        loop {
+            let start = self.pos;
+            while self
+                .data
+                .get(self.pos)
+                .is_some_and(|a| *a != b'"' && *a != b'\\')
+            {
+                self.pos += 1;
+            }
+            ret.push_str(std::str::from_utf8(&self.data[start..self.pos])?);
+            let Some(c) = self.data.get(self.pos) else {
+                bail!("Unterminated JSON string");
+            };
+            self.pos += 1;
+            if *c == b'"' {
+                return Ok(ret);
+            }
+            let Some(escape) = self.data.get(self.pos) else {
+                bail!("Unterminated JSON string");
+            };
+            self.pos += 1;
+            ret.push(match escape {
+                b'"' => '"',
+                b'\\' => '\\',
+                b'/' => '/',
+                b'b' => '\u{8}',
+                b'f' => '\u{c}',
+                b'n' => '\n',
+                b'r' => '\r',
+                b't' => '\t',
+                b'u' => {
+                    let mut code = self.hex4()?;
+                    if (0xd800..0xdc00).contains(&code) && self.data[self.pos..].starts_with(b"\\u")
+                    {
+                        self.pos += 2;
+                        let low = self.hex4()?;
+                        code =
+                            0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                    }
+                    char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                }
+                _ => bail!("Invalid escape in JSON string at byte {}", self.pos - 1),
+            });
+        }
+    }
+}
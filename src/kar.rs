@@ -0,0 +1,230 @@
+//! MIDI karaoke (`.kar`) import
+//!
+//! Lyrics are taken from lyric meta events, or from the text events of the
+//! `Words` track used by `.kar` files. The melody is the track whose notes line
+//! up with the most syllables. `@T` info lines fill in the title and artist,
+//! `@L` the language, and syllables starting with `/` or `\` begin a new line.
+use crate::midi::{self, EventKind, Smf};
+use crate::{Note, NoteType, Song};
+use anyhow::{bail, Result};
+
+/// Resolution of the generated chart: 16 USDX beats per quarter note
+const BEATS_PER_QUARTER: f64 = 16.0;
+
+struct Syllable {
+    tick: u64,
+    text: String,
+    line_break_before: bool,
+}
+
+struct MidiNote {
+    start: u64,
+    end: u64,
+    key: u8,
+}
+
+impl Song {
+    /// Import a Type-1 MIDI karaoke file
+    pub fn from_kar(data: &[u8]) -> Result<Song> {
+        let smf = Smf::parse(data)?;
+        let mut titles = vec![];
+        let mut language = None;
+        for e in smf.tracks.iter().flatten() {
+            if let EventKind::Meta { kind, data } = &e.kind {
+                if *kind != midi::META_TEXT {
+                    continue;
+                }
+                let text = midi::decode_text(data);
+                if let Some(a) = text.strip_prefix("@T") {
+                    titles.push(a.trim().to_string());
+                } else if let Some(a) = text.strip_prefix("@L") {
+                    language = Some(a.trim().to_string());
+                }
+            }
+        }
+
+        let syllables = lyric_track(&smf);
+        if syllables.is_empty() {
+            bail!("MIDI file contains no lyrics!");
+        }
+        let tolerance = smf.division as u64 / 8;
+        let melody = smf
+            .tracks
+            .iter()
+            .map(|t| track_notes(t))
+            .max_by_key(|notes| {
+                syllables
+                    .iter()
+                    .filter(|s| {
+                        notes
+                            .iter()
+                            .any(|n| n.start + tolerance >= s.tick && n.start <= s.tick + tolerance)
+                    })
+                    .count()
+            })
+            .unwrap_or_default();
+        if melody.is_empty() {
+            bail!("MIDI file contains no melody notes!");
+        }
+
+        let tempo_map = smf.tempo_map();
+        let first_tempo = tempo_map.first().map(|a| a.1).unwrap_or(500_000);
+        let ms_per_beat = first_tempo as f64 / 1000.0 / BEATS_PER_QUARTER;
+        let gap = smf.tick_to_ms(&tempo_map, melody[0].start).round();
+        let to_beat = |tick: u64| ((smf.tick_to_ms(&tempo_map, tick) - gap) / ms_per_beat).round();
+
+        let title = titles
+            .first()
+            .cloned()
+            .or_else(|| track_name(&smf))
+            .unwrap_or_else(|| "Unknown".to_string());
+        let mut song = Song::new(&title, (15000.0 / ms_per_beat) as f32, gap as u32);
+        song.artist = titles.get(1).cloned();
+        song.language = language;
+
+        let mut syllables = syllables.into_iter().peekable();
+        let mut last_end = None;
+        for (i, n) in melody.iter().enumerate() {
+            let next_start = melody.get(i + 1).map(|a| a.start).unwrap_or(u64::MAX);
+            let mut lyric = String::new();
+            let mut line_break = false;
+            while let Some(s) = syllables.next_if(|s| s.tick < next_start.saturating_sub(tolerance))
+            {
+                line_break |= s.line_break_before && lyric.is_empty();
+                lyric.push_str(&s.text);
+            }
+            let start = to_beat(n.start);
+            let end = to_beat(n.end).max(start + 1.0);
+            if let (true, Some(last_end)) = (line_break, last_end) {
+                song.notes
+                    .push(Note::line_break(start.min(last_end) as u32));
+            }
+            if lyric.is_empty() {
+                lyric.push('~');
+            }
+            song.notes.push(Note::new(
+                NoteType::Normal,
+                start as u32,
+                (end - start) as u32,
+                n.key as i32 - 60,
+                &lyric,
+            ));
+            last_end = Some(end);
+        }
+        Ok(song)
+    }
+
+    /// Import a MIDI karaoke file from disk
+    pub fn from_kar_file(path: &str) -> Result<Song> {
+        let data = std::fs::read(path)?;
+        Song::from_kar(&data)
+    }
+}
+
+/// Lyrics of the track carrying the most lyric (or, failing that, text) events
+fn lyric_track(smf: &Smf) -> Vec<Syllable> {
+    let events_of = |wanted: u8| {
+        smf.tracks
+            .iter()
+            .map(|t| {
+                t.iter()
+                    .filter_map(|e| match &e.kind {
+                        EventKind::Meta { kind, data } if *kind == wanted => {
+                            Some((e.tick, midi::decode_text(data)))
+                        }
+                        _ => None,
+                    })
+                    .filter(|(_, text)| !text.starts_with('@'))
+                    .collect::<Vec<_>>()
+            })
+            .max_by_key(|a| a.len())
+            .unwrap_or_default()
+    };
+    let mut events = events_of(midi::META_LYRIC);
+    if events.is_empty() {
+        events = events_of(midi::META_TEXT);
+    }
+    let mut ret = vec![];
+    let mut line_break_pending = false;
+    for (tick, text) in events {
+        let line_break_before = line_break_pending || text.starts_with(['/', '\\']);
+        line_break_pending = text.ends_with(['\r', '\n']);
+        let text = text
+            .trim_start_matches(['/', '\\'])
+            .trim_end_matches(['\r', '\n']);
+        if text.is_empty() {
+            line_break_pending |= line_break_before;
+            continue;
+        }
+        ret.push(Syllable {
+            tick,
+            text: text.to_string(),
+            line_break_before,
+        });
+    }
+    ret
+}
+
+/// Monophonic notes of a track, ignoring the percussion channel
+fn track_notes(track: &[midi::Event]) -> Vec<MidiNote> {
+    let mut open: Vec<(u8, u8, u64)> = vec![];
+    let mut notes = vec![];
+    for e in track {
+        match e.kind {
+            EventKind::NoteOn { channel, key } if channel != 9 => open.push((channel, key, e.tick)),
+            EventKind::NoteOff { channel, key } => {
+                if let Some(i) = open.iter().position(|a| a.0 == channel && a.1 == key) {
+                    let (_, key, start) = open.remove(i);
+                    notes.push(MidiNote {
+                        start,
+                        end: e.tick,
+                        key,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    notes.sort_by_key(|a| a.start);
+    notes.dedup_by_key(|a| a.start);
+    for i in 1..notes.len() {
+        if notes[i - 1].end > notes[i].start {
+            notes[i - 1].end = notes[i].start;
+        }
+    }
+    notes
+}
+
+fn track_name(smf: &Smf) -> Option<String> {
+    smf.tracks.first()?.iter().find_map(|e| match &e.kind {
+        EventKind::Meta { kind, data } if *kind == midi::META_TRACK_NAME => {
+            Some(midi::decode_text(data))
+        }
+        _ => None,
+    })
+}
+
+#[test]
+pub fn test_kar_import() {
+    let song = Song::from_kar_file("tests/twinkle.kar").unwrap();
+    assert_eq!(song.title, "Twinkle Twinkle");
+    assert_eq!(song.artist.as_deref(), Some("Traditional"));
+    let lyrics = song
+        .notes
+        .iter()
+        .filter_map(|n| n.lyric.as_deref())
+        .collect::<String>();
+    assert_eq!(lyrics, "Twinkle twinklelittle star");
+    assert_eq!(song.notes[0].note_tone, Some(0));
+    assert!(song
+        .notes
+        .iter()
+        .any(|n| n.note_type == NoteType::LineBreak));
+
+    let mut data = std::fs::read("tests/twinkle.kar").unwrap();
+    for division in [[0, 0], [0xe7, 0x28]] {
+        data[12..14].copy_from_slice(&division);
+        let error = Song::from_kar(&data).unwrap_err().to_string();
+        assert!(error.starts_with("Unsupported MIDI division"), "{}", error);
+    }
+}
@@ -0,0 +1,339 @@
+//! Karaoke Mugen export
+//!
+//! Karaoke Mugen describes a song with a `.kara.json` metadata file and keeps
+//! the lyrics in an ASS subtitle with `\k` karaoke timing. Tags (singers,
+//! languages, genres) are written by name; Karaoke Mugen's editor links them to
+//! its tag repository when the song is imported.
+use crate::filename::{song_base_name, MAX_NAME_LEN};
+use crate::json::Value;
+use crate::{NoteType, Song, Voice};
+use anyhow::Result;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the `.kara.json` format written
+const KARA_VERSION: f64 = 4.0;
+
+/// Files making up a Karaoke Mugen song
+#[derive(Debug, Clone, PartialEq)]
+pub struct KaraokeMugen {
+    /// File name without extension shared by the metadata and subtitle files
+    pub base_name: String,
+    /// Contents of `<base_name>.kara.json`
+    pub kara_json: String,
+    /// Contents of `<base_name>.ass`
+    pub ass: String,
+}
+
+impl KaraokeMugen {
+    /// Write the `.kara.json` and `.ass` files into `dir`
+    pub fn write_to(&self, dir: &str) -> Result<()> {
+        let dir = Path::new(dir);
+        std::fs::write(
+            dir.join(format!("{}.kara.json", self.base_name)),
+            &self.kara_json,
+        )?;
+        std::fs::write(dir.join(format!("{}.ass", self.base_name)), &self.ass)?;
+        Ok(())
+    }
+}
+
+/// Stable UUID-formatted ID derived from artist and title, so exporting
+/// the same song again updates it in Karaoke Mugen instead of duplicating it
+fn kara_id(song: &Song) -> String {
+    let key = format!(
+        "{}\0{}",
+        song.artist.as_deref().unwrap_or_default(),
+        song.title
+    );
+    let hash = |seed: u64| {
+        key.bytes().fold(seed, |acc, b| {
+            (acc ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    };
+    let high = hash(0xcbf2_9ce4_8422_2325);
+    let low = hash(high ^ 0x9e37_79b9_7f4a_7c15);
+    let hex = format!("{:016x}{:016x}", high, low);
+    // Mark it as a version 4 UUID of the RFC 4122 variant
+    format!(
+        "{}-{}-4{}-{:x}{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[13..16],
+        0x8 | (u8::from_str_radix(&hex[16..17], 16).unwrap_or(0) & 0x3),
+        &hex[17..20],
+        &hex[20..32]
+    )
+}
+
+/// Current time as an ISO 8601 UTC timestamp
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|a| a.as_secs())
+        .unwrap_or_default() as i64;
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.000Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// ASS timestamp (`h:mm:ss.cc`) of a time in ms
+fn ass_time(ms: f64) -> String {
+    let cs = (ms.max(0.0) / 10.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        cs / 360000,
+        cs / 6000 % 60,
+        cs / 100 % 60,
+        cs % 100
+    )
+}
+
+/// Escape characters ASS would interpret as override blocks or line breaks
+fn ass_text(text: &str) -> String {
+    text.replace('{', "(").replace('}', ")").replace('\\', "/")
+}
+
+fn tag_list(names: &[&str]) -> Value {
+    Value::Array(
+        names
+            .iter()
+            .filter(|a| !a.trim().is_empty())
+            .map(|a| Value::from(a.trim()))
+            .collect(),
+    )
+}
+
+impl Song {
+    /// Export as Karaoke Mugen metadata and an ASS karaoke subtitle
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/i_hate_everything_about_you.txt").unwrap();
+    /// let kara = song.to_karaoke_mugen();
+    /// assert_eq!(kara.base_name, "Three Days Grace - I Hate Everything About You");
+    /// assert!(kara.ass.contains("{\\k"));
+    /// ```
+    pub fn to_karaoke_mugen(&self) -> KaraokeMugen {
+        let base_name = song_base_name(
+            self.artist.as_deref(),
+            &self.title,
+            MAX_NAME_LEN - ".kara.json".len(),
+        );
+        // Karaoke Mugen uses ISO 639-2/B codes and "und" for unknown languages
+        let language = self
+            .languages()
+            .first()
+            .and_then(|l| l.canonical)
+            .map_or("und", |l| l.iso639_2);
+        let media = self.video.as_deref().or(self.mp3.as_deref());
+        let duration = self
+            .notes
+            .iter()
+            .map(|n| self.beat_to_ms((n.beat_number + n.note_length.unwrap_or(0)) as f64))
+            .fold(0.0, f64::max);
+        let mut singers = vec![];
+        if let Some(artist) = self.artist.as_deref() {
+            singers.push(artist);
+        }
+        singers.extend(self.singer_p1.as_deref());
+        singers.extend(self.singer_p2.as_deref());
+        let now = timestamp();
+        let kara = Value::Object(vec![
+            (
+                "header".to_string(),
+                Value::Object(vec![
+                    ("version".to_string(), Value::Number(KARA_VERSION)),
+                    (
+                        "description".to_string(),
+                        Value::from("Karaoke Mugen Karaoke Data File"),
+                    ),
+                ]),
+            ),
+            (
+                "medias".to_string(),
+                Value::Array(vec![Value::Object(vec![
+                    ("version".to_string(), Value::from("Default")),
+                    ("filename".to_string(), Value::from(media)),
+                    ("audiogain".to_string(), Value::Number(0.0)),
+                    (
+                        "duration".to_string(),
+                        Value::Number((duration / 1000.0).ceil()),
+                    ),
+                    ("filesize".to_string(), Value::Number(0.0)),
+                    ("default".to_string(), Value::Bool(true)),
+                    (
+                        "lyrics".to_string(),
+                        Value::Array(vec![Value::Object(vec![
+                            (
+                                "filename".to_string(),
+                                Value::from(format!("{}.ass", base_name).as_str()),
+                            ),
+                            ("default".to_string(), Value::Bool(true)),
+                            ("version".to_string(), Value::from("Default")),
+                        ])]),
+                    ),
+                ])]),
+            ),
+            (
+                "data".to_string(),
+                Value::Object(vec![
+                    ("kid".to_string(), Value::from(kara_id(self).as_str())),
+                    (
+                        "titles".to_string(),
+                        Value::Object(vec![(
+                            language.to_string(),
+                            Value::from(self.title.as_str()),
+                        )]),
+                    ),
+                    ("titles_default_language".to_string(), Value::from(language)),
+                    (
+                        "year".to_string(),
+                        self.year
+                            .as_deref()
+                            .and_then(|a| a.trim().parse::<f64>().ok())
+                            .map(Value::Number)
+                            .unwrap_or(Value::Null),
+                    ),
+                    (
+                        "tags".to_string(),
+                        Value::Object(vec![
+                            ("singers".to_string(), tag_list(&singers)),
+                            ("langs".to_string(), tag_list(&[language])),
+                            (
+                                "genres".to_string(),
+                                tag_list(&self.genre.as_deref().into_iter().collect::<Vec<_>>()),
+                            ),
+                        ]),
+                    ),
+                    ("created_at".to_string(), Value::from(now.as_str())),
+                    ("modified_at".to_string(), Value::from(now.as_str())),
+                ]),
+            ),
+        ]);
+        KaraokeMugen {
+            ass: self.to_ass(),
+            kara_json: kara.to_string(),
+            base_name,
+        }
+    }
+
+    /// Lyrics as an ASS subtitle with one `\k` timed event per line
+    fn to_ass(&self) -> String {
+        let mut ret = String::new();
+        ret.push_str("[Script Info]\n");
+        ret.push_str(&format!("Title: {}\n", self.title));
+        ret.push_str("ScriptType: v4.00+\nPlayResX: 1280\nPlayResY: 720\n\n");
+        ret.push_str("[V4+ Styles]\n");
+        ret.push_str("Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n");
+        ret.push_str("Style: Default,Arial,48,&H00FFFFFF,&H000088EF,&H00000000,&H00000000,0,0,0,0,100,100,0,0,1,2,0,2,10,10,40,1\n\n");
+        ret.push_str("[Events]\n");
+        ret.push_str(
+            "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        );
+
+        let (p1, p2) = (
+            self.singer_p1.as_deref().unwrap_or("P1"),
+            self.singer_p2.as_deref().unwrap_or("P2"),
+        );
+        let both = format!("{} & {}", p1, p2);
+        let singer = |voice: Option<Voice>| match voice {
+            Some(Voice::P1) => p1,
+            Some(Voice::P2) => p2,
+            Some(Voice::Both) => both.as_str(),
+            None => "",
+        };
+        // (start, end, voice, text) of the line being built
+        let mut line: Option<(f64, f64, Option<Voice>, String)> = None;
+        let mut flush = |line: Option<(f64, f64, Option<Voice>, String)>| {
+            if let Some((start, end, voice, text)) = line {
+                ret.push_str(&format!(
+                    "Dialogue: 0,{},{},Default,{},0,0,0,karaoke,{}\n",
+                    ass_time(start),
+                    ass_time(end),
+                    singer(voice),
+                    text
+                ));
+            }
+        };
+        for note in self.notes.iter() {
+            if note.note_type == NoteType::LineBreak
+                || line.as_ref().is_some_and(|a| a.2 != note.voice)
+            {
+                flush(line.take());
+                if note.note_type == NoteType::LineBreak {
+                    continue;
+                }
+            }
+            let start = self.beat_to_ms(note.beat_number as f64);
+            let end = self.beat_to_ms((note.beat_number + note.note_length.unwrap_or(0)) as f64);
+            let current = line.get_or_insert((start, start, note.voice, String::new()));
+            let gap = ((start - current.1) / 10.0).round();
+            if gap > 0.0 {
+                current.3.push_str(&format!("{{\\k{}}}", gap));
+            }
+            current.3.push_str(&format!(
+                "{{\\k{}}}{}",
+                ((end - start.max(current.1)) / 10.0).round().max(0.0),
+                ass_text(note.lyric.as_deref().unwrap_or_default())
+            ));
+            current.1 = current.1.max(end);
+        }
+        flush(line);
+        ret
+    }
+}
+
+#[test]
+pub fn test_karaoke_mugen_export() {
+    let song = Song::from_file("tests/duet.txt").unwrap();
+    let kara = song.to_karaoke_mugen();
+    let json = Value::parse(&kara.kara_json).unwrap();
+    let data = json.get("data").unwrap();
+    assert_eq!(
+        data.get("titles")
+            .and_then(|a| a.get("eng"))
+            .and_then(Value::as_str),
+        Some("Duet Test")
+    );
+    assert_eq!(data.get("kid"), Some(&Value::from(kara_id(&song).as_str())));
+    let singers = data.get("tags").and_then(|a| a.get("singers")).unwrap();
+    assert_eq!(singers.items().len(), 3);
+    assert_eq!(
+        json.get("medias").unwrap().items()[0]
+            .get("filename")
+            .and_then(Value::as_str),
+        Some("duet.mp3")
+    );
+
+    let dialogue = kara
+        .ass
+        .lines()
+        .filter(|a| a.starts_with("Dialogue:"))
+        .collect::<Vec<_>>();
+    assert_eq!(dialogue.len(), 4);
+    // 60000 / (312.5 * 4) = 48 ms per beat
+    assert_eq!(
+        dialogue[0],
+        "Dialogue: 0,0:00:01.20,0:00:01.58,Default,Singer One,0,0,0,karaoke,{\\k19}Hel{\\k19}lo"
+    );
+    assert!(dialogue[2].contains(",Singer Two,"));
+    assert_eq!(timestamp().len(), 24);
+}
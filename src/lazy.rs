@@ -0,0 +1,93 @@
+//! Songs that parse their notes on first use
+//!
+//! Browsing, searching or deduplicating by metadata only needs the headers.
+//! A [`LazySong`] parses those right away and keeps the chart text around,
+//! so the notes are only parsed when something asks for them.
+use crate::{CompatProfile, Note, Song, SongRef};
+use anyhow::Result;
+use std::sync::OnceLock;
+
+/// Song with parsed headers and notes parsed on demand
+#[derive(Debug)]
+pub struct LazySong {
+    /// Headers of the song, with `notes` left empty
+    song: Song,
+    text: String,
+    profile: CompatProfile,
+    notes: OnceLock<Vec<Note>>,
+}
+
+impl LazySong {
+    /// Parse the headers of a chart and keep its notes for later
+    /// ```rust
+    /// use usdx_parser::lazy::LazySong;
+    ///
+    /// let text = std::fs::read_to_string("tests/queen_bohemian_rhapsody.txt").unwrap();
+    /// let song = LazySong::parse(text).unwrap();
+    /// assert_eq!(song.metadata().title, "Bohemian Rhapsody");
+    /// assert!(!song.is_parsed());
+    /// assert!(!song.notes().is_empty());
+    /// assert!(song.is_parsed());
+    /// ```
+    pub fn parse(text: String) -> Result<LazySong> {
+        Self::parse_with(text, CompatProfile::default())
+    }
+
+    /// Like [`LazySong::parse`], using the tolerance rules of a specific game
+    pub fn parse_with(text: String, profile: CompatProfile) -> Result<LazySong> {
+        let song = SongRef::parse_headers(&text, profile)?.to_song();
+        Ok(LazySong {
+            song,
+            text,
+            profile,
+            notes: OnceLock::new(),
+        })
+    }
+
+    /// Read a chart and parse its headers
+    pub fn from_file(path: &str) -> Result<LazySong> {
+        Self::parse(std::fs::read_to_string(path)?)
+    }
+
+    /// The headers, whose `notes` are always empty; use [`LazySong::notes`]
+    pub fn metadata(&self) -> &Song {
+        &self.song
+    }
+
+    /// The notes, parsed the first time this is called
+    pub fn notes(&self) -> &[Note] {
+        self.notes.get_or_init(|| {
+            // The headers parsed before, so parsing again can't fail
+            SongRef::parse_with(&self.text, self.profile)
+                .map(|s| s.notes.iter().map(|n| n.to_note()).collect())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Whether the notes were parsed already
+    pub fn is_parsed(&self) -> bool {
+        self.notes.get().is_some()
+    }
+
+    /// Complete song, parsing the notes if that hasn't happened yet
+    pub fn into_song(self) -> Song {
+        self.notes();
+        let mut song = self.song;
+        song.notes = self.notes.into_inner().unwrap_or_default();
+        song
+    }
+}
+
+#[test]
+pub fn test_lazy_notes() {
+    let text = std::fs::read_to_string("tests/duet.txt").unwrap();
+    let lazy = LazySong::parse(text.clone()).unwrap();
+    assert!(lazy.metadata().notes.is_empty());
+    assert_eq!(lazy.metadata().singer_p2.as_deref(), Some("Singer Two"));
+    assert!(!lazy.is_parsed());
+    assert_eq!(
+        lazy.into_song().to_string(),
+        Song::try_from(text).unwrap().to_string()
+    );
+    assert!(LazySong::parse("#TITLE:No BPM\n".to_string()).is_err());
+}
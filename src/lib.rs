@@ -1,12 +1,151 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 use anyhow::{bail, Result};
-use std::str::FromStr;
+use core::fmt;
+use core::ops::Index;
+use core::str::FromStr;
+
+pub use borrowed::{NoteRef, SongRef};
+pub use compat::CompatProfile;
+pub use limits::ParseLimits;
+pub use options::{EmptyHeaders, ParseOptions};
+
+#[cfg(any(test, feature = "test-support"))]
+pub mod arbitrary;
+#[cfg(feature = "image")]
+pub mod artwork;
+#[cfg(feature = "std")]
+pub mod assets;
+#[cfg(feature = "std")]
+pub mod audacity;
+#[cfg(feature = "audio-analysis")]
+pub mod audio;
+#[cfg(feature = "std")]
+mod binary;
+mod borrowed;
+#[cfg(feature = "cache")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod catalog;
+#[cfg(feature = "std")]
+pub mod compact;
+pub mod compat;
+#[cfg(feature = "std")]
+pub mod convert;
+pub mod cursor;
+pub mod density;
+pub mod duet;
+#[cfg(feature = "std")]
+pub mod duplicates;
+pub mod edit;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "ffmpeg")]
+pub mod ffmpeg;
+#[cfg(feature = "std")]
+pub mod filename;
+#[cfg(feature = "std")]
+pub mod fix;
+#[cfg(feature = "std")]
+pub mod folder;
+#[cfg(feature = "std")]
+pub mod group;
+#[cfg(feature = "hyphenation")]
+pub mod hyphenation;
+pub mod index;
+#[cfg(feature = "std")]
+mod intern;
+#[cfg(feature = "std")]
+mod json;
+#[cfg(feature = "std")]
+pub mod kar;
+#[cfg(feature = "std")]
+pub mod karaoke_mugen;
+#[cfg(feature = "std")]
+pub mod lazy;
+#[cfg(feature = "std")]
+pub mod library;
+pub mod limits;
+#[cfg(feature = "std")]
+pub mod lint;
+pub mod lyrics;
+#[cfg(feature = "std")]
+mod midi;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(feature = "musicbrainz")]
+pub mod musicbrainz;
+pub mod nfc;
+#[cfg(feature = "std")]
+pub mod normalize;
+pub mod options;
+pub mod pauses;
+#[cfg(feature = "std")]
+pub mod playlist;
+pub mod preview;
+#[cfg(feature = "std")]
+pub mod query;
+pub mod realign;
+#[cfg(feature = "std")]
+mod recode;
+#[cfg(feature = "regex")]
+pub mod regex;
+mod replace;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod rockband;
+#[cfg(feature = "romanization")]
+pub mod romanize;
+#[cfg(feature = "scores")]
+pub mod scores;
+pub mod scoring;
+#[cfg(feature = "std")]
+pub mod search;
+#[cfg(feature = "std")]
+pub mod singstar;
+#[cfg(feature = "std")]
+pub mod sort;
+#[cfg(feature = "spellcheck")]
+pub mod spellcheck;
+#[cfg(feature = "scores")]
+mod sqlite;
+#[cfg(feature = "tags")]
+pub mod tags;
+#[cfg(feature = "async")]
+mod task;
+#[cfg(feature = "std")]
+pub mod text;
+#[cfg(feature = "std")]
+pub mod textgrid;
+pub mod timeline;
+#[cfg(feature = "audio-analysis")]
+pub mod transcribe;
+pub mod transform;
+pub mod typography;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "std")]
+mod xml;
 
 /// Song information
+///
+/// Clones share the lyrics of the notes, so copies kept as undo snapshots
+/// cost one vector of notes rather than an allocation per syllable.
 #[derive(Debug, Clone)]
 pub struct Song {
     pub artist: Option<String>,
     pub title: String,
-    /// Path to the audio file
+    /// Path to the audio file, from `#MP3` or else `#AUDIO`
     pub mp3: Option<String>,
     pub video: Option<String>,
     pub edition: Option<String>,
@@ -17,9 +156,102 @@ pub struct Song {
     pub bpm: f32,
     /// Delay in ms before the lyrics start after song
     pub gap: u32,
-    pub video_gap: Option<u32>,
+    /// Seconds into the video at which the audio starts
+    pub video_gap: Option<f32>,
+    /// Second of the audio singing starts from, skipping an intro
+    pub start: Option<f32>,
+    /// Second of the audio song selection plays as a preview
+    pub preview_start: Option<f32>,
+    /// Path to the cover image
+    pub cover: Option<String>,
+    /// Path to the background image
+    pub background: Option<String>,
+    /// Name of the first duet singer
+    pub singer_p1: Option<String>,
+    /// Name of the second duet singer
+    pub singer_p2: Option<String>,
     /// All notes with lyrics
     pub notes: Vec<Note>,
+    /// Order the headers were read in, which serializing keeps; headers
+    /// missing here are written after these in the default order
+    pub header_order: Vec<Header>,
+    /// Headers without a field of their own, like `#CREATOR` or `#RELATIVE`,
+    /// as written with the number of [`Song::header_order`] entries before them
+    pub other_headers: Vec<(usize, String, String)>,
+    /// Whether the chart counted beats from each line break (`#RELATIVE:yes`);
+    /// `notes` always hold absolute beats
+    pub relative: bool,
+    /// For relative charts, the beat each line of notes counted from, in file order
+    pub line_offsets: Vec<u32>,
+}
+
+/// Header of a song, in the order they are written by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Header {
+    Artist,
+    Title,
+    Mp3,
+    Edition,
+    Genre,
+    Year,
+    Language,
+    Bpm,
+    Gap,
+    Video,
+    VideoGap,
+    Start,
+    PreviewStart,
+    Cover,
+    Background,
+    SingerP1,
+    SingerP2,
+}
+
+impl Header {
+    /// Every header in the default order
+    pub const ALL: [Header; 17] = [
+        Self::Artist,
+        Self::Title,
+        Self::Mp3,
+        Self::Edition,
+        Self::Genre,
+        Self::Year,
+        Self::Language,
+        Self::Bpm,
+        Self::Gap,
+        Self::Video,
+        Self::VideoGap,
+        Self::Start,
+        Self::PreviewStart,
+        Self::Cover,
+        Self::Background,
+        Self::SingerP1,
+        Self::SingerP2,
+    ];
+
+    /// Tag written for the header by `profile`
+    pub fn tag(self, profile: CompatProfile) -> &'static str {
+        let [tag_p1, tag_p2] = profile.duet_singer_tags();
+        match self {
+            Self::Artist => "ARTIST",
+            Self::Title => "TITLE",
+            Self::Mp3 => "MP3",
+            Self::Edition => "EDITION",
+            Self::Genre => "GENRE",
+            Self::Year => "YEAR",
+            Self::Language => "LANGUAGE",
+            Self::Bpm => "BPM",
+            Self::Gap => "GAP",
+            Self::Video => "VIDEO",
+            Self::VideoGap => "VIDEOGAP",
+            Self::Start => "START",
+            Self::PreviewStart => "PREVIEWSTART",
+            Self::Cover => "COVER",
+            Self::Background => "BACKGROUND",
+            Self::SingerP1 => tag_p1,
+            Self::SingerP2 => tag_p2,
+        }
+    }
 }
 
 impl TryFrom<String> for Song {
@@ -41,193 +273,284 @@ impl TryFrom<String> for Song {
     /// assert!(song.is_ok());
     /// ```
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let lines = value.lines().map(|a| a.trim_start());
-        let artist = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#ARTIST:"))
-            .map(|a| a.to_string())
-            .next();
-        let title = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#TITLE:"))
-            .map(|a| a.to_string())
-            .next();
-        let mp3 = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#MP3:"))
-            .map(|a| a.to_string())
-            .next();
-        let video = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#VIDEO:"))
-            .map(|a| a.to_string())
-            .next();
-        let edition = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#EDITION:"))
-            .map(|a| a.to_string())
-            .next();
-        let genre = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#GENRE:"))
-            .map(|a| a.to_string())
-            .next();
-        let year = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#YEAR:"))
-            .map(|a| a.to_string())
-            .next();
-        let language = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#LANGUAGE:"))
-            .map(|a| a.to_string())
-            .next();
-        let bpm = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#BPM:"))
-            .map(|a| a.to_string())
-            .next();
-        let gap = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#GAP:"))
-            .map(|a| a.to_string())
-            .next();
-        let video_gap = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#VIDEOGAP:"))
-            .map(|a| a.to_string())
-            .next();
-        let relative = lines
-            .clone()
-            .filter_map(|l| l.strip_prefix("#RELATIVE:"))
-            .map(|a| a.to_string())
-            .next()
-            .unwrap_or("no".to_string());
-        let relative = parse_yes_no(&relative);
-        let mut counter = 0;
-        let notes = lines
-            .filter(|a| !(a.starts_with('#') || a.starts_with('E') || a.is_empty()))
-            .filter_map(|a| Note::try_from(a).ok())
-            .map(|mut note| {
-                if relative {
-                    if let Some(offset) = note.update_offset() {
-                        note.offset(counter);
-                        counter += offset;
-                    } else {
-                        note.offset(counter);
-                    }
-                }
-                note
-            })
-            .collect::<Vec<_>>();
+        Song::from_str_with(&value, CompatProfile::default())
+    }
+}
 
-        let title = if let Some(a) = title {
-            a
-        } else {
-            bail!("No title specified!");
-        };
+impl fmt::Display for Song {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_with(CompatProfile::default()))
+    }
+}
 
-        let bpm = if let Some(a) = bpm {
-            let a = a.replace(',', ".");
-            if let Ok(a) = a.parse::<f32>() {
-                a
-            } else {
-                bail!("BPM specified failed to be parsed!");
-            }
-        } else {
-            bail!("No bpm specified!");
-        };
+impl Song {
+    /// Creates an empty song with only the required headers set
+    pub fn new(title: &str, bpm: f32, gap: u32) -> Self {
+        Self {
+            artist: None,
+            title: title.to_string(),
+            mp3: None,
+            video: None,
+            edition: None,
+            genre: None,
+            year: None,
+            language: None,
+            bpm,
+            gap,
+            video_gap: None,
+            start: None,
+            preview_start: None,
+            cover: None,
+            background: None,
+            singer_p1: None,
+            singer_p2: None,
+            notes: vec![],
+            header_order: vec![],
+            other_headers: vec![],
+            relative: false,
+            line_offsets: vec![],
+        }
+    }
 
-        let gap = if let Some(a) = gap {
-            a.parse::<u32>()?
-        } else {
-            bail!("No gap specified!");
-        };
+    /// Parse song from file
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/i_hate_everything_about_you.txt");
+    /// assert!(song.is_ok());
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn from_file(path: &str) -> Result<Song> {
+        let string = std::fs::read_to_string(path)?;
+        Song::try_from(string)
+    }
 
-        let video_gap = if let Some(a) = video_gap {
-            Some(a.parse::<u32>()?)
-        } else {
-            None
-        };
+    /// Parse song text using the tolerance rules of a specific game
+    /// ```rust
+    /// use usdx_parser::{CompatProfile, Song};
+    ///
+    /// let text = "#title:Lowercase\n#bpm:100\n#gap:0\n";
+    /// assert!(Song::from_str_with(text, CompatProfile::Performous).is_ok());
+    /// assert!(Song::from_str_with(text, CompatProfile::WorldParty).is_err());
+    /// ```
+    pub fn from_str_with(value: &str, profile: CompatProfile) -> Result<Song> {
+        Ok(SongRef::parse_with(value, profile)?.to_song())
+    }
 
-        Ok(Self {
-            artist,
-            title,
-            mp3,
-            video,
-            edition,
-            genre,
-            year,
-            language,
-            bpm,
-            gap,
-            video_gap,
-            notes,
-        })
+    /// Parse song text from an untrusted source, failing once it exceeds `limits`
+    pub fn from_str_limited(
+        value: &str,
+        profile: CompatProfile,
+        limits: &ParseLimits,
+    ) -> Result<Song> {
+        Ok(SongRef::parse_limited(value, profile, limits)?.to_song())
     }
-}
 
-fn parse_yes_no(input: &str) -> bool {
-    match input {
-        "yes" | "true" => true,
-        "no" | "false" => false,
-        _ => unimplemented!(),
+    /// Parse song text within `limits`, reading it as `options` say
+    /// ```rust
+    /// use usdx_parser::{CompatProfile, ParseLimits, ParseOptions, Song};
+    ///
+    /// let options = ParseOptions {
+    ///     strict: true,
+    ///     ..Default::default()
+    /// };
+    /// let text = "#TITLE:T\n#BPM:100\n: 0 4 0 a\n";
+    /// let limits = ParseLimits::unlimited();
+    /// assert!(Song::from_str_with_options(text, CompatProfile::default(), &limits, &options).is_err());
+    /// ```
+    pub fn from_str_with_options(
+        value: &str,
+        profile: CompatProfile,
+        limits: &ParseLimits,
+        options: &ParseOptions,
+    ) -> Result<Song> {
+        let mut song = SongRef::parse_with_options(value, profile, limits, options)?.to_song();
+        if options.normalize_nfc {
+            song.normalize_nfc();
+        }
+        Ok(song)
+    }
+
+    /// Parse song from file using the tolerance rules of a specific game
+    #[cfg(feature = "std")]
+    pub fn from_file_with(path: &str, profile: CompatProfile) -> Result<Song> {
+        let string = std::fs::read_to_string(path)?;
+        Song::from_str_with(&string, profile)
+    }
+
+    /// Header line of the song, `None` when it's unset
+    fn header_line(&self, header: Header, profile: CompatProfile) -> Option<String> {
+        let decimal = |a: f32| {
+            a.to_string()
+                .replace('.', &profile.decimal_separator().to_string())
+        };
+        let value = match header {
+            Header::Artist => self.artist.clone()?,
+            Header::Title => self.title.clone(),
+            Header::Mp3 => self.mp3.clone()?,
+            Header::Edition => self.edition.clone()?,
+            Header::Genre => self.genre.clone()?,
+            Header::Year => self.year.clone()?,
+            Header::Language => self.language.clone()?,
+            Header::Bpm => decimal(self.bpm),
+            Header::Gap => self.gap.to_string(),
+            Header::Video => self.video.clone()?,
+            Header::VideoGap => decimal(self.video_gap?),
+            Header::Start => decimal(self.start?),
+            Header::PreviewStart => decimal(self.preview_start?),
+            Header::Cover => self.cover.clone()?,
+            Header::Background => self.background.clone()?,
+            Header::SingerP1 => self.singer_p1.clone()?,
+            Header::SingerP2 => self.singer_p2.clone()?,
+        };
+        Some(format!("#{}:{}\n", header.tag(profile), value))
+    }
+
+    /// Serialize the song with the output quirks of a specific game
+    ///
+    /// Headers keep the order of [`Song::header_order`], with the
+    /// [`Song::other_headers`] back where they were read.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:Song\n#CREATOR:me\n#BPM:100\n#ARTIST:Band\n#GAP:0\nE\n";
+    /// let song: Song = text.parse().unwrap();
+    /// assert_eq!(song.to_string(), text);
+    /// ```
+    pub fn to_string_with(&self, profile: CompatProfile) -> String {
+        self.chart_text(profile, false)
     }
-}
 
-impl ToString for Song {
-    fn to_string(&self) -> String {
+    /// Chart text with `#RELATIVE:yes` if `relative`, which the beats then
+    /// have to be counted for
+    pub(crate) fn chart_text(&self, profile: CompatProfile, relative: bool) -> String {
         let mut ret = String::new();
-        if let Some(artist) = self.artist.as_ref() {
-            ret.push_str(&format!("#ARTIST:{}\n", artist));
-        }
-        ret.push_str(&format!("#TITLE:{}\n", self.title));
-        if let Some(mp3) = self.mp3.as_ref() {
-            ret.push_str(&format!("#MP3:{}\n", mp3));
-        }
-        if let Some(edition) = self.edition.as_ref() {
-            ret.push_str(&format!("#EDITION:{}\n", edition));
-        }
-        if let Some(genre) = self.genre.as_ref() {
-            ret.push_str(&format!("#GENRE:{}\n", genre));
-        }
-        if let Some(year) = self.year.as_ref() {
-            ret.push_str(&format!("#YEAR:{}\n", year));
+        let is_relative = |tag: &str| tag.eq_ignore_ascii_case("RELATIVE");
+        // Kept where it was read, or else written after `#GAP`
+        let mut relative_pending = relative;
+        let recorded = self.other_headers.iter().any(|a| is_relative(&a.1));
+        let mut other = |ret: &mut String, tag: &str, value: &str| {
+            if !is_relative(tag) {
+                ret.push_str(&format!("#{}:{}\n", tag, value));
+            } else if relative_pending {
+                relative_pending = false;
+                match value.trim() {
+                    "yes" | "true" => ret.push_str(&format!("#{}:{}\n", tag, value)),
+                    _ => ret.push_str("#RELATIVE:yes\n"),
+                }
+            }
+        };
+        let rest = Header::ALL
+            .into_iter()
+            .filter(|h| !self.header_order.contains(h));
+        let mut written = vec![];
+        for (i, header) in self.header_order.iter().copied().enumerate() {
+            for (_, tag, value) in self.other_headers.iter().filter(|a| a.0 == i) {
+                other(&mut ret, tag, value);
+            }
+            if written.contains(&header) {
+                continue;
+            }
+            written.push(header);
+            if let Some(line) = self.header_line(header, profile) {
+                ret.push_str(&line);
+            }
+            if header == Header::Gap && relative && !recorded {
+                ret.push_str("#RELATIVE:yes\n");
+            }
         }
-        if let Some(language) = self.language.as_ref() {
-            ret.push_str(&format!("#LANGUAGE:{}\n", language));
+        let count = self.header_order.len();
+        for (_, tag, value) in self.other_headers.iter().filter(|a| a.0 >= count) {
+            other(&mut ret, tag, value);
         }
-        ret.push_str(&format!(
-            "#BPM:{}\n",
-            self.bpm.to_string().replace('.', ",")
-        ));
-        ret.push_str(&format!("#GAP:{}\n", self.gap));
-        if let Some(video) = self.video.as_ref() {
-            ret.push_str(&format!("#VIDEO:{}\n", video));
+        for header in rest {
+            if let Some(line) = self.header_line(header, profile) {
+                ret.push_str(&line);
+            }
+            if header == Header::Gap && relative && !recorded {
+                ret.push_str("#RELATIVE:yes\n");
+            }
         }
-        if let Some(video_gap) = self.video_gap.as_ref() {
-            ret.push_str(&format!("#VIDEOGAP:{}\n", video_gap));
+        if self.notes.iter().any(|n| n.second_lyric.is_some()) {
+            // One header per sentence, its syllables separated by `|`
+            let numbers =
+                borrowed::sentence_numbers(self.notes.iter().map(|n| (&n.note_type, n.voice)));
+            let mut lines: Vec<Vec<String>> = vec![];
+            for (note, number) in self.notes.iter().zip(numbers) {
+                let Some(i) = number else {
+                    continue;
+                };
+                if lines.len() <= i {
+                    lines.push(vec![]);
+                }
+                let lyric = note.second_lyric.as_deref().unwrap_or_default();
+                lines[i].push(lyric.replace('|', "/"));
+            }
+            for line in lines {
+                ret.push_str(&format!("#LYRICS2:{}\n", line.join("|")));
+            }
         }
+        let mut voice = None;
         for n in self.notes.iter() {
+            if n.voice != voice {
+                if let Some(v) = n.voice {
+                    ret.push_str(&format!("{}\n", v));
+                }
+                voice = n.voice;
+            }
             ret.push_str(&n.to_string());
             ret.push('\n');
         }
         ret.push_str("E\n");
         ret
     }
-}
 
-impl Song {
-    /// Parse song from file
+    /// Converts a beat number to milliseconds from the start of the audio
+    ///
+    /// USDX beats are quarter notes of the `#BPM` value, so one beat lasts
+    /// `60000 / (bpm * 4)` ms and beat 0 happens at `#GAP`.
+    pub fn beat_to_ms(&self, beat: f64) -> f64 {
+        self.gap as f64 + beat * 15000.0 / self.bpm as f64
+    }
+
+    /// Converts milliseconds from the start of the audio to a (fractional) beat number
+    pub fn ms_to_beat(&self, ms: f64) -> f64 {
+        (ms - self.gap as f64) * self.bpm as f64 / 15000.0
+    }
+
+    /// Whether the notes are split between two singers
     /// ```rust
     /// use usdx_parser::Song;
     ///
-    /// let song = Song::from_file("tests/i_hate_everything_about_you.txt");
-    /// assert!(song.is_ok());
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// assert!(song.is_duet());
+    /// assert!(!song.has_cover());
     /// ```
-    pub fn from_file(path: &str) -> Result<Song> {
-        let string = std::fs::read_to_string(path)?;
-        Song::try_from(string)
+    pub fn is_duet(&self) -> bool {
+        self.notes.iter().any(|n| n.voice.is_some())
+    }
+
+    pub fn has_video(&self) -> bool {
+        self.video.is_some()
+    }
+
+    pub fn has_cover(&self) -> bool {
+        self.cover.is_some()
+    }
+
+    /// Whether the chart was read with `#RELATIVE:yes`
+    pub fn is_relative(&self) -> bool {
+        self.relative
+    }
+
+    /// Whether every sung note is freestyle, so there is nothing to score
+    pub fn is_freestyle_only(&self) -> bool {
+        let mut sung = self
+            .notes
+            .iter()
+            .filter(|n| n.note_type != NoteType::LineBreak)
+            .peekable();
+        sung.peek().is_some() && sung.all(|n| n.note_type == NoteType::Freestyle)
     }
 }
 
@@ -247,8 +570,40 @@ impl FromStr for Song {
     }
 }
 
+/// Iterates over the notes, line breaks included
+/// ```rust
+/// use usdx_parser::{NoteType, Song};
+///
+/// let song = Song::from_file("tests/duet.txt").unwrap();
+/// let golden = (&song).into_iter().filter(|n| n.note_type == NoteType::Golden);
+/// assert_eq!(golden.count(), 1);
+/// assert_eq!(song[0].lyric.as_deref(), Some("Hel"));
+/// ```
+impl<'a> IntoIterator for &'a Song {
+    type Item = &'a Note;
+    type IntoIter = core::slice::Iter<'a, Note>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.notes.iter()
+    }
+}
+
+impl Index<usize> for Song {
+    type Output = Note;
+
+    fn index(&self, i: usize) -> &Note {
+        &self.notes[i]
+    }
+}
+
+impl Extend<Note> for Song {
+    fn extend<T: IntoIterator<Item = Note>>(&mut self, iter: T) {
+        self.notes.extend(iter);
+    }
+}
+
 /// Note information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Note {
     pub note_type: NoteType,
     /// Number of beats after start of the song when this note happens
@@ -256,15 +611,56 @@ pub struct Note {
     /// Number of beats this note lasts
     pub note_length: Option<u32>,
     pub note_tone: Option<i32>,
-    /// String content for this note
-    pub lyric: Option<String>,
+    /// String content for this note, shared between clones of the note
+    pub lyric: Option<Arc<str>>,
+    /// Lyric of a second layer such as a transliteration or translation,
+    /// written to `#LYRICS2` headers
+    pub second_lyric: Option<Arc<str>>,
+    /// Duet singer of this note, `None` for solo songs
+    pub voice: Option<Voice>,
+    /// For line breaks written as `- <end> <start>`, the beat the next line starts at
+    pub line_start: Option<u32>,
 }
 
 impl Note {
+    /// Creates a sung note
+    pub fn new(
+        note_type: NoteType,
+        beat_number: u32,
+        note_length: u32,
+        note_tone: i32,
+        lyric: &str,
+    ) -> Self {
+        Self {
+            note_type,
+            beat_number,
+            note_length: Some(note_length),
+            note_tone: Some(note_tone),
+            lyric: Some(lyric.into()),
+            second_lyric: None,
+            voice: None,
+            line_start: None,
+        }
+    }
+
+    /// Creates a line break at `beat_number`
+    pub fn line_break(beat_number: u32) -> Self {
+        Self {
+            note_type: NoteType::LineBreak,
+            beat_number,
+            note_length: None,
+            note_tone: None,
+            lyric: None,
+            second_lyric: None,
+            voice: None,
+            line_start: None,
+        }
+    }
+
     // Updates the offset if the note is LineBreak
     pub fn update_offset(&self) -> Option<u32> {
         if self.note_type == NoteType::LineBreak {
-            Some(self.beat_number)
+            Some(self.line_start.unwrap_or(self.beat_number))
         } else {
             None
         }
@@ -274,6 +670,7 @@ impl Note {
     // Used for relative lyrics
     pub fn offset(&mut self, n: u32) {
         self.beat_number += n;
+        self.line_start = self.line_start.map(|a| a + n);
     }
 }
 
@@ -281,34 +678,21 @@ impl TryFrom<&str> for Note {
     type Error = anyhow::Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let mut splot = value.split(' ');
-        let note_type = splot.next().unwrap().try_into()?;
-        let beat_number = splot.next().unwrap().parse::<u32>()?;
-        let (note_length, note_tone, lyric) = if note_type == NoteType::LineBreak {
-            (None, None, None)
-        } else {
-            let note_length = splot.next().unwrap().parse::<u32>()?;
-            let note_tone = splot.next().unwrap().parse::<i32>()?;
-            let lyric = splot.collect::<Vec<_>>().join(" ");
-            (Some(note_length), Some(note_tone), Some(lyric))
-        };
-        Ok(Self {
-            note_type,
-            beat_number,
-            note_length,
-            note_tone,
-            lyric,
-        })
+        Ok(NoteRef::try_from(value)?.to_note())
     }
 }
 
-impl ToString for Note {
-    fn to_string(&self) -> String {
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.note_type {
-            NoteType::LineBreak => format!("{} {}", self.note_type.to_string(), self.beat_number),
-            _ => format!(
+            NoteType::LineBreak => match self.line_start {
+                Some(start) => write!(f, "{} {} {}", self.note_type, self.beat_number, start),
+                None => write!(f, "{} {}", self.note_type, self.beat_number),
+            },
+            _ => write!(
+                f,
                 "{} {} {} {} {}",
-                self.note_type.to_string(),
+                self.note_type,
                 self.beat_number,
                 self.note_length.unwrap(),
                 self.note_tone.unwrap(),
@@ -341,15 +725,45 @@ impl TryFrom<&str> for NoteType {
     }
 }
 
-impl ToString for NoteType {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for NoteType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             Self::Normal => ":",
             Self::Golden => "*",
             Self::Freestyle => "F",
             Self::LineBreak => "-",
+        })
+    }
+}
+
+/// Singer of a note in a duet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Voice {
+    P1,
+    P2,
+    /// Sung by both singers, marked `P3`
+    Both,
+}
+
+impl Voice {
+    /// Parses a voice marker line like `P1` or `P 1`
+    pub fn from_marker(line: &str) -> Option<Voice> {
+        match line.strip_prefix('P')?.trim() {
+            "1" => Some(Self::P1),
+            "2" => Some(Self::P2),
+            "3" => Some(Self::Both),
+            _ => None,
         }
-        .to_string()
+    }
+}
+
+impl fmt::Display for Voice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::P1 => "P1",
+            Self::P2 => "P2",
+            Self::Both => "P3",
+        })
     }
 }
 
@@ -369,5 +783,139 @@ pub fn test_manual_serde_relative() {
     assert!(song.is_ok());
     let song = song.unwrap();
     // dbg!(song);
-    println!("{}", song.to_string());
+    println!("{}", song);
+    assert!(song.relative);
+    let breaks = song
+        .notes
+        .iter()
+        .filter(|n| n.note_type == NoteType::LineBreak)
+        .count();
+    assert_eq!(song.line_offsets.len(), breaks + 1);
+    assert_eq!(song.line_offsets[0], 0);
+    assert!(song.line_offsets.windows(2).all(|w| w[0] <= w[1]));
+    assert!(!Song::from_file("tests/duet.txt").unwrap().relative);
+
+    // Unknown headers like `#CREATOR` stay where they were
+    let written = song.to_relative_string_with(CompatProfile::default());
+    assert_eq!(written.replace('\n', "\r\n").as_bytes(), text.as_bytes());
+    assert!(song.to_string().contains("#YEAR:2016\n#CREATOR:aac\n#MP3:"));
+    assert!(!song.to_string().contains("#RELATIVE"));
+}
+
+#[test]
+pub fn test_two_beat_line_breaks() {
+    let text = "#TITLE:T\n#BPM:100\n#GAP:0\n#RELATIVE:yes\n: 0 4 0 a\n- 4 6\n: 0 2 0 b\nE\n";
+    let song = Song::from_str(text).unwrap();
+    assert_eq!(song.notes[1].line_start, Some(6));
+    assert_eq!(song.notes[2].beat_number, 6);
+    let absolute = "#TITLE:T\n#BPM:100\n#GAP:0\n: 0 4 0 a\n- 4 6\n: 6 2 0 b\nE\n";
+    assert_eq!(song.to_string(), absolute);
+    let relative = song.to_relative_string_with(CompatProfile::default());
+    assert!(relative.contains("- 4 6\n: 0 2 0 b\n"));
+    assert!(Song::from_str("#TITLE:T\n#BPM:100\n#GAP:0\n- 4 x\n").is_ok());
+}
+
+#[test]
+pub fn test_second_lyrics() {
+    let text = "#TITLE:T\n#BPM:100\n#GAP:0\n#LYRICS2:ko|n'ni|chi|wa\n#LYRICS2:| two\n#LYRICS2:p2\n\
+        P1\n: 0 1 0 こ\n: 1 1 0 ん\n: 2 1 0 に\n: 3 1 0 ちは\n- 5\n: 6 1 0 a\n: 7 1 0  b\nP2\n: 8 1 0 c\nE\n";
+    let song = Song::from_str(text).unwrap();
+    let second: Vec<_> = song
+        .notes
+        .iter()
+        .map(|n| n.second_lyric.as_deref())
+        .collect();
+    assert_eq!(
+        second,
+        [
+            Some("ko"),
+            Some("n'ni"),
+            Some("chi"),
+            Some("wa"),
+            None,
+            None,
+            Some(" two"),
+            Some("p2")
+        ]
+    );
+    assert_eq!(song.to_string(), text);
+    assert_eq!(Song::from_json(&song.to_json()).unwrap().to_string(), text);
+    // Charts without the headers don't get any
+    assert!(!Song::from_file("tests/duet.txt")
+        .unwrap()
+        .to_string()
+        .contains("#LYRICS2"));
+}
+
+#[test]
+pub fn test_header_dispatch() {
+    let text = "#TITLE:First\n#AUDIO:song.ogg\n#TITLE:Second\n#BPM:100\n: 0 4 0 La\n#GAP:20\n#ARTISTS:Nobody\nE\n";
+    let song = Song::from_str(text).unwrap();
+    assert_eq!(song.title, "First");
+    assert_eq!(song.gap, 20);
+    assert_eq!(song.artist, None);
+    assert_eq!(song.notes.len(), 1);
+    let mp3 = Song::from_str_with(text, CompatProfile::Performous)
+        .unwrap()
+        .mp3;
+    assert_eq!(mp3.as_deref(), Some("song.ogg"));
+    let song = Song::from_str_with(text, CompatProfile::WorldParty).unwrap();
+    assert_eq!(song.mp3, None);
+    assert!(song.to_string().contains("#AUDIO:song.ogg\n"));
+    let both = "#TITLE:T\n#AUDIO:new.ogg\n#MP3:old.mp3\n#BPM:100\n#GAP:0\nE\n";
+    assert_eq!(
+        Song::from_str(both).unwrap().mp3.as_deref(),
+        Some("old.mp3")
+    );
+}
+
+#[test]
+pub fn test_song_predicates() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    assert!(!song.is_duet());
+    assert!(song.has_video());
+    assert!(!song.is_relative());
+    assert!(!song.is_freestyle_only());
+    assert!(Song::from_file("tests/please_tell_rosie.txt")
+        .unwrap()
+        .is_relative());
+    let mut song = Song::new("Free", 100.0, 0);
+    assert!(!song.is_freestyle_only());
+    song.notes
+        .push(Note::new(NoteType::Freestyle, 0, 4, 0, "la"));
+    song.notes.push(Note::line_break(6));
+    assert!(song.is_freestyle_only());
+}
+
+#[test]
+pub fn test_song_collection_traits() {
+    let mut song = Song::new("Traits", 100.0, 0);
+    song.extend([
+        Note::new(NoteType::Normal, 0, 2, 0, "a"),
+        Note::line_break(3),
+    ]);
+    song.extend(vec![Note::new(NoteType::Golden, 4, 2, 0, "b")]);
+    assert_eq!(song[2].beat_number, 4);
+    let beats: Vec<u32> = (&song).into_iter().map(|n| n.beat_number).collect();
+    assert_eq!(beats, [0, 3, 4]);
+    let mut count = 0;
+    for _ in &song {
+        count += 1;
+    }
+    assert_eq!(count, 3);
+}
+
+#[test]
+pub fn test_clone_shares_lyrics() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let copy = song.clone();
+    for (a, b) in song.notes.iter().zip(copy.notes.iter()) {
+        match (&a.lyric, &b.lyric) {
+            (Some(a), Some(b)) => assert!(Arc::ptr_eq(a, b)),
+            (a, b) => assert_eq!(a, b),
+        }
+    }
+    let mut edited = copy.clone();
+    edited.notes[0].lyric = Some("Was".into());
+    assert_eq!(copy.notes[0].lyric.as_deref(), Some("Is"));
 }
@@ -0,0 +1,590 @@
+//! Song library scanning
+//!
+//! Walks a songs directory the way the games do: every `.txt` file below the
+//! root is a chart candidate, files that don't start with a header (readmes,
+//! lyrics dumps) are skipped and parse failures are collected per file instead
+//! of aborting the scan.
+use crate::binary::{read_song, write_song, Reader, Writer};
+pub use crate::intern::{Interner, SongMetadata};
+use crate::Song;
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Parsed song and the chart file it came from
+#[derive(Debug, Clone)]
+pub struct SongEntry {
+    pub path: PathBuf,
+    pub song: Song,
+}
+
+/// Chart file that could not be read or parsed
+#[derive(Debug)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+/// Summary of a scanned library
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LibraryStats {
+    /// `.txt` files found below the root
+    pub files: usize,
+    pub songs: usize,
+    /// Files that were not charts
+    pub skipped: usize,
+    pub errors: usize,
+    pub duets: usize,
+    /// Sung notes of all songs, line breaks excluded
+    pub notes: usize,
+}
+
+/// Every song found below a songs directory
+#[derive(Debug, Default)]
+pub struct SongLibrary {
+    pub root: PathBuf,
+    /// Songs sorted by path
+    pub songs: Vec<SongEntry>,
+    pub errors: Vec<ScanError>,
+    /// `.txt` files that were not charts
+    pub skipped: Vec<PathBuf>,
+    /// State of every file the last scan read, used to skip unchanged files
+    pub index: BTreeMap<PathBuf, FileStamp>,
+}
+
+/// Identifies the version of a file that was scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+    /// 64 bit FNV-1a hash of the contents
+    pub hash: u64,
+}
+
+/// What a [`SongLibrary::rescan`] found, counted in files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RescanSummary {
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+const INDEX_MAGIC: &[u8] = b"USDXIDX\0";
+const INDEX_VERSION: u64 = 8;
+
+enum IndexedFile<'a> {
+    Song(&'a Song),
+    Skipped,
+    Failed(String),
+}
+
+/// Files touched by a rescan
+#[cfg_attr(not(feature = "watch"), allow(dead_code))]
+pub(crate) struct Changes {
+    pub summary: RescanSummary,
+    pub added: Vec<PathBuf>,
+    pub changed: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+}
+
+/// Result of loading one chart candidate
+pub(crate) enum Loaded {
+    Song(Box<Song>),
+    Skipped,
+    Failed(anyhow::Error),
+}
+
+impl SongLibrary {
+    /// Scan `root` recursively and parse every chart
+    ///
+    /// With the `parallel` feature the files are parsed on all available cores.
+    /// ```rust
+    /// use usdx_parser::library::SongLibrary;
+    ///
+    /// let library = SongLibrary::scan("tests/library").unwrap();
+    /// let stats = library.stats();
+    /// assert_eq!(stats.songs, 2);
+    /// assert_eq!(stats.errors, 1);
+    /// ```
+    pub fn scan(root: &str) -> Result<SongLibrary> {
+        #[cfg(feature = "parallel")]
+        let threads = std::thread::available_parallelism().map_or(1, |a| a.get());
+        #[cfg(not(feature = "parallel"))]
+        let threads = 1;
+        SongLibrary::scan_with_threads(root, threads)
+    }
+
+    /// Scan `root` reading and parsing files on `threads` worker threads
+    ///
+    /// The result is identical to a sequential scan, including its order.
+    #[cfg(feature = "parallel")]
+    pub fn scan_parallel(root: &str, threads: usize) -> Result<SongLibrary> {
+        SongLibrary::scan_with_threads(root, threads)
+    }
+
+    pub(crate) fn scan_with_threads(root: &str, threads: usize) -> Result<SongLibrary> {
+        let files = chart_candidates(Path::new(root))?;
+        let mut library = SongLibrary {
+            root: PathBuf::from(root),
+            ..Default::default()
+        };
+        let results = load_all(&files, threads);
+        for (path, (loaded, stamp)) in files.into_iter().zip(results) {
+            library.add(path, loaded, stamp);
+        }
+        Ok(library)
+    }
+
+    /// Files are added in path order, which keeps `songs` sorted
+    pub(crate) fn add(&mut self, path: PathBuf, loaded: Loaded, stamp: Option<FileStamp>) {
+        if let Some(stamp) = stamp {
+            self.index.insert(path.clone(), stamp);
+        }
+        match loaded {
+            Loaded::Song(song) => self.songs.push(SongEntry { path, song: *song }),
+            Loaded::Skipped => self.skipped.push(path),
+            Loaded::Failed(error) => self.errors.push(ScanError { path, error }),
+        }
+    }
+
+    /// Drop everything known about `path`
+    pub(crate) fn remove(&mut self, path: &Path) {
+        self.index.remove(path);
+        self.songs.retain(|e| e.path != path);
+        self.errors.retain(|e| e.path != path);
+        self.skipped.retain(|e| e != path);
+    }
+
+    /// Bring the library up to date with the files below its root
+    ///
+    /// Files whose size and modification time are unchanged are not read at all,
+    /// and files that were touched without changing their content are not parsed
+    /// again.
+    /// ```rust
+    /// use usdx_parser::library::SongLibrary;
+    ///
+    /// let mut library = SongLibrary::scan("tests/library").unwrap();
+    /// let changes = library.rescan().unwrap();
+    /// assert_eq!(changes.unchanged, 4);
+    /// ```
+    pub fn rescan(&mut self) -> Result<RescanSummary> {
+        Ok(self.rescan_changes()?.summary)
+    }
+
+    /// Rescan and report which files were added, changed or removed
+    pub(crate) fn rescan_changes(&mut self) -> Result<Changes> {
+        #[cfg(feature = "parallel")]
+        let threads = std::thread::available_parallelism().map_or(1, |a| a.get());
+        #[cfg(not(feature = "parallel"))]
+        let threads = 1;
+
+        let files = chart_candidates(&self.root)?;
+        let mut summary = RescanSummary::default();
+        let mut added = vec![];
+        // Files that couldn't be read have no stamp but an error entry
+        let mut removed = self
+            .index
+            .keys()
+            .chain(self.errors.iter().map(|e| &e.path))
+            .filter(|a| files.binary_search(a).is_err())
+            .cloned()
+            .collect::<Vec<_>>();
+        removed.sort();
+        removed.dedup();
+        for path in removed.iter() {
+            self.remove(path);
+        }
+        summary.removed = removed.len();
+
+        let mut changed = vec![];
+        for path in files {
+            let Some(stamp) = self.index.get(&path) else {
+                summary.added += 1;
+                added.push(path);
+                continue;
+            };
+            let metadata = std::fs::metadata(&path).ok();
+            let modified = metadata.as_ref().and_then(|a| a.modified().ok());
+            if metadata.is_some_and(|a| a.len() == stamp.len) && modified == stamp.modified {
+                summary.unchanged += 1;
+                continue;
+            }
+            match std::fs::read(&path) {
+                Ok(data) if content_hash(&data) == stamp.hash => {
+                    summary.unchanged += 1;
+                    if let Some(stamp) = self.index.get_mut(&path) {
+                        stamp.modified = modified;
+                    }
+                }
+                _ => {
+                    summary.changed += 1;
+                    changed.push(path);
+                }
+            }
+        }
+
+        let reload = added
+            .iter()
+            .chain(changed.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        let results = load_all(&reload, threads);
+        for (path, (loaded, stamp)) in reload.into_iter().zip(results) {
+            self.remove(&path);
+            self.add(path, loaded, stamp);
+        }
+        self.songs.sort_by(|a, b| a.path.cmp(&b.path));
+        self.errors.sort_by(|a, b| a.path.cmp(&b.path));
+        self.skipped.sort();
+        Ok(Changes {
+            summary,
+            added,
+            changed,
+            removed,
+        })
+    }
+
+    /// Serialize the library, including the file stamps, into the index format
+    pub fn to_index_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.data.extend_from_slice(INDEX_MAGIC);
+        w.varint(INDEX_VERSION);
+        w.str(&self.root.to_string_lossy());
+        let mut files = self
+            .songs
+            .iter()
+            .map(|e| (&e.path, IndexedFile::Song(&e.song)))
+            .chain(self.skipped.iter().map(|p| (p, IndexedFile::Skipped)))
+            .chain(
+                self.errors
+                    .iter()
+                    .map(|e| (&e.path, IndexedFile::Failed(format!("{:#}", e.error)))),
+            )
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| a.0.cmp(b.0));
+        w.varint(files.len() as u64);
+        for (path, file) in files {
+            w.str(&path.to_string_lossy());
+            let stamp = self.index.get(path);
+            w.option(stamp, |w, stamp| {
+                let modified = stamp
+                    .modified
+                    .and_then(|a| a.duration_since(UNIX_EPOCH).ok());
+                w.option(modified, |w, a| {
+                    w.varint(a.as_secs());
+                    w.varint(a.subsec_nanos() as u64);
+                });
+                w.varint(stamp.len);
+                w.varint(stamp.hash);
+            });
+            match file {
+                IndexedFile::Song(song) => {
+                    w.u8(0);
+                    write_song(&mut w, song);
+                }
+                IndexedFile::Skipped => w.u8(1),
+                IndexedFile::Failed(error) => {
+                    w.u8(2);
+                    w.str(&error);
+                }
+            }
+        }
+        w.data
+    }
+
+    /// Restore a library from [`SongLibrary::to_index_bytes`] output
+    pub fn from_index_bytes(data: &[u8]) -> Result<SongLibrary> {
+        let mut r = Reader::new(data);
+        if r.take(INDEX_MAGIC.len())? != INDEX_MAGIC {
+            bail!("Not a library index");
+        }
+        let version = r.varint()?;
+        if version != INDEX_VERSION {
+            bail!("Unsupported library index version {}", version);
+        }
+        let mut library = SongLibrary {
+            root: PathBuf::from(r.str()?),
+            ..Default::default()
+        };
+        for _ in 0..r.varint()? {
+            let path = PathBuf::from(r.str()?);
+            let stamp = r.option(|r| {
+                let modified = r.option(|r| {
+                    let secs = r.varint()?;
+                    let nanos = r.varint()? as u32;
+                    Ok(UNIX_EPOCH + Duration::new(secs, nanos))
+                })?;
+                Ok(FileStamp {
+                    modified,
+                    len: r.varint()?,
+                    hash: r.varint()?,
+                })
+            })?;
+            let loaded = match r.u8()? {
+                0 => Loaded::Song(Box::new(read_song(&mut r)?)),
+                1 => Loaded::Skipped,
+                2 => Loaded::Failed(anyhow!(r.string()?)),
+                a => bail!("Invalid library index entry kind {}", a),
+            };
+            library.add(path, loaded, stamp);
+        }
+        Ok(library)
+    }
+
+    /// Save the library index so a later run can start from it
+    ///
+    /// The file is replaced atomically, so a crash never leaves a truncated index.
+    pub fn save_index(&self, path: &str) -> Result<()> {
+        let tmp = format!("{}.tmp", path);
+        std::fs::write(&tmp, self.to_index_bytes())?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Load a saved index; call [`SongLibrary::rescan`] afterwards to pick up
+    /// what changed on disk in the meantime
+    /// ```rust
+    /// use usdx_parser::library::SongLibrary;
+    ///
+    /// let library = SongLibrary::scan("tests/library").unwrap();
+    /// let path = std::env::temp_dir().join("usdx_doc_index.bin");
+    /// library.save_index(path.to_str().unwrap()).unwrap();
+    /// let mut loaded = SongLibrary::load_index(path.to_str().unwrap()).unwrap();
+    /// assert_eq!(loaded.stats(), library.stats());
+    /// assert_eq!(loaded.rescan().unwrap().unchanged, 4);
+    /// ```
+    pub fn load_index(path: &str) -> Result<SongLibrary> {
+        SongLibrary::from_index_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn stats(&self) -> LibraryStats {
+        LibraryStats {
+            files: self.songs.len() + self.skipped.len() + self.errors.len(),
+            songs: self.songs.len(),
+            skipped: self.skipped.len(),
+            errors: self.errors.len(),
+            duets: self.songs.iter().filter(|e| e.song.is_duet()).count(),
+            notes: self
+                .songs
+                .iter()
+                .flat_map(|e| e.song.notes.iter())
+                .filter(|n| n.note_type != crate::NoteType::LineBreak)
+                .count(),
+        }
+    }
+}
+
+/// All `.txt` files below `root`, sorted by path
+///
+/// Symlinked directories are not followed, so link loops can't hang the scan.
+pub(crate) fn chart_candidates(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if path
+                .extension()
+                .is_some_and(|a| a.eq_ignore_ascii_case("txt"))
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Chart text from raw file contents
+///
+/// Older charts are usually CP1252 rather than UTF-8, which is what anything
+/// that isn't valid UTF-8 is read as. A UTF-8 byte order mark is dropped.
+pub(crate) fn decode_chart(data: &[u8]) -> String {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    match std::str::from_utf8(data) {
+        Ok(a) => a.to_string(),
+        Err(_) => crate::recode::decode_cp1252(data),
+    }
+}
+
+/// Whether text looks like a chart, which always starts with a `#` header
+pub(crate) fn is_chart(text: &str) -> bool {
+    text.lines()
+        .map(|a| a.trim())
+        .find(|a| !a.is_empty())
+        .is_some_and(|a| a.starts_with('#'))
+}
+
+/// Stable hash of file contents, also used by the on-disk index
+pub(crate) fn content_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |acc, &b| {
+        (acc ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Read and parse one chart candidate
+pub(crate) fn load(path: &Path) -> (Loaded, Option<FileStamp>) {
+    let modified = std::fs::metadata(path).and_then(|a| a.modified()).ok();
+    let data = match std::fs::read(path) {
+        Ok(a) => a,
+        Err(e) => return (Loaded::Failed(e.into()), None),
+    };
+    let stamp = FileStamp {
+        modified,
+        len: data.len() as u64,
+        hash: content_hash(&data),
+    };
+    let text = decode_chart(&data);
+    if !is_chart(&text) {
+        return (Loaded::Skipped, Some(stamp));
+    }
+    let loaded = match Song::try_from(text) {
+        Ok(song) => Loaded::Song(Box::new(song)),
+        Err(e) => Loaded::Failed(e),
+    };
+    (loaded, Some(stamp))
+}
+
+/// Load `files` on `threads` worker threads, returning the results in order
+fn load_all(files: &[PathBuf], threads: usize) -> Vec<(Loaded, Option<FileStamp>)> {
+    if threads <= 1 {
+        return files.iter().map(|a| load(a)).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(files.len()));
+    std::thread::scope(|scope| {
+        for _ in 0..threads.min(files.len().max(1)) {
+            scope.spawn(|| loop {
+                // Workers take files one at a time, so a few huge charts
+                // don't leave the other threads idle
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = files.get(i) else {
+                    break;
+                };
+                let loaded = load(path);
+                results
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push((i, loaded));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+    results.sort_by_key(|a| a.0);
+    results.into_iter().map(|a| a.1).collect()
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+pub fn test_parallel_scan() {
+    let sequential = SongLibrary::scan_parallel("tests/library", 1).unwrap();
+    let parallel = SongLibrary::scan_parallel("tests/library", 8).unwrap();
+    assert_eq!(parallel.stats(), sequential.stats());
+    let paths = |l: &SongLibrary| l.songs.iter().map(|e| e.path.clone()).collect::<Vec<_>>();
+    assert_eq!(paths(&parallel), paths(&sequential));
+}
+
+#[test]
+pub fn test_library_scan() {
+    let library = SongLibrary::scan("tests/library").unwrap();
+    let stats = library.stats();
+    assert_eq!(
+        stats,
+        LibraryStats {
+            files: 4,
+            songs: 2,
+            skipped: 1,
+            errors: 1,
+            duets: 1,
+            notes: 8,
+        }
+    );
+    assert!(library.errors[0].path.ends_with("Broken/broken.txt"));
+    assert!(library.skipped[0].ends_with("Broken/readme.txt"));
+    // The byte order mark must not hide the first header
+    let solo = &library.songs[0].song;
+    assert_eq!(solo.artist.as_deref(), Some("Solo"));
+    assert_eq!(library.songs[1].song.title, "Duet Test");
+
+    // Charts that aren't UTF-8 are read as CP1252
+    let dir = std::env::temp_dir().join(format!("usdx_cp1252_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let chart = b"#TITLE:Don\x92t Stop Me Now\n#BPM:100\n#GAP:0\n: 0 1 0 a\nE\n";
+    std::fs::write(dir.join("song.txt"), chart).unwrap();
+    let library = SongLibrary::scan(dir.to_str().unwrap()).unwrap();
+    assert_eq!(library.songs[0].song.title, "Don\u{2019}t Stop Me Now");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+pub fn test_incremental_rescan() {
+    let dir = std::env::temp_dir().join(format!("usdx_rescan_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("a")).unwrap();
+    std::fs::create_dir_all(dir.join("b")).unwrap();
+    std::fs::copy("tests/duet.txt", dir.join("a/song.txt")).unwrap();
+    std::fs::copy("tests/duet.txt", dir.join("b/song.txt")).unwrap();
+    let mut library = SongLibrary::scan(dir.to_str().unwrap()).unwrap();
+    assert_eq!(library.index.len(), 2);
+
+    // Rewriting identical content only refreshes the stamp
+    std::fs::write(
+        dir.join("a/song.txt"),
+        std::fs::read("tests/duet.txt").unwrap(),
+    )
+    .unwrap();
+    let text = std::fs::read_to_string("tests/duet.txt").unwrap();
+    std::fs::write(dir.join("b/song.txt"), text.replace("Duet Test", "Changed")).unwrap();
+    std::fs::copy("tests/duet.txt", dir.join("c.txt")).unwrap();
+    let summary = library.rescan().unwrap();
+    assert_eq!(summary.changed, 1);
+    assert_eq!(summary.added, 1);
+    assert_eq!(summary.unchanged, 1);
+    assert_eq!(library.songs[1].song.title, "Changed");
+
+    std::fs::remove_file(dir.join("a/song.txt")).unwrap();
+    let summary = library.rescan().unwrap();
+    assert_eq!(summary.removed, 1);
+    assert_eq!(summary.unchanged, 2);
+    assert_eq!(library.songs.len(), 2);
+    assert_eq!(library.index.len(), 2);
+
+    // Errors of files that couldn't be read go away with the file
+    library.errors.push(ScanError {
+        path: dir.join("unreadable.txt"),
+        error: anyhow!("Permission denied"),
+    });
+    let summary = library.rescan().unwrap();
+    assert_eq!(summary.removed, 1);
+    assert!(library.errors.is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+pub fn test_index_round_trip() {
+    let library = SongLibrary::scan("tests/library").unwrap();
+    let bytes = library.to_index_bytes();
+    let loaded = SongLibrary::from_index_bytes(&bytes).unwrap();
+    assert_eq!(loaded.stats(), library.stats());
+    assert_eq!(loaded.index, library.index);
+    assert_eq!(loaded.root, library.root);
+    assert_eq!(loaded.skipped, library.skipped);
+    assert_eq!(
+        format!("{}", loaded.errors[0].error),
+        format!("{:#}", library.errors[0].error)
+    );
+    for (a, b) in loaded.songs.iter().zip(library.songs.iter()) {
+        assert_eq!(a.path, b.path);
+        assert_eq!(a.song.to_string(), b.song.to_string());
+    }
+    assert!(SongLibrary::from_index_bytes(&bytes[..bytes.len() / 2]).is_err());
+}
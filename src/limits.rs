@@ -0,0 +1,105 @@
+//! Limits for parsing untrusted charts
+//!
+//! Parsing takes time linear in the size of a chart and never panics on
+//! malformed input, but a service accepting uploads still wants to turn away
+//! oversized files before spending memory on them. [`ParseLimits`] bounds the
+//! input size, the length of single lines and headers and the number of notes.
+//! How leniently a chart is read is up to [`crate::options::ParseOptions`].
+use anyhow::{bail, Result};
+
+/// Upper bounds checked while parsing, `None` meaning unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ParseLimits {
+    /// Size of the whole chart in bytes
+    pub max_input_len: Option<usize>,
+    /// Length of a single line in bytes
+    pub max_line_len: Option<usize>,
+    /// Length of a header value in bytes
+    pub max_header_len: Option<usize>,
+    /// Number of notes, line breaks included
+    pub max_notes: Option<usize>,
+}
+
+impl ParseLimits {
+    /// No limits, which is what the other parsing functions use
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Limits generous enough for any real chart, meant for user uploads
+    pub fn untrusted() -> Self {
+        Self {
+            max_input_len: Some(1 << 20),
+            max_line_len: Some(4096),
+            max_header_len: Some(1024),
+            max_notes: Some(20_000),
+        }
+    }
+
+    pub(crate) fn check_input(&self, len: usize) -> Result<()> {
+        if self.max_input_len.is_some_and(|max| len > max) {
+            bail!("Chart is {} bytes long, more than the limit", len);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_line(&self, number: usize, line: &str) -> Result<()> {
+        if self.max_line_len.is_some_and(|max| line.len() > max) {
+            bail!(
+                "Line {} is {} bytes long, more than the limit",
+                number,
+                line.len()
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_header(&self, number: usize, tag: &str, value: &str) -> Result<()> {
+        if self.max_header_len.is_some_and(|max| value.len() > max) {
+            bail!("#{} on line {} is longer than the limit", tag, number);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_notes(&self, number: usize, count: usize) -> Result<()> {
+        if self.max_notes.is_some_and(|max| count > max) {
+            bail!("Note on line {} is above the limit of notes", number);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_parse_limits() {
+    use crate::{CompatProfile, Song};
+
+    let text = std::fs::read_to_string("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let parse =
+        |text: &str, limits| Song::from_str_limited(text, CompatProfile::default(), &limits);
+    assert!(parse(&text, ParseLimits::untrusted()).is_ok());
+    let limits = ParseLimits {
+        max_input_len: Some(100),
+        ..ParseLimits::unlimited()
+    };
+    assert!(parse(&text, limits).is_err());
+    let limits = ParseLimits {
+        max_notes: Some(10),
+        ..ParseLimits::unlimited()
+    };
+    assert!(parse(&text, limits).is_err());
+    let long_title = format!("#TITLE:{}\n#BPM:100\n#GAP:0\n", "a".repeat(2000));
+    assert!(parse(&long_title, ParseLimits::unlimited()).is_ok());
+    assert!(parse(&long_title, ParseLimits::untrusted()).is_err());
+    let long_line = format!("#TITLE:T\n#BPM:100\n#GAP:0\n: 0 1 0 {}\n", "a".repeat(5000));
+    assert!(parse(&long_line, ParseLimits::untrusted()).is_err());
+    // Malformed input is an error, never a panic
+    for text in [
+        "#TITLE:T\n#BPM:100\n#GAP:0\n#RELATIVE:maybe\n",
+        "#TITLE:T\n#BPM:100\n#GAP:0\n#RELATIVE:yes\n: 4294967295 1 0 a\n- 4294967295\n: 4294967295 1 0 b\n",
+        "#TITLE:T\n#BPM:inf\n#GAP:0\n",
+        "#TITLE:T\n#BPM:100\n#GAP:0\nF\n: 1\n* 1 2\n",
+    ] {
+        let _ = parse(text, ParseLimits::untrusted());
+    }
+    assert!(parse("#TITLE:T\n#BPM:0\n#GAP:0\n", ParseLimits::untrusted()).is_err());
+}
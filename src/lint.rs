@@ -0,0 +1,493 @@
+//! Chart linting and validation against the format specification
+//!
+//! [`Song::lint`] finds problems in the notes and headers of a parsed song.
+//! [`validate`] also checks the chart text against a version of the
+//! UltraStar format specification, which deprecates or drops some headers
+//! the games still read. [`Song::lint_for`] checks that a song has the
+//! headers a specific game needs.
+use crate::json::Value;
+use crate::library::{chart_candidates, decode_chart, is_chart};
+use crate::{CompatProfile, Header, NoteType, Song, Voice};
+use anyhow::{bail, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Notes held longer than this many milliseconds are likely typos
+const MAX_NOTE_MS: f64 = 10_000.0;
+/// Semitones a tone may be from the middle of its voice's range
+const MAX_TONE_DISTANCE: i32 = 24;
+
+/// How bad a [`Lint`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Plays, but is likely a mistake or outdated
+    Warning,
+    /// Breaks the chart or the specification
+    Error,
+}
+
+/// Problem found in a chart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub severity: Severity,
+    /// Line of the chart text, when the problem is tied to one
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Version of the format specification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FormatVersion {
+    V1_0_0,
+    V1_1_0,
+    V1_2_0,
+}
+
+/// Lints found in one chart file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub lints: Vec<Lint>,
+}
+
+impl Lint {
+    fn new(severity: Severity, line: Option<usize>, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+impl FromStr for FormatVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim() {
+            "1.0.0" => Self::V1_0_0,
+            "1.1.0" => Self::V1_1_0,
+            "1.2.0" => Self::V1_2_0,
+            _ => bail!("Unknown format version: {}", s),
+        })
+    }
+}
+
+impl fmt::Display for FormatVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::V1_0_0 => "1.0.0",
+            Self::V1_1_0 => "1.1.0",
+            Self::V1_2_0 => "1.2.0",
+        })
+    }
+}
+
+impl Song {
+    /// Problems in the notes and headers of the song
+    /// ```rust
+    /// use usdx_parser::lint::Severity;
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// assert!(song.lint().iter().all(|l| l.severity < Severity::Error));
+    /// ```
+    pub fn lint(&self) -> Vec<Lint> {
+        use Severity::*;
+
+        let mut lints = vec![];
+        if self.artist.is_none() {
+            lints.push(Lint::new(Warning, None, "Song has no artist"));
+        }
+        if self.mp3.is_none() {
+            lints.push(Lint::new(Warning, None, "Song has no audio file"));
+        }
+        lints.extend(lint_notes(self));
+        lints
+    }
+
+    /// Headers `profile` needs or expects that the song doesn't set
+    ///
+    /// Missing required headers are errors, missing recommended ones warnings.
+    /// ```rust
+    /// use usdx_parser::{CompatProfile, Song};
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// let lints = song.lint_for(CompatProfile::Vocaluxe);
+    /// assert_eq!(lints.len(), 1);
+    /// assert_eq!(lints[0].message, "Missing #COVER for Vocaluxe");
+    /// ```
+    pub fn lint_for(&self, profile: CompatProfile) -> Vec<Lint> {
+        let has = |header: Header| match header {
+            Header::Title => !self.title.trim().is_empty(),
+            // Always written, but a chart read without it relies on the default
+            Header::Gap => self.header_order.is_empty() || self.header_order.contains(&header),
+            _ => self.header_line(header, profile).is_some(),
+        };
+        let required = profile
+            .required_headers()
+            .iter()
+            .map(|&h| (Severity::Error, h));
+        let recommended = profile
+            .recommended_headers()
+            .iter()
+            .map(|&h| (Severity::Warning, h));
+        required
+            .chain(recommended)
+            .filter(|&(_, header)| !has(header))
+            .map(|(severity, header)| {
+                let message = format!("Missing #{} for {}", header.tag(profile), profile);
+                Lint::new(severity, None, message)
+            })
+            .collect()
+    }
+}
+
+/// Problems in the notes of `song`
+fn lint_notes(song: &Song) -> Vec<Lint> {
+    use Severity::*;
+
+    let mut lints = vec![];
+    if song.notes.is_empty() {
+        lints.push(Lint::new(Error, None, "Song has no notes"));
+    }
+    let duet = song.is_duet();
+    if duet && (song.singer_p1.is_none() || song.singer_p2.is_none()) {
+        lints.push(Lint::new(Warning, None, "Duet doesn't name both singers"));
+    }
+    for voice in [None, Some(Voice::P1), Some(Voice::P2), Some(Voice::Both)] {
+        let mut tones: Vec<i32> = song
+            .notes
+            .iter()
+            .filter(|n| n.voice == voice && n.note_type != NoteType::Freestyle)
+            .filter_map(|n| n.note_tone)
+            .collect();
+        tones.sort_unstable();
+        let middle = tones.get(tones.len() / 2).copied();
+        let mut last: Option<(u32, u32)> = None;
+        for note in song.notes.iter().filter(|n| n.voice == voice) {
+            let beat = note.beat_number;
+            if let Some((start, end)) = last {
+                if beat < start {
+                    lints.push(Lint::new(
+                        Error,
+                        None,
+                        format!(
+                            "Note at beat {} starts before the one at beat {}",
+                            beat, start
+                        ),
+                    ));
+                } else if beat < end && note.note_type != NoteType::LineBreak {
+                    lints.push(Lint::new(
+                        Warning,
+                        None,
+                        format!("Note at beat {} overlaps the one at beat {}", beat, start),
+                    ));
+                }
+            }
+            let sung = note.note_type != NoteType::LineBreak;
+            match note.note_length {
+                Some(0) => lints.push(Lint::new(
+                    Warning,
+                    None,
+                    format!("Note at beat {} has no length", beat),
+                )),
+                None if sung => lints.push(Lint::new(
+                    Error,
+                    None,
+                    format!("Note at beat {} has no length", beat),
+                )),
+                // Lengths that went negative in an edit wrap around to huge ones
+                Some(length) if length as f64 * 15000.0 / song.bpm as f64 > MAX_NOTE_MS => lints
+                    .push(Lint::new(
+                        Warning,
+                        None,
+                        format!(
+                            "Note at beat {} is held for {:.0} seconds",
+                            beat,
+                            length as f64 * 15.0 / song.bpm as f64
+                        ),
+                    )),
+                _ => {}
+            }
+            // A tone far from all others is usually a length typed into the tone column
+            if let (Some(tone), Some(middle)) = (note.note_tone, middle) {
+                if sung && tone.abs_diff(middle) > MAX_TONE_DISTANCE as u32 {
+                    lints.push(Lint::new(
+                        Warning,
+                        None,
+                        format!(
+                            "Note at beat {} has tone {}, far outside the song's range",
+                            beat, tone
+                        ),
+                    ));
+                }
+            }
+            last = Some((
+                beat,
+                beat.saturating_add(note.note_length.unwrap_or_default()),
+            ));
+        }
+    }
+    lints
+}
+
+/// Check chart text against `version` of the specification
+///
+/// Includes the [`Song::lint`] findings once the text parses.
+/// ```rust
+/// use usdx_parser::lint::{validate, FormatVersion};
+///
+/// let text = "#VERSION:1.1.0\n#ARTIST:A\n#TITLE:T\n#MP3:a.mp3\n#BPM:100\n#GAP:0\n: 0 4 0 la\nE\n";
+/// let lints = validate(text, FormatVersion::V1_1_0);
+/// assert_eq!(lints[0].message, "#MP3 is deprecated, use #AUDIO");
+/// assert_eq!(lints[0].line, Some(4));
+/// ```
+pub fn validate(text: &str, version: FormatVersion) -> Vec<Lint> {
+    use Severity::*;
+
+    let mut lints = vec![];
+    let mut headers: Vec<(String, usize, &str)> = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let Some((tag, value)) = line
+            .trim_start()
+            .strip_prefix('#')
+            .and_then(|a| a.split_once(':'))
+        else {
+            continue;
+        };
+        headers.push((tag.trim().to_ascii_uppercase(), number + 1, value.trim()));
+    }
+    let header = |tag: &str| headers.iter().find(|h| h.0 == tag);
+    match header("VERSION") {
+        None => lints.push(Lint::new(Warning, None, "Chart has no #VERSION")),
+        Some((_, line, value)) if value.parse::<FormatVersion>().ok() != Some(version) => {
+            lints.push(Lint::new(
+                Warning,
+                Some(*line),
+                format!(
+                    "Chart declares version {}, checking against {}",
+                    value, version
+                ),
+            ));
+        }
+        Some(_) => {}
+    }
+    for tag in ["TITLE", "ARTIST", "BPM"] {
+        if header(tag).is_none() {
+            lints.push(Lint::new(
+                Error,
+                None,
+                format!("Required #{} is missing", tag),
+            ));
+        }
+    }
+    let audio = if version >= FormatVersion::V1_1_0 {
+        "AUDIO"
+    } else {
+        "MP3"
+    };
+    if header(audio).is_none() {
+        match header("MP3") {
+            Some((_, line, _)) => lints.push(Lint::new(
+                Warning,
+                Some(*line),
+                "#MP3 is deprecated, use #AUDIO",
+            )),
+            None => lints.push(Lint::new(
+                Error,
+                None,
+                format!("Required #{} is missing", audio),
+            )),
+        }
+    }
+    for (tag, line, _) in &headers {
+        let message = match tag.as_str() {
+            "RELATIVE" => "#RELATIVE was removed in 1.0.0, use absolute beats",
+            "ENCODING" => "#ENCODING was removed in 1.0.0, charts are UTF-8",
+            "DUETSINGERP1" | "DUETSINGERP2" if version >= FormatVersion::V1_1_0 => {
+                lints.push(Lint::new(
+                    Warning,
+                    Some(*line),
+                    format!("#{} is deprecated, use #P{}", tag, &tag[tag.len() - 1..]),
+                ));
+                continue;
+            }
+            _ => continue,
+        };
+        lints.push(Lint::new(Error, Some(*line), message));
+    }
+    match text.parse::<Song>() {
+        // The headers were checked above
+        Ok(song) => lints.extend(lint_notes(&song)),
+        Err(e) => lints.push(Lint::new(Error, None, e.to_string())),
+    }
+    lints
+}
+
+/// Lint the chart at `path`, or every chart below it for a directory
+///
+/// With a `version` the charts are validated against it as well.
+pub fn lint_path(path: &str, version: Option<FormatVersion>) -> Result<Vec<FileReport>> {
+    let path = Path::new(path);
+    let dir = path.is_dir();
+    let files = if dir {
+        chart_candidates(path)?
+    } else {
+        vec![path.to_path_buf()]
+    };
+    let mut reports = vec![];
+    for path in files {
+        let data = std::fs::read(&path)?;
+        let text = decode_chart(&data);
+        // Readmes and the like in a songs directory aren't charts
+        if dir && !is_chart(&text) {
+            continue;
+        }
+        let mut lints = vec![];
+        if let Some(version) = version {
+            if std::str::from_utf8(&data).is_err() {
+                lints.push(Lint::new(Severity::Error, None, "Chart is not valid UTF-8"));
+            }
+            lints.extend(validate(&text, version));
+        } else {
+            match text.parse::<Song>() {
+                Ok(song) => lints = song.lint(),
+                Err(e) => lints.push(Lint::new(Severity::Error, None, e.to_string())),
+            }
+        }
+        reports.push(FileReport { path, lints });
+    }
+    Ok(reports)
+}
+
+impl fmt::Display for FileReport {
+    /// One `path:line: severity: message` line per lint
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for lint in &self.lints {
+            write!(f, "{}", self.path.display())?;
+            if let Some(line) = lint.line {
+                write!(f, ":{}", line)?;
+            }
+            writeln!(f, ": {}: {}", lint.severity, lint.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports as a JSON array of `{"path", "lints": [{"severity", "line", "message"}]}`
+pub fn reports_to_json(reports: &[FileReport]) -> String {
+    let reports = reports
+        .iter()
+        .map(|r| {
+            let lints = r
+                .lints
+                .iter()
+                .map(|l| {
+                    Value::Object(vec![
+                        (
+                            "severity".to_string(),
+                            Value::String(l.severity.to_string()),
+                        ),
+                        (
+                            "line".to_string(),
+                            l.line.map_or(Value::Null, |a| Value::Number(a as f64)),
+                        ),
+                        ("message".to_string(), Value::from(l.message.as_str())),
+                    ])
+                })
+                .collect();
+            Value::Object(vec![
+                (
+                    "path".to_string(),
+                    Value::String(r.path.display().to_string()),
+                ),
+                ("lints".to_string(), Value::Array(lints)),
+            ])
+        })
+        .collect();
+    Value::Array(reports).to_string()
+}
+
+#[test]
+pub fn test_lint_and_validate() {
+    let song: Song = "#TITLE:T\n#BPM:100\n#GAP:0\n: 8 4 0 a\n: 10 4 0 b\n: 2 0 0 c\n"
+        .parse()
+        .unwrap();
+    let messages = song
+        .lint()
+        .into_iter()
+        .map(|l| l.message)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "Song has no artist",
+            "Song has no audio file",
+            "Note at beat 10 overlaps the one at beat 8",
+            "Note at beat 2 starts before the one at beat 10",
+            "Note at beat 2 has no length",
+        ]
+    );
+    let text = std::fs::read_to_string("tests/please_tell_rosie.txt").unwrap();
+    let lints = validate(&text, FormatVersion::V1_0_0);
+    assert!(lints
+        .iter()
+        .any(|l| l.severity == Severity::Error && l.message.starts_with("#RELATIVE")));
+    let reports = lint_path("tests/library", None).unwrap();
+    assert_eq!(reports.len(), 3);
+    assert!(reports_to_json(&reports).starts_with(r#"[{"path":"#));
+    assert!("2.0.0".parse::<FormatVersion>().is_err());
+
+    let mut song: Song = "#TITLE:T\n#ARTIST:A\n#MP3:a.mp3\n#BPM:100\n: 0 4 0 a\nE\n"
+        .parse()
+        .unwrap();
+    let lints = song.lint_for(CompatProfile::Usdx);
+    assert_eq!(
+        lints[0],
+        Lint::new(Severity::Error, None, "Missing #GAP for USDX")
+    );
+    assert_eq!(lints[1].message, "Missing #COVER for USDX");
+    let lints = song.lint_for(CompatProfile::Performous);
+    assert!(lints.iter().all(|l| l.severity == Severity::Warning));
+    song.cover = Some("cover.jpg".to_string());
+    song.header_order.push(Header::Gap);
+    assert_eq!(song.lint_for(CompatProfile::Usdx), []);
+
+    // Held for 12 seconds, and length and tone swapped
+    let song: Song =
+        "#TITLE:T\n#BPM:100\n#GAP:0\n: 0 80 3 a\n: 80 4 2 b\n: 84 2 40 c\n: 86 4 4 d\n"
+            .parse()
+            .unwrap();
+    let messages = lint_notes(&song)
+        .into_iter()
+        .map(|l| l.message)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "Note at beat 0 is held for 12 seconds",
+            "Note at beat 84 has tone 40, far outside the song's range",
+        ]
+    );
+    let mut song = song;
+    song.notes[1].note_length = None;
+    song.notes[2].note_length = Some(4u32.wrapping_sub(6));
+    let lints = lint_notes(&song);
+    assert!(lints
+        .iter()
+        .any(|l| l.severity == Severity::Error && l.message == "Note at beat 80 has no length"));
+    assert!(lints
+        .iter()
+        .any(|l| l.message.starts_with("Note at beat 84 is held for")));
+}
@@ -0,0 +1,330 @@
+//! Plain text lyrics
+//!
+//! Joins the syllables of a chart back into lines of text, for search
+//! indexes, printed lyrics and karaoke books. Holds (`~`) are dropped and
+//! the spaces around syllables collapse into single spaces between words.
+//!
+//! [`Song::words`] groups the syllables into [`Word`]s with their timing,
+//! for subtitle exporters and alignment tools, and [`Song::sentences`] into
+//! the [`Sentence`]s the game shows at once.
+use crate::{Note, NoteType, Song, Voice};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Notes between two line breaks of one voice
+#[derive(Debug, Clone, Copy)]
+pub struct Sentence<'a> {
+    /// Sung notes of the sentence, without the line breaks around it
+    pub notes: &'a [Note],
+    /// Duet singer of the sentence, `None` for solo songs
+    pub voice: Option<Voice>,
+}
+
+impl Sentence<'_> {
+    /// Text of the sentence as UltraStar Deluxe displays it
+    ///
+    /// The game joins the syllables exactly as written, so the spaces they
+    /// start or end with are what separates words. Holds (`~`) are not shown
+    /// and the spaces at both ends of the sentence are cut.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    /// assert_eq!(song.sentences()[0].text(), "Is this the real life?");
+    /// ```
+    pub fn text(&self) -> String {
+        let mut ret = String::new();
+        for note in self.notes {
+            ret.extend(
+                note.lyric
+                    .iter()
+                    .flat_map(|l| l.chars())
+                    .filter(|&c| c != '~'),
+            );
+        }
+        ret.trim().to_string()
+    }
+
+    /// Beat of the first note
+    pub fn start_beat(&self) -> u32 {
+        self.notes.first().map_or(0, |n| n.beat_number)
+    }
+
+    /// Beat after the last note ends
+    pub fn end_beat(&self) -> u32 {
+        self.notes
+            .iter()
+            .map(|n| {
+                n.beat_number
+                    .saturating_add(n.note_length.unwrap_or_default())
+            })
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// Syllables sung as one word
+#[derive(Debug, Clone)]
+pub struct Word<'a> {
+    /// Text of the word without holds or surrounding spaces
+    pub text: String,
+    /// Notes making up the word
+    pub notes: Vec<&'a Note>,
+    /// Duet singer of the word, `None` for solo songs
+    pub voice: Option<Voice>,
+    /// Start of the first note in ms
+    pub start: f64,
+    /// End of the last note in ms
+    pub end: f64,
+}
+
+impl Song {
+    /// Lyrics with one sentence per line
+    ///
+    /// Duets list every voice after the other, each headed by the name of its
+    /// singer and separated by an empty line.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// assert_eq!(
+    ///     song.lyrics(),
+    ///     "Singer One\nHello\nthere\n\nSinger Two\nHi back\nyeah\n"
+    /// );
+    /// ```
+    pub fn lyrics(&self) -> String {
+        if !self.is_duet() {
+            return join(&lines(self, None));
+        }
+        let mut ret = String::new();
+        for (voice, singer) in [
+            (Voice::P1, &self.singer_p1),
+            (Voice::P2, &self.singer_p2),
+            (Voice::Both, &None),
+        ] {
+            let lines = lines(self, Some(voice));
+            if lines.is_empty() {
+                continue;
+            }
+            if !ret.is_empty() {
+                ret.push('\n');
+            }
+            match singer {
+                Some(singer) => ret.push_str(singer),
+                None => ret.push_str(&voice.to_string()),
+            }
+            ret.push('\n');
+            ret.push_str(&join(&lines));
+        }
+        ret
+    }
+
+    /// Lyrics of one duet singer, one sentence per line
+    pub fn voice_lyrics(&self, voice: Voice) -> String {
+        join(&lines(self, Some(voice)))
+    }
+
+    /// Sentences of every voice, in the order of the notes
+    ///
+    /// Line breaks directly after each other don't make empty sentences.
+    pub fn sentences(&self) -> Vec<Sentence<'_>> {
+        let mut ret = Vec::new();
+        let mut start = 0;
+        for (i, note) in self.notes.iter().enumerate() {
+            let voice = self.notes[start].voice;
+            if note.note_type == NoteType::LineBreak || note.voice != voice {
+                if start < i {
+                    ret.push(Sentence {
+                        notes: &self.notes[start..i],
+                        voice,
+                    });
+                }
+                start = if note.note_type == NoteType::LineBreak {
+                    i + 1
+                } else {
+                    i
+                };
+            }
+        }
+        if start < self.notes.len() {
+            ret.push(Sentence {
+                notes: &self.notes[start..],
+                voice: self.notes[start].voice,
+            });
+        }
+        ret
+    }
+
+    /// Syllables grouped into words, in the order of the notes
+    ///
+    /// A syllable starts a new word after a line break, or when whitespace
+    /// separates it from the previous one. Holds (`~`) always continue the
+    /// word before them.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// let words = song.words();
+    /// assert_eq!(words[0].text, "Hello");
+    /// assert_eq!(words[0].notes.len(), 2);
+    /// assert_eq!((words[0].start, words[0].end), (1200.0, 1584.0));
+    /// ```
+    pub fn words(&self) -> Vec<Word<'_>> {
+        self.word_notes()
+            .into_iter()
+            .map(|(_, indices)| {
+                let notes: Vec<&Note> = indices.iter().map(|&i| &self.notes[i]).collect();
+                let text = notes
+                    .iter()
+                    .map(|n| n.lyric.as_deref().unwrap_or_default())
+                    .map(|l| l.trim().trim_start_matches('~').trim())
+                    .collect();
+                let first = notes[0];
+                let last = notes[notes.len() - 1];
+                let end = last
+                    .beat_number
+                    .saturating_add(last.note_length.unwrap_or_default());
+                Word {
+                    text,
+                    voice: first.voice,
+                    start: self.beat_to_ms(first.beat_number as f64),
+                    end: self.beat_to_ms(end as f64),
+                    notes,
+                }
+            })
+            .collect()
+    }
+
+    /// Indices of the notes of every word, with the number of its sentence
+    /// in [`Song::sentences`]
+    pub(crate) fn word_notes(&self) -> Vec<(usize, Vec<usize>)> {
+        let mut ret: Vec<(usize, Vec<usize>)> = Vec::new();
+        // Whether the next syllable continues the last word
+        let mut open = false;
+        // Whether the next syllable starts a sentence
+        let mut fresh = true;
+        let mut voice = None;
+        for (i, note) in self.notes.iter().enumerate() {
+            if note.note_type == NoteType::LineBreak || note.voice != voice {
+                fresh = true;
+                voice = note.voice;
+                if note.note_type == NoteType::LineBreak {
+                    continue;
+                }
+            }
+            let lyric = note.lyric.as_deref().unwrap_or_default();
+            let hold = lyric.starts_with('~');
+            match ret.last_mut() {
+                Some((_, word))
+                    if !fresh && (hold || (open && !lyric.starts_with(char::is_whitespace))) =>
+                {
+                    word.push(i)
+                }
+                last => {
+                    let sentence = match (last, fresh) {
+                        (None, _) => 0,
+                        (Some((s, _)), true) => *s + 1,
+                        (Some((s, _)), false) => *s,
+                    };
+                    ret.push((sentence, vec![i]));
+                }
+            }
+            fresh = false;
+            open = !lyric.ends_with(char::is_whitespace);
+        }
+        ret
+    }
+}
+
+/// Text of every non-empty sentence of `voice`
+fn lines(song: &Song, voice: Option<Voice>) -> Vec<String> {
+    song.sentences()
+        .iter()
+        .filter(|s| s.voice == voice)
+        .map(|s| tidy(&s.text()))
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+/// Leaves single spaces between words
+fn tidy(line: &str) -> String {
+    let mut ret = String::with_capacity(line.len());
+    for word in line.split_whitespace() {
+        if !ret.is_empty() {
+            ret.push(' ');
+        }
+        ret.push_str(word);
+    }
+    ret
+}
+
+fn join(lines: &[String]) -> String {
+    let mut ret = String::new();
+    for line in lines {
+        ret.push_str(line);
+        ret.push('\n');
+    }
+    ret
+}
+
+#[test]
+pub fn test_plain_lyrics() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let lyrics = song.lyrics();
+    assert!(lyrics.starts_with("Is this the real life?\nIs this just fantasy?\n"));
+    assert!(lyrics.contains("\nI'm just a poor boy,\n"));
+    assert!(!lyrics.contains('~'));
+    assert!(!lyrics.contains("  "));
+    let duet = Song::from_file("tests/duet.txt").unwrap();
+    assert_eq!(duet.voice_lyrics(Voice::P2), "Hi back\nyeah\n");
+    assert_eq!(Song::new("Empty", 100.0, 0).lyrics(), "");
+}
+
+#[test]
+pub fn test_word_grouping() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let words = song.words();
+    let text: Vec<&str> = words.iter().take(6).map(|w| w.text.as_str()).collect();
+    assert_eq!(text, ["Is", "this", "the", "real", "life?", "Is"]);
+    let boy = words.iter().find(|w| w.text == "boy,").unwrap();
+    assert_eq!(boy.notes.len(), 2);
+    assert_eq!(boy.end, song.beat_to_ms(525.0));
+    assert!(words
+        .iter()
+        .all(|w| !w.text.contains('~') && w.start <= w.end));
+    let duet = Song::from_file("tests/duet.txt").unwrap();
+    let words = duet.words();
+    assert_eq!(words.len(), 5);
+    assert_eq!(words[3].text, "back");
+    assert_eq!(words[3].voice, Some(Voice::P2));
+}
+
+#[test]
+pub fn test_sentence_text() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let sentences = song.sentences();
+    assert_eq!(sentences[1].text(), "Is this just fantasy?");
+    assert_eq!(
+        (sentences[1].start_beat(), sentences[1].end_beat()),
+        (70, 108)
+    );
+    // Spaces inside the sentence stay as written
+    let text = "#TITLE:T\n#BPM:100\n#GAP:0\n: 0 1 0  A\n: 1 1 0  ~  b \n- 3\n- 4\n: 5 1 0 c\nE\n";
+    let song: Song = text.parse().unwrap();
+    let sentences = song.sentences();
+    assert_eq!(sentences.len(), 2);
+    assert_eq!(sentences[0].text(), "A   b");
+    assert_eq!(sentences[1].notes.len(), 1);
+    let duet = Song::from_file("tests/duet.txt").unwrap();
+    let voices: Vec<_> = duet.sentences().iter().map(|s| s.voice).collect();
+    assert_eq!(
+        voices,
+        [
+            Some(Voice::P1),
+            Some(Voice::P1),
+            Some(Voice::P2),
+            Some(Voice::P2)
+        ]
+    );
+}
@@ -0,0 +1,262 @@
+//! Minimal Standard MIDI File reader and writer shared by the MIDI based converters
+use anyhow::{bail, Result};
+
+/// Parsed Standard MIDI File
+#[derive(Debug, Clone)]
+pub(crate) struct Smf {
+    /// Ticks per quarter note
+    pub division: u16,
+    pub tracks: Vec<Vec<Event>>,
+}
+
+/// Event with an absolute tick position inside its track
+#[derive(Debug, Clone)]
+pub(crate) struct Event {
+    pub tick: u64,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum EventKind {
+    NoteOn { channel: u8, key: u8 },
+    NoteOff { channel: u8, key: u8 },
+    Meta { kind: u8, data: Vec<u8> },
+    Other,
+}
+
+pub(crate) const META_TEXT: u8 = 0x01;
+pub(crate) const META_TRACK_NAME: u8 = 0x03;
+pub(crate) const META_LYRIC: u8 = 0x05;
+pub(crate) const META_TEMPO: u8 = 0x51;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.pos + n > self.data.len() {
+            bail!("Unexpected end of MIDI data");
+        }
+        let ret = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(ret)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let a = self.take(4)?;
+        Ok(u32::from_be_bytes([a[0], a[1], a[2], a[3]]))
+    }
+
+    fn var_len(&mut self) -> Result<u32> {
+        let mut ret = 0u32;
+        for _ in 0..4 {
+            let b = self.u8()?;
+            ret = (ret << 7) | (b & 0x7f) as u32;
+            if b & 0x80 == 0 {
+                return Ok(ret);
+            }
+        }
+        bail!("Variable length quantity is too long");
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+impl Smf {
+    pub fn parse(data: &[u8]) -> Result<Smf> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader.take(4)? != b"MThd" {
+            bail!("Not a MIDI file");
+        }
+        let header_len = reader.u32()? as usize;
+        let header = reader.take(header_len)?;
+        if header.len() < 6 {
+            bail!("MIDI header is too short");
+        }
+        let division = u16::from_be_bytes([header[4], header[5]]);
+        // Only ticks per quarter note, SMPTE frames have the high bit set
+        if division == 0 || division & 0x8000 != 0 {
+            bail!("Unsupported MIDI division {:#06x}", division);
+        }
+        let mut tracks = vec![];
+        while !reader.is_empty() {
+            let id = reader.take(4)?;
+            let len = reader.u32()? as usize;
+            let chunk = reader.take(len)?;
+            if id == b"MTrk" {
+                tracks.push(parse_track(chunk)?);
+            }
+        }
+        Ok(Smf { division, tracks })
+    }
+
+    /// Tempo changes as `(tick, microseconds per quarter note)`, sorted by tick
+    pub fn tempo_map(&self) -> Vec<(u64, u32)> {
+        let mut map = self
+            .tracks
+            .iter()
+            .flatten()
+            .filter_map(|e| match &e.kind {
+                EventKind::Meta { kind, data } if *kind == META_TEMPO && data.len() == 3 => {
+                    Some((e.tick, u32::from_be_bytes([0, data[0], data[1], data[2]])))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        map.sort_by_key(|a| a.0);
+        map
+    }
+
+    /// Converts an absolute tick to milliseconds using the file's tempo map
+    pub fn tick_to_ms(&self, tempo_map: &[(u64, u32)], tick: u64) -> f64 {
+        let mut ms = 0.0;
+        let mut last_tick = 0;
+        // 120 BPM until the first tempo event
+        let mut tempo = 500_000;
+        for &(at, new_tempo) in tempo_map.iter().take_while(|a| a.0 < tick) {
+            ms += (at - last_tick) as f64 * tempo as f64 / self.division as f64 / 1000.0;
+            last_tick = at;
+            tempo = new_tempo;
+        }
+        ms + (tick - last_tick) as f64 * tempo as f64 / self.division as f64 / 1000.0
+    }
+}
+
+impl Smf {
+    /// Serializes as a format 1 file; `Other` events are dropped
+    ///
+    /// Events of a track are written in tick order, with note offs before note
+    /// ons on the same tick so back-to-back notes of one key don't cut each other.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = b"MThd".to_vec();
+        ret.extend_from_slice(&6u32.to_be_bytes());
+        ret.extend_from_slice(&1u16.to_be_bytes());
+        ret.extend_from_slice(&(self.tracks.len() as u16).to_be_bytes());
+        ret.extend_from_slice(&self.division.to_be_bytes());
+        for track in self.tracks.iter() {
+            let mut events = track.iter().collect::<Vec<_>>();
+            events.sort_by_key(|e| (e.tick, !matches!(e.kind, EventKind::NoteOff { .. })));
+            let mut chunk = vec![];
+            let mut tick = 0;
+            for event in events {
+                let data = match &event.kind {
+                    EventKind::NoteOn { channel, key } => vec![0x90 | channel, *key, 100],
+                    EventKind::NoteOff { channel, key } => vec![0x80 | channel, *key, 0],
+                    EventKind::Meta { kind, data } => {
+                        let mut ret = vec![0xff, *kind];
+                        write_var_len(&mut ret, data.len() as u32);
+                        ret.extend_from_slice(data);
+                        ret
+                    }
+                    EventKind::Other => continue,
+                };
+                write_var_len(&mut chunk, (event.tick - tick) as u32);
+                tick = event.tick;
+                chunk.extend(data);
+            }
+            chunk.extend_from_slice(&[0, 0xff, 0x2f, 0]);
+            ret.extend_from_slice(b"MTrk");
+            ret.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            ret.extend(chunk);
+        }
+        ret
+    }
+}
+
+fn write_var_len(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+fn parse_track(data: &[u8]) -> Result<Vec<Event>> {
+    let mut reader = Reader { data, pos: 0 };
+    let mut events = vec![];
+    let mut tick = 0u64;
+    let mut running_status = None;
+    while !reader.is_empty() {
+        tick += reader.var_len()? as u64;
+        let mut status = reader.u8()?;
+        let kind = match status {
+            0xff => {
+                let kind = reader.u8()?;
+                let len = reader.var_len()? as usize;
+                let data = reader.take(len)?.to_vec();
+                if kind == 0x2f {
+                    break;
+                }
+                EventKind::Meta { kind, data }
+            }
+            0xf0 | 0xf7 => {
+                let len = reader.var_len()? as usize;
+                reader.take(len)?;
+                EventKind::Other
+            }
+            _ => {
+                let first = if status & 0x80 == 0 {
+                    let Some(running) = running_status else {
+                        bail!("Data byte without status at tick {}", tick);
+                    };
+                    let first = status;
+                    status = running;
+                    first
+                } else {
+                    running_status = Some(status);
+                    reader.u8()?
+                };
+                let channel = status & 0x0f;
+                match status & 0xf0 {
+                    0x80 => {
+                        reader.u8()?;
+                        EventKind::NoteOff {
+                            channel,
+                            key: first,
+                        }
+                    }
+                    0x90 => {
+                        let velocity = reader.u8()?;
+                        if velocity == 0 {
+                            EventKind::NoteOff {
+                                channel,
+                                key: first,
+                            }
+                        } else {
+                            EventKind::NoteOn {
+                                channel,
+                                key: first,
+                            }
+                        }
+                    }
+                    0xa0 | 0xb0 | 0xe0 => {
+                        reader.u8()?;
+                        EventKind::Other
+                    }
+                    0xc0 | 0xd0 => EventKind::Other,
+                    _ => bail!("Unknown MIDI status byte {:#x}", status),
+                }
+            }
+        };
+        events.push(Event { tick, kind });
+    }
+    Ok(events)
+}
+
+/// Decodes MIDI text which is UTF-8 in newer files and Latin-1 in older ones
+pub(crate) fn decode_text(data: &[u8]) -> String {
+    match std::str::from_utf8(data) {
+        Ok(a) => a.to_string(),
+        Err(_) => data.iter().map(|&b| b as char).collect(),
+    }
+}
@@ -0,0 +1,169 @@
+//! Parsing straight from memory-mapped files
+//!
+//! Combined with [`SongRef`] a chart is parsed without copying it into a
+//! `String` first, so bulk processing keeps only the pages it is working on
+//! in memory. On 64 bit Unix systems the file is mapped with `mmap`; other
+//! platforms read it into memory instead.
+//!
+//! Like with any memory map, the file must not be truncated by another
+//! process while it is mapped.
+use crate::{CompatProfile, SongRef};
+use anyhow::{bail, Result};
+use std::fs::File;
+use std::ops::Deref;
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod sys {
+    use std::ffi::c_void;
+    use std::os::raw::c_int;
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+
+    extern "C" {
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: i64,
+        ) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+enum Data {
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    Mapped(*const u8, usize),
+    Owned(Vec<u8>),
+}
+
+/// Read-only contents of a file, mapped into memory where supported
+pub struct MappedFile {
+    data: Data,
+}
+
+// The mapping is read-only and owned by this value alone
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Map the file at `path`
+    pub fn open(path: &str) -> Result<MappedFile> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let Ok(len) = usize::try_from(len) else {
+            bail!("{} is too large to map", path);
+        };
+        Self::map(file, len)
+    }
+
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    fn map(file: File, len: usize) -> Result<MappedFile> {
+        use std::os::unix::io::AsRawFd;
+
+        // Mapping zero bytes is an error
+        if len == 0 {
+            return Ok(MappedFile {
+                data: Data::Owned(vec![]),
+            });
+        }
+        // SAFETY: a private read-only mapping of an open file, unmapped on drop
+        let ptr = unsafe {
+            sys::mmap(
+                std::ptr::null_mut(),
+                len,
+                sys::PROT_READ,
+                sys::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr as isize == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(MappedFile {
+            data: Data::Mapped(ptr as *const u8, len),
+        })
+    }
+
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    fn map(mut file: File, len: usize) -> Result<MappedFile> {
+        use std::io::Read;
+
+        let mut data = Vec::with_capacity(len);
+        file.read_to_end(&mut data)?;
+        Ok(MappedFile {
+            data: Data::Owned(data),
+        })
+    }
+
+    /// Whether the contents are actually mapped rather than read
+    pub fn is_mapped(&self) -> bool {
+        !matches!(self.data, Data::Owned(_))
+    }
+
+    /// Parse the chart in the file without copying it
+    /// ```rust
+    /// use usdx_parser::mmap::MappedFile;
+    ///
+    /// let file = MappedFile::open("tests/queen_bohemian_rhapsody.txt").unwrap();
+    /// let song = file.song().unwrap();
+    /// assert_eq!(song.artist, Some("Queen"));
+    /// ```
+    pub fn song(&self) -> Result<SongRef<'_>> {
+        self.song_with(CompatProfile::default())
+    }
+
+    /// Like [`MappedFile::song`], using the tolerance rules of a specific game
+    pub fn song_with(&self, profile: CompatProfile) -> Result<SongRef<'_>> {
+        let Ok(text) = std::str::from_utf8(self) else {
+            bail!("Chart is not valid UTF-8, it can't be parsed in place");
+        };
+        SongRef::parse_with(text.strip_prefix('\u{feff}').unwrap_or(text), profile)
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.data {
+            // SAFETY: the mapping stays valid until drop
+            #[cfg(all(unix, target_pointer_width = "64"))]
+            Data::Mapped(ptr, len) => unsafe { std::slice::from_raw_parts(*ptr, *len) },
+            Data::Owned(data) => data,
+        }
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        #[cfg(all(unix, target_pointer_width = "64"))]
+        if let Data::Mapped(ptr, len) = self.data {
+            // SAFETY: unmaps exactly the region mapped in `map`
+            unsafe {
+                sys::munmap(ptr as *mut _, len);
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_mapped_parsing() {
+    let file = MappedFile::open("tests/library/Nested/Solo - Short/Solo - Short.txt").unwrap();
+    assert_eq!(
+        file.is_mapped(),
+        cfg!(all(unix, target_pointer_width = "64"))
+    );
+    let song = file.song().unwrap();
+    assert_eq!(song.title, "Short");
+    assert_eq!(song.notes.len(), 2);
+    let text = std::fs::read("tests/duet.txt").unwrap();
+    assert_eq!(
+        &*MappedFile::open("tests/duet.txt").unwrap(),
+        text.as_slice()
+    );
+    assert!(MappedFile::open("tests/missing.txt").is_err());
+}
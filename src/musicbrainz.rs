@@ -0,0 +1,167 @@
+//! MusicBrainz metadata lookup
+//!
+//! Searches the MusicBrainz recording index for a song's artist and title and
+//! returns the matches as suggestions. Nothing is written back to the song.
+//!
+//! The crate does no networking itself: the caller passes a fetcher that
+//! performs the HTTP GET (MusicBrainz asks for a descriptive `User-Agent`
+//! and at most one request per second) and returns the response body.
+use crate::json::Value;
+use crate::Song;
+use anyhow::Result;
+
+/// Search endpoint of the MusicBrainz web service
+pub const SEARCH_URL: &str = "https://musicbrainz.org/ws/2/recording";
+
+/// One recording matching a lookup
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// MusicBrainz recording ID
+    pub id: String,
+    /// Search relevance from 0 to 100
+    pub score: u8,
+    /// Artist credit as MusicBrainz spells it, including join phrases
+    pub artist: String,
+    pub title: String,
+    /// Year of the first release containing the recording
+    pub year: Option<String>,
+    /// Most voted tag of the recording
+    pub genre: Option<String>,
+}
+
+/// URL searching for recordings by `artist` with the title `title`
+pub fn search_url(artist: &str, title: &str, limit: usize) -> String {
+    let query = format!(
+        "artist:\"{}\" AND recording:\"{}\"",
+        escape_query(artist),
+        escape_query(title)
+    );
+    format!(
+        "{}?query={}&fmt=json&limit={}",
+        SEARCH_URL,
+        percent_encode(&query),
+        limit
+    )
+}
+
+/// Parse a recording search response into candidates, best match first
+pub fn parse_response(body: &str) -> Result<Vec<Candidate>> {
+    let json = Value::parse(body)?;
+    let mut ret = vec![];
+    for recording in json.get("recordings").map(Value::items).unwrap_or_default() {
+        let text = |key: &str| recording.get(key).and_then(Value::as_str);
+        let artist = recording
+            .get("artist-credit")
+            .map(Value::items)
+            .unwrap_or_default()
+            .iter()
+            .map(|credit| {
+                let name = credit
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let join = credit
+                    .get("joinphrase")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                format!("{}{}", name, join)
+            })
+            .collect::<String>();
+        let genre = recording
+            .get("tags")
+            .map(Value::items)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|tag| {
+                let count = tag.get("count").and_then(Value::as_f64).unwrap_or_default();
+                Some((count, tag.get("name")?.as_str()?))
+            })
+            .fold(None, |best: Option<(f64, &str)>, tag| match best {
+                Some(best) if best.0 >= tag.0 => Some(best),
+                _ => Some(tag),
+            })
+            .map(|a| a.1.to_string());
+        ret.push(Candidate {
+            id: text("id").unwrap_or_default().to_string(),
+            score: recording
+                .get("score")
+                .and_then(Value::as_f64)
+                .unwrap_or_default()
+                .clamp(0.0, 100.0) as u8,
+            artist,
+            title: text("title").unwrap_or_default().to_string(),
+            year: text("first-release-date")
+                .and_then(|a| a.get(..4))
+                .map(|a| a.to_string()),
+            genre,
+        });
+    }
+    ret.sort_by_key(|a| std::cmp::Reverse(a.score));
+    Ok(ret)
+}
+
+impl Song {
+    /// Look this song up on MusicBrainz, returning at most `limit` candidates
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    /// let candidates = song
+    ///     .musicbrainz_candidates(5, |_url| Ok(std::fs::read_to_string("tests/musicbrainz.json")?))
+    ///     .unwrap();
+    /// assert_eq!(candidates[0].year.as_deref(), Some("1975"));
+    /// ```
+    pub fn musicbrainz_candidates<F>(&self, limit: usize, fetch: F) -> Result<Vec<Candidate>>
+    where
+        F: FnOnce(&str) -> Result<String>,
+    {
+        let url = search_url(
+            self.artist.as_deref().unwrap_or_default(),
+            &self.title,
+            limit,
+        );
+        let mut candidates = parse_response(&fetch(&url)?)?;
+        candidates.truncate(limit);
+        Ok(candidates)
+    }
+}
+
+/// Escape Lucene query syntax inside a quoted phrase
+fn escape_query(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut ret = String::new();
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                ret.push(b as char)
+            }
+            _ => ret.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    ret
+}
+
+#[test]
+pub fn test_musicbrainz_lookup() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let mut requested = String::new();
+    let candidates = song
+        .musicbrainz_candidates(10, |url| {
+            requested = url.to_string();
+            Ok(std::fs::read_to_string("tests/musicbrainz.json")?)
+        })
+        .unwrap();
+    assert!(requested.starts_with(SEARCH_URL));
+    assert!(
+        requested.contains("artist%3A%22Queen%22%20AND%20recording%3A%22Bohemian%20Rhapsody%22")
+    );
+    assert_eq!(candidates.len(), 2);
+    assert_eq!(candidates[0].artist, "Queen");
+    assert_eq!(candidates[0].title, "Bohemian Rhapsody");
+    assert_eq!(candidates[0].genre.as_deref(), Some("rock"));
+    assert_eq!(candidates[1].artist, "Queen & David Bowie");
+    assert_eq!(candidates[1].year, None);
+}
@@ -0,0 +1,154 @@
+//! Unicode NFC for lyrics and headers
+//!
+//! Charts written on macOS often have decomposed letters: an `e` followed by
+//! a combining accent where other systems write `é` as one character. Both
+//! look the same but compare and search differently. [`nfc`] composes them
+//! in the cases charts run into: Latin letters with one diacritic, kana with
+//! voicing marks and Hangul syllables. There's no full Unicode table behind
+//! it, other sequences are kept as they are.
+use crate::Song;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Combining marks with the letters they compose with and the results, index by index
+const COMPOSITIONS: &[(char, &str, &str)] = &[
+    ('\u{300}', "AEIOUaeiouNnWwYy", "ÀÈÌÒÙàèìòùǸǹẀẁỲỳ"),
+    (
+        '\u{301}',
+        "AEIOUYaeiouyCcNnSsZzLlRrGg",
+        "ÁÉÍÓÚÝáéíóúýĆćŃńŚśŹźĹĺŔŕǴǵ",
+    ),
+    (
+        '\u{302}',
+        "AEIOUaeiouCcGgHhJjSsWwYy",
+        "ÂÊÎÔÛâêîôûĈĉĜĝĤĥĴĵŜŝŴŵŶŷ",
+    ),
+    ('\u{303}', "AONaonIiUuEeYy", "ÃÕÑãõñĨĩŨũẼẽỸỹ"),
+    ('\u{304}', "AaEeIiOoUu", "ĀāĒēĪīŌōŪū"),
+    ('\u{306}', "AaEeGgIiOoUu", "ĂăĔĕĞğĬĭŎŏŬŭ"),
+    ('\u{307}', "CcEeGgIZz", "ĊċĖėĠġİŻż"),
+    ('\u{308}', "AEIOUaeiouyY", "ÄËÏÖÜäëïöüÿŸ"),
+    ('\u{30a}', "AaUu", "ÅåŮů"),
+    ('\u{30b}', "OoUu", "ŐőŰű"),
+    ('\u{30c}', "CcDdEeNnRrSsTtZz", "ČčĎďĚěŇňŘřŠšŤťŽž"),
+    ('\u{326}', "SsTt", "ȘșȚț"),
+    ('\u{327}', "CcSsTtGgKkLlNnRr", "ÇçŞşŢţĢģĶķĻļŅņŖŗ"),
+    ('\u{328}', "AaEeIiUu", "ĄąĘęĮįŲų"),
+    (
+        '\u{3099}',
+        "かきくけこさしすせそたちつてとはひふへほうカキクケコサシスセソタチツテトハヒフヘホウワヰヱヲゝヽ",
+        "がぎぐげござじずぜぞだぢづでどばびぶべぼゔガギグゲゴザジズゼゾダヂヅデドバビブベボヴヷヸヹヺゞヾ",
+    ),
+    ('\u{309a}', "はひふへほハヒフヘホ", "ぱぴぷぺぽパピプペポ"),
+];
+
+const HANGUL_BASE: u32 = 0xac00;
+const HANGUL_LEADS: u32 = 0x1100;
+const HANGUL_VOWELS: u32 = 0x1161;
+/// One before the first trailing consonant, as syllables may have none
+const HANGUL_TAILS: u32 = 0x11a7;
+
+/// `first` and `second` as one character, if they compose
+fn compose(first: char, second: char) -> Option<char> {
+    let (a, b) = (first as u32, second as u32);
+    if (HANGUL_LEADS..HANGUL_LEADS + 19).contains(&a)
+        && (HANGUL_VOWELS..HANGUL_VOWELS + 21).contains(&b)
+    {
+        let syllable = ((a - HANGUL_LEADS) * 21 + b - HANGUL_VOWELS) * 28;
+        return char::from_u32(HANGUL_BASE + syllable);
+    }
+    if (HANGUL_BASE..HANGUL_BASE + 11172).contains(&a)
+        && (a - HANGUL_BASE).is_multiple_of(28)
+        && (HANGUL_TAILS + 1..HANGUL_TAILS + 28).contains(&b)
+    {
+        return char::from_u32(a + b - HANGUL_TAILS);
+    }
+    let (_, bases, composed) = COMPOSITIONS.iter().find(|a| a.0 == second)?;
+    let i = bases.chars().position(|c| c == first)?;
+    composed.chars().nth(i)
+}
+
+/// `text` with the decomposed letters this module knows composed
+/// ```rust
+/// use usdx_parser::nfc::nfc;
+///
+/// assert_eq!(nfc("Cafe\u{301}"), "Café");
+/// assert_eq!(nfc("\u{1100}\u{1161}"), "가");
+/// assert_eq!(nfc("Café"), "Café");
+/// ```
+pub fn nfc(text: &str) -> String {
+    let mut ret: Vec<char> = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match ret.last().and_then(|&last| compose(last, c)) {
+            Some(composed) => *ret.last_mut().unwrap() = composed,
+            None => ret.push(c),
+        }
+    }
+    ret.into_iter().collect()
+}
+
+/// Replace `text` by its NFC form, returning whether that changed it
+fn normalize(text: &mut String) -> bool {
+    let composed = nfc(text);
+    let changed = composed != *text;
+    *text = composed;
+    changed
+}
+
+impl Song {
+    /// Compose decomposed letters of the headers and lyrics, see [`nfc`]
+    ///
+    /// Returns how many headers and lyrics changed.
+    pub fn normalize_nfc(&mut self) -> usize {
+        let mut count = normalize(&mut self.title) as usize;
+        for text in [
+            &mut self.artist,
+            &mut self.mp3,
+            &mut self.video,
+            &mut self.edition,
+            &mut self.genre,
+            &mut self.year,
+            &mut self.language,
+            &mut self.cover,
+            &mut self.background,
+            &mut self.singer_p1,
+            &mut self.singer_p2,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            count += normalize(text) as usize;
+        }
+        for note in &mut self.notes {
+            let Some(lyric) = &note.lyric else {
+                continue;
+            };
+            let composed = nfc(lyric);
+            if composed != **lyric {
+                note.lyric = Some(composed.into());
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+#[test]
+pub fn test_nfc() {
+    for (_, bases, composed) in COMPOSITIONS {
+        assert_eq!(bases.chars().count(), composed.chars().count());
+    }
+    assert_eq!(nfc("Mo\u{308}tley Cru\u{308}e"), "Mötley Crüe");
+    assert_eq!(nfc("\u{1112}\u{1161}\u{11ab}"), "한");
+    assert_eq!(nfc("か\u{3099}ハ\u{309a}"), "がパ");
+    // Marks without a known composition stay
+    assert_eq!(nfc("q\u{301}\u{301}"), "q\u{301}\u{301}");
+
+    let text =
+        "#TITLE:Cafe\u{301}\n#ARTIST:Bjo\u{308}rk\n#BPM:100\n#GAP:0\n: 0 4 0 Ole\u{301}\nE\n";
+    let mut song: Song = text.parse().unwrap();
+    assert_eq!(song.normalize_nfc(), 3);
+    assert_eq!(song.artist.as_deref(), Some("Björk"));
+    assert_eq!(song.notes[0].lyric.as_deref(), Some("Olé"));
+    assert_eq!(song.normalize_nfc(), 0);
+}
@@ -0,0 +1,295 @@
+//! Canonical forms of free-form `#LANGUAGE` and `#GENRE` values
+//!
+//! Community charts spell the same language as "English", "Englisch", "eng"
+//! or "EN", and genres just as freely. These tables map the common spellings
+//! to one language with its ISO 639 codes and to a small genre taxonomy, while
+//! the raw value is kept next to it for display.
+use crate::text::tokens;
+use crate::Song;
+
+/// Language with its ISO 639 codes
+#[derive(Debug, PartialEq, Eq)]
+pub struct Language {
+    /// English name
+    pub name: &'static str,
+    /// ISO 639-1 code
+    pub iso639_1: &'static str,
+    /// ISO 639-2/B code
+    pub iso639_2: &'static str,
+    /// Other spellings, in [`key`] form
+    aliases: &'static [&'static str],
+}
+
+macro_rules! languages {
+    ($($name:literal $one:literal $two:literal [$($alias:literal),*]),* $(,)?) => {
+        /// Every language the normalizer knows
+        pub const LANGUAGES: &[Language] = &[$(Language {
+            name: $name,
+            iso639_1: $one,
+            iso639_2: $two,
+            aliases: &[$($alias),*],
+        }),*];
+    };
+}
+
+languages! {
+    "English" "en" "eng" ["englisch", "anglais", "ingles", "inglese", "engels"],
+    "German" "de" "ger" ["deutsch", "deu", "allemand", "aleman", "tedesco", "duits"],
+    "French" "fr" "fre" ["francais", "fra", "franzosisch", "frances", "francese"],
+    "Spanish" "es" "spa" ["espanol", "castellano", "spanisch", "espagnol", "spagnolo"],
+    "Italian" "it" "ita" ["italiano", "italienisch", "italien"],
+    "Portuguese" "pt" "por" ["portugues", "portugiesisch", "portoghese"],
+    "Dutch" "nl" "dut" ["nederlands", "nld", "niederlandisch", "hollandisch", "flemish", "vlaams"],
+    "Polish" "pl" "pol" ["polski", "polnisch"],
+    "Russian" "ru" "rus" ["russkij", "russisch"],
+    "Ukrainian" "uk" "ukr" ["ukrainisch"],
+    "Czech" "cs" "cze" ["cestina", "ces", "tschechisch"],
+    "Slovak" "sk" "slo" ["slovencina", "slk", "slowakisch"],
+    "Slovenian" "sl" "slv" ["slovene", "slovenscina", "slowenisch"],
+    "Croatian" "hr" "hrv" ["hrvatski", "kroatisch"],
+    "Serbian" "sr" "srp" ["srpski", "serbisch"],
+    "Bulgarian" "bg" "bul" ["bulgarisch"],
+    "Romanian" "ro" "rum" ["romana", "ron", "rumanisch"],
+    "Hungarian" "hu" "hun" ["magyar", "ungarisch"],
+    "Greek" "el" "gre" ["ell", "griechisch"],
+    "Turkish" "tr" "tur" ["turkce", "turkisch"],
+    "Swedish" "sv" "swe" ["svenska", "schwedisch"],
+    "Norwegian" "no" "nor" ["norsk", "norwegisch"],
+    "Danish" "da" "dan" ["dansk", "danisch"],
+    "Finnish" "fi" "fin" ["suomi", "finnisch"],
+    "Icelandic" "is" "ice" ["islenska", "isl", "islandisch"],
+    "Estonian" "et" "est" ["eesti", "estnisch"],
+    "Latvian" "lv" "lav" ["latviesu", "lettisch"],
+    "Lithuanian" "lt" "lit" ["lietuviu", "litauisch"],
+    "Irish" "ga" "gle" ["gaeilge", "irisch"],
+    "Welsh" "cy" "wel" ["cymraeg", "walisisch"],
+    "Catalan" "ca" "cat" ["catala", "katalanisch"],
+    "Basque" "eu" "baq" ["euskara", "baskisch"],
+    "Galician" "gl" "glg" ["galego", "galicisch"],
+    "Luxembourgish" "lb" "ltz" ["letzebuergesch", "luxemburgisch"],
+    "Afrikaans" "af" "afr" [],
+    "Latin" "la" "lat" ["latein", "latina"],
+    "Hebrew" "he" "heb" ["hebraisch"],
+    "Arabic" "ar" "ara" ["arabisch"],
+    "Hindi" "hi" "hin" [],
+    "Japanese" "ja" "jpn" ["nihongo", "japanisch"],
+    "Korean" "ko" "kor" ["hangugeo", "koreanisch"],
+    "Chinese" "zh" "chi" ["zho", "mandarin", "cantonese", "chinesisch"],
+    "Vietnamese" "vi" "vie" ["vietnamesisch"],
+    "Indonesian" "id" "ind" ["bahasa indonesia", "indonesisch"],
+    "Tagalog" "tl" "tgl" ["filipino"],
+}
+
+/// Canonical genres and their other spellings, in [`key`] form
+const GENRES: &[(&str, &[&str])] = &[
+    (
+        "Pop",
+        &[
+            "pop music",
+            "schlagerpop",
+            "dance pop",
+            "synthpop",
+            "synth pop",
+            "k pop",
+            "j pop",
+            "kpop",
+            "jpop",
+        ],
+    ),
+    (
+        "Rock",
+        &[
+            "hard rock",
+            "rock n roll",
+            "rocknroll",
+            "rock and roll",
+            "soft rock",
+            "classic rock",
+            "pop rock",
+        ],
+    ),
+    (
+        "Alternative",
+        &[
+            "alternative rock",
+            "grunge",
+            "indie",
+            "indie rock",
+            "alt rock",
+        ],
+    ),
+    ("Punk", &["punk rock", "pop punk", "punkrock"]),
+    ("Metal", &["heavy metal", "hardrock metal", "nu metal"]),
+    ("Hip-Hop", &["hip hop", "hiphop", "rap"]),
+    (
+        "R&B",
+        &["r b", "rnb", "r n b", "rhythm and blues", "soul", "funk"],
+    ),
+    (
+        "Electronic",
+        &[
+            "electro",
+            "edm",
+            "techno",
+            "house",
+            "trance",
+            "dance",
+            "eurodance",
+            "drum and bass",
+            "dubstep",
+        ],
+    ),
+    ("Disco", &[]),
+    ("Jazz", &["swing"]),
+    ("Blues", &[]),
+    ("Country", &["country music"]),
+    ("Folk", &["volksmusik", "folk rock"]),
+    ("Schlager", &["deutscher schlager", "volkstumliche musik"]),
+    ("Classical", &["klassik", "classic", "opera", "oper"]),
+    (
+        "Soundtrack",
+        &[
+            "ost",
+            "film",
+            "movie",
+            "filmmusik",
+            "score",
+            "game",
+            "video game",
+            "anime",
+        ],
+    ),
+    ("Musical", &["musicals", "broadway"]),
+    ("Reggae", &["ska", "dancehall"]),
+    ("Latin", &["latin pop", "salsa", "reggaeton"]),
+    ("Children", &["kids", "kinder", "kinderlieder", "childrens"]),
+    ("Christmas", &["xmas", "weihnachten", "weihnachtslieder"]),
+    ("Gospel", &["christian", "worship"]),
+    ("World", &["world music", "weltmusik"]),
+];
+
+/// Folded words of `text` joined with single spaces, the form tables match on
+fn key(text: &str) -> String {
+    tokens(text).join(" ")
+}
+
+/// Raw header value together with its canonical form, if it has one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Normalized<T> {
+    pub raw: String,
+    pub canonical: Option<T>,
+}
+
+/// Raw values of a header that lists several languages or genres
+fn split(raw: &str) -> impl Iterator<Item = &str> {
+    raw.split([',', '/', ';', '|'])
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+}
+
+/// Language of a single `#LANGUAGE` value
+/// ```rust
+/// # use usdx_parser::normalize::language;
+/// assert_eq!(language("Englisch").unwrap().iso639_1, "en");
+/// assert_eq!(language("ENG").unwrap().name, "English");
+/// assert_eq!(language("Français").unwrap().iso639_2, "fre");
+/// assert!(language("Klingon").is_none());
+/// ```
+pub fn language(raw: &str) -> Option<&'static Language> {
+    let key = key(raw);
+    LANGUAGES.iter().find(|l| {
+        key == l.iso639_1
+            || key == l.iso639_2
+            || key == self::key(l.name)
+            || l.aliases.contains(&key.as_str())
+    })
+}
+
+/// Every language of a `#LANGUAGE` header like "English, German"
+pub fn languages(raw: &str) -> Vec<Normalized<&'static Language>> {
+    split(raw)
+        .map(|a| Normalized {
+            raw: a.to_string(),
+            canonical: language(a),
+        })
+        .collect()
+}
+
+/// Canonical genre of a single `#GENRE` value
+///
+/// Unknown values fall back to their longest known ending, so "80s Rock" is Rock.
+/// ```rust
+/// # use usdx_parser::normalize::genre;
+/// assert_eq!(genre("HipHop"), Some("Hip-Hop"));
+/// assert_eq!(genre("Rock 'n' Roll"), Some("Rock"));
+/// assert_eq!(genre("German Pop"), Some("Pop"));
+/// assert_eq!(genre("Polka"), None);
+/// ```
+pub fn genre(raw: &str) -> Option<&'static str> {
+    let words = tokens(raw);
+    (0..words.len()).find_map(|start| {
+        let key = words[start..].join(" ");
+        GENRES
+            .iter()
+            .find(|g| key == self::key(g.0) || g.1.contains(&key.as_str()))
+            .map(|g| g.0)
+    })
+}
+
+/// Every genre of a `#GENRE` header like "Pop/Rock"
+pub fn genres(raw: &str) -> Vec<Normalized<&'static str>> {
+    split(raw)
+        .map(|a| Normalized {
+            raw: a.to_string(),
+            canonical: genre(a),
+        })
+        .collect()
+}
+
+impl Song {
+    /// Normalized languages of the `#LANGUAGE` header
+    pub fn languages(&self) -> Vec<Normalized<&'static Language>> {
+        self.language.as_deref().map(languages).unwrap_or_default()
+    }
+
+    /// Normalized genres of the `#GENRE` header
+    pub fn genres(&self) -> Vec<Normalized<&'static str>> {
+        self.genre.as_deref().map(genres).unwrap_or_default()
+    }
+}
+
+#[test]
+pub fn test_normalization() {
+    let mut song = Song::new("Title", 100.0, 0);
+    song.language = Some("english / Deutsch, elbisch".to_string());
+    song.genre = Some("R'n'B;Pop-Rock".to_string());
+    let languages = song.languages();
+    assert_eq!(
+        languages
+            .iter()
+            .map(|l| l.canonical.map(|a| a.iso639_1))
+            .collect::<Vec<_>>(),
+        [Some("en"), Some("de"), None]
+    );
+    assert_eq!(languages[2].raw, "elbisch");
+    assert_eq!(
+        song.genres(),
+        [
+            Normalized {
+                raw: "R'n'B".to_string(),
+                canonical: Some("R&B")
+            },
+            Normalized {
+                raw: "Pop-Rock".to_string(),
+                canonical: Some("Rock")
+            }
+        ]
+    );
+    // Every spelling has to resolve to one language only
+    for l in LANGUAGES {
+        for name in [l.iso639_1, l.iso639_2].iter().chain(l.aliases) {
+            assert_eq!(language(name), Some(l), "{}", name);
+        }
+    }
+    assert!(Song::new("Title", 100.0, 0).genres().is_empty());
+}
@@ -0,0 +1,117 @@
+//! How leniently charts are read
+//!
+//! Headers belong before the first note; hand-edited charts sometimes have
+//! stray ones further down, which are read with a logged warning by default
+//! and rejected in strict mode. The same goes for a missing `#GAP`, which is
+//! read as 0 like USDX does. Anything after the closing `E` line is ignored
+//! either way.
+//!
+//! Headers without a value, like a bare `#VIDEO:`, are treated as absent by
+//! default, which is how the games read them. [`EmptyHeaders`] can keep
+//! them instead, with or without a warning.
+//!
+//! Whitespace around header values is cut, as a stray space after the file
+//! name in `#MP3:` keeps the game from finding the audio. Keeping it exactly
+//! makes for lossless round trips of hand-edited charts.
+//!
+//! Decomposed letters, which editors on macOS tend to write, can be composed
+//! to NFC while parsing, see [`crate::nfc`].
+
+/// What to do with optional headers whose value is empty or only whitespace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyHeaders {
+    /// Read them as if the header was missing, so a later one can set it
+    #[default]
+    Absent,
+    /// Keep the value as it is, which is written back unchanged
+    Keep,
+    /// Keep the value and log a warning
+    Warn,
+}
+
+/// Switches for reading charts, all off by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ParseOptions {
+    /// Reject headers after the first note and charts without `#GAP`
+    /// instead of warning about them
+    pub strict: bool,
+    /// Treatment of optional headers without a value
+    pub empty_headers: EmptyHeaders,
+    /// Keep whitespace around header values instead of trimming it
+    pub keep_header_whitespace: bool,
+    /// Compose decomposed letters in headers and lyrics, see [`crate::nfc`];
+    /// only applies to owned songs
+    pub normalize_nfc: bool,
+}
+
+#[test]
+pub fn test_parse_options() {
+    use crate::{CompatProfile, ParseLimits, Song};
+
+    let parse = |text: &str, options| {
+        Song::from_str_with_options(
+            text,
+            CompatProfile::default(),
+            &ParseLimits::unlimited(),
+            &options,
+        )
+    };
+    let late = "#TITLE:T\n#BPM:100\n: 0 4 0 a\n#GAP:20\n: 4 4 0 b\nE\n: 8 4 0 c\n#ARTIST:A\n";
+    let song = parse(late, ParseOptions::default()).unwrap();
+    assert_eq!(song.gap, 20);
+    assert_eq!(song.notes.len(), 2);
+    assert_eq!(song.artist, None);
+    let strict = ParseOptions {
+        strict: true,
+        ..Default::default()
+    };
+    let error = parse(late, strict).unwrap_err();
+    assert_eq!(error.to_string(), "Header on line 4 comes after the notes");
+    let no_gap = "#TITLE:T\n#BPM:100\n: 0 4 0 a\nE\n";
+    assert_eq!(parse(no_gap, ParseOptions::default()).unwrap().gap, 0);
+    let error = parse(no_gap, strict).unwrap_err();
+    assert_eq!(error.to_string(), "No gap specified!");
+
+    let empty = "#TITLE:T\n#VIDEO:\n#COVER: \n#COVER:c.jpg\n#BPM:100\n#GAP:0\nE\n";
+    let song = parse(empty, ParseOptions::default()).unwrap();
+    assert_eq!(
+        (song.video.as_deref(), song.cover.as_deref()),
+        (None, Some("c.jpg"))
+    );
+    assert_eq!(
+        song.to_string(),
+        "#TITLE:T\n#COVER:c.jpg\n#BPM:100\n#GAP:0\nE\n"
+    );
+    let keep = ParseOptions {
+        empty_headers: EmptyHeaders::Keep,
+        ..Default::default()
+    };
+    let song = parse(empty, keep).unwrap();
+    assert_eq!(song.video.as_deref(), Some(""));
+    assert_eq!(song.cover.as_deref(), Some(""));
+
+    let spaced = "#TITLE: T \n#MP3:song.mp3 \n#BPM: 100\n#GAP:0 \nE\n";
+    let song = parse(spaced, ParseOptions::default()).unwrap();
+    assert_eq!(
+        (song.title.as_str(), song.mp3.as_deref()),
+        ("T", Some("song.mp3"))
+    );
+    let lossless = ParseOptions {
+        keep_header_whitespace: true,
+        ..Default::default()
+    };
+    let text = "#TITLE: T \n#MP3:song.mp3 \n#BPM:100\n#GAP:0\n: 0 1 0 a\nE\n";
+    let song = parse(text, lossless).unwrap();
+    assert_eq!(song.to_string(), text);
+
+    let decomposed = "#TITLE:Cafe\u{301}\n#BPM:100\n#GAP:0\nE\n";
+    assert_eq!(
+        parse(decomposed, ParseOptions::default()).unwrap().title,
+        "Cafe\u{301}"
+    );
+    let nfc = ParseOptions {
+        normalize_nfc: true,
+        ..Default::default()
+    };
+    assert_eq!(parse(decomposed, nfc).unwrap().title, "Café");
+}
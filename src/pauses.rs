@@ -0,0 +1,129 @@
+//! Pauses between notes and sentences
+//!
+//! How much room a chart leaves to breathe says a lot about how singable it
+//! is. [`Song::pauses`] measures the silence between consecutive notes of a
+//! sentence and between consecutive sentences of a singer, and points out
+//! sentences that run into the next one without a pause long enough to
+//! take a breath.
+use crate::Song;
+use alloc::vec::Vec;
+
+/// Shortest pause that leaves time to breathe, in milliseconds
+pub const BREATH_MS: f64 = 200.0;
+
+/// Spread of a set of pauses, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PauseStats {
+    pub min: f64,
+    pub median: f64,
+    pub max: f64,
+}
+
+/// Pauses of a whole chart
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Pauses {
+    /// Between consecutive notes of a sentence, `None` without any
+    pub notes: Option<PauseStats>,
+    /// Between consecutive sentences of a singer, `None` without any
+    pub sentences: Option<PauseStats>,
+    /// Indices into [`Song::sentences`] of sentences that have no pause of
+    /// [`BREATH_MS`] inside them or before the next one
+    pub breathless: Vec<usize>,
+}
+
+impl PauseStats {
+    fn of(mut pauses: Vec<f64>) -> Option<Self> {
+        if pauses.is_empty() {
+            return None;
+        }
+        pauses.sort_by(f64::total_cmp);
+        let half = pauses.len() / 2;
+        let median = match pauses.len() % 2 {
+            0 => (pauses[half - 1] + pauses[half]) / 2.0,
+            _ => pauses[half],
+        };
+        Some(Self {
+            min: pauses[0],
+            median,
+            max: pauses[pauses.len() - 1],
+        })
+    }
+}
+
+impl Song {
+    /// Statistics of the pauses in the chart; overlapping notes count as no pause
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// let pauses = song.pauses();
+    /// // "Hel" and "lo" follow each other directly, "Hi" and " back" 96 ms apart
+    /// assert_eq!(pauses.notes.unwrap().min, 0.0);
+    /// assert_eq!(pauses.notes.unwrap().max, 96.0);
+    /// // "lo" ends 192 ms before " there"
+    /// assert_eq!(pauses.breathless, [0]);
+    /// ```
+    pub fn pauses(&self) -> Pauses {
+        let ms = |beat: u32| self.beat_to_ms(beat as f64);
+        let pause = |end: u32, start: u32| (ms(start) - ms(end)).max(0.0);
+        let sentences = self.sentences();
+        let mut notes = Vec::new();
+        let mut between = Vec::new();
+        let mut breathless = Vec::new();
+        for (i, sentence) in sentences.iter().enumerate() {
+            let mut longest: f64 = 0.0;
+            for pair in sentence.notes.windows(2) {
+                let end = pair[0]
+                    .beat_number
+                    .saturating_add(pair[0].note_length.unwrap_or_default());
+                let p = pause(end, pair[1].beat_number);
+                longest = longest.max(p);
+                notes.push(p);
+            }
+            let next = sentences[i + 1..]
+                .iter()
+                .find(|s| s.voice == sentence.voice);
+            match next {
+                Some(next) => {
+                    let p = pause(sentence.end_beat(), next.start_beat());
+                    longest = longest.max(p);
+                    between.push(p);
+                }
+                None => longest = f64::INFINITY,
+            }
+            if longest < BREATH_MS {
+                breathless.push(i);
+            }
+        }
+        Pauses {
+            notes: PauseStats::of(notes),
+            sentences: PauseStats::of(between),
+            breathless,
+        }
+    }
+}
+
+#[test]
+pub fn test_pauses() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let pauses = song.pauses();
+    let notes = pauses.notes.unwrap();
+    let sentences = pauses.sentences.unwrap();
+    assert!(notes.min <= notes.median && notes.median <= notes.max);
+    assert!(sentences.min <= sentences.median && sentences.median <= sentences.max);
+    assert!(sentences.median > notes.median);
+    assert!(pauses.breathless.len() < song.sentences().len());
+
+    let text =
+        "#TITLE:T\n#BPM:150\n#GAP:0\n: 0 4 0 a\n: 4 4 0 b\n- 8\n: 8 4 0 c\n- 20\n: 20 4 0 d\nE\n";
+    let song: Song = text.parse().unwrap();
+    let pauses = song.pauses();
+    // 100 ms per beat: the first line runs straight into the second
+    assert_eq!(pauses.breathless, [0]);
+    let sentences = pauses.sentences.unwrap();
+    assert_eq!(
+        (sentences.min, sentences.median, sentences.max),
+        (0.0, 400.0, 800.0)
+    );
+    assert_eq!(Song::new("Empty", 100.0, 0).pauses(), Pauses::default());
+}
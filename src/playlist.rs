@@ -0,0 +1,159 @@
+//! UltraStar playlist (`.upl`) files
+//!
+//! A playlist starts with `#Name:` followed by one `Artist : Title` line per
+//! song. Other lines starting with `#` are treated as comments.
+use crate::library::SongLibrary;
+use crate::query::Filter;
+use crate::Song;
+use anyhow::{bail, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Playlist information
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Playlist {
+    pub name: String,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+/// Song reference inside a playlist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaylistEntry {
+    pub artist: String,
+    pub title: String,
+}
+
+impl PlaylistEntry {
+    /// Whether this entry refers to `song`, ignoring case and surrounding whitespace
+    pub fn matches(&self, song: &Song) -> bool {
+        let same = |a: &str, b: &str| a.trim().to_lowercase() == b.trim().to_lowercase();
+        same(song.artist.as_deref().unwrap_or_default(), &self.artist)
+            && same(&song.title, &self.title)
+    }
+}
+
+impl Playlist {
+    /// Parse playlist from file
+    pub fn from_file(path: &str) -> Result<Playlist> {
+        let string = std::fs::read_to_string(path)?;
+        Playlist::from_str(&string)
+    }
+
+    /// Write the playlist to a `.upl` file
+    pub fn write_file(&self, path: &str) -> Result<()> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Looks up every entry in `songs`, keeping the playlist order
+    /// ```rust
+    /// use usdx_parser::playlist::Playlist;
+    /// use usdx_parser::Song;
+    /// use std::str::FromStr;
+    ///
+    /// let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    /// let playlist = Playlist::from_str("#Name: Rock\nqueen : bohemian rhapsody\nNobody : Nothing\n").unwrap();
+    /// let resolved = playlist.resolve(std::slice::from_ref(&song));
+    /// assert!(resolved[0].is_some());
+    /// assert!(resolved[1].is_none());
+    /// ```
+    pub fn resolve<'a, I>(&self, songs: I) -> Vec<Option<&'a Song>>
+    where
+        I: IntoIterator<Item = &'a Song>,
+        I::IntoIter: Clone,
+    {
+        let songs = songs.into_iter();
+        self.entries
+            .iter()
+            .map(|e| songs.clone().find(|s| e.matches(s)))
+            .collect()
+    }
+
+    /// Appends an entry for `song`
+    pub fn push(&mut self, song: &Song) {
+        self.entries.push(PlaylistEntry {
+            artist: song.artist.clone().unwrap_or_default(),
+            title: song.title.clone(),
+        });
+    }
+}
+
+impl SongLibrary {
+    /// Playlist called `name` of every song matching `filter`, in library order
+    /// ```rust
+    /// use usdx_parser::library::SongLibrary;
+    /// use usdx_parser::query::Filter;
+    /// use std::str::FromStr;
+    ///
+    /// let library = SongLibrary::scan("tests/library").unwrap();
+    /// let filter = Filter::from_str("language:english duet:yes").unwrap();
+    /// let playlist = library.make_playlist(&filter, "English duets");
+    /// assert_eq!(playlist.to_string(), "#Name: English duets\n#Songs:\nVarious : Duet Test\n");
+    /// ```
+    pub fn make_playlist(&self, filter: &Filter, name: &str) -> Playlist {
+        let mut playlist = Playlist {
+            name: name.to_string(),
+            entries: Vec::new(),
+        };
+        for entry in self.filter(filter) {
+            playlist.push(&entry.song);
+        }
+        playlist
+    }
+}
+
+impl FromStr for Playlist {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut playlist = Playlist::default();
+        for line in s.lines().map(|a| a.trim()).filter(|a| !a.is_empty()) {
+            if let Some(name) = line.strip_prefix("#Name:") {
+                playlist.name = name.trim().to_string();
+                continue;
+            }
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((artist, title)) = line.split_once(" : ") else {
+                bail!("Playlist entry is not in 'Artist : Title' form: {}", line);
+            };
+            playlist.entries.push(PlaylistEntry {
+                artist: artist.trim().to_string(),
+                title: title.trim().to_string(),
+            });
+        }
+        Ok(playlist)
+    }
+}
+
+impl fmt::Display for Playlist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "#Name: {}", self.name)?;
+        writeln!(f, "#Songs:")?;
+        for e in self.entries.iter() {
+            writeln!(f, "{} : {}", e.artist, e.title)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_playlist_serde() {
+    let text = "#Name: Party\n#Songs:\nQueen : Bohemian Rhapsody\nThree Days Grace : I Hate Everything About You\n";
+    let playlist = Playlist::from_str(text).unwrap();
+    assert_eq!(playlist.name, "Party");
+    assert_eq!(playlist.entries.len(), 2);
+    assert_eq!(playlist.to_string(), text);
+
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let crlf =
+        Playlist::from_str("#Name: Party\r\n#Songs:\r\nQUEEN : Bohemian Rhapsody\r\n").unwrap();
+    assert_eq!(crlf.entries[0].title, "Bohemian Rhapsody");
+    assert!(crlf.entries[0].matches(&song));
+    let spaced = PlaylistEntry {
+        artist: " Queen".to_string(),
+        title: "Bohemian Rhapsody\r".to_string(),
+    };
+    assert!(spaced.matches(&song));
+}
@@ -0,0 +1,171 @@
+//! Short snippets of a song for previews in song selection
+//!
+//! Games play a few seconds of the selected song, usually from
+//! `#PREVIEWSTART` or the chorus. [`Song::preview`] finds the sentences
+//! around such a start that last about the requested time and returns them
+//! as a chart of their own, with the times to play the audio for.
+//! [`Song::preview_start`] suggests a start for charts that have none.
+use crate::lyrics::Sentence;
+use crate::{Note, NoteType, Song};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Sentences of a song making up a preview
+#[derive(Debug, Clone)]
+pub struct Preview {
+    /// Start of the first sentence, in ms from the start of the audio
+    pub start_ms: f64,
+    /// End of the last sentence, in ms from the start of the audio
+    pub end_ms: f64,
+    /// The song with only the preview's notes; beats and `#GAP` are unchanged,
+    /// so the notes line up with the full audio
+    pub song: Song,
+}
+
+impl Song {
+    /// Start of the first sentence sung most often, which tends to be the
+    /// chorus, in ms from the start of the audio
+    ///
+    /// Without repeated sentences it's the start of the first one, `None`
+    /// for songs without notes.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:T\n#BPM:150\n#GAP:0\n: 0 2 0 verse\n- 4\n: 5 2 0 chorus\n\
+    ///     - 8\n: 10 2 0 bridge\n- 14\n: 15 2 0 chorus\nE\n";
+    /// let song: Song = text.parse().unwrap();
+    /// assert_eq!(song.preview_start(), Some(500.0));
+    /// ```
+    pub fn preview_start(&self) -> Option<f64> {
+        let sentences = self.sentences();
+        // Times sung and first start of every text
+        let mut texts = BTreeMap::<String, (usize, u32)>::new();
+        for sentence in &sentences {
+            let entry = texts
+                .entry(sentence.text().to_lowercase())
+                .or_insert((0, sentence.start_beat()));
+            entry.0 += 1;
+            entry.1 = entry.1.min(sentence.start_beat());
+        }
+        let (_, beat) = texts
+            .into_values()
+            .max_by_key(|&(count, beat)| (count, core::cmp::Reverse(beat)))?;
+        Some(self.beat_to_ms(beat as f64))
+    }
+
+    /// Whole sentences from around `start_ms` lasting at least `duration_ms`,
+    /// `None` for songs without notes
+    ///
+    /// `start_ms` is in ms from the start of the audio; `#PREVIEWSTART` is in
+    /// seconds. The preview starts with the sentence sung at `start_ms`, or
+    /// the next one when it falls into a pause, and without a start at the
+    /// one [`Song::preview_start`] suggests. Sentences are added until the
+    /// preview is long enough or the song ends; duet sentences overlapping
+    /// the preview are always part of it.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/duet.txt").unwrap();
+    /// let preview = song.preview(Some(1800.0), 1000.0).unwrap();
+    /// // "there" is sung from 1776 to 2064 ms, P2's "Hi back" until 2832 ms
+    /// assert_eq!((preview.start_ms, preview.end_ms), (1776.0, 2832.0));
+    /// assert_eq!(preview.song.voice_lyrics(usdx_parser::Voice::P2), "Hi back\n");
+    /// ```
+    pub fn preview(&self, start_ms: Option<f64>, duration_ms: f64) -> Option<Preview> {
+        let start_ms = match start_ms {
+            Some(a) => a,
+            None => self.preview_start()?,
+        };
+        let ms = |beat: u32| self.beat_to_ms(beat as f64);
+        let mut sentences = self.sentences();
+        sentences.sort_by_key(Sentence::start_beat);
+        let first = sentences
+            .iter()
+            .position(|s| ms(s.end_beat()) > start_ms)
+            .unwrap_or(sentences.len().checked_sub(1)?);
+        let start = sentences[first].start_beat();
+        let mut end = sentences[first].end_beat();
+        let mut last = first;
+        for (i, sentence) in sentences.iter().enumerate().skip(first + 1) {
+            if sentence.start_beat() >= end && ms(end) - ms(start) >= duration_ms {
+                break;
+            }
+            end = end.max(sentence.end_beat());
+            last = i;
+        }
+        let selected = &sentences[first..=last];
+        let kept = |note: &Note| {
+            selected
+                .iter()
+                .any(|s| s.notes.as_ptr_range().contains(&(note as *const Note)))
+        };
+
+        // Line breaks only between kept notes of the same singer
+        let mut notes: Vec<Note> = vec![];
+        for note in &self.notes {
+            let previous = notes.last();
+            if note.note_type == NoteType::LineBreak {
+                if previous
+                    .is_some_and(|n| n.note_type != NoteType::LineBreak && n.voice == note.voice)
+                {
+                    notes.push(note.clone());
+                }
+            } else if kept(note) {
+                if previous
+                    .is_some_and(|n| n.note_type == NoteType::LineBreak && n.voice != note.voice)
+                {
+                    notes.pop();
+                }
+                notes.push(note.clone());
+            }
+        }
+        if notes
+            .last()
+            .is_some_and(|n| n.note_type == NoteType::LineBreak)
+        {
+            notes.pop();
+        }
+
+        let mut song = self.clone();
+        song.notes = notes;
+        song.relative = false;
+        song.line_offsets.clear();
+        Some(Preview {
+            start_ms: ms(start),
+            end_ms: ms(end),
+            song,
+        })
+    }
+}
+
+#[test]
+pub fn test_preview() {
+    use crate::Voice;
+
+    let text = "#TITLE:T\n#BPM:150\n#GAP:0\n: 0 2 0 verse\n- 4\n: 5 2 0 chorus\n- 8\n\
+        : 10 2 0 bridge\n- 14\n: 15 2 0 chorus\n- 18\n: 20 30 0 outro\nE\n";
+    let song: Song = text.parse().unwrap();
+    // 100 ms per beat; without a start the preview begins with the chorus
+    let preview = song.preview(None, 1000.0).unwrap();
+    assert_eq!((preview.start_ms, preview.end_ms), (500.0, 1700.0));
+    assert_eq!(preview.song.lyrics(), "chorus\nbridge\nchorus\n");
+    assert_eq!(preview.song.notes[0].beat_number, 5);
+    // A start in a pause moves to the next sentence, one past the end to the last
+    let preview = song.preview(Some(1850.0), 1000.0).unwrap();
+    assert_eq!((preview.start_ms, preview.end_ms), (2000.0, 5000.0));
+    assert_eq!(
+        preview.song.to_string(),
+        "#TITLE:T\n#BPM:150\n#GAP:0\n: 20 30 0 outro\nE\n"
+    );
+    assert_eq!(song.preview(Some(1e9), 0.0).unwrap().song.notes.len(), 1);
+    assert!(Song::new("Empty", 100.0, 0).preview(None, 1000.0).is_none());
+
+    let duet = Song::from_file("tests/duet.txt").unwrap();
+    let preview = duet.preview(Some(0.0), 100.0).unwrap();
+    assert_eq!(preview.song.voice_lyrics(Voice::P1), "Hello\n");
+    assert_eq!(preview.song.voice_lyrics(Voice::P2), "");
+    let whole = duet.preview(Some(0.0), 1e9).unwrap();
+    assert_eq!(whole.song.to_string(), duet.to_string());
+}
@@ -0,0 +1,286 @@
+//! Song filters and the query language for them
+//!
+//! A query is a list of space separated terms that all have to match:
+//!
+//! - `word` or `"some words"` matches artist or title
+//! - `artist:`, `title:` and `edition:` match part of the header
+//! - `language:` and `genre:` match one of the listed languages or genres,
+//!   any spelling [`normalize`](crate::normalize) knows works
+//! - `year:` and `bpm:` take a number or an inclusive range like `1990..2000`,
+//!   `..1980` or `2000..`
+//! - `duet:yes` / `duet:no`
+//!
+//! Values may list alternatives separated by `|` (`genre:Rock|Metal`) and a
+//! leading `-` negates a term. Matching ignores case and diacritics.
+use crate::library::{SongEntry, SongLibrary};
+use crate::text::fold;
+use crate::{normalize, Song};
+use anyhow::{bail, Result};
+use std::str::FromStr;
+
+/// Header a [`Filter::Contains`] or [`Filter::Is`] looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    Artist,
+    Title,
+    Edition,
+    Genre,
+    Language,
+}
+
+impl TextField {
+    fn get(self, song: &Song) -> Option<&str> {
+        match self {
+            Self::Artist => song.artist.as_deref(),
+            Self::Title => Some(&song.title),
+            Self::Edition => song.edition.as_deref(),
+            Self::Genre => song.genre.as_deref(),
+            Self::Language => song.language.as_deref(),
+        }
+    }
+}
+
+/// Numeric property a [`Filter::Range`] looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberField {
+    /// First four digit number of `#YEAR`
+    Year,
+    Bpm,
+}
+
+impl NumberField {
+    fn get(self, song: &Song) -> Option<f64> {
+        match self {
+            Self::Year => year(song).map(f64::from),
+            Self::Bpm => Some(song.bpm as f64),
+        }
+    }
+}
+
+/// First four digit number of `#YEAR`, so "ca. 1995" is 1995
+pub(crate) fn year(song: &Song) -> Option<u32> {
+    let year = song.year.as_deref()?;
+    let start = year.find(|c: char| c.is_ascii_digit())?;
+    year.get(start..start + 4)?.parse().ok()
+}
+
+/// Composable condition on a song
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// Header contains the text
+    Contains(TextField, String),
+    /// Header equals the text
+    Is(TextField, String),
+    /// Artist or title contains the text
+    Text(String),
+    /// Number lies within the inclusive bounds
+    Range(NumberField, Option<f64>, Option<f64>),
+    Duet(bool),
+    All(Vec<Filter>),
+    Any(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn and(self, other: Filter) -> Filter {
+        match self {
+            Filter::All(mut a) => {
+                a.push(other);
+                Filter::All(a)
+            }
+            a => Filter::All(vec![a, other]),
+        }
+    }
+
+    pub fn or(self, other: Filter) -> Filter {
+        match self {
+            Filter::Any(mut a) => {
+                a.push(other);
+                Filter::Any(a)
+            }
+            a => Filter::Any(vec![a, other]),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Filter {
+        Filter::Not(Box::new(self))
+    }
+
+    pub fn matches(&self, song: &Song) -> bool {
+        match self {
+            Self::Contains(field, text) => field
+                .get(song)
+                .is_some_and(|a| fold(a).contains(&fold(text))),
+            Self::Is(TextField::Language, text) => {
+                let wanted = normalize::language(text);
+                song.languages()
+                    .iter()
+                    .any(|l| match (l.canonical, wanted) {
+                        (Some(a), Some(b)) => a == b,
+                        _ => fold(&l.raw) == fold(text),
+                    })
+            }
+            Self::Is(TextField::Genre, text) => {
+                let wanted = normalize::genre(text);
+                song.genres().iter().any(|g| match (g.canonical, wanted) {
+                    (Some(a), Some(b)) => a == b,
+                    _ => fold(&g.raw) == fold(text),
+                })
+            }
+            Self::Is(field, text) => field
+                .get(song)
+                .is_some_and(|a| fold(a.trim()) == fold(text)),
+            Self::Text(text) => {
+                Self::Contains(TextField::Artist, text.clone()).matches(song)
+                    || Self::Contains(TextField::Title, text.clone()).matches(song)
+            }
+            Self::Range(field, low, high) => field.get(song).is_some_and(|a| {
+                low.is_none_or(|low| a >= low) && high.is_none_or(|high| a <= high)
+            }),
+            Self::Duet(duet) => song.is_duet() == *duet,
+            Self::All(a) => a.iter().all(|f| f.matches(song)),
+            Self::Any(a) => a.iter().any(|f| f.matches(song)),
+            Self::Not(a) => !a.matches(song),
+        }
+    }
+}
+
+/// Splits a query into terms, keeping quoted parts together
+fn terms(query: &str) -> Result<Vec<String>> {
+    let mut terms = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    terms.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if quoted {
+        bail!("Unterminated quote in query");
+    }
+    if !current.is_empty() {
+        terms.push(current);
+    }
+    Ok(terms)
+}
+
+fn parse_bound(text: &str) -> Result<Option<f64>> {
+    let text = text.trim().replace(',', ".");
+    if text.is_empty() {
+        return Ok(None);
+    }
+    match text.parse() {
+        Ok(a) => Ok(Some(a)),
+        Err(_) => bail!("Invalid number in query: {}", text),
+    }
+}
+
+fn parse_value(key: &str, value: &str) -> Result<Filter> {
+    let text = |field| Ok(Filter::Contains(field, value.to_string()));
+    let exact = |field| Ok(Filter::Is(field, value.to_string()));
+    let range = |field| {
+        let (low, high) = match value.split_once("..") {
+            Some((low, high)) => (parse_bound(low)?, parse_bound(high)?),
+            None => {
+                let a = parse_bound(value)?;
+                (a, a)
+            }
+        };
+        Ok(Filter::Range(field, low, high))
+    };
+    match key.to_lowercase().as_str() {
+        "artist" => text(TextField::Artist),
+        "title" => text(TextField::Title),
+        "edition" => text(TextField::Edition),
+        "genre" => exact(TextField::Genre),
+        "language" | "lang" => exact(TextField::Language),
+        "year" => range(NumberField::Year),
+        "bpm" => range(NumberField::Bpm),
+        "duet" => match value.to_lowercase().as_str() {
+            "yes" | "true" => Ok(Filter::Duet(true)),
+            "no" | "false" => Ok(Filter::Duet(false)),
+            _ => bail!("duet: expects yes or no, not {}", value),
+        },
+        _ => bail!("Unknown query key: {}", key),
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    /// ```rust
+    /// use usdx_parser::query::Filter;
+    /// use usdx_parser::Song;
+    /// use std::str::FromStr;
+    ///
+    /// let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    /// let filter = Filter::from_str("queen language:english -genre:Pop bpm:200..300").unwrap();
+    /// assert!(filter.matches(&song));
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filters = vec![];
+        for term in terms(s)? {
+            let (negated, term) = match term.strip_prefix('-') {
+                Some(a) if !a.is_empty() => (true, a),
+                _ => (false, term.as_str()),
+            };
+            let mut alternatives = vec![];
+            match term.split_once(':') {
+                Some((key, values)) => {
+                    for value in values.split('|') {
+                        alternatives.push(parse_value(key, value)?);
+                    }
+                }
+                None => alternatives.push(Filter::Text(term.to_string())),
+            }
+            let filter = if alternatives.len() == 1 {
+                alternatives.remove(0)
+            } else {
+                Filter::Any(alternatives)
+            };
+            filters.push(if negated { filter.not() } else { filter });
+        }
+        Ok(Filter::All(filters))
+    }
+}
+
+impl SongLibrary {
+    /// Songs matching `filter`, in library order
+    pub fn filter<'a>(&'a self, filter: &'a Filter) -> impl Iterator<Item = &'a SongEntry> + 'a {
+        self.songs.iter().filter(|e| filter.matches(&e.song))
+    }
+}
+
+#[test]
+pub fn test_query_language() {
+    let library = SongLibrary::scan("tests/library").unwrap();
+    let titles = |query: &str| {
+        let filter = Filter::from_str(query).unwrap();
+        library
+            .filter(&filter)
+            .map(|e| e.song.title.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(titles("duet:yes"), ["Duet Test"]);
+    assert_eq!(titles("-duet:yes"), ["Short"]);
+    assert_eq!(titles("bpm:..250"), ["Short"]);
+    assert_eq!(titles("artist:solo|various"), ["Short", "Duet Test"]);
+    assert_eq!(titles("\"duet test\" language:ENGLISH"), ["Duet Test"]);
+    assert_eq!(titles("language:englisch|eng"), ["Duet Test"]);
+    assert!(titles("year:1990..2000").is_empty());
+    assert!(Filter::from_str("colour:red").is_err());
+    assert!(Filter::from_str("year:abc").is_err());
+
+    let mut song = Song::new("Old", 100.0, 0);
+    song.year = Some("ca. 1995".to_string());
+    let nineties = Filter::Range(NumberField::Year, Some(1990.0), Some(1999.0));
+    assert!(nineties.clone().matches(&song));
+    assert!(!nineties.and(Filter::Duet(true)).matches(&song));
+}
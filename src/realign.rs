@@ -0,0 +1,212 @@
+//! Corrected lyrics onto existing notes
+//!
+//! Fixing typos in a finished chart means retyping every affected syllable.
+//! [`Song::realign_lyrics`] takes the corrected text as a plain lyric sheet,
+//! one line per sentence like [`Song::lyrics`] writes it, and puts its words
+//! onto the notes of the matching words. Timing and pitch stay as they are.
+//! Punctuation standing alone in the sheet belongs to the word before it.
+//!
+//! A word sung on several notes is split like the old word was: every note
+//! keeps as many characters as it had and the last one takes the rest.
+//! Hyphens split it explicitly instead, when they give exactly one part per
+//! note, so `fan-ta-sy` puts one part on each of three notes. Holds and the
+//! spaces around syllables are kept.
+use crate::{Song, Voice};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Part of a lyric sheet that didn't fit the notes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The sheet has another number of lines than there are sentences;
+    /// the lines both have were still aligned
+    Lines { expected: usize, found: usize },
+    /// A line has another number of words than its sentence, which was left as it was
+    Words {
+        sentence: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl Song {
+    /// Put the words of `sheet` onto the notes, returning what didn't fit
+    ///
+    /// Lines of the sheet match the sentences of every voice in the order of
+    /// the notes; empty lines are skipped.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let mut song = Song::from_file("tests/duet.txt").unwrap();
+    /// let problems = song.realign_lyrics("Jello\nthere\nHi back\nyeah\n");
+    /// assert!(problems.is_empty());
+    /// assert_eq!(song.notes[0].lyric.as_deref(), Some("Jel"));
+    /// ```
+    pub fn realign_lyrics(&mut self, sheet: &str) -> Vec<Mismatch> {
+        self.realign(sheet, |_| true)
+    }
+
+    /// Like [`Song::realign_lyrics`], for the sentences of one duet singer
+    pub fn realign_voice_lyrics(&mut self, voice: Voice, sheet: &str) -> Vec<Mismatch> {
+        self.realign(sheet, |v| v == Some(voice))
+    }
+
+    fn realign(&mut self, sheet: &str, voice: impl Fn(Option<Voice>) -> bool) -> Vec<Mismatch> {
+        let mut sentences: Vec<Vec<Vec<usize>>> = Vec::new();
+        let mut last = None;
+        for (sentence, word) in self.word_notes() {
+            if !voice(self.notes[word[0]].voice) {
+                continue;
+            }
+            if last != Some(sentence) {
+                sentences.push(Vec::new());
+                last = Some(sentence);
+            }
+            if let Some(words) = sentences.last_mut() {
+                words.push(word);
+            }
+        }
+        let lines: Vec<&str> = sheet
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect();
+        let mut ret = Vec::new();
+        if lines.len() != sentences.len() {
+            ret.push(Mismatch::Lines {
+                expected: sentences.len(),
+                found: lines.len(),
+            });
+        }
+        for (sentence, (line, words)) in lines.iter().zip(&sentences).enumerate() {
+            let tokens = words_of(line);
+            if tokens.len() != words.len() {
+                ret.push(Mismatch::Words {
+                    sentence,
+                    expected: words.len(),
+                    found: tokens.len(),
+                });
+                continue;
+            }
+            for (token, notes) in tokens.iter().zip(words) {
+                self.put_word(token, notes);
+            }
+        }
+        ret
+    }
+
+    /// Spread `word` over the notes at `indices`
+    fn put_word(&mut self, word: &str, indices: &[usize]) {
+        // Every lyric split into what stays around the text and the text
+        let parts: Vec<(&str, &str, &str)> = indices
+            .iter()
+            .map(|&i| {
+                let lyric = self.notes[i].lyric.as_deref().unwrap_or_default();
+                let text = lyric.trim_start();
+                let text = text.strip_prefix('~').unwrap_or(text).trim_start();
+                let start = lyric.len() - text.len();
+                let end = start + text.trim_end().len();
+                (&lyric[..start], &lyric[start..end], &lyric[end..])
+            })
+            .collect();
+        let receivers: Vec<usize> = (0..parts.len())
+            .filter(|&i| !parts[i].1.is_empty())
+            .collect();
+        let receivers = match receivers.is_empty() {
+            true => [0].to_vec(),
+            false => receivers,
+        };
+        let mut pieces: Vec<String> = (0..parts.len()).map(|_| String::new()).collect();
+        let explicit: Vec<&str> = word.split('-').collect();
+        if explicit.len() > 1 && explicit.len() == receivers.len() {
+            for (&i, piece) in receivers.iter().zip(explicit) {
+                pieces[i].push_str(piece);
+            }
+        } else {
+            let mut rest = word;
+            for (n, &i) in receivers.iter().enumerate() {
+                if n + 1 == receivers.len() {
+                    pieces[i].push_str(rest);
+                    break;
+                }
+                let split = rest
+                    .char_indices()
+                    .nth(parts[i].1.chars().count())
+                    .map_or(rest.len(), |(a, _)| a);
+                pieces[i].push_str(&rest[..split]);
+                rest = &rest[split..];
+            }
+        }
+        let lyrics: Vec<String> = parts
+            .iter()
+            .zip(pieces)
+            .map(|((before, _, after), piece)| [*before, &piece, *after].concat())
+            .collect();
+        for (&i, lyric) in indices.iter().zip(lyrics) {
+            self.notes[i].lyric = Some(lyric.into());
+        }
+    }
+}
+
+/// Words of a sheet line, with punctuation standing alone joined to the word before
+fn words_of(line: &str) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    for token in line.split_whitespace() {
+        match ret.last_mut() {
+            Some(word) if !token.chars().any(char::is_alphanumeric) => word.push_str(token),
+            _ => ret.push(token.into()),
+        }
+    }
+    ret
+}
+
+#[test]
+pub fn test_realign_lyrics() {
+    let mut song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let original = song.clone();
+    let sheet = song.lyrics().replacen("fantasy", "fan-ta-see", 1);
+    let sheet = sheet.replacen("poor boy", "pure joy", 1);
+    assert_eq!(song.realign_lyrics(&sheet), []);
+    let lyric = |song: &Song, i: usize| song.notes[i].lyric.as_deref().unwrap().to_string();
+    assert_eq!(
+        [lyric(&song, 9), lyric(&song, 10), lyric(&song, 11)],
+        [" fan", "ta", "see?"]
+    );
+    // " poor", " bo", "~y," keep their spaces and the hold
+    let poor = original
+        .notes
+        .iter()
+        .position(|n| n.lyric.as_deref() == Some(" poor"))
+        .unwrap();
+    let joy: Vec<_> = (poor..poor + 3).map(|i| lyric(&song, i)).collect();
+    assert_eq!(joy, [" pure", " jo", "~y,"]);
+    assert!(song.lyrics().contains("I'm just a pure joy,\n"));
+    for (a, b) in song.notes.iter().zip(&original.notes) {
+        assert_eq!((a.beat_number, a.note_tone), (b.beat_number, b.note_tone));
+    }
+
+    let mut song = original.clone();
+    let problems = song.realign_lyrics("Was this the real life?\nIs this just\n");
+    assert_eq!(song.notes[0].lyric.as_deref(), Some("Was"));
+    assert_eq!(song.notes[9].lyric, original.notes[9].lyric);
+    let sentences = original.sentences().len();
+    assert_eq!(
+        problems,
+        [
+            Mismatch::Lines {
+                expected: sentences,
+                found: 2
+            },
+            Mismatch::Words {
+                sentence: 1,
+                expected: 4,
+                found: 3
+            }
+        ]
+    );
+
+    let mut duet = Song::from_file("tests/duet.txt").unwrap();
+    assert_eq!(duet.realign_voice_lyrics(Voice::P2, "Hey back\nyes\n"), []);
+    assert_eq!(duet.voice_lyrics(Voice::P2), "Hey back\nyes\n");
+    assert_eq!(duet.voice_lyrics(Voice::P1), "Hello\nthere\n");
+}
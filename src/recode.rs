@@ -0,0 +1,97 @@
+//! Converting a library's charts to UTF-8
+//!
+//! Charts that aren't valid UTF-8 are CP1252 in practice, which is what older
+//! Windows editors wrote. [`SongLibrary::recode_to_utf8`] rewrites them as
+//! UTF-8 and drops their `#ENCODING` header, which the format no longer has.
+use crate::library::{chart_candidates, is_chart, SongLibrary};
+use anyhow::Result;
+use std::path::PathBuf;
+
+/// Characters CP1252 puts at 0x80 to 0x9f, where Latin-1 has control codes
+const CP1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// Text of CP1252 encoded `data`
+pub(crate) fn decode_cp1252(data: &[u8]) -> String {
+    data.iter()
+        .map(|&b| match b {
+            0x80..=0x9f => CP1252_HIGH[b as usize - 0x80],
+            _ => b as char,
+        })
+        .collect()
+}
+
+/// UTF-8 chart text for CP1252 file contents, `None` for files that already are UTF-8
+fn recode(data: &[u8]) -> Option<String> {
+    if std::str::from_utf8(data).is_ok() {
+        return None;
+    }
+    let text = decode_cp1252(data);
+    if !is_chart(&text) {
+        return None;
+    }
+    Some(
+        text.split_inclusive('\n')
+            .filter(|line| {
+                let line = line.trim_start().as_bytes();
+                !(line.len() >= 10 && line[..10].eq_ignore_ascii_case(b"#ENCODING:"))
+            })
+            .collect(),
+    )
+}
+
+impl SongLibrary {
+    /// Rewrite every chart below the library root that isn't UTF-8, returning their paths
+    ///
+    /// With `dry_run` nothing is written, the files that would be are only
+    /// listed. Files are replaced atomically, so an interrupted run leaves
+    /// every chart either as it was or converted.
+    pub fn recode_to_utf8(&self, dry_run: bool) -> Result<Vec<PathBuf>> {
+        let mut ret = vec![];
+        for path in chart_candidates(&self.root)? {
+            let Some(text) = recode(&std::fs::read(&path)?) else {
+                continue;
+            };
+            if !dry_run {
+                let tmp = path.with_extension("txt.tmp");
+                std::fs::write(&tmp, text)?;
+                std::fs::rename(tmp, &path)?;
+            }
+            ret.push(path);
+        }
+        Ok(ret)
+    }
+}
+
+#[test]
+pub fn test_recode_to_utf8() {
+    let dir = std::env::temp_dir().join(format!("usdx_recode_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("Old")).unwrap();
+    let old = dir.join("Old").join("old.txt");
+    std::fs::write(
+        &old,
+        b"#TITLE:Caf\xe9 \x93Noir\x94\r\n#ENCODING:CP1252\r\n#BPM:100\r\n#GAP:0\r\n: 0 4 0 \xe0\r\nE\r\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("new.txt"), "#TITLE:Café\n#BPM:100\n#GAP:0\nE\n").unwrap();
+    std::fs::write(dir.join("notes.txt"), b"not a chart \xff").unwrap();
+    let library = SongLibrary::scan(dir.to_str().unwrap()).unwrap();
+
+    assert_eq!(
+        library.recode_to_utf8(true).unwrap(),
+        std::slice::from_ref(&old)
+    );
+    assert!(std::str::from_utf8(&std::fs::read(&old).unwrap()).is_err());
+    assert_eq!(
+        library.recode_to_utf8(false).unwrap(),
+        std::slice::from_ref(&old)
+    );
+    assert_eq!(
+        std::fs::read_to_string(&old).unwrap(),
+        "#TITLE:Café “Noir”\r\n#BPM:100\r\n#GAP:0\r\n: 0 4 0 à\r\nE\r\n"
+    );
+    assert!(library.recode_to_utf8(false).unwrap().is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+}
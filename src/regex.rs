@@ -0,0 +1,479 @@
+//! Small regular expressions for lyric search and replace
+//!
+//! A backtracking matcher for the common subset of the usual syntax:
+//! literals, `.`, classes like `[a-z]` and `[^0-9]`, the escapes `\d`, `\w`
+//! and `\s` with their negations, anchors `^` and `$`, groups with `|`,
+//! non-capturing `(?:...)` groups and the quantifiers `*`, `+`, `?`, `{n}`,
+//! `{n,}` and `{n,m}`, each lazy when followed by `?`. A leading `(?i)`
+//! ignores case. Lyrics lines are short, so backtracking stays cheap.
+use anyhow::{bail, Result};
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::Chars;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Start,
+    End,
+    Group {
+        alternatives: Vec<Vec<Node>>,
+        capture: Option<usize>,
+    },
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+/// Spans of the whole match and of every capture group
+type Captures = Vec<Option<(usize, usize)>>;
+
+/// Compiled pattern
+#[derive(Debug, Clone)]
+pub struct Regex {
+    alternatives: Vec<Vec<Node>>,
+    /// Capture groups, the whole match included
+    groups: usize,
+    ignore_case: bool,
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    groups: usize,
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+
+impl Parser<'_> {
+    fn alternatives(&mut self) -> Result<Vec<Vec<Node>>> {
+        let mut ret = vec![self.sequence()?];
+        while self.chars.next_if_eq(&'|').is_some() {
+            ret.push(self.sequence()?);
+        }
+        Ok(ret)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>> {
+        let mut ret = vec![];
+        while let Some(&c) = self.chars.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            self.chars.next();
+            let atom = self.atom(c)?;
+            ret.push(self.quantified(atom)?);
+        }
+        Ok(ret)
+    }
+
+    fn atom(&mut self, c: char) -> Result<Node> {
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '(' => {
+                let capture = if self.chars.next_if_eq(&'?').is_some() {
+                    if self.chars.next() != Some(':') {
+                        bail!("Unsupported group in pattern");
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups - 1)
+                };
+                let alternatives = self.alternatives()?;
+                if self.chars.next() != Some(')') {
+                    bail!("Unclosed group in pattern");
+                }
+                Node::Group {
+                    alternatives,
+                    capture,
+                }
+            }
+            '[' => self.class()?,
+            '\\' => self.escape()?,
+            '*' | '+' | '?' | '{' => bail!("Nothing to repeat before '{}'", c),
+            c => Node::Char(c),
+        })
+    }
+
+    fn escape(&mut self) -> Result<Node> {
+        let class = |ranges: &[(char, char)], negated| Node::Class {
+            ranges: ranges.to_vec(),
+            negated,
+        };
+        Ok(match self.chars.next() {
+            None => bail!("Pattern ends with a backslash"),
+            Some('d') => class(DIGIT, false),
+            Some('D') => class(DIGIT, true),
+            Some('w') => class(WORD, false),
+            Some('W') => class(WORD, true),
+            Some('s') => class(SPACE, false),
+            Some('S') => class(SPACE, true),
+            Some('n') => Node::Char('\n'),
+            Some('t') => Node::Char('\t'),
+            Some(c) => Node::Char(c),
+        })
+    }
+
+    fn class(&mut self) -> Result<Node> {
+        let negated = self.chars.next_if_eq(&'^').is_some();
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let c = match self.chars.next() {
+                None => bail!("Unclosed character class in pattern"),
+                Some(']') if !first => break,
+                Some('\\') => match self.escape()? {
+                    Node::Char(c) => c,
+                    Node::Class { ranges: r, .. } => {
+                        ranges.extend(r);
+                        first = false;
+                        continue;
+                    }
+                    _ => unreachable!(),
+                },
+                Some(c) => c,
+            };
+            first = false;
+            let mut lookahead = self.chars.clone();
+            if lookahead.next() == Some('-') && lookahead.peek().is_some_and(|&e| e != ']') {
+                self.chars.next();
+                let Some(end) = self.chars.next() else {
+                    bail!("Unclosed character class in pattern");
+                };
+                if end < c {
+                    bail!("Invalid range {}-{} in pattern", c, end);
+                }
+                ranges.push((c, end));
+            } else {
+                ranges.push((c, c));
+            }
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let mut ret = None;
+        while let Some(d) = self.chars.peek().and_then(|c| c.to_digit(10)) {
+            self.chars.next();
+            ret = Some(ret.unwrap_or(0u32).saturating_mul(10).saturating_add(d));
+        }
+        ret
+    }
+
+    fn quantified(&mut self, node: Node) -> Result<Node> {
+        let (min, max) = match self.chars.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.chars.next();
+                let Some(min) = self.number() else {
+                    bail!("Invalid repetition in pattern");
+                };
+                let max = if self.chars.next_if_eq(&',').is_some() {
+                    self.number()
+                } else {
+                    Some(min)
+                };
+                if self.chars.peek() != Some(&'}') || max.is_some_and(|m| m < min) {
+                    bail!("Invalid repetition in pattern");
+                }
+                (min, max)
+            }
+            _ => return Ok(node),
+        };
+        self.chars.next();
+        let greedy = self.chars.next_if_eq(&'?').is_none();
+        if matches!(node, Node::Start | Node::End) {
+            bail!("Nothing to repeat in pattern");
+        }
+        Ok(Node::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+            greedy,
+        })
+    }
+}
+
+/// One search over a text
+struct Matcher<'a> {
+    regex: &'a Regex,
+    text: &'a str,
+}
+
+impl Matcher<'_> {
+    fn alternatives(
+        &self,
+        alternatives: &[Vec<Node>],
+        pos: usize,
+        caps: &mut Captures,
+        next: &mut dyn FnMut(usize, &mut Captures) -> bool,
+    ) -> bool {
+        for alternative in alternatives {
+            let saved = caps.clone();
+            if self.sequence(alternative, pos, caps, next) {
+                return true;
+            }
+            *caps = saved;
+        }
+        false
+    }
+
+    fn sequence(
+        &self,
+        nodes: &[Node],
+        pos: usize,
+        caps: &mut Captures,
+        next: &mut dyn FnMut(usize, &mut Captures) -> bool,
+    ) -> bool {
+        let Some((first, rest)) = nodes.split_first() else {
+            return next(pos, caps);
+        };
+        self.node(first, pos, caps, &mut |p, c| {
+            self.sequence(rest, p, c, next)
+        })
+    }
+
+    fn char_matches(&self, node: &Node, c: char) -> bool {
+        let fold = |c: char| match self.regex.ignore_case {
+            true => c.to_lowercase().next().unwrap_or(c),
+            false => c,
+        };
+        match node {
+            Node::Char(a) => fold(*a) == fold(c),
+            Node::Any => c != '\n',
+            Node::Class { ranges, negated } => {
+                let within = |c: char| ranges.iter().any(|&(a, b)| a <= c && c <= b);
+                let upper = c.to_uppercase().next().unwrap_or(c);
+                let found =
+                    within(c) || (self.regex.ignore_case && (within(fold(c)) || within(upper)));
+                found != *negated
+            }
+            _ => false,
+        }
+    }
+
+    fn node(
+        &self,
+        node: &Node,
+        pos: usize,
+        caps: &mut Captures,
+        next: &mut dyn FnMut(usize, &mut Captures) -> bool,
+    ) -> bool {
+        match node {
+            Node::Start => pos == 0 && next(pos, caps),
+            Node::End => pos == self.text.len() && next(pos, caps),
+            Node::Group {
+                alternatives,
+                capture,
+            } => self.alternatives(alternatives, pos, caps, &mut |p, c| {
+                let Some(i) = *capture else {
+                    return next(p, c);
+                };
+                let old = c[i].replace((pos, p));
+                next(p, c) || {
+                    c[i] = old;
+                    false
+                }
+            }),
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => self.repeat(node, *min, *max, *greedy, 0, pos, caps, next),
+            _ => match self.text[pos..].chars().next() {
+                Some(c) if self.char_matches(node, c) => next(pos + c.len_utf8(), caps),
+                _ => false,
+            },
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn repeat(
+        &self,
+        node: &Node,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+        count: u32,
+        pos: usize,
+        caps: &mut Captures,
+        next: &mut dyn FnMut(usize, &mut Captures) -> bool,
+    ) -> bool {
+        let done = count >= min;
+        let more = max.is_none_or(|m| count < m);
+        if !greedy && done && next(pos, caps) {
+            return true;
+        }
+        if more
+            && self.node(node, pos, caps, &mut |p, c| {
+                // An empty repetition matches any number of times
+                if p == pos {
+                    return next(p, c);
+                }
+                self.repeat(node, min, max, greedy, count + 1, p, c, next)
+            })
+        {
+            return true;
+        }
+        greedy && done && next(pos, caps)
+    }
+}
+
+impl Regex {
+    /// Compile `pattern`
+    /// ```rust
+    /// use usdx_parser::regex::Regex;
+    ///
+    /// let regex = Regex::new("(?i)colou?r").unwrap();
+    /// assert_eq!(regex.replace_all("Colour and color", "hue"), "hue and hue");
+    /// assert!(Regex::new("(unclosed").is_err());
+    /// ```
+    pub fn new(pattern: &str) -> Result<Regex> {
+        let (pattern, ignore_case) = match pattern.strip_prefix("(?i)") {
+            Some(a) => (a, true),
+            None => (pattern, false),
+        };
+        let mut parser = Parser {
+            chars: pattern.chars().peekable(),
+            groups: 1,
+        };
+        let alternatives = parser.alternatives()?;
+        if parser.chars.next().is_some() {
+            bail!("Unmatched ')' in pattern");
+        }
+        Ok(Regex {
+            alternatives,
+            groups: parser.groups,
+            ignore_case,
+        })
+    }
+
+    /// Spans of the first match at or after `start` and of its groups
+    fn captures_at(&self, text: &str, start: usize) -> Option<Captures> {
+        let matcher = Matcher { regex: self, text };
+        let mut caps = vec![None; self.groups];
+        for (pos, _) in text[start..]
+            .char_indices()
+            .map(|(i, c)| (start + i, c))
+            .chain([(text.len(), ' ')])
+        {
+            let found = matcher.alternatives(&self.alternatives, pos, &mut caps, &mut |p, c| {
+                c[0] = Some((pos, p));
+                true
+            });
+            if found {
+                return Some(caps);
+            }
+        }
+        None
+    }
+
+    /// First match at or after byte `start`
+    pub fn find_at(&self, text: &str, start: usize) -> Option<Range<usize>> {
+        self.captures_at(text, start)?[0].map(|(a, b)| a..b)
+    }
+
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        self.find_at(text, 0)
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    /// First non-empty match at or after `start`, with `replacement` expanded
+    ///
+    /// `$0` to `$9` in `replacement` stand for the match and its groups, `$$`
+    /// for a dollar sign.
+    pub(crate) fn replace_at(
+        &self,
+        text: &str,
+        mut start: usize,
+        replacement: &str,
+    ) -> Option<(Range<usize>, String)> {
+        loop {
+            let caps = self.captures_at(text, start)?;
+            let (a, b) = caps[0]?;
+            if a == b {
+                start = a + text[a..].chars().next()?.len_utf8();
+                continue;
+            }
+            let mut ret = String::new();
+            let mut chars = replacement.chars().peekable();
+            while let Some(c) = chars.next() {
+                if c != '$' {
+                    ret.push(c);
+                } else if chars.next_if_eq(&'$').is_some() {
+                    ret.push('$');
+                } else if let Some(i) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    chars.next();
+                    if let Some(Some((a, b))) = caps.get(i as usize) {
+                        ret.push_str(&text[*a..*b]);
+                    }
+                } else {
+                    ret.push('$');
+                }
+            }
+            return Some((a..b, ret));
+        }
+    }
+
+    /// `text` with every non-empty match replaced
+    pub fn replace_all(&self, text: &str, replacement: &str) -> String {
+        let mut ret = String::new();
+        let mut pos = 0;
+        while let Some((range, with)) = self.replace_at(text, pos, replacement) {
+            ret.push_str(&text[pos..range.start]);
+            ret.push_str(&with);
+            pos = range.end;
+        }
+        ret.push_str(&text[pos..]);
+        ret
+    }
+}
+
+#[test]
+pub fn test_regex() {
+    let cases = [
+        ("a+b", "xaaab", Some(1..5)),
+        ("^ab", "cab", None),
+        ("b$", "abb", Some(2..3)),
+        ("[^a-c]+", "abcdef", Some(3..6)),
+        ("\\d{2,3}", "a1234", Some(1..4)),
+        ("\\d{2,3}?", "a1234", Some(1..3)),
+        ("(?:ab|a)c", "abc", Some(0..3)),
+        ("a.*?c", "abcbc", Some(0..3)),
+        ("x*", "abc", Some(0..0)),
+        ("(?i)STRASSE", "Straße strasse", Some(8..15)),
+        ("[\\w']+", " don't ", Some(1..6)),
+        ("ü+", "grüüße", Some(2..6)),
+    ];
+    for (pattern, text, expected) in cases {
+        let regex = Regex::new(pattern).unwrap();
+        assert_eq!(regex.find(text), expected, "{}", pattern);
+    }
+    let regex = Regex::new("(\\w+) (\\w+)").unwrap();
+    assert_eq!(
+        regex.replace_all("hello world", "$2 $1 $$3"),
+        "world hello $3"
+    );
+    assert_eq!(Regex::new("x*").unwrap().replace_all("abc", "-"), "abc");
+    for invalid in ["(a", "a)", "[a", "*a", "a{2,1}", "[z-a]", "\\", "^*"] {
+        assert!(Regex::new(invalid).is_err(), "{}", invalid);
+    }
+}
@@ -0,0 +1,175 @@
+//! Find and replace across syllables
+//!
+//! Every sentence is searched as one piece of text, so a word split into
+//! several notes is still found. The replacement is spread over the notes
+//! the match covered: each keeps as many characters as it had of the match
+//! and the last one takes the rest, which keeps "co" + "lour" as "co" + "lor".
+use crate::{NoteType, Song};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use core::ops::Range;
+
+impl Song {
+    /// Replace every occurrence of `pattern` in the lyrics, returning how many there were
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:T\n#BPM:100\n#GAP:0\n: 0 2 0 co\n: 2 2 0 lour\n: 4 2 0  red\nE\n";
+    /// let mut song: Song = text.parse().unwrap();
+    /// assert_eq!(song.replace_lyrics("colour", "color"), 1);
+    /// assert_eq!(song.notes[1].lyric.as_deref(), Some("lor"));
+    /// ```
+    pub fn replace_lyrics(&mut self, pattern: &str, replacement: &str) -> usize {
+        if pattern.is_empty() {
+            return 0;
+        }
+        self.replace_matches(|text, from| {
+            let start = from + text[from..].find(pattern)?;
+            Some((start..start + pattern.len(), replacement.to_string()))
+        })
+    }
+
+    /// Replace every match of `regex` in the lyrics, returning how many there were
+    ///
+    /// `$0` to `$9` in `replacement` stand for the match and its groups.
+    #[cfg(feature = "regex")]
+    pub fn replace_lyrics_regex(
+        &mut self,
+        regex: &crate::regex::Regex,
+        replacement: &str,
+    ) -> usize {
+        self.replace_matches(|text, from| regex.replace_at(text, from, replacement))
+    }
+
+    /// Replace what `find` reports in the text of every sentence
+    ///
+    /// `find` returns the next non-empty match at or after a byte offset of
+    /// the text, with what to put in its place.
+    fn replace_matches(
+        &mut self,
+        mut find: impl FnMut(&str, usize) -> Option<(Range<usize>, String)>,
+    ) -> usize {
+        let mut count = 0;
+        let mut sentence = vec![];
+        for i in 0..=self.notes.len() {
+            let note = self
+                .notes
+                .get(i)
+                .map(|n| (n.note_type == NoteType::LineBreak, n.voice));
+            let ends = match note {
+                None => true,
+                Some((line_break, voice)) => {
+                    line_break
+                        || sentence
+                            .first()
+                            .is_some_and(|&s: &usize| self.notes[s].voice != voice)
+                }
+            };
+            if ends && !sentence.is_empty() {
+                count += self.replace_in(&sentence, &mut find);
+                sentence.clear();
+            }
+            if note.is_some_and(|(line_break, _)| !line_break) {
+                sentence.push(i);
+            }
+        }
+        count
+    }
+
+    /// Replace matches in the sentence made of the notes at `indices`
+    fn replace_in(
+        &mut self,
+        indices: &[usize],
+        find: &mut impl FnMut(&str, usize) -> Option<(Range<usize>, String)>,
+    ) -> usize {
+        let mut text = String::new();
+        let mut spans = vec![];
+        for &i in indices {
+            let start = text.len();
+            text.push_str(self.notes[i].lyric.as_deref().unwrap_or_default());
+            spans.push(start..text.len());
+        }
+        let mut matches = vec![];
+        let mut from = 0;
+        while let Some((range, with)) = find(&text, from) {
+            from = range.end;
+            matches.push((range, with));
+        }
+        if matches.is_empty() {
+            return 0;
+        }
+        let mut lyrics = vec![String::new(); spans.len()];
+        let copy = |range: Range<usize>, lyrics: &mut [String]| {
+            for (span, lyric) in spans.iter().zip(lyrics.iter_mut()) {
+                let (a, b) = (range.start.max(span.start), range.end.min(span.end));
+                if a < b {
+                    lyric.push_str(&text[a..b]);
+                }
+            }
+        };
+        let mut pos = 0;
+        for (range, with) in &matches {
+            copy(pos..range.start, &mut lyrics);
+            let first = spans.partition_point(|s| s.end <= range.start);
+            let last = spans.partition_point(|s| s.end < range.end);
+            let mut rest = with.as_str();
+            for n in first..last {
+                let covered = &text[range.start.max(spans[n].start)..spans[n].end];
+                let split = rest
+                    .char_indices()
+                    .nth(covered.chars().count())
+                    .map_or(rest.len(), |(i, _)| i);
+                lyrics[n].push_str(&rest[..split]);
+                rest = &rest[split..];
+            }
+            lyrics[last].push_str(rest);
+            pos = range.end;
+        }
+        copy(pos..text.len(), &mut lyrics);
+        for (&i, lyric) in indices.iter().zip(lyrics) {
+            let note = &mut self.notes[i];
+            if note.lyric.as_deref().unwrap_or_default() != lyric {
+                note.lyric = Some(lyric.into());
+            }
+        }
+        matches.len()
+    }
+}
+
+#[test]
+pub fn test_replace_lyrics() {
+    let mut song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let count = song.notes.len();
+    // Split as " fan" + "ta" + "sy?"
+    assert_eq!(song.replace_lyrics("fantasy", "dream"), 1);
+    let lyrics: Vec<_> = song.notes[9..12]
+        .iter()
+        .map(|n| n.lyric.as_deref().unwrap())
+        .collect();
+    assert_eq!(lyrics, [" dre", "am", "?"]);
+    assert_eq!(song.replace_lyrics("Scaramouche", "Pierrot"), 1);
+    assert!(song.lyrics().contains("Pierrot, scaramouche,"));
+    assert_eq!(song.notes.len(), count);
+    // Matches never span line breaks
+    assert_eq!(song.replace_lyrics("life?Is", "x"), 0);
+    assert_eq!(song.replace_lyrics("", "x"), 0);
+
+    let mut duet = Song::from_file("tests/duet.txt").unwrap();
+    assert_eq!(duet.replace_lyrics("o", "0"), 1);
+    assert_eq!(duet.notes[1].lyric.as_deref(), Some("l0"));
+    // Longer replacements grow the last note of the match
+    assert_eq!(duet.replace_lyrics("Hel", "Good"), 1);
+    assert_eq!(duet.notes[0].lyric.as_deref(), Some("Good"));
+    assert_eq!(duet.replace_lyrics("Goodl0", "Hey"), 1);
+    assert_eq!(duet.notes[0].lyric.as_deref(), Some("Hey"));
+    assert_eq!(duet.notes[1].lyric.as_deref(), Some(""));
+}
+
+#[cfg(feature = "regex")]
+#[test]
+pub fn test_replace_lyrics_regex() {
+    let mut song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let regex = crate::regex::Regex::new("(?i)gali(le)o").unwrap();
+    assert_eq!(song.replace_lyrics_regex(&regex, "[$1]"), 5);
+    assert!(song.lyrics().contains("[le],"));
+}
@@ -0,0 +1,150 @@
+//! Library overview for dashboards
+use crate::library::{LibraryStats, SongLibrary};
+use crate::{query, NoteType, Song};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Counts and problems of a whole library, ready to render
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LibraryReport {
+    pub stats: LibraryStats,
+    /// Songs per language, by canonical name where known; a song counts
+    /// once for every language it lists
+    pub languages: BTreeMap<String, usize>,
+    /// Songs per genre, by canonical name where known
+    pub genres: BTreeMap<String, usize>,
+    /// Songs per decade, keyed by its first year
+    pub decades: BTreeMap<u32, usize>,
+    /// Fraction of songs that are duets
+    pub duet_share: f32,
+    /// Charts without `#COVER` or whose cover file doesn't exist
+    pub missing_covers: Vec<PathBuf>,
+    /// Charts without `#VIDEO` or whose video file doesn't exist
+    pub missing_videos: Vec<PathBuf>,
+    /// Mean [`Song::difficulty`] of all songs
+    pub average_difficulty: f32,
+    /// Charts that failed to parse with their error
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+impl Song {
+    /// Rough difficulty: sung notes per second of singing, weighted by the
+    /// pitch range in octaves
+    ///
+    /// Around 1 is an easy ballad, fast songs with wide jumps go past 5.
+    pub fn difficulty(&self) -> f32 {
+        let sung = self
+            .notes
+            .iter()
+            .filter(|n| n.note_type != NoteType::LineBreak)
+            .collect::<Vec<_>>();
+        let (Some(first), Some(last)) = (sung.first(), sung.last()) else {
+            return 0.0;
+        };
+        let end = last.beat_number + last.note_length.unwrap_or(0);
+        let seconds =
+            (self.beat_to_ms(end as f64) - self.beat_to_ms(first.beat_number as f64)) / 1000.0;
+        if seconds <= 0.0 {
+            return 0.0;
+        }
+        let tones = sung.iter().filter_map(|n| n.note_tone);
+        let range = match (tones.clone().min(), tones.max()) {
+            (Some(low), Some(high)) => (high - low) as f32 / 12.0,
+            _ => 0.0,
+        };
+        sung.len() as f32 / seconds as f32 * (1.0 + range)
+    }
+}
+
+/// The referenced file is missing, or the header isn't set
+fn missing(dir: &Path, file: Option<&str>) -> bool {
+    file.is_none_or(|a| !dir.join(a).is_file())
+}
+
+impl SongLibrary {
+    /// Summarize the library by language, genre and decade along with its problems
+    /// ```rust
+    /// use usdx_parser::library::SongLibrary;
+    ///
+    /// let report = SongLibrary::scan("tests/library").unwrap().report();
+    /// assert_eq!(report.languages["English"], 1);
+    /// assert_eq!(report.duet_share, 0.5);
+    /// assert_eq!(report.failures.len(), 1);
+    /// ```
+    pub fn report(&self) -> LibraryReport {
+        let mut report = LibraryReport {
+            stats: self.stats(),
+            ..Default::default()
+        };
+        for entry in self.songs.iter() {
+            let song = &entry.song;
+            for language in song.languages() {
+                let name = language
+                    .canonical
+                    .map_or(language.raw, |l| l.name.to_string());
+                *report.languages.entry(name).or_default() += 1;
+            }
+            for genre in song.genres() {
+                let name = genre.canonical.map_or(genre.raw, |g| g.to_string());
+                *report.genres.entry(name).or_default() += 1;
+            }
+            if let Some(year) = query::year(song) {
+                *report.decades.entry(year / 10 * 10).or_default() += 1;
+            }
+            let dir = entry.path.parent().unwrap_or(Path::new(""));
+            if missing(dir, song.cover.as_deref()) {
+                report.missing_covers.push(entry.path.clone());
+            }
+            if missing(dir, song.video.as_deref()) {
+                report.missing_videos.push(entry.path.clone());
+            }
+            report.average_difficulty += song.difficulty();
+        }
+        if !self.songs.is_empty() {
+            report.duet_share = report.stats.duets as f32 / self.songs.len() as f32;
+            report.average_difficulty /= self.songs.len() as f32;
+        }
+        report.failures = self
+            .errors
+            .iter()
+            .map(|e| (e.path.clone(), format!("{:#}", e.error)))
+            .collect();
+        report
+    }
+}
+
+#[test]
+pub fn test_library_report() {
+    use crate::library::SongEntry;
+
+    let mut library = SongLibrary::default();
+    for (year, genre, language) in [
+        ("1994", "Rock", "Englisch"),
+        ("1999", "Pop/Rock", "English, German"),
+        ("2003", "Schlager", "Deutsch"),
+    ] {
+        let mut song = Song::from_file("tests/duet.txt").unwrap();
+        song.year = Some(year.to_string());
+        song.genre = Some(genre.to_string());
+        song.language = Some(language.to_string());
+        song.cover = Some("duet.txt".to_string());
+        library.songs.push(SongEntry {
+            path: PathBuf::from("tests").join(year),
+            song,
+        });
+    }
+    let report = library.report();
+    let counts = |map: &BTreeMap<String, usize>| {
+        map.iter()
+            .map(|(k, v)| format!("{} {}", k, v))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(counts(&report.languages), ["English 2", "German 2"]);
+    assert_eq!(counts(&report.genres), ["Pop 1", "Rock 2", "Schlager 1"]);
+    assert_eq!(report.decades, BTreeMap::from([(1990, 2), (2000, 1)]));
+    assert_eq!(report.duet_share, 1.0);
+    assert!(report.missing_covers.is_empty());
+    assert_eq!(report.missing_videos.len(), 3);
+    assert!(report.average_difficulty > 0.0);
+    assert_eq!(Song::new("Empty", 100.0, 0).difficulty(), 0.0);
+}
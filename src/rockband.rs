@@ -0,0 +1,199 @@
+//! Rock Band style vocals MIDI export
+//!
+//! The chart is written as a `PART VOCALS` track following the Rock Band
+//! authoring conventions: pitches on MIDI notes 36-84, one lyric event per
+//! syllable (`-` joins syllables of a word, `#` marks unpitched notes and `+`
+//! a held pitch change), phrase markers on note 105 for every line and
+//! overdrive phrases on note 116 for lines containing golden notes. Duets
+//! additionally get `HARM1` and `HARM2` harmony tracks.
+use crate::midi::{Event, EventKind, Smf, META_LYRIC, META_TEMPO, META_TRACK_NAME};
+use crate::{Note, NoteType, Song, Voice};
+use anyhow::Result;
+
+/// Ticks per quarter note of the exported file
+const DIVISION: u16 = 480;
+/// Ticks per USDX beat, which is a sixteenth note of `#BPM`
+const TICKS_PER_BEAT: u64 = DIVISION as u64 / 4;
+const PHRASE_KEY: u8 = 105;
+const OVERDRIVE_KEY: u8 = 116;
+const META_TIME_SIGNATURE: u8 = 0x58;
+
+/// Vocal note range of the Rock Band games
+const LOWEST_PITCH: i32 = 36;
+const HIGHEST_PITCH: i32 = 84;
+
+/// MIDI key of a USDX tone, moved by octaves into the supported range
+fn pitch(tone: i32) -> u8 {
+    let mut key = tone + 60;
+    while key < LOWEST_PITCH {
+        key += 12;
+    }
+    while key > HIGHEST_PITCH {
+        key -= 12;
+    }
+    key as u8
+}
+
+fn meta(tick: u64, kind: u8, data: &[u8]) -> Event {
+    Event {
+        tick,
+        kind: EventKind::Meta {
+            kind,
+            data: data.to_vec(),
+        },
+    }
+}
+
+fn note(events: &mut Vec<Event>, key: u8, start: u64, end: u64) {
+    events.push(Event {
+        tick: start,
+        kind: EventKind::NoteOn { channel: 0, key },
+    });
+    events.push(Event {
+        tick: end.max(start + 1),
+        kind: EventKind::NoteOff { channel: 0, key },
+    });
+}
+
+/// Lyric of a syllable in Rock Band notation
+fn lyric(note: &Note, next: Option<&Note>) -> String {
+    let raw = note.lyric.as_deref().unwrap_or_default();
+    let text = raw.trim();
+    let mut ret = if text == "~" {
+        "+".to_string()
+    } else {
+        text.to_string()
+    };
+    let continues_word = next.is_some_and(|n| {
+        let next = n.lyric.as_deref().unwrap_or_default();
+        !next.starts_with(' ') && next.trim() != "~"
+    });
+    if continues_word && !raw.ends_with(' ') {
+        ret.push('-');
+    }
+    if note.note_type == NoteType::Freestyle {
+        ret.push('#');
+    }
+    ret
+}
+
+impl Song {
+    fn rock_band_tick(&self, beat: u32) -> u64 {
+        let gap = self.gap as f64 * self.bpm as f64 * DIVISION as f64 / 60000.0;
+        gap.round() as u64 + beat as u64 * TICKS_PER_BEAT
+    }
+
+    /// Vocal track of one voice named `name`
+    fn rock_band_track(&self, name: &str, voice: Option<Voice>) -> Vec<Event> {
+        let mut events = vec![meta(0, META_TRACK_NAME, name.as_bytes())];
+        // Sentences sung by both duet singers are part of each singer's track
+        let lines = self
+            .sentences()
+            .into_iter()
+            .filter(|s| voice.is_none() || s.voice == voice || s.voice == Some(Voice::Both));
+        for line in lines.map(|s| s.notes) {
+            let (Some(first), Some(last)) = (line.first(), line.last()) else {
+                continue;
+            };
+            let start = self.rock_band_tick(first.beat_number);
+            let end = self.rock_band_tick(last.beat_number + last.note_length.unwrap_or(0));
+            note(&mut events, PHRASE_KEY, start, end);
+            if line.iter().any(|n| n.note_type == NoteType::Golden) {
+                note(&mut events, OVERDRIVE_KEY, start, end);
+            }
+            for (i, n) in line.iter().enumerate() {
+                let start = self.rock_band_tick(n.beat_number);
+                let end = self.rock_band_tick(n.beat_number + n.note_length.unwrap_or(0));
+                let text = lyric(n, line.get(i + 1));
+                events.push(meta(start, META_LYRIC, text.as_bytes()));
+                note(&mut events, pitch(n.note_tone.unwrap_or(0)), start, end);
+            }
+        }
+        events
+    }
+
+    /// Export the notes as a Rock Band style vocals MIDI file
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let song = Song::from_file("tests/i_hate_everything_about_you.txt").unwrap();
+    /// let midi = song.to_rock_band_midi();
+    /// assert!(midi.starts_with(b"MThd"));
+    /// ```
+    pub fn to_rock_band_midi(&self) -> Vec<u8> {
+        let tempo = (60_000_000.0 / self.bpm as f64).round() as u32;
+        let tempo_track = vec![
+            meta(0, META_TRACK_NAME, self.title.as_bytes()),
+            meta(0, META_TIME_SIGNATURE, &[4, 2, 24, 8]),
+            meta(0, META_TEMPO, &tempo.to_be_bytes()[1..]),
+        ];
+        let mut tracks = vec![tempo_track];
+        if self.is_duet() {
+            tracks.push(self.rock_band_track("PART VOCALS", Some(Voice::P1)));
+            tracks.push(self.rock_band_track("HARM1", Some(Voice::P1)));
+            tracks.push(self.rock_band_track("HARM2", Some(Voice::P2)));
+        } else {
+            tracks.push(self.rock_band_track("PART VOCALS", None));
+        }
+        Smf {
+            division: DIVISION,
+            tracks,
+        }
+        .to_bytes()
+    }
+
+    /// Write the Rock Band style vocals MIDI file to `path`
+    pub fn write_rock_band_midi(&self, path: &str) -> Result<()> {
+        std::fs::write(path, self.to_rock_band_midi())?;
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_rock_band_export() {
+    use crate::midi::decode_text;
+
+    let song = Song::from_file("tests/duet.txt").unwrap();
+    let smf = Smf::parse(&song.to_rock_band_midi()).unwrap();
+    assert_eq!(smf.tracks.len(), 4);
+    let name = |track: &[Event]| {
+        track.iter().find_map(|e| match &e.kind {
+            EventKind::Meta { kind, data } if *kind == META_TRACK_NAME => Some(decode_text(data)),
+            _ => None,
+        })
+    };
+    assert_eq!(name(&smf.tracks[1]).as_deref(), Some("PART VOCALS"));
+    assert_eq!(name(&smf.tracks[3]).as_deref(), Some("HARM2"));
+    let lyrics = |track: &[Event]| {
+        track
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Meta { kind, data } if *kind == META_LYRIC => Some(decode_text(data)),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(lyrics(&smf.tracks[1]), ["Hel-", "lo", "there"]);
+    assert_eq!(lyrics(&smf.tracks[3]), ["Hi", "back", "yeah#"]);
+    let ons = |track: &[Event], key: u8| {
+        track
+            .iter()
+            .filter(|e| e.kind == EventKind::NoteOn { channel: 0, key })
+            .map(|e| e.tick)
+            .collect::<Vec<_>>()
+    };
+    // GAP 1200 ms at 312.5 BPM is 3000 ticks
+    assert_eq!(ons(&smf.tracks[1], PHRASE_KEY), [3000, 4440]);
+    assert_eq!(ons(&smf.tracks[1], 65), [3000]);
+    assert_eq!(ons(&smf.tracks[3], OVERDRIVE_KEY), [5400]);
+    let text = "#TITLE:T\n#BPM:150\n#GAP:0\nP1\n: 0 2 0 one\nP3\n: 4 2 0 all\nP2\n\
+        : 0 2 0 two\nE\n";
+    let duet: Song = text.parse().unwrap();
+    let smf = Smf::parse(&duet.to_rock_band_midi()).unwrap();
+    assert_eq!(lyrics(&smf.tracks[1]), ["one", "all"]);
+    assert_eq!(lyrics(&smf.tracks[3]), ["two", "all"]);
+    assert_eq!(ons(&smf.tracks[2], PHRASE_KEY).len(), 2);
+    let smf = Smf::parse(&song.to_rock_band_midi()).unwrap();
+    let tempo_map = smf.tempo_map();
+    assert_eq!(smf.tick_to_ms(&tempo_map, 3000).round(), 1200.0);
+}
@@ -0,0 +1,241 @@
+//! Romanized lyrics for Japanese, Korean and Chinese songs
+//!
+//! [`Romanizer`] writes kana in Hepburn romaji and Hangul in the Revised
+//! Romanization of Korean, syllable by syllable and without the sound
+//! changes between them. Han characters, kanji included, have too many
+//! readings to guess; the caller supplies those it wants converted, for
+//! example pinyin from a dictionary, and the others are kept as they are.
+//! [`Song::romanized_lyrics`] gives the romanized lyric of every note next
+//! to the original, [`Song::romanized`] a copy of the song sung in it.
+use crate::{NoteType, Song};
+use std::collections::HashMap;
+
+/// Romaji of the hiragana from ぁ (U+3041) to ゖ (U+3096), in code point order
+const KANA: [&str; 86] = [
+    "a", "a", "i", "i", "u", "u", "e", "e", "o", "o", "ka", "ga", "ki", "gi", "ku", "gu", "ke",
+    "ge", "ko", "go", "sa", "za", "shi", "ji", "su", "zu", "se", "ze", "so", "zo", "ta", "da",
+    "chi", "ji", "", "tsu", "zu", "te", "de", "to", "do", "na", "ni", "nu", "ne", "no", "ha", "ba",
+    "pa", "hi", "bi", "pi", "fu", "bu", "pu", "he", "be", "pe", "ho", "bo", "po", "ma", "mi", "mu",
+    "me", "mo", "ya", "ya", "yu", "yu", "yo", "yo", "ra", "ri", "ru", "re", "ro", "wa", "wa", "wi",
+    "we", "o", "n", "vu", "ka", "ke",
+];
+
+/// Small kana that join the syllable before them
+const SMALL_Y: [(char, &str); 3] = [('ゃ', "a"), ('ゅ', "u"), ('ょ', "o")];
+const SMALL_VOWELS: [(char, &str); 5] = [
+    ('ぁ', "a"),
+    ('ぃ', "i"),
+    ('ぅ', "u"),
+    ('ぇ', "e"),
+    ('ぉ', "o"),
+];
+
+const HANGUL_INITIALS: [&str; 19] = [
+    "g", "kk", "n", "d", "tt", "r", "m", "b", "pp", "s", "ss", "", "j", "jj", "ch", "k", "t", "p",
+    "h",
+];
+const HANGUL_MEDIALS: [&str; 21] = [
+    "a", "ae", "ya", "yae", "eo", "e", "yeo", "ye", "o", "wa", "wae", "oe", "yo", "u", "wo", "we",
+    "wi", "yu", "eu", "ui", "i",
+];
+const HANGUL_FINALS: [&str; 28] = [
+    "", "k", "k", "k", "n", "n", "n", "t", "l", "k", "m", "l", "l", "l", "p", "l", "m", "p", "p",
+    "t", "t", "ng", "t", "t", "k", "t", "p", "t",
+];
+
+/// Converts lyrics to the Latin alphabet
+#[derive(Debug, Clone, Default)]
+pub struct Romanizer {
+    /// Romanized readings of characters the romanizer can't read itself,
+    /// such as Han characters
+    pub readings: HashMap<char, String>,
+}
+
+impl Romanizer {
+    /// Romanizer for kana and Hangul only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Romanizer also reading the characters of `readings`
+    pub fn with_readings(readings: impl IntoIterator<Item = (char, String)>) -> Self {
+        Self {
+            readings: readings.into_iter().collect(),
+        }
+    }
+
+    /// `text` in the Latin alphabet; what isn't known is kept
+    /// ```rust
+    /// use usdx_parser::romanize::Romanizer;
+    ///
+    /// let romanizer = Romanizer::with_readings([('你', "ni".into()), ('好', "hao".into())]);
+    /// assert_eq!(romanizer.romanize("きょうはラーメン"), "kyouharaamen");
+    /// assert_eq!(romanizer.romanize("사랑해"), "saranghae");
+    /// assert_eq!(romanizer.romanize("你好!"), "nihao!");
+    /// ```
+    pub fn romanize(&self, text: &str) -> String {
+        self.romanize_before(text, "")
+    }
+
+    /// `text` romanized as if `next` followed it, which a final っ doubles the consonant of
+    fn romanize_before(&self, text: &str, next: &str) -> String {
+        let mut ret = String::with_capacity(text.len());
+        // Romaji of the last kana, which small kana change
+        let mut last: &str = "";
+        let mut double = false;
+        for c in text.chars() {
+            // Katakana use the romaji of the hiragana 0x60 before them
+            let kana = match c {
+                'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+                _ => c,
+            };
+            if let Some(&(_, vowel)) = SMALL_Y.iter().find(|a| a.0 == kana) {
+                if matches!(last, "shi" | "chi" | "ji") {
+                    ret.pop();
+                } else if last.len() == 2 && last.ends_with('i') {
+                    ret.pop();
+                    ret.push('y');
+                } else {
+                    ret.push('y');
+                }
+                ret.push_str(vowel);
+                last = "";
+                continue;
+            }
+            if let Some(&(_, vowel)) = SMALL_VOWELS.iter().find(|a| a.0 == kana) {
+                let joins = match vowel {
+                    "i" => matches!(last, "fu" | "vu" | "te" | "de"),
+                    "e" => matches!(last, "fu" | "vu" | "shi" | "chi" | "ji"),
+                    _ => matches!(last, "fu" | "vu"),
+                };
+                if joins {
+                    ret.pop();
+                }
+                ret.push_str(vowel);
+                last = "";
+                continue;
+            }
+            match kana {
+                'っ' => {
+                    double = true;
+                    continue;
+                }
+                'ー' => {
+                    if let Some(vowel) = ret.chars().last().filter(|c| "aeiou".contains(*c)) {
+                        ret.push(vowel);
+                    }
+                }
+                'ぁ'..='ゖ' => {
+                    last = KANA[kana as usize - 'ぁ' as usize];
+                    if double {
+                        ret.push_str(doubled(last));
+                    }
+                    ret.push_str(last);
+                }
+                '가'..='힣' => {
+                    let index = c as usize - '가' as usize;
+                    let syllable = [
+                        HANGUL_INITIALS[index / (21 * 28)],
+                        HANGUL_MEDIALS[index / 28 % 21],
+                        HANGUL_FINALS[index % 28],
+                    ];
+                    if double {
+                        ret.push_str(doubled(syllable[0]));
+                    }
+                    ret.extend(syllable);
+                }
+                _ => match self.readings.get(&c) {
+                    Some(reading) => ret.push_str(reading),
+                    None => ret.push(c),
+                },
+            }
+            double = false;
+            if !('ぁ'..='ゖ').contains(&kana) {
+                last = "";
+            }
+        }
+        if double && !next.is_empty() {
+            ret.push_str(doubled(&self.romanize(next)));
+        }
+        ret
+    }
+}
+
+/// Consonant a っ before `romaji` stands for
+fn doubled(romaji: &str) -> &str {
+    match romaji.chars().next() {
+        _ if romaji.starts_with("ch") => "t",
+        Some(c) if c.is_ascii_alphabetic() && !"aeioun".contains(c) => &romaji[..1],
+        _ => "",
+    }
+}
+
+impl Song {
+    /// Romanized lyric of every note, `None` for notes without a lyric
+    ///
+    /// The lyrics are parallel to [`Song::notes`]. A っ ending a note
+    /// doubles the consonant of the next note of the sentence.
+    /// ```rust
+    /// use usdx_parser::romanize::Romanizer;
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:T\n#BPM:100\n#GAP:0\n: 0 2 0 まっ\n: 2 2 0 ちゃ\nE\n";
+    /// let song: Song = text.parse().unwrap();
+    /// let lyrics = song.romanized_lyrics(&Romanizer::new());
+    /// assert_eq!(lyrics, [Some("mat".to_string()), Some("cha".to_string())]);
+    /// ```
+    pub fn romanized_lyrics(&self, romanizer: &Romanizer) -> Vec<Option<String>> {
+        (0..self.notes.len())
+            .map(|i| {
+                let lyric = self.notes[i].lyric.as_deref()?;
+                let next = self
+                    .notes
+                    .get(i + 1)
+                    .filter(|n| {
+                        n.note_type != NoteType::LineBreak && n.voice == self.notes[i].voice
+                    })
+                    .and_then(|n| n.lyric.as_deref())
+                    .unwrap_or_default();
+                Some(romanizer.romanize_before(lyric, next))
+            })
+            .collect()
+    }
+
+    /// Copy of the song with romanized lyrics
+    pub fn romanized(&self, romanizer: &Romanizer) -> Song {
+        let mut ret = self.clone();
+        for (note, lyric) in ret.notes.iter_mut().zip(self.romanized_lyrics(romanizer)) {
+            note.lyric = lyric.map(Into::into);
+        }
+        ret
+    }
+}
+
+#[test]
+pub fn test_romanize() {
+    let romanizer = Romanizer::new();
+    for (text, romaji) in [
+        ("がっこう", "gakkou"),
+        ("しゃしん", "shashin"),
+        ("ファイト", "faito"),
+        ("パーティー", "paatii"),
+        ("ちょっと", "chotto"),
+        ("ジェット", "jetto"),
+        ("한국어", "hangukeo"),
+        ("ありがとう 123", "arigatou 123"),
+    ] {
+        assert_eq!(romanizer.romanize(text), romaji, "{}", text);
+    }
+
+    let text = "#TITLE:T\n#BPM:100\n#GAP:0\n: 0 2 0 がっ\n: 2 2 0 こう\n- 4\n: 4 2 0 きっ\n- 6\n\
+        : 6 2 0 사랑\nE\n";
+    let song: Song = text.parse().unwrap();
+    let lyrics = song.romanized_lyrics(&romanizer);
+    assert_eq!(lyrics[0].as_deref(), Some("gak"));
+    assert_eq!(lyrics[2], None);
+    // Nothing follows in the sentence, so the っ is dropped
+    assert_eq!(lyrics[3].as_deref(), Some("ki"));
+    let romanized = song.romanized(&romanizer);
+    assert_eq!(romanized.notes[5].lyric.as_deref(), Some("sarang"));
+    assert_eq!(romanized.notes.len(), song.notes.len());
+}
@@ -0,0 +1,217 @@
+//! UltraStar Deluxe score database (`Ultrastar.db`) reader
+//!
+//! USDX keeps play statistics in the `us_songs` table and high scores in
+//! `us_scores`, linked by the song's `ID`.
+use crate::sqlite::{Database, Table, Value};
+use crate::Song;
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+/// Difficulty a score was sung on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl TryFrom<i64> for Difficulty {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::Easy,
+            1 => Self::Medium,
+            2 => Self::Hard,
+            _ => bail!("Unknown difficulty: {}", value),
+        })
+    }
+}
+
+/// Single high score entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HighScore {
+    pub player: String,
+    pub score: u32,
+    pub difficulty: Difficulty,
+    /// Unix timestamp of when the score was set
+    pub date: Option<i64>,
+}
+
+/// Play statistics of one song
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SongStats {
+    pub artist: String,
+    pub title: String,
+    pub times_played: u32,
+    /// High scores, best first
+    pub scores: Vec<HighScore>,
+}
+
+/// Scores of every song in a USDX database, keyed by artist and title
+#[derive(Debug, Clone, Default)]
+pub struct ScoreDatabase {
+    songs: HashMap<(String, String), SongStats>,
+}
+
+fn key(artist: &str, title: &str) -> (String, String) {
+    (artist.trim().to_lowercase(), title.trim().to_lowercase())
+}
+
+impl ScoreDatabase {
+    /// Read scores from an `Ultrastar.db` file
+    /// ```rust
+    /// use usdx_parser::scores::ScoreDatabase;
+    ///
+    /// let db = ScoreDatabase::from_file("tests/Ultrastar.db").unwrap();
+    /// let stats = db.get("Queen", "Bohemian Rhapsody").unwrap();
+    /// assert_eq!(stats.scores[0].player, "Freddie");
+    /// ```
+    pub fn from_file(path: &str) -> Result<ScoreDatabase> {
+        ScoreDatabase::from_bytes(std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<ScoreDatabase> {
+        let db = Database::from_bytes(data)?;
+        let Some(songs) = db.table("us_songs")? else {
+            bail!("Database has no us_songs table");
+        };
+        let scores = db.table("us_scores")?;
+
+        let [id, artist, title, times_played] =
+            columns(&songs, ["ID", "Artist", "Title", "TimesPlayed"])?;
+        let mut by_id = HashMap::new();
+        for (_, row) in songs.rows.iter() {
+            let text = |i: usize| row.get(i).and_then(Value::as_text).unwrap_or_default();
+            let Some(song_id) = row.get(id).and_then(Value::as_integer) else {
+                continue;
+            };
+            by_id.insert(
+                song_id,
+                SongStats {
+                    artist: text(artist),
+                    title: text(title),
+                    times_played: row
+                        .get(times_played)
+                        .and_then(Value::as_integer)
+                        .unwrap_or_default() as u32,
+                    scores: vec![],
+                },
+            );
+        }
+
+        if let Some(scores) = scores {
+            let [song_id, difficulty, player, score, date] =
+                columns(&scores, ["SongID", "Difficulty", "Player", "Score", "Date"])?;
+            for (_, row) in scores.rows.iter() {
+                let int = |i: usize| row.get(i).and_then(Value::as_integer);
+                let Some(stats) = int(song_id).and_then(|a| by_id.get_mut(&a)) else {
+                    continue;
+                };
+                // One broken row shouldn't hide every other score
+                let difficulty = match int(difficulty).unwrap_or_default().try_into() {
+                    Ok(a) => a,
+                    Err(e) => {
+                        tracing::warn!("Skipping score of song {}: {}", stats.title, e);
+                        continue;
+                    }
+                };
+                stats.scores.push(HighScore {
+                    player: row.get(player).and_then(Value::as_text).unwrap_or_default(),
+                    score: int(score).unwrap_or_default() as u32,
+                    difficulty,
+                    date: int(date),
+                });
+            }
+        }
+
+        let songs = by_id
+            .into_values()
+            .map(|mut s| {
+                s.scores.sort_by_key(|a| std::cmp::Reverse(a.score));
+                (key(&s.artist, &s.title), s)
+            })
+            .collect();
+        Ok(ScoreDatabase { songs })
+    }
+
+    /// Statistics for a song, matching artist and title case-insensitively
+    pub fn get(&self, artist: &str, title: &str) -> Option<&SongStats> {
+        self.songs.get(&key(artist, title))
+    }
+
+    /// Statistics for a parsed song
+    pub fn for_song(&self, song: &Song) -> Option<&SongStats> {
+        self.get(song.artist.as_deref().unwrap_or_default(), &song.title)
+    }
+
+    pub fn songs(&self) -> impl Iterator<Item = &SongStats> {
+        self.songs.values()
+    }
+}
+
+fn columns<const N: usize>(table: &Table, names: [&str; N]) -> Result<[usize; N]> {
+    let mut ret = [0; N];
+    for (i, name) in names.iter().enumerate() {
+        let Some(column) = table.column(name) else {
+            bail!("Column {} is missing", name);
+        };
+        ret[i] = column;
+    }
+    Ok(ret)
+}
+
+#[test]
+pub fn test_score_database() {
+    let db = ScoreDatabase::from_file("tests/Ultrastar.db").unwrap();
+    assert_eq!(db.songs().count(), 201);
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let stats = db.for_song(&song).unwrap();
+    assert_eq!(stats.times_played, 12);
+    assert_eq!(stats.scores.len(), 3);
+    assert_eq!(stats.scores[0].score, 9120);
+    assert_eq!(stats.scores[0].difficulty, Difficulty::Hard);
+    let long = db.songs().find(|s| s.artist == "Filler 7").unwrap();
+    assert_eq!(long.title.len(), 3000);
+
+    // An unknown difficulty drops that score only
+    let mut data = std::fs::read("tests/Ultrastar.db").unwrap();
+    let at = data.windows(7).position(|a| a == b"Freddie").unwrap() - 1;
+    assert_eq!(data[at], 2);
+    data[at] = 7;
+    let db = ScoreDatabase::from_bytes(data.clone()).unwrap();
+    assert_eq!(db.for_song(&song).unwrap().scores.len(), 2);
+
+    // A schema whose parentheses are the wrong way round has no columns
+    let mut swapped = data.clone();
+    let sql = swapped
+        .windows(23)
+        .position(|a| a == b"CREATE TABLE [us_songs]")
+        .unwrap();
+    let open = sql + 24;
+    let close = sql
+        + swapped[sql..]
+            .windows(5)
+            .position(|a| a == b"NULL)")
+            .unwrap()
+        + 4;
+    assert_eq!((swapped[open], swapped[close]), (b'(', b')'));
+    swapped[open] = b')';
+    swapped[close] = b'(';
+    assert!(ScoreDatabase::from_bytes(swapped).is_err());
+
+    // Corrupt files fail instead of panicking
+    for at in (0..data.len()).step_by(11) {
+        let mut corrupt = data.clone();
+        corrupt[at] ^= 0xff;
+        let _ = ScoreDatabase::from_bytes(corrupt);
+    }
+    for len in (0..data.len()).step_by(97) {
+        assert!(ScoreDatabase::from_bytes(data[..len].to_vec()).is_err());
+    }
+    data[16..18].copy_from_slice(&[0, 0]);
+    assert!(ScoreDatabase::from_bytes(data.clone()).is_err());
+    data[16..18].copy_from_slice(&[2, 0]);
+    data[20] = 100;
+    assert!(ScoreDatabase::from_bytes(data).is_err());
+}
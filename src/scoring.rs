@@ -0,0 +1,671 @@
+//! Scores as karaoke games award them
+//!
+//! Every beat of a sung note is worth the same share of the song's points,
+//! golden beats a multiple of that, so a song is worth the same whatever its
+//! length. On top of that a share of the points is a bonus for lines sung
+//! well. [`ScoringModel`] holds these weights; its default is the model of
+//! UltraStar Deluxe, other values simulate forks and house rules.
+//! [`Song::score`] works out the score of a performance from how much of
+//! every note was hit.
+//!
+//! How close to a note's pitch singers have to be depends on the
+//! [`Difficulty`]; [`Song::hit_ratios`] gets the hits of every note from
+//! pitch samples with its [`Tolerance`]. Games score while the song plays
+//! with a [`ScoringSession`] instead.
+//!
+//! In duets both singers are scored on their own: each one on the notes of
+//! their voice and the notes both sing, for full points each.
+use crate::borrowed::sentence_numbers;
+use crate::{Note, NoteType, Song, Voice};
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::bail;
+use core::fmt;
+use core::str::FromStr;
+
+/// Point weights of a scoring system
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoringModel {
+    /// Points for a perfectly sung song, line bonus included
+    pub max_points: f64,
+    /// Share of `max_points` awarded for lines, from 0 to 1
+    pub line_bonus_share: f64,
+    /// What a golden beat is worth in normal beats
+    pub golden_multiplier: f64,
+    /// Whether freestyle notes score like normal notes
+    pub freestyle_scores: bool,
+}
+
+impl ScoringModel {
+    /// UltraStar Deluxe's model: 10000 points, 1000 of them line bonus,
+    /// golden notes count double and freestyle notes not at all
+    pub const USDX: ScoringModel = ScoringModel {
+        max_points: 10000.0,
+        line_bonus_share: 0.1,
+        golden_multiplier: 2.0,
+        freestyle_scores: false,
+    };
+
+    /// Normal beats one beat of a note of `note_type` is worth
+    pub fn weight(&self, note_type: &NoteType) -> f64 {
+        match note_type {
+            NoteType::Normal => 1.0,
+            NoteType::Golden => self.golden_multiplier,
+            NoteType::Freestyle if self.freestyle_scores => 1.0,
+            NoteType::Freestyle | NoteType::LineBreak => 0.0,
+        }
+    }
+
+    /// Points all lines together award
+    pub fn line_bonus(&self) -> f64 {
+        self.max_points * self.line_bonus_share.clamp(0.0, 1.0)
+    }
+}
+
+impl Default for ScoringModel {
+    fn default() -> Self {
+        Self::USDX
+    }
+}
+
+/// Difficulty a song is sung on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    /// The game's tolerance on this difficulty: two semitones on easy, one
+    /// on medium and none on hard, in any octave
+    pub fn tolerance(&self) -> Tolerance {
+        let semitones = match self {
+            Self::Easy => 2,
+            Self::Medium => 1,
+            Self::Hard => 0,
+        };
+        Tolerance {
+            semitones,
+            octave_agnostic: true,
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Easy => "easy",
+            Self::Medium => "medium",
+            Self::Hard => "hard",
+        })
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = anyhow::Error;
+
+    /// Difficulty name in any case, like `easy` or `Hard`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "easy" => Self::Easy,
+            "medium" => Self::Medium,
+            "hard" => Self::Hard,
+            _ => bail!("Unknown difficulty: {}", s),
+        })
+    }
+}
+
+/// How close sung pitch has to be to a note's to hit it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tolerance {
+    /// Semitones the sung pitch, rounded to the nearest one, may be off
+    pub semitones: u32,
+    /// Whether a note may be sung in any octave, as the games allow
+    pub octave_agnostic: bool,
+}
+
+impl Tolerance {
+    /// Whether `pitch` hits a note of `tone`; both are in semitones from C4,
+    /// like the tones of the chart, `pitch` with fractions
+    /// ```rust
+    /// use usdx_parser::scoring::Difficulty;
+    ///
+    /// let medium = Difficulty::Medium.tolerance();
+    /// assert!(medium.matches(5, 6.4));
+    /// assert!(medium.matches(5, -6.0));
+    /// assert!(!medium.matches(5, 3.4));
+    /// ```
+    pub fn matches(&self, tone: i32, pitch: f64) -> bool {
+        let mut difference = pitch - tone as f64;
+        if !difference.is_finite() {
+            return false;
+        }
+        if self.octave_agnostic {
+            // Into the octave around the note, from -6 up to 6 semitones
+            difference %= 12.0;
+            if difference >= 6.0 {
+                difference -= 12.0;
+            } else if difference < -6.0 {
+                difference += 12.0;
+            }
+        }
+        difference.abs() < self.semitones as f64 + 0.5
+    }
+}
+
+impl From<Difficulty> for Tolerance {
+    fn from(difficulty: Difficulty) -> Self {
+        difficulty.tolerance()
+    }
+}
+
+/// Points of a performance
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Score {
+    /// Points of normal (and scoring freestyle) notes
+    pub notes: f64,
+    /// Points of golden notes
+    pub golden: f64,
+    pub line_bonus: f64,
+}
+
+impl Score {
+    /// All points, rounded to whole ones
+    pub fn total(&self) -> u32 {
+        (self.notes + self.golden + self.line_bonus + 0.5) as u32
+    }
+}
+
+/// Whether the singer of `voice` sings `note`; solo notes and `None` are everyone's
+fn sings(voice: Option<Voice>, note: &Note) -> bool {
+    match (voice, note.voice) {
+        (Some(voice), Some(sung_by)) => sung_by == voice || sung_by == Voice::Both,
+        _ => true,
+    }
+}
+
+/// Points a note is worth to the singer of `voice` when sung completely
+fn note_points(notes: &[Note], model: &ScoringModel, voice: Option<Voice>) -> Vec<f64> {
+    let values: Vec<f64> = notes
+        .iter()
+        .map(|n| match sings(voice, n) {
+            true => model.weight(&n.note_type) * n.note_length.unwrap_or_default() as f64,
+            false => 0.0,
+        })
+        .collect();
+    let total: f64 = values.iter().sum();
+    if total <= 0.0 {
+        return vec![0.0; notes.len()];
+    }
+    let per_value = (model.max_points - model.line_bonus()) / total;
+    values.into_iter().map(|a| a * per_value).collect()
+}
+
+/// Score of `hits` with the bonus of every line, by sentence number
+fn score_lines(
+    notes: &[Note],
+    lines: &[Option<usize>],
+    points: &[f64],
+    model: &ScoringModel,
+    hits: &[f64],
+) -> (Score, Vec<f64>) {
+    // Points available and points sung of every line
+    let mut line_points: Vec<(f64, f64)> = vec![];
+    let mut ret = Score::default();
+    for (i, note) in notes.iter().enumerate() {
+        let Some(line) = lines[i] else {
+            continue;
+        };
+        let sung = points[i] * hits.get(i).copied().unwrap_or_default().clamp(0.0, 1.0);
+        match note.note_type {
+            NoteType::Golden => ret.golden += sung,
+            _ => ret.notes += sung,
+        }
+        if line_points.len() <= line {
+            line_points.resize(line + 1, (0.0, 0.0));
+        }
+        line_points[line].0 += points[i];
+        line_points[line].1 += sung;
+    }
+    let scoring = line_points.iter().filter(|a| a.0 > 0.0).count();
+    let per_line = model.line_bonus() / scoring.max(1) as f64;
+    let bonuses: Vec<f64> = line_points
+        .iter()
+        .map(|&(max, sung)| match max > 0.0 {
+            true => per_line * sung / max,
+            false => 0.0,
+        })
+        .collect();
+    ret.line_bonus = bonuses.iter().sum();
+    (ret, bonuses)
+}
+
+impl Song {
+    /// Points every note is worth when sung completely, parallel to
+    /// [`Song::notes`]; line breaks and non-scoring notes are worth none
+    pub fn note_points(&self, model: &ScoringModel) -> Vec<f64> {
+        note_points(&self.notes, model, None)
+    }
+
+    /// Points every note is worth to the duet singer of `voice`, see
+    /// [`Song::note_points`]; notes of the other singer are worth none
+    pub fn voice_note_points(&self, model: &ScoringModel, voice: Voice) -> Vec<f64> {
+        note_points(&self.notes, model, Some(voice))
+    }
+
+    /// Score of a performance, given the share from 0 to 1 of every note that
+    /// was hit
+    ///
+    /// `hits` is parallel to [`Song::notes`], notes past its end count as
+    /// missed. Every line with points gets the same part of the line bonus,
+    /// scaled by the share of its points that was sung. All notes are one
+    /// singer's, duets are scored per singer with [`Song::voice_score`].
+    /// ```rust
+    /// use usdx_parser::scoring::ScoringModel;
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:T\n#BPM:100\n#GAP:0\n: 0 2 0 one\n* 2 2 0 two\n- 4\n: 4 4 0 three\nE\n";
+    /// let song: Song = text.parse().unwrap();
+    /// let model = ScoringModel::default();
+    /// assert_eq!(song.score(&model, &[1.0, 1.0, 0.0, 1.0]).total(), 10000);
+    /// // The golden note is worth 3600 points and two thirds of its line's bonus
+    /// assert_eq!(song.score(&model, &[1.0, 0.0, 0.0, 1.0]).total(), 6067);
+    /// ```
+    pub fn score(&self, model: &ScoringModel, hits: &[f64]) -> Score {
+        let lines = sentence_numbers(self.notes.iter().map(|n| (&n.note_type, n.voice)));
+        score_lines(&self.notes, &lines, &self.note_points(model), model, hits).0
+    }
+
+    /// Score of the duet singer of `voice`, see [`Song::score`]
+    ///
+    /// The singer is scored on the notes of `voice` and those marked for both
+    /// singers, which make up the song's full points on their own.
+    /// ```rust
+    /// use usdx_parser::scoring::ScoringModel;
+    /// use usdx_parser::{Song, Voice};
+    ///
+    /// let text = "#TITLE:T\n#BPM:100\n#GAP:0\nP1\n: 0 2 0 one\n- 3\nP3\n: 4 2 0 all\nP2\n\
+    ///     : 8 2 0 two\nE\n";
+    /// let song: Song = text.parse().unwrap();
+    /// let model = ScoringModel::default();
+    /// let hits = [1.0, 0.0, 1.0, 0.0];
+    /// assert_eq!(song.voice_score(&model, Voice::P1, &hits).total(), 10000);
+    /// assert_eq!(song.voice_score(&model, Voice::P2, &hits).total(), 5000);
+    /// ```
+    pub fn voice_score(&self, model: &ScoringModel, voice: Voice, hits: &[f64]) -> Score {
+        let lines = sentence_numbers(self.notes.iter().map(|n| (&n.note_type, n.voice)));
+        let points = self.voice_note_points(model, voice);
+        score_lines(&self.notes, &lines, &points, model, hits).0
+    }
+
+    /// Share of every note that `samples` hit, parallel to [`Song::notes`]
+    ///
+    /// Samples are pairs of a time in ms from the start of the audio and the
+    /// pitch detected then, in semitones from C4 or `None` for silence; every
+    /// sample during a note counts the same. Notes without samples are missed.
+    /// ```rust
+    /// use usdx_parser::scoring::{Difficulty, ScoringModel};
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:T\n#BPM:150\n#GAP:0\n: 0 4 0 one\n: 4 4 7 two\nE\n";
+    /// let song: Song = text.parse().unwrap();
+    /// let samples = [(0.0, Some(1.0)), (200.0, Some(12.0)), (400.0, Some(9.0)), (600.0, None)];
+    /// assert_eq!(song.hit_ratios(Difficulty::Hard.tolerance(), &samples), [0.5, 0.0]);
+    /// let hits = song.hit_ratios(Difficulty::Easy.tolerance(), &samples);
+    /// assert_eq!(hits, [1.0, 0.5]);
+    /// assert_eq!(song.score(&ScoringModel::default(), &hits).total(), 7500);
+    /// ```
+    pub fn hit_ratios(&self, tolerance: Tolerance, samples: &[(f64, Option<f64>)]) -> Vec<f64> {
+        let mut session = ScoringSession::new(self, ScoringModel::default(), tolerance);
+        for &(time_ms, pitch) in samples {
+            session.push(time_ms, pitch);
+        }
+        session.hit_ratios()
+    }
+}
+
+impl Song {
+    /// Start and end in ms and tone of `note`, `None` if it can't be hit
+    fn note_span(&self, note: &Note) -> Option<(f64, f64, i32)> {
+        match (note.note_tone, &note.note_type) {
+            (Some(tone), NoteType::Normal | NoteType::Golden | NoteType::Freestyle) => {
+                let start = note.beat_number as f64;
+                let end = start + note.note_length.unwrap_or_default() as f64;
+                Some((self.beat_to_ms(start), self.beat_to_ms(end), tone))
+            }
+            _ => None,
+        }
+    }
+
+    /// Index in [`Song::notes`] of the note `pitch`, sung `time_ms` after the
+    /// start of the audio, hits; `None` if it hits none
+    ///
+    /// A note lasts from its start up to the start of the beat after it, so a
+    /// sample right on the boundary of two notes is the next note's. Of notes
+    /// sung at the same time, as in duets, the one closest in pitch wins.
+    /// `pitch` is in semitones from C4 and with the tolerance's octave
+    /// agnosticism any octave hits.
+    /// ```rust
+    /// use usdx_parser::scoring::Difficulty;
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:T\n#BPM:150\n#GAP:0\n: 0 2 0 one\n: 2 2 7 two\nE\n";
+    /// let song: Song = text.parse().unwrap();
+    /// let easy = Difficulty::Easy.tolerance();
+    /// assert_eq!(song.hit_test(150.0, -11.0, easy), Some(0));
+    /// assert_eq!(song.hit_test(200.0, 0.0, easy), None);
+    /// assert_eq!(song.hit_test(200.0, 19.0, easy), Some(1));
+    /// assert_eq!(song.hit_test(400.0, 7.0, easy), None);
+    /// ```
+    pub fn hit_test(&self, time_ms: f64, pitch: f64, tolerance: Tolerance) -> Option<usize> {
+        let distance = |tone: i32| {
+            let difference = pitch - tone as f64;
+            match tolerance.octave_agnostic {
+                true => {
+                    let difference = difference.abs() % 12.0;
+                    difference.min(12.0 - difference)
+                }
+                false => difference.abs(),
+            }
+        };
+        self.notes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, note)| Some((i, self.note_span(note)?)))
+            .filter(|&(_, (start, end, tone))| {
+                time_ms >= start && time_ms < end && tolerance.matches(tone, pitch)
+            })
+            .min_by(|a, b| distance(a.1 .2).total_cmp(&distance(b.1 .2)))
+            .map(|(i, _)| i)
+    }
+}
+
+/// Score of one singer, kept up to date while the song plays
+///
+/// Feed it the pitch detected from the microphone every few ms with
+/// [`ScoringSession::push`]; the hits, line bonuses and score so far are
+/// there at any time. Lines still to come count as missed.
+/// ```rust
+/// use usdx_parser::scoring::{Difficulty, ScoringModel, ScoringSession};
+/// use usdx_parser::Song;
+///
+/// let text = "#TITLE:T\n#BPM:150\n#GAP:0\n: 0 4 0 one\n- 4\n: 4 4 7 two\nE\n";
+/// let song: Song = text.parse().unwrap();
+/// let tolerance = Difficulty::Medium.tolerance();
+/// let mut session = ScoringSession::new(&song, ScoringModel::default(), tolerance);
+/// // Every 100 ms, which is a beat at 150 BPM
+/// for (time_ms, pitch) in [(0.0, Some(0.2)), (100.0, Some(1.0)), (200.0, None), (300.0, Some(12.0))] {
+///     session.push(time_ms, pitch);
+/// }
+/// assert_eq!(session.hit_ratios(), [0.75, 0.0, 0.0]);
+/// assert_eq!(session.line_bonuses(), [375.0, 0.0]);
+/// assert_eq!(session.score().total(), 3750);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScoringSession<'a> {
+    song: &'a Song,
+    model: ScoringModel,
+    tolerance: Tolerance,
+    points: Vec<f64>,
+    lines: Vec<Option<usize>>,
+    /// Start and end in ms and tone of every note that can be hit
+    spans: Vec<Option<(f64, f64, i32)>>,
+    /// Samples during and samples hitting every note
+    counts: Vec<(usize, usize)>,
+}
+
+impl<'a> ScoringSession<'a> {
+    /// Session for a singer of `song`, before any sample; all notes are theirs
+    pub fn new(song: &'a Song, model: ScoringModel, tolerance: Tolerance) -> Self {
+        Self::with_voice(song, None, model, tolerance)
+    }
+
+    /// Session for the duet singer of `voice`, who is scored as
+    /// [`Song::voice_score`] does; samples during the other singer's notes
+    /// are ignored
+    pub fn for_voice(
+        song: &'a Song,
+        voice: Voice,
+        model: ScoringModel,
+        tolerance: Tolerance,
+    ) -> Self {
+        Self::with_voice(song, Some(voice), model, tolerance)
+    }
+
+    fn with_voice(
+        song: &'a Song,
+        voice: Option<Voice>,
+        model: ScoringModel,
+        tolerance: Tolerance,
+    ) -> Self {
+        let spans = song
+            .notes
+            .iter()
+            .map(|n| song.note_span(n).filter(|_| sings(voice, n)))
+            .collect();
+        Self {
+            song,
+            model,
+            tolerance,
+            points: note_points(&song.notes, &model, voice),
+            lines: sentence_numbers(song.notes.iter().map(|n| (&n.note_type, n.voice))),
+            spans,
+            counts: vec![(0, 0); song.notes.len()],
+        }
+    }
+
+    /// Count the pitch detected at `time_ms` from the start of the audio, in
+    /// semitones from C4 or `None` for silence, returning whether it hit a note
+    pub fn push(&mut self, time_ms: f64, pitch: Option<f64>) -> bool {
+        let mut hit = false;
+        for (span, counts) in self.spans.iter().zip(&mut self.counts) {
+            let Some((start, end, tone)) = *span else {
+                continue;
+            };
+            if time_ms >= start && time_ms < end {
+                let matches = pitch.is_some_and(|p| self.tolerance.matches(tone, p));
+                counts.0 += 1;
+                counts.1 += matches as usize;
+                hit |= matches;
+            }
+        }
+        hit
+    }
+
+    /// Share of every note hit so far, parallel to [`Song::notes`]
+    pub fn hit_ratios(&self) -> Vec<f64> {
+        self.counts
+            .iter()
+            .map(|&(count, hits)| match count {
+                0 => 0.0,
+                _ => hits as f64 / count as f64,
+            })
+            .collect()
+    }
+
+    /// Line bonus every line earned so far, by the line's position in
+    /// [`Song::sentences`]
+    pub fn line_bonuses(&self) -> Vec<f64> {
+        self.score_lines().1
+    }
+
+    /// Score so far
+    pub fn score(&self) -> Score {
+        self.score_lines().0
+    }
+
+    fn score_lines(&self) -> (Score, Vec<f64>) {
+        let hits = self.hit_ratios();
+        score_lines(
+            &self.song.notes,
+            &self.lines,
+            &self.points,
+            &self.model,
+            &hits,
+        )
+    }
+}
+
+#[test]
+pub fn test_score() {
+    let text = "#TITLE:T\n#BPM:100\n#GAP:0\n: 0 2 0 a\n* 2 2 0 b\n- 4\nF 4 4 0 c\n- 8\n\
+        : 8 4 0 d\nE\n";
+    let song: Song = text.parse().unwrap();
+    let usdx = ScoringModel::default();
+    // 2 + 4 + 4 normal beats share 9000 points
+    assert_eq!(
+        song.note_points(&usdx),
+        [1800.0, 3600.0, 0.0, 0.0, 0.0, 3600.0]
+    );
+    let perfect = song.score(&usdx, &[1.0; 6]);
+    assert_eq!(perfect.total(), 10000);
+    assert_eq!((perfect.golden, perfect.line_bonus), (3600.0, 1000.0));
+    // The freestyle line doesn't share in the line bonus
+    let half = song.score(&usdx, &[1.0, 1.0, 0.0, 1.0, 0.0, 0.5]);
+    assert_eq!((half.notes, half.line_bonus), (3600.0, 750.0));
+    assert_eq!(song.score(&usdx, &[]), Score::default());
+
+    let house = ScoringModel {
+        max_points: 1200.0,
+        line_bonus_share: 0.0,
+        golden_multiplier: 1.0,
+        freestyle_scores: true,
+    };
+    assert_eq!(
+        song.note_points(&house),
+        [200.0, 200.0, 0.0, 400.0, 0.0, 400.0]
+    );
+    assert_eq!(song.score(&house, &[1.0, 1.0, 0.0, 1.0]).total(), 800);
+    assert_eq!(Song::new("Empty", 100.0, 0).score(&usdx, &[]).total(), 0);
+}
+
+#[test]
+pub fn test_difficulty() {
+    assert_eq!("HARD ".parse::<Difficulty>().unwrap(), Difficulty::Hard);
+    assert!("insane".parse::<Difficulty>().is_err());
+    assert_eq!(Difficulty::Medium.to_string(), "medium");
+
+    let hard = Tolerance::from(Difficulty::Hard);
+    assert!(hard.matches(0, 0.49));
+    assert!(!hard.matches(0, 0.5));
+    assert!(hard.matches(0, 24.2));
+    assert!(hard.matches(-3, -15.0));
+    assert!(!hard.matches(0, f64::NAN));
+    let exact = Tolerance {
+        octave_agnostic: false,
+        ..hard
+    };
+    assert!(!exact.matches(0, 12.0));
+    let easy = Difficulty::Easy.tolerance();
+    // Six semitones off is as close as the note an octave away
+    assert!(easy.matches(0, 10.0) && easy.matches(0, -2.0) && !easy.matches(0, 6.0));
+
+    let text = "#TITLE:T\n#BPM:150\n#GAP:0\n: 0 4 0 a\n- 4\nF 6 2 3 b\n- 8\n: 8 2 0 c\nE\n";
+    let song: Song = text.parse().unwrap();
+    // 100 ms per beat, samples every 100 ms
+    let samples: Vec<_> = (0..12).map(|i| (i as f64 * 100.0, Some(2.0))).collect();
+    assert_eq!(song.hit_ratios(easy, &samples), [1.0, 0.0, 1.0, 0.0, 1.0]);
+    let samples: Vec<_> = (0..12).map(|i| (i as f64 * 100.0, Some(3.0))).collect();
+    assert_eq!(song.hit_ratios(hard, &samples), [0.0, 0.0, 1.0, 0.0, 0.0]);
+    assert_eq!(song.hit_ratios(hard, &[]), [0.0; 5]);
+}
+
+#[test]
+pub fn test_scoring_session() {
+    let text = "#TITLE:T\n#BPM:150\n#GAP:0\n: 0 2 0 a\n* 2 2 2 b\n- 4\nF 4 2 0 c\n- 6\n\
+        : 6 4 5 d\nE\n";
+    let song: Song = text.parse().unwrap();
+    let model = ScoringModel::default();
+    let mut session = ScoringSession::new(&song, model, Difficulty::Hard.tolerance());
+    assert_eq!(session.score(), Score::default());
+    // Samples every 50 ms, half a beat
+    let pitches = [0.0, 0.0, 12.0, 0.0, 2.0, 2.0, 14.0, 2.0];
+    let pitches = pitches.map(Some).into_iter().chain([None; 4]);
+    let mut samples = vec![];
+    for (i, pitch) in pitches.chain([Some(5.0), Some(0.0)]).enumerate() {
+        let sample = (i as f64 * 50.0, pitch);
+        assert_eq!(session.push(sample.0, sample.1), i < 8 || i == 12);
+        samples.push(sample);
+        if i == 7 {
+            // The first line is sung in full, the last one is still to come
+            assert_eq!(session.score().total(), 5400 + 500);
+        }
+    }
+    assert!(!session.push(1e9, Some(5.0)));
+    assert_eq!(
+        session.hit_ratios(),
+        song.hit_ratios(session.tolerance, &samples)
+    );
+    assert_eq!(session.hit_ratios(), [1.0, 1.0, 0.0, 0.0, 0.0, 0.5]);
+    assert_eq!(session.line_bonuses(), [500.0, 0.0, 250.0]);
+    assert_eq!(session.score(), song.score(&model, &session.hit_ratios()));
+    assert_eq!(session.score().total(), 7950);
+}
+
+#[test]
+pub fn test_hit_test() {
+    let song = Song::from_file("tests/duet.txt").unwrap();
+    let hard = Difficulty::Hard.tolerance();
+    for (i, note) in song.notes.iter().enumerate() {
+        let Some(tone) = note
+            .note_tone
+            .filter(|_| note.note_type != NoteType::LineBreak)
+        else {
+            continue;
+        };
+        let start = song.beat_to_ms(note.beat_number as f64);
+        assert_eq!(song.hit_test(start, tone as f64 + 12.0, hard), Some(i));
+        let exact = Tolerance {
+            octave_agnostic: false,
+            ..hard
+        };
+        assert_eq!(song.hit_test(start, tone as f64 + 12.0, exact), None);
+    }
+    assert_eq!(song.hit_test(-1.0, 0.0, Difficulty::Easy.tolerance()), None);
+
+    // Overlapping notes go to the closest one that's hit
+    let text = "#TITLE:T\n#BPM:150\n#GAP:0\n: 0 4 0 a\n: 0 4 3 b\nE\n";
+    let song: Song = text.parse().unwrap();
+    let easy = Difficulty::Easy.tolerance();
+    assert_eq!(song.hit_test(0.0, 1.0, easy), Some(0));
+    assert_eq!(song.hit_test(0.0, 14.0, easy), Some(1));
+    assert_eq!(song.hit_test(0.0, 8.0, easy), None);
+}
+
+#[test]
+pub fn test_duet_scores() {
+    let song = Song::from_file("tests/duet.txt").unwrap();
+    let model = ScoringModel::default();
+    let perfect = [1.0; 64];
+    for voice in [Voice::P1, Voice::P2] {
+        assert_eq!(song.voice_score(&model, voice, &perfect).total(), 10000);
+        let points = song.voice_note_points(&model, voice);
+        for (note, points) in song.notes.iter().zip(points) {
+            if note.voice.is_some_and(|v| v != voice && v != Voice::Both) {
+                assert_eq!(points, 0.0);
+            }
+        }
+    }
+
+    let text = "#TITLE:T\n#BPM:150\n#GAP:0\nP1\n: 0 2 0 one\n- 3\nP3\n: 4 2 0 all\nP2\n\
+        : 4 2 7 two\nE\n";
+    let song: Song = text.parse().unwrap();
+    let tolerance = Difficulty::Hard.tolerance();
+    let mut p1 = ScoringSession::for_voice(&song, Voice::P1, model, tolerance);
+    let mut p2 = ScoringSession::for_voice(&song, Voice::P2, model, tolerance);
+    for time_ms in [0.0, 100.0, 400.0, 500.0] {
+        p1.push(time_ms, Some(0.0));
+    }
+    // P2's shared part and own line are at the same time, each gets a hit
+    p2.push(400.0, Some(0.0));
+    p2.push(500.0, Some(7.0));
+    assert_eq!(p1.hit_ratios(), [1.0, 0.0, 1.0, 0.0]);
+    assert_eq!(p1.score().total(), 10000);
+    assert_eq!(p2.hit_ratios(), [0.0, 0.0, 0.5, 0.5]);
+    assert_eq!(p2.score().total(), 5000);
+    assert_eq!(
+        p2.score(),
+        song.voice_score(&model, Voice::P2, &p2.hit_ratios())
+    );
+}
@@ -0,0 +1,193 @@
+//! Full-text search over a song library
+//!
+//! Artist, title, edition and the lyrics of every song are split into folded
+//! words, so matching ignores case and diacritics. All query words have to
+//! match; the last one also matches as a prefix so search-as-you-type works.
+//! Hits in the title weigh most, followed by artist, edition and lyrics.
+use crate::library::SongLibrary;
+use crate::text::tokens;
+use crate::{NoteType, Song};
+use std::collections::BTreeMap;
+
+/// Searchable parts of a song and how much a hit in each counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Artist,
+    Edition,
+    Lyrics,
+}
+
+impl Field {
+    fn weight(self) -> f32 {
+        match self {
+            Self::Title => 4.0,
+            Self::Artist => 3.0,
+            Self::Edition => 1.5,
+            Self::Lyrics => 1.0,
+        }
+    }
+}
+
+/// Song matching a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    /// Index into [`SongLibrary::songs`]
+    pub song: usize,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Posting {
+    song: u32,
+    field: Field,
+    count: u32,
+}
+
+/// Inverted index of the words in a library
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    words: BTreeMap<String, Vec<Posting>>,
+}
+
+/// Full lyric text of a song, with line breaks as spaces
+pub(crate) fn lyrics(song: &Song) -> String {
+    let mut ret = String::new();
+    for note in song.notes.iter() {
+        match note.note_type {
+            NoteType::LineBreak => ret.push(' '),
+            _ => ret.push_str(note.lyric.as_deref().unwrap_or_default()),
+        }
+    }
+    ret
+}
+
+impl SearchIndex {
+    /// Index every song of `library`; rebuild it after the library changes
+    pub fn new(library: &SongLibrary) -> SearchIndex {
+        let mut index = SearchIndex::default();
+        for (i, entry) in library.songs.iter().enumerate() {
+            let song = &entry.song;
+            let fields = [
+                (Field::Title, song.title.clone()),
+                (Field::Artist, song.artist.clone().unwrap_or_default()),
+                (Field::Edition, song.edition.clone().unwrap_or_default()),
+                (Field::Lyrics, lyrics(song)),
+            ];
+            for (field, text) in fields {
+                let mut counts = BTreeMap::<String, u32>::new();
+                for word in tokens(&text) {
+                    *counts.entry(word).or_default() += 1;
+                }
+                for (word, count) in counts {
+                    index.words.entry(word).or_default().push(Posting {
+                        song: i as u32,
+                        field,
+                        count,
+                    });
+                }
+            }
+        }
+        index
+    }
+
+    /// Scores of the songs containing `word`, or a word starting with it
+    fn word_scores(&self, word: &str, prefix: bool) -> BTreeMap<u32, f32> {
+        let mut scores = BTreeMap::new();
+        let mut add = |postings: &[Posting], factor: f32| {
+            for p in postings {
+                // Repeated words add less and less, so a chorus doesn't dominate
+                let score = p.field.weight() * (1.0 + (p.count as f32).ln()) * factor;
+                let entry = scores.entry(p.song).or_insert(0.0f32);
+                *entry = entry.max(score);
+            }
+        };
+        if let Some(postings) = self.words.get(word) {
+            add(postings, 1.0);
+        }
+        if prefix {
+            for (_, postings) in self
+                .words
+                .range(word.to_string()..)
+                .skip_while(|a| a.0 == word)
+                .take_while(|a| a.0.starts_with(word))
+            {
+                add(postings, 0.5);
+            }
+        }
+        scores
+    }
+
+    /// Songs matching every word of `query`, best first
+    /// ```rust
+    /// use usdx_parser::library::SongLibrary;
+    /// use usdx_parser::search::SearchIndex;
+    ///
+    /// let library = SongLibrary::scan("tests/library").unwrap();
+    /// let index = SearchIndex::new(&library);
+    /// let hits = index.search("duet hel");
+    /// assert_eq!(library.songs[hits[0].song].song.title, "Duet Test");
+    /// ```
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        let words = tokens(query);
+        let mut total: Option<BTreeMap<u32, f32>> = None;
+        for (i, word) in words.iter().enumerate() {
+            let scores = self.word_scores(word, i + 1 == words.len());
+            total = Some(match total {
+                None => scores,
+                Some(total) => total
+                    .into_iter()
+                    .filter_map(|(song, a)| Some((song, a + scores.get(&song)?)))
+                    .collect(),
+            });
+        }
+        let mut hits = total
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(song, score)| SearchHit {
+                song: song as usize,
+                score,
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.song.cmp(&b.song)));
+        hits
+    }
+}
+
+impl SongLibrary {
+    /// Build a [`SearchIndex`] and run a single query against it
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        SearchIndex::new(self).search(query)
+    }
+}
+
+#[test]
+pub fn test_search_ranking() {
+    use crate::library::SongEntry;
+    use std::path::PathBuf;
+
+    let mut library = SongLibrary::default();
+    let mut add = |title: &str, artist: &str, lyric: &str| {
+        let mut song = Song::new(title, 100.0, 0);
+        song.artist = Some(artist.to_string());
+        song.notes
+            .push(crate::Note::new(NoteType::Normal, 0, 4, 0, lyric));
+        library.songs.push(SongEntry {
+            path: PathBuf::from(title),
+            song,
+        });
+    };
+    add("Café del Mar", "Energy 52", "no words");
+    add("Something Else", "Cafe Society", "la la");
+    add("Ballad", "Nobody", "a cafe at night");
+    let hits = library.search("CAFE");
+    let titles = hits
+        .iter()
+        .map(|h| library.songs[h.song].song.title.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, ["Café del Mar", "Something Else", "Ballad"]);
+    assert_eq!(library.search("caf").len(), 3);
+    assert_eq!(library.search("cafe night").len(), 1);
+    assert!(library.search("caf night").is_empty());
+    assert!(library.search("").is_empty());
+}
@@ -0,0 +1,148 @@
+//! SingStar `melody.xml` import
+//!
+//! Durations are counted in the file's `Resolution` (semiquavers unless stated
+//! otherwise) from the start of the audio, and notes with `MidiNote="0"` are
+//! rests. Syllables ending in `-` continue the same word. A second `TRACK`
+//! makes the song a duet, its notes sung by P2.
+use crate::{xml, Note, NoteType, Song, Voice};
+use anyhow::{bail, Result};
+
+impl Song {
+    /// Convert a SingStar `melody.xml` into a song
+    ///
+    /// The melody file does not carry the song title, so it is set to the
+    /// track's `Name` attribute and should usually be replaced by the caller.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let xml = r#"<MELODY Tempo="120" Resolution="Semiquaver">
+    ///   <TRACK Name="Player1" Artist="Someone">
+    ///     <SENTENCE>
+    ///       <NOTE MidiNote="0" Duration="8" Lyric="" />
+    ///       <NOTE MidiNote="60" Duration="2" Lyric="Hel-" />
+    ///       <NOTE MidiNote="62" Duration="2" Lyric="lo" Bonus="Yes" />
+    ///     </SENTENCE>
+    ///   </TRACK>
+    /// </MELODY>"#;
+    /// let song = Song::from_singstar_xml(xml).unwrap();
+    /// assert_eq!(song.artist.as_deref(), Some("Someone"));
+    /// assert_eq!(song.notes[0].beat_number, 8);
+    /// assert_eq!(song.notes[1].lyric.as_deref(), Some("lo"));
+    /// ```
+    pub fn from_singstar_xml(input: &str) -> Result<Song> {
+        let melody = xml::parse(input)?;
+        if !melody.name.eq_ignore_ascii_case("MELODY") {
+            bail!("Root element is <{}>, expected <MELODY>", melody.name);
+        }
+        let tempo = match melody.attr("Tempo") {
+            Some(a) => a.replace(',', ".").parse::<f32>()?,
+            None => bail!("No tempo specified!"),
+        };
+        // USDX beats are sixteenth notes of #BPM
+        let bpm = match melody.attr("Resolution").unwrap_or("Semiquaver") {
+            a if a.eq_ignore_ascii_case("Semiquaver") => tempo,
+            a if a.eq_ignore_ascii_case("Demisemiquaver") => tempo * 2.0,
+            a => bail!("Unknown resolution: {}", a),
+        };
+        let tracks: Vec<_> = melody.children_named("TRACK").take(2).collect();
+        let Some(track) = tracks.first() else {
+            bail!("No track found!");
+        };
+
+        let mut song = Song::new(track.attr("Name").unwrap_or("Unknown"), bpm, 0);
+        song.artist = track.attr("Artist").map(|a| a.to_string());
+        song.genre = melody.attr("Genre").map(|a| a.to_string());
+        song.year = melody.attr("Year").map(|a| a.to_string());
+        if let [p1, p2] = tracks[..] {
+            song.singer_p1 = p1.attr("Name").map(|a| a.to_string());
+            song.singer_p2 = p2.attr("Name").map(|a| a.to_string());
+        }
+
+        for (track, voice) in tracks.iter().zip([Voice::P1, Voice::P2]) {
+            let voice = (tracks.len() > 1).then_some(voice);
+            let start = song.notes.len();
+            read_track(track, &mut song.notes)?;
+            for note in &mut song.notes[start..] {
+                note.voice = voice;
+            }
+        }
+        Ok(song)
+    }
+
+    /// Convert a SingStar `melody.xml` file into a song
+    pub fn from_singstar_file(path: &str) -> Result<Song> {
+        let string = std::fs::read_to_string(path)?;
+        Song::from_singstar_xml(&string)
+    }
+}
+
+/// Append the notes of a `TRACK`, counting beats from the start of the audio
+fn read_track(track: &xml::Element, notes: &mut Vec<Note>) -> Result<()> {
+    let start = notes.len();
+    let mut beat = 0;
+    for sentence in track.children_named("SENTENCE") {
+        if notes.len() > start {
+            notes.push(Note::line_break(beat));
+        }
+        let mut continues_word = true;
+        for note in sentence.children_named("NOTE") {
+            let duration = match note.attr("Duration") {
+                Some(a) => a.parse::<u32>()?,
+                None => bail!("Note is missing a duration!"),
+            };
+            let midi_note = note.attr("MidiNote").unwrap_or("0").parse::<i32>()?;
+            if midi_note == 0 {
+                beat += duration;
+                continue;
+            }
+            let yes = |name: &str| {
+                note.attr(name)
+                    .is_some_and(|a| a.eq_ignore_ascii_case("Yes"))
+            };
+            let note_type = if yes("FreeStyle") || yes("Rap") {
+                NoteType::Freestyle
+            } else if yes("Bonus") {
+                NoteType::Golden
+            } else {
+                NoteType::Normal
+            };
+            let raw = note.attr("Lyric").unwrap_or_default().trim();
+            let (raw, next_continues) = match raw.strip_suffix('-') {
+                Some(a) => (a, true),
+                None => (raw, false),
+            };
+            let lyric = match (raw.is_empty(), continues_word) {
+                (true, _) => "~".to_string(),
+                (false, true) => raw.to_string(),
+                (false, false) => format!(" {}", raw),
+            };
+            notes.push(Note::new(note_type, beat, duration, midi_note - 60, &lyric));
+            continues_word = next_continues;
+            beat += duration;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_singstar_duet() {
+    let xml = r#"<?xml version="1.0"?>
+<MELODY Tempo="120" Genre="Rock > Pop">
+  <TRACK Name="Left" Artist="Band">
+    <SENTENCE><NOTE MidiNote="60" Duration="2" Lyric="one" /></SENTENCE>
+    <SENTENCE><NOTE MidiNote="62" Duration="2" Lyric="two" /></SENTENCE>
+  </TRACK>
+  <TRACK Name="Right">
+    <SENTENCE><NOTE MidiNote="0" Duration="4" /><NOTE MidiNote="64" Duration="2" Lyric="three" /></SENTENCE>
+  </TRACK>
+</MELODY>"#;
+    let song = Song::from_singstar_xml(xml).unwrap();
+    assert_eq!(song.genre.as_deref(), Some("Rock > Pop"));
+    assert_eq!(song.singer_p1.as_deref(), Some("Left"));
+    assert_eq!(song.singer_p2.as_deref(), Some("Right"));
+    let voices: Vec<_> = song.notes.iter().map(|n| n.voice).collect();
+    let (p1, p2) = (Some(Voice::P1), Some(Voice::P2));
+    assert_eq!(voices, [p1, p1, p1, p2]);
+    assert_eq!(song.notes[3].beat_number, 4);
+    assert_eq!(song.notes[3].lyric.as_deref(), Some("three"));
+}
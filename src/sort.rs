@@ -0,0 +1,176 @@
+//! Sorting songs the way people expect in a song list
+//!
+//! Leading articles are moved out of the way ("The Beatles" sorts under B and
+//! groups as "Beatles, The"), and comparisons ignore case and diacritics. The
+//! articles depend on the `#LANGUAGE` of the song; without one the articles of
+//! all supported languages except the English "a" and "an" are used, since
+//! those often start a title rather than being an article.
+use crate::text::fold;
+use crate::{normalize, Song};
+use std::cmp::Ordering;
+
+/// Articles by ISO 639-1 code of their language
+const ARTICLES: &[(&str, &[&str])] = &[
+    ("en", &["the", "a", "an"]),
+    ("de", &["der", "die", "das"]),
+    ("es", &["el", "la", "los", "las"]),
+    ("fr", &["le", "la", "les"]),
+    ("it", &["il", "lo", "la", "gli", "le"]),
+    ("nl", &["de", "het"]),
+];
+
+fn articles(language: Option<&str>) -> Vec<&'static str> {
+    // Mixed language songs use "English, German" and the like
+    let codes = language
+        .map(normalize::languages)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|l| l.canonical)
+        .map(|l| l.iso639_1)
+        .collect::<Vec<_>>();
+    let found = ARTICLES
+        .iter()
+        .filter(|a| codes.contains(&a.0))
+        .flat_map(|a| a.1.iter().copied())
+        .collect::<Vec<_>>();
+    if !found.is_empty() {
+        return found;
+    }
+    ARTICLES
+        .iter()
+        .flat_map(|a| a.1.iter().copied())
+        .filter(|a| !matches!(*a, "a" | "an"))
+        .collect()
+}
+
+/// `text` split into leading article and remainder
+fn split_article<'a>(text: &'a str, language: Option<&str>) -> (Option<&'a str>, &'a str) {
+    let text = text.trim();
+    let Some((first, rest)) = text.split_once(' ') else {
+        return (None, text);
+    };
+    let rest = rest.trim_start();
+    if !rest.is_empty() && articles(language).contains(&fold(first).as_str()) {
+        (Some(first), rest)
+    } else {
+        (None, text)
+    }
+}
+
+/// Display form for grouping, with the article moved to the end
+/// ```rust
+/// # use usdx_parser::sort::sort_key;
+/// assert_eq!(sort_key("The Beatles", None), "Beatles, The");
+/// assert_eq!(sort_key("Die Ärzte", Some("German")), "Ärzte, Die");
+/// assert_eq!(sort_key("A Kind of Magic", None), "A Kind of Magic");
+/// ```
+pub fn sort_key(text: &str, language: Option<&str>) -> String {
+    match split_article(text, language) {
+        (Some(article), rest) => format!("{}, {}", rest, article),
+        (None, rest) => rest.to_string(),
+    }
+}
+
+/// Key that compares the way [`compare`] does
+pub fn collation_key(text: &str, language: Option<&str>) -> String {
+    fold(split_article(text, language).1)
+}
+
+/// Compares two names ignoring leading articles, case and diacritics
+/// ```rust
+/// # use usdx_parser::sort::compare;
+/// use std::cmp::Ordering;
+///
+/// assert_eq!(compare("The Beatles", "ABBA", None), Ordering::Greater);
+/// assert_eq!(compare("Édith Piaf", "elvis", None), Ordering::Less);
+/// ```
+pub fn compare(a: &str, b: &str, language: Option<&str>) -> Ordering {
+    collation_key(a, language)
+        .cmp(&collation_key(b, language))
+        .then_with(|| a.cmp(b))
+}
+
+impl Song {
+    /// Artist in [`sort_key`] form, empty without an artist
+    pub fn artist_sort_key(&self) -> String {
+        sort_key(
+            self.artist.as_deref().unwrap_or_default(),
+            self.language.as_deref(),
+        )
+    }
+
+    /// Title in [`sort_key`] form
+    pub fn title_sort_key(&self) -> String {
+        sort_key(&self.title, self.language.as_deref())
+    }
+}
+
+fn compare_field(a: &Song, b: &Song, field: impl Fn(&Song) -> &str) -> Ordering {
+    collation_key(field(a), a.language.as_deref())
+        .cmp(&collation_key(field(b), b.language.as_deref()))
+}
+
+fn artist(song: &Song) -> &str {
+    song.artist.as_deref().unwrap_or_default()
+}
+
+fn title(song: &Song) -> &str {
+    &song.title
+}
+
+/// Comparator for `sort_by` that orders by artist, then title
+/// ```rust
+/// use usdx_parser::{sort, Song};
+///
+/// let mut songs = vec![Song::new("Yesterday", 96.0, 0), Song::new("Dancing Queen", 100.0, 0)];
+/// songs[0].artist = Some("The Beatles".to_string());
+/// songs[1].artist = Some("ABBA".to_string());
+/// songs.sort_by(sort::by_artist);
+/// assert_eq!(songs[0].title, "Dancing Queen");
+/// ```
+pub fn by_artist(a: &Song, b: &Song) -> Ordering {
+    compare_field(a, b, artist).then_with(|| compare_field(a, b, title))
+}
+
+/// Comparator for `sort_by` that orders by title, then artist
+pub fn by_title(a: &Song, b: &Song) -> Ordering {
+    compare_field(a, b, title).then_with(|| compare_field(a, b, artist))
+}
+
+#[test]
+pub fn test_sorting() {
+    let names = [
+        "The Rolling Stones",
+        "Los Lobos",
+        "Die Ärzte",
+        "a-ha",
+        "Ärzte ohne Grenzen",
+        "Zaz",
+        "The",
+    ];
+    let mut sorted = names.to_vec();
+    sorted.sort_by(|a, b| compare(a, b, None));
+    assert_eq!(
+        sorted,
+        [
+            "a-ha",
+            "Die Ärzte",
+            "Ärzte ohne Grenzen",
+            "Los Lobos",
+            "The Rolling Stones",
+            "The",
+            "Zaz"
+        ]
+    );
+    assert_eq!(sort_key("La Bamba", Some("English")), "La Bamba");
+    assert_eq!(sort_key("La Bamba", Some("Spanish")), "Bamba, La");
+    assert_eq!(sort_key("An Ode", Some("Englisch, German")), "Ode, An");
+
+    let mut a = Song::new("Alpha", 100.0, 0);
+    a.artist = Some("The Band".to_string());
+    let mut b = Song::new("Beta", 100.0, 0);
+    b.artist = Some("Band".to_string());
+    assert_eq!(by_artist(&a, &b), Ordering::Less);
+    assert_eq!(by_title(&b, &a), Ordering::Greater);
+    assert_eq!(a.artist_sort_key(), "Band, The");
+}
@@ -0,0 +1,368 @@
+//! Spell checking lyrics with Hunspell dictionaries
+//!
+//! [`Dictionary::parse`] reads the `.aff` and `.dic` files of a Hunspell
+//! dictionary, the format LibreOffice and Firefox dictionaries come in. Only
+//! what checking needs is read: the word list and the prefix and suffix
+//! rules. Compounding, suggestions and morphology are left out.
+//! [`Song::spellcheck`] checks every word of the lyrics against the
+//! dictionary for the song's language and reports where the unknown ones are.
+use crate::{Song, Voice};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, HashMap};
+
+/// Word of the lyrics the dictionary doesn't know
+#[derive(Debug, Clone, PartialEq)]
+pub struct Misspelling {
+    /// The word without punctuation around it
+    pub word: String,
+    /// Duet singer of the word, `None` for solo songs
+    pub voice: Option<Voice>,
+    /// Index into [`Song::sentences`] of the sentence the word is in
+    pub sentence: usize,
+    /// Index into [`Song::notes`] of the first note of the word
+    pub note: usize,
+    /// Start of the word in ms from the start of the audio
+    pub start: f64,
+}
+
+/// Words and affix rules of one language
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    /// Every stem with the flags of the affixes it takes
+    words: HashMap<String, Vec<u32>>,
+    prefixes: Vec<Affix>,
+    suffixes: Vec<Affix>,
+}
+
+/// One prefix or suffix rule
+#[derive(Debug, Clone)]
+struct Affix {
+    flag: u32,
+    /// Whether the rule combines with rules of the other side
+    cross: bool,
+    /// Letters the rule removes from the stem
+    strip: String,
+    /// Letters the rule adds
+    add: String,
+    /// What the stem has to start (prefixes) or end (suffixes) with
+    condition: Vec<Condition>,
+}
+
+/// One character of an affix condition
+#[derive(Debug, Clone)]
+enum Condition {
+    Any,
+    /// A character of the set, or of none of them when negated
+    Set(Vec<char>, bool),
+}
+
+/// How flags are written, from the `FLAG` option
+#[derive(Debug, Clone, Copy)]
+enum FlagType {
+    Char,
+    Long,
+    Num,
+}
+
+impl FlagType {
+    fn parse(self, flags: &str) -> Vec<u32> {
+        match self {
+            Self::Char => flags.chars().map(|c| c as u32).collect(),
+            Self::Long => {
+                let chars: Vec<char> = flags.chars().collect();
+                chars
+                    .chunks(2)
+                    .map(|p| (p[0] as u32) << 16 | p.get(1).map_or(0, |&c| c as u32))
+                    .collect()
+            }
+            Self::Num => flags
+                .split(',')
+                .filter_map(|a| a.trim().parse().ok())
+                .collect(),
+        }
+    }
+}
+
+/// Conditions parsed from Hunspell's pattern syntax, `.` being the empty condition
+fn parse_condition(text: &str) -> Vec<Condition> {
+    let mut ret = vec![];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        ret.push(match c {
+            '.' => Condition::Any,
+            '[' => {
+                let mut set: Vec<char> = chars.by_ref().take_while(|&c| c != ']').collect();
+                let negated = set.first() == Some(&'^');
+                if negated {
+                    set.remove(0);
+                }
+                Condition::Set(set, negated)
+            }
+            c => Condition::Set(vec![c], false),
+        });
+    }
+    if text == "." {
+        ret.clear();
+    }
+    ret
+}
+
+impl Condition {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Set(set, negated) => set.contains(&c) != *negated,
+        }
+    }
+}
+
+/// Whether `chars` fit `condition` one by one
+fn fits(condition: &[Condition], chars: &[char]) -> bool {
+    condition.len() <= chars.len() && condition.iter().zip(chars).all(|(a, &c)| a.matches(c))
+}
+
+impl Dictionary {
+    /// Read a dictionary from the text of its `.aff` and `.dic` files
+    ///
+    /// Both have to be UTF-8; convert dictionaries with another `SET` first.
+    /// ```rust
+    /// use usdx_parser::spellcheck::Dictionary;
+    ///
+    /// let aff = "SET UTF-8\nSFX S Y 1\nSFX S 0 s [^s]\n";
+    /// let dictionary = Dictionary::parse(aff, "2\nsong/S\nsing\n").unwrap();
+    /// assert!(dictionary.check("songs"));
+    /// assert!(dictionary.check("Sing"));
+    /// assert!(!dictionary.check("sings"));
+    /// ```
+    pub fn parse(aff: &str, dic: &str) -> Result<Self> {
+        let mut ret = Self::default();
+        let mut flag_type = FlagType::Char;
+        // Whether the rules of every affix class combine, from their header
+        let mut cross = HashMap::new();
+        for line in aff.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                ["FLAG", "long", ..] => flag_type = FlagType::Long,
+                ["FLAG", "num", ..] => flag_type = FlagType::Num,
+                ["FLAG", "UTF-8", ..] => flag_type = FlagType::Char,
+                ["SET", set, ..] if !set.eq_ignore_ascii_case("UTF-8") => {
+                    bail!("Unsupported dictionary encoding: {}", set)
+                }
+                [kind @ ("PFX" | "SFX"), flag, combines, count]
+                    if count.parse::<usize>().is_ok() =>
+                {
+                    cross.insert((kind, flag), combines == "Y");
+                }
+                [kind @ ("PFX" | "SFX"), flag, strip, add, ref rest @ ..] => {
+                    let zero = |a: &str| {
+                        if a == "0" {
+                            String::new()
+                        } else {
+                            a.to_string()
+                        }
+                    };
+                    // Continuation flags after the slash aren't supported
+                    let add = add.split('/').next().unwrap_or_default();
+                    let affix = Affix {
+                        flag: flag_type.parse(flag).first().copied().unwrap_or_default(),
+                        cross: cross.get(&(kind, flag)).copied().unwrap_or_default(),
+                        strip: zero(strip),
+                        add: zero(add),
+                        condition: parse_condition(rest.first().copied().unwrap_or(".")),
+                    };
+                    match kind {
+                        "PFX" => ret.prefixes.push(affix),
+                        _ => ret.suffixes.push(affix),
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut lines = dic.lines();
+        // The first line is the number of words
+        if lines
+            .clone()
+            .next()
+            .is_some_and(|a| a.trim().parse::<usize>().is_ok())
+        {
+            lines.next();
+        }
+        for line in lines {
+            let entry = line.split(['\t', ' ']).next().unwrap_or_default();
+            if entry.is_empty() {
+                continue;
+            }
+            let (word, flags) = entry.split_once('/').unwrap_or((entry, ""));
+            ret.words
+                .entry(word.to_string())
+                .or_default()
+                .extend(flag_type.parse(flags));
+        }
+        Ok(ret)
+    }
+
+    /// Whether the dictionary knows `word`
+    ///
+    /// Capitalized and all uppercase words are also looked up in lowercase,
+    /// as they are at the start of a sentence or in shouted lines.
+    pub fn check(&self, word: &str) -> bool {
+        let lower = word.to_lowercase();
+        self.known(word) || (lower != word && self.known(&lower))
+    }
+
+    fn known(&self, word: &str) -> bool {
+        let chars: Vec<char> = word.chars().collect();
+        if self.words.contains_key(word) || self.suffixed(&chars, None) {
+            return true;
+        }
+        self.prefixes.iter().any(|p| {
+            let Some(rest) = strip_prefix(&chars, &p.add) else {
+                return false;
+            };
+            let stem: Vec<char> = p.strip.chars().chain(rest.iter().copied()).collect();
+            if !fits(&p.condition, &stem) {
+                return false;
+            }
+            self.has_flag(&stem.iter().collect::<String>(), p.flag)
+                || (p.cross && self.suffixed(&stem, Some(p.flag)))
+        })
+    }
+
+    /// Whether `chars` is a stem with one of the suffixes, whose stem also
+    /// takes the prefix `prefix` when given
+    fn suffixed(&self, chars: &[char], prefix: Option<u32>) -> bool {
+        self.suffixes.iter().any(|s| {
+            if prefix.is_some() && !s.cross {
+                return false;
+            }
+            let add: Vec<char> = s.add.chars().collect();
+            if add.len() >= chars.len() || !chars.ends_with(&add) {
+                return false;
+            }
+            let mut stem = chars[..chars.len() - add.len()].to_vec();
+            stem.extend(s.strip.chars());
+            let tail: Vec<char> = stem.iter().rev().copied().collect();
+            let condition: Vec<Condition> = s.condition.iter().rev().cloned().collect();
+            let stem: String = stem.into_iter().collect();
+            fits(&condition, &tail)
+                && self.has_flag(&stem, s.flag)
+                && prefix.is_none_or(|p| self.has_flag(&stem, p))
+        })
+    }
+
+    fn has_flag(&self, word: &str, flag: u32) -> bool {
+        self.words.get(word).is_some_and(|f| f.contains(&flag))
+    }
+}
+
+/// `chars` without `prefix`, when they start with it and go on after it
+fn strip_prefix<'a>(chars: &'a [char], prefix: &str) -> Option<&'a [char]> {
+    let prefix: Vec<char> = prefix.chars().collect();
+    (prefix.len() < chars.len() && chars.starts_with(&prefix)).then(|| &chars[prefix.len()..])
+}
+
+impl Song {
+    /// Words of the lyrics the dictionary for the song's language doesn't know
+    ///
+    /// `dictionaries` maps ISO 639-1 codes to dictionaries; the first
+    /// language of `#LANGUAGE` with one is used, a song with none of them
+    /// gives no misspellings. Punctuation around words is ignored, as are
+    /// words with digits.
+    /// ```rust
+    /// use std::collections::BTreeMap;
+    /// use usdx_parser::spellcheck::Dictionary;
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:T\n#BPM:100\n#GAP:0\n#LANGUAGE:English\n: 0 4 0 Sing,\n: 4 4 0  sogn\nE\n";
+    /// let song: Song = text.parse().unwrap();
+    /// let dictionaries = BTreeMap::from([("en".to_string(), Dictionary::parse("", "sing\nsong").unwrap())]);
+    /// let misspellings = song.spellcheck(&dictionaries);
+    /// assert_eq!(misspellings.len(), 1);
+    /// assert_eq!((misspellings[0].word.as_str(), misspellings[0].note), ("sogn", 1));
+    /// ```
+    pub fn spellcheck(&self, dictionaries: &BTreeMap<String, Dictionary>) -> Vec<Misspelling> {
+        let Some(dictionary) = self
+            .languages()
+            .iter()
+            .filter_map(|l| l.canonical)
+            .find_map(|l| dictionaries.get(l.iso639_1))
+        else {
+            return vec![];
+        };
+        let mut ret = vec![];
+        for (word, (sentence, notes)) in self.words().into_iter().zip(self.word_notes()) {
+            if word.text.contains(|c: char| c.is_numeric()) {
+                continue;
+            }
+            let text = word.text.replace('’', "'");
+            let text = text.trim_matches(|c: char| !c.is_alphabetic());
+            if text.is_empty() {
+                continue;
+            }
+            if !dictionary.check(text) {
+                ret.push(Misspelling {
+                    word: text.to_string(),
+                    voice: word.voice,
+                    sentence,
+                    note: notes[0],
+                    start: word.start,
+                });
+            }
+        }
+        ret
+    }
+}
+
+#[test]
+pub fn test_spellcheck() {
+    let aff = "SET UTF-8\nFLAG long\nPFX Un Y 1\nPFX Un 0 un .\nSFX Ed Y 2\n\
+        SFX Ed 0 ed [^y]\nSFX Ed y ied y\nSFX Sx N 1\nSFX Sx 0 s .\n";
+    let dic = "4\ndo/UnSx\ncarry/EdUn\nlock/EdUn\ntry/Ed\tpo:verb\n";
+    let dictionary = Dictionary::parse(aff, dic).unwrap();
+    for word in [
+        "do",
+        "undo",
+        "dos",
+        "carried",
+        "locked",
+        "unlocked",
+        "uncarried",
+        "Tried",
+    ] {
+        assert!(dictionary.check(word), "{}", word);
+    }
+    // Sx doesn't combine with prefixes, and "tryed" breaks the condition
+    for word in ["undos", "tryed", "untried", "ed", "un"] {
+        assert!(!dictionary.check(word), "{}", word);
+    }
+    assert!(Dictionary::parse("SET ISO8859-1\n", "").is_err());
+    let numbered = Dictionary::parse("FLAG num\nSFX 12 Y 1\nSFX 12 0 s .\n", "cat/3,12").unwrap();
+    assert!(numbered.check("cats"));
+
+    let text =
+        "#TITLE:T\n#BPM:100\n#GAP:0\n#LANGUAGE:English\nP1\n: 0 2 0 \"Un\n: 2 2 0 locked!\"\n\
+        - 4\n: 4 2 0 tryed\nP2\n: 4 2 0 4ever\n: 6 2 0  dos\n: 8 2 0  lok\nE\n";
+    let song: Song = text.parse().unwrap();
+    let dictionaries = BTreeMap::from([("en".to_string(), dictionary)]);
+    let misspellings = song.spellcheck(&dictionaries);
+    assert_eq!(
+        misspellings,
+        [
+            Misspelling {
+                word: "tryed".to_string(),
+                voice: Some(Voice::P1),
+                sentence: 1,
+                note: 3,
+                start: 600.0,
+            },
+            Misspelling {
+                word: "lok".to_string(),
+                voice: Some(Voice::P2),
+                sentence: 2,
+                note: 6,
+                start: 1200.0,
+            },
+        ]
+    );
+    assert!(song.spellcheck(&BTreeMap::new()).is_empty());
+}
@@ -0,0 +1,338 @@
+//! Minimal read-only SQLite 3 file reader
+//!
+//! Supports walking table b-trees (including overflow pages) and decoding
+//! records, which is all that is needed to read USDX's score database.
+//! Indexes, WAL files and writing are not supported.
+use anyhow::{bail, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Self::Integer(a) => Some(*a),
+            Self::Real(a) => Some(*a as i64),
+            Self::Text(a) => a.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Text value, also accepting UTF-8 blobs as USDX stores strings that way
+    pub fn as_text(&self) -> Option<String> {
+        match self {
+            Self::Text(a) => Some(a.clone()),
+            Self::Blob(a) => Some(String::from_utf8_lossy(a).into_owned()),
+            Self::Integer(a) => Some(a.to_string()),
+            Self::Real(a) => Some(a.to_string()),
+            Self::Null => None,
+        }
+    }
+}
+
+/// Rows of a table: column names and `(rowid, values)` pairs
+pub(crate) struct Table {
+    pub columns: Vec<String>,
+    pub rows: Vec<(i64, Vec<Value>)>,
+}
+
+impl Table {
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|a| a.eq_ignore_ascii_case(name))
+    }
+}
+
+pub(crate) struct Database {
+    data: Vec<u8>,
+    page_size: usize,
+    usable_size: usize,
+    encoding: u32,
+}
+
+impl Database {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Database> {
+        if data.len() < 100 || &data[..16] != b"SQLite format 3\0" {
+            bail!("Not an SQLite 3 database");
+        }
+        let page_size = match u16::from_be_bytes([data[16], data[17]]) {
+            1 => 65536,
+            a => a as usize,
+        };
+        if page_size < 512 || !page_size.is_power_of_two() {
+            bail!("Invalid page size {}", page_size);
+        }
+        // SQLite itself refuses files leaving less than 480 usable bytes per page
+        let usable_size = page_size - data[20] as usize;
+        if usable_size < 480 {
+            bail!("Invalid reserved space {}", data[20]);
+        }
+        let encoding = u32::from_be_bytes([data[56], data[57], data[58], data[59]]);
+        Ok(Database {
+            data,
+            page_size,
+            usable_size,
+            encoding,
+        })
+    }
+
+    /// Read every row of a table, returning `None` if it does not exist
+    pub fn table(&self, name: &str) -> Result<Option<Table>> {
+        let mut master = vec![];
+        self.walk(1, &mut master, 0)?;
+        for (_, row) in master {
+            let get = |i: usize| row.get(i).and_then(|a| a.as_text());
+            if get(0).as_deref() != Some("table")
+                || !get(1).is_some_and(|a| a.eq_ignore_ascii_case(name))
+            {
+                continue;
+            }
+            let Some(root) = row.get(3).and_then(|a| a.as_integer()) else {
+                bail!("Table {} has no root page", name);
+            };
+            let columns = parse_columns(&get(4).unwrap_or_default());
+            let mut rows = vec![];
+            self.walk(root as usize, &mut rows, 0)?;
+            // INTEGER PRIMARY KEY columns are stored as NULL and aliased to the rowid
+            if let Some(pk) = rowid_alias(&get(4).unwrap_or_default()) {
+                for (rowid, values) in rows.iter_mut() {
+                    if let Some(v @ Value::Null) = values.get_mut(pk) {
+                        *v = Value::Integer(*rowid);
+                    }
+                }
+            }
+            return Ok(Some(Table { columns, rows }));
+        }
+        Ok(None)
+    }
+
+    fn page(&self, number: usize) -> Result<&[u8]> {
+        let page = number
+            .checked_sub(1)
+            .and_then(|a| a.checked_mul(self.page_size))
+            .and_then(|start| self.data.get(start..start + self.page_size));
+        let Some(page) = page else {
+            bail!("Page {} is out of bounds", number);
+        };
+        Ok(page)
+    }
+
+    fn walk(&self, number: usize, rows: &mut Vec<(i64, Vec<Value>)>, depth: usize) -> Result<()> {
+        if depth > 64 {
+            bail!("B-tree is too deep, the database is probably corrupt");
+        }
+        let page = self.page(number)?;
+        let header = if number == 1 { 100 } else { 0 };
+        let kind = page[header];
+        let cells = u16::from_be_bytes([page[header + 3], page[header + 4]]) as usize;
+        let (pointers, right_most) = match kind {
+            0x0d => (header + 8, None),
+            0x05 => (
+                header + 12,
+                Some(u32::from_be_bytes(page[header + 8..header + 12].try_into()?) as usize),
+            ),
+            _ => bail!("Page {} is not a table b-tree page", number),
+        };
+        for i in 0..cells {
+            let offset = u16::from_be_bytes(bytes(page, pointers + i * 2)?) as usize;
+            if kind == 0x05 {
+                let child = u32::from_be_bytes(bytes(page, offset)?) as usize;
+                self.walk(child, rows, depth + 1)?;
+                continue;
+            }
+            let Some(cell) = page.get(offset..) else {
+                bail!("Cell {} of page {} is out of bounds", i, number);
+            };
+            let (payload_size, a) = varint(cell);
+            let (rowid, b) = varint(&cell[a..]);
+            let payload = self.payload(page, offset + a + b, payload_size as usize)?;
+            rows.push((rowid as i64, self.record(&payload)?));
+        }
+        if let Some(right_most) = right_most {
+            self.walk(right_most, rows, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn payload(&self, page: &[u8], start: usize, size: usize) -> Result<Vec<u8>> {
+        let usable = self.usable_size;
+        let max_local = usable - 35;
+        let local = if size <= max_local {
+            size
+        } else {
+            let min_local = (usable - 12) * 32 / 255 - 23;
+            let k = min_local + (size - min_local) % (usable - 4);
+            if k <= max_local {
+                k
+            } else {
+                min_local
+            }
+        };
+        let Some(local_payload) = page.get(start..start + local) else {
+            bail!("Cell payload runs past the end of its page");
+        };
+        let mut ret = local_payload.to_vec();
+        if local < size {
+            // Overflow pages come out of the file, which bounds real payloads
+            if size > self.data.len() {
+                bail!("Cell payload is larger than the database");
+            }
+            let mut next = u32::from_be_bytes(bytes(page, start + local)?) as usize;
+            while ret.len() < size {
+                let overflow = self.page(next)?;
+                next = u32::from_be_bytes(overflow[..4].try_into()?) as usize;
+                let take = (size - ret.len()).min(usable - 4);
+                ret.extend_from_slice(&overflow[4..4 + take]);
+                if next == 0 && ret.len() < size {
+                    bail!("Overflow chain ends early");
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn record(&self, payload: &[u8]) -> Result<Vec<Value>> {
+        let (header_size, mut pos) = varint(payload);
+        let header_size = header_size as usize;
+        if header_size > payload.len() {
+            bail!("Record header is larger than the record");
+        }
+        let mut types = vec![];
+        while pos < header_size {
+            let (t, n) = varint(&payload[pos..]);
+            types.push(t);
+            pos += n;
+        }
+        let mut body = header_size;
+        let mut values = vec![];
+        for t in types {
+            let len = match t {
+                0 | 8 | 9 => 0,
+                1..=4 => t as usize,
+                5 => 6,
+                6 | 7 => 8,
+                n if n >= 12 => ((n - 12) / 2) as usize,
+                _ => bail!("Unknown serial type {}", t),
+            };
+            let Some(bytes) = payload.get(body..body.saturating_add(len)) else {
+                bail!("Record value runs past the end of the record");
+            };
+            body += len;
+            values.push(match t {
+                0 => Value::Null,
+                8 => Value::Integer(0),
+                9 => Value::Integer(1),
+                1..=6 => {
+                    let mut v = if bytes[0] & 0x80 != 0 { -1i64 } else { 0 };
+                    for b in bytes {
+                        v = (v << 8) | *b as i64;
+                    }
+                    Value::Integer(v)
+                }
+                7 => Value::Real(f64::from_be_bytes(bytes.try_into()?)),
+                n if n % 2 == 0 => Value::Blob(bytes.to_vec()),
+                _ => Value::Text(self.decode_text(bytes)),
+            });
+        }
+        Ok(values)
+    }
+
+    fn decode_text(&self, bytes: &[u8]) -> String {
+        let units = |f: fn([u8; 2]) -> u16| {
+            bytes
+                .chunks_exact(2)
+                .map(|a| f([a[0], a[1]]))
+                .collect::<Vec<_>>()
+        };
+        match self.encoding {
+            2 => String::from_utf16_lossy(&units(u16::from_le_bytes)),
+            3 => String::from_utf16_lossy(&units(u16::from_be_bytes)),
+            _ => String::from_utf8_lossy(bytes).into_owned(),
+        }
+    }
+}
+
+/// The `N` bytes of `data` at `at`, failing if they run past its end
+fn bytes<const N: usize>(data: &[u8], at: usize) -> Result<[u8; N]> {
+    match data.get(at..at.saturating_add(N)) {
+        Some(a) => Ok(a.try_into()?),
+        None => bail!("Read past the end of a page"),
+    }
+}
+
+/// SQLite variable length integer, returning the value and its length in bytes
+fn varint(data: &[u8]) -> (u64, usize) {
+    let mut ret = 0u64;
+    for (i, b) in data.iter().enumerate().take(9) {
+        if i == 8 {
+            return ((ret << 8) | *b as u64, 9);
+        }
+        ret = (ret << 7) | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            return (ret, i + 1);
+        }
+    }
+    (ret, data.len().min(9))
+}
+
+/// Column definitions of a `CREATE TABLE` statement
+fn column_definitions(sql: &str) -> Vec<&str> {
+    let (Some(start), Some(end)) = (sql.find('('), sql.rfind(')')) else {
+        return vec![];
+    };
+    if end <= start {
+        return vec![];
+    }
+    let mut ret = vec![];
+    let mut depth = 0;
+    let mut last = start + 1;
+    for (i, c) in sql[..end].char_indices().skip_while(|a| a.0 <= start) {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                ret.push(sql[last..i].trim());
+                last = i + 1;
+            }
+            _ => {}
+        }
+    }
+    ret.push(sql[last..end].trim());
+    ret.into_iter()
+        .filter(|a| {
+            let upper = a.to_ascii_uppercase();
+            ![
+                "PRIMARY KEY",
+                "UNIQUE",
+                "CHECK",
+                "FOREIGN KEY",
+                "CONSTRAINT",
+            ]
+            .iter()
+            .any(|c| upper.starts_with(c))
+        })
+        .collect()
+}
+
+fn parse_columns(sql: &str) -> Vec<String> {
+    column_definitions(sql)
+        .into_iter()
+        .filter_map(|a| a.split_whitespace().next())
+        .map(|a| a.trim_matches(['[', ']', '"', '`']).to_string())
+        .collect()
+}
+
+fn rowid_alias(sql: &str) -> Option<usize> {
+    column_definitions(sql).iter().position(|a| {
+        let upper = a.to_ascii_uppercase();
+        let mut words = upper.split_whitespace().skip(1);
+        words.next() == Some("INTEGER") && upper.contains("PRIMARY KEY")
+    })
+}
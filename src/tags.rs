@@ -0,0 +1,1238 @@
+//! Audio file metadata (ID3v1/ID3v2, Ogg Vorbis/Opus comments and FLAC)
+use crate::Song;
+use anyhow::{bail, Result};
+use std::path::Path;
+
+/// Embedded cover art
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Picture {
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+impl Picture {
+    /// File extension for the image, from its MIME type or else its content
+    pub fn extension(&self) -> &'static str {
+        match self.mime.trim().to_ascii_lowercase().as_str() {
+            "image/png" | "png" => "png",
+            "image/jpeg" | "image/jpg" | "jpg" | "jpeg" => "jpg",
+            "image/gif" => "gif",
+            "image/bmp" => "bmp",
+            "image/webp" => "webp",
+            _ if self.data.starts_with(b"\x89PNG") => "png",
+            _ if self.data.starts_with(b"GIF8") => "gif",
+            _ => "jpg",
+        }
+    }
+}
+
+/// Metadata read from an audio file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AudioTags {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub year: Option<String>,
+    pub genre: Option<String>,
+    /// Front cover, or the first embedded picture if none is marked as such
+    pub picture: Option<Picture>,
+}
+
+impl AudioTags {
+    /// Read the tags of an MP3, Ogg (Vorbis/Opus) or FLAC file
+    pub fn from_file(path: &str) -> Result<AudioTags> {
+        AudioTags::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Read tags from the contents of an audio file, detecting the format from its magic bytes
+    pub fn from_bytes(data: &[u8]) -> Result<AudioTags> {
+        if data.starts_with(b"ID3") {
+            let mut tags = read_id3v2(data)?;
+            if let Some(v1) = read_id3v1(data) {
+                tags.merge(v1);
+            }
+            Ok(tags)
+        } else if data.starts_with(b"OggS") {
+            read_ogg(data)
+        } else if data.starts_with(b"fLaC") {
+            read_flac(data)
+        } else if let Some(tags) = read_id3v1(data) {
+            Ok(tags)
+        } else {
+            bail!("Unsupported audio format or no tags present");
+        }
+    }
+
+    /// Fills fields that are still empty from `other`
+    fn merge(&mut self, other: AudioTags) {
+        self.artist = self.artist.take().or(other.artist);
+        self.title = self.title.take().or(other.title);
+        self.year = self.year.take().or(other.year);
+        self.genre = self.genre.take().or(other.genre);
+        self.picture = self.picture.take().or(other.picture);
+    }
+
+    /// Stores a text value, ignoring empty ones
+    fn set(&mut self, key: &str, value: String) {
+        let value = value.trim_matches(char::from(0)).trim().to_string();
+        if value.is_empty() {
+            return;
+        }
+        let field = match key.to_ascii_uppercase().as_str() {
+            "TPE1" | "TP1" | "ARTIST" => &mut self.artist,
+            "TIT2" | "TT2" | "TITLE" => &mut self.title,
+            "TYER" | "TYE" | "TDRC" | "DATE" | "YEAR" => {
+                let year = value.chars().take(4).collect::<String>();
+                if year.len() == 4 && year.chars().all(|a| a.is_ascii_digit()) {
+                    self.year.get_or_insert(year);
+                }
+                return;
+            }
+            "TCON" | "TCO" | "GENRE" => {
+                self.genre.get_or_insert(id3_genre(&value));
+                return;
+            }
+            _ => return,
+        };
+        field.get_or_insert(value);
+    }
+
+    fn set_picture(&mut self, picture: Picture, front_cover: bool) {
+        if front_cover || self.picture.is_none() {
+            self.picture = Some(picture);
+        }
+    }
+}
+
+impl Song {
+    /// Fill missing `#ARTIST`, `#TITLE`, `#YEAR` and `#GENRE` from the tags of the `#MP3` file
+    ///
+    /// `song_dir` is the folder containing the song's txt, which the audio path is relative to.
+    /// Headers that are already set are left alone. Nothing is written to disk; the read
+    /// tags are returned so that the embedded picture can be used for a missing `#COVER`,
+    /// which [`Song::extract_cover`] saves.
+    pub fn fill_from_audio_tags(&mut self, song_dir: &str) -> Result<AudioTags> {
+        let Some(mp3) = self.mp3.as_ref() else {
+            bail!("No audio file specified!");
+        };
+        let path = Path::new(song_dir).join(mp3);
+        let tags = AudioTags::from_bytes(&std::fs::read(path)?)?;
+        if self.artist.is_none() {
+            self.artist = tags.artist.clone();
+        }
+        if self.title.trim().is_empty() {
+            if let Some(title) = tags.title.as_ref() {
+                self.title = title.clone();
+            }
+        }
+        if self.year.is_none() {
+            self.year = tags.year.clone();
+        }
+        if self.genre.is_none() {
+            self.genre = tags.genre.clone();
+        }
+        Ok(tags)
+    }
+
+    /// Save the picture embedded in the `#MP3` file as `Artist - Title [CO].ext`
+    /// next to the chart and point `#COVER` to it
+    ///
+    /// Songs whose `#COVER` file exists are left alone. Returns the name of the
+    /// written cover, `None` if there was nothing to do or no picture embedded.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let dir = std::env::temp_dir().join(format!("usdx_cover_doc_{}", std::process::id()));
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::copy("tests/tags/tagged.flac", dir.join("song.flac")).unwrap();
+    /// let mut song = Song::new("Title", 300.0, 0);
+    /// song.mp3 = Some("song.flac".to_string());
+    /// let cover = song.extract_cover(dir.to_str().unwrap()).unwrap();
+    /// assert_eq!(song.cover, cover);
+    /// assert!(dir.join(song.cover.unwrap()).is_file());
+    /// # std::fs::remove_dir_all(dir).unwrap();
+    /// ```
+    pub fn extract_cover(&mut self, song_dir: &str) -> Result<Option<String>> {
+        let dir = Path::new(song_dir);
+        if self.cover.as_ref().is_some_and(|a| dir.join(a).is_file()) {
+            return Ok(None);
+        }
+        let Some(mp3) = self.mp3.as_ref() else {
+            bail!("No audio file specified!");
+        };
+        let Some(picture) = AudioTags::from_bytes(&std::fs::read(dir.join(mp3))?)?.picture else {
+            return Ok(None);
+        };
+        let name = format!("{} [CO].{}", self.folder_name(), picture.extension());
+        let tmp = dir.join(format!("{}.tmp", name));
+        std::fs::write(&tmp, &picture.data)?;
+        std::fs::rename(tmp, dir.join(&name))?;
+        self.cover = Some(name.clone());
+        Ok(Some(name))
+    }
+}
+
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |acc, b| (acc << 7) | (*b & 0x7f) as usize)
+}
+
+fn remove_unsynchronisation(data: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(data.len());
+    for (i, b) in data.iter().enumerate() {
+        if *b == 0 && i > 0 && data[i - 1] == 0xff {
+            continue;
+        }
+        ret.push(*b);
+    }
+    ret
+}
+
+/// Decodes ID3v2 text with a leading encoding byte
+fn id3_text(encoding: u8, data: &[u8]) -> String {
+    match encoding {
+        1 | 2 => {
+            let big_endian = match data {
+                [0xfe, 0xff, ..] => true,
+                [0xff, 0xfe, ..] => false,
+                _ => encoding == 2,
+            };
+            let data = if data.starts_with(&[0xfe, 0xff]) || data.starts_with(&[0xff, 0xfe]) {
+                &data[2..]
+            } else {
+                data
+            };
+            let units = data
+                .chunks_exact(2)
+                .map(|a| {
+                    if big_endian {
+                        u16::from_be_bytes([a[0], a[1]])
+                    } else {
+                        u16::from_le_bytes([a[0], a[1]])
+                    }
+                })
+                .collect::<Vec<_>>();
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(data).into_owned(),
+        _ => data.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Splits off a NUL terminated string, which is two bytes wide for UTF-16
+fn split_terminated(encoding: u8, data: &[u8]) -> (&[u8], &[u8]) {
+    if encoding == 1 || encoding == 2 {
+        let mut i = 0;
+        while i + 1 < data.len() {
+            if data[i] == 0 && data[i + 1] == 0 {
+                return (&data[..i], &data[i + 2..]);
+            }
+            i += 2;
+        }
+    } else if let Some(i) = data.iter().position(|a| *a == 0) {
+        return (&data[..i], &data[i + 1..]);
+    }
+    (data, &[])
+}
+
+struct Id3Frame {
+    id: String,
+    /// Frame content with any unsynchronisation removed
+    data: Vec<u8>,
+    /// Frame header and content as stored in the tag
+    raw: Vec<u8>,
+}
+
+struct Id3Tag {
+    version: u8,
+    frames: Vec<Id3Frame>,
+    /// Size of the whole tag in the file, including headers
+    len: usize,
+}
+
+fn parse_id3v2(data: &[u8]) -> Result<Id3Tag> {
+    if data.len() < 10 {
+        bail!("ID3v2 header is truncated");
+    }
+    let version = data[3];
+    let flags = data[5];
+    let size = syncsafe(&data[6..10]);
+    if 10 + size > data.len() {
+        bail!("ID3v2 tag is larger than the file");
+    }
+    let mut body = data[10..10 + size].to_vec();
+    if flags & 0x80 != 0 && version < 4 {
+        body = remove_unsynchronisation(&body);
+    }
+    let mut pos = 0;
+    if flags & 0x40 != 0 && body.len() >= 4 {
+        pos = if version >= 4 {
+            syncsafe(&body[..4])
+        } else {
+            u32::from_be_bytes([body[0], body[1], body[2], body[3]]) as usize + 4
+        };
+    }
+    let (id_len, size_len, header_len) = if version == 2 { (3, 3, 6) } else { (4, 4, 10) };
+    let mut frames = vec![];
+    while pos + header_len <= body.len() && body[pos] != 0 {
+        let id = String::from_utf8_lossy(&body[pos..pos + id_len]).into_owned();
+        let size_bytes = &body[pos + id_len..pos + id_len + size_len];
+        let frame_size = match version {
+            2 | 3 => size_bytes.iter().fold(0, |acc, b| (acc << 8) | *b as usize),
+            _ => syncsafe(size_bytes),
+        };
+        let frame_flags = if version >= 3 { body[pos + 9] } else { 0 };
+        let start = pos + header_len;
+        let end = start + frame_size;
+        if end > body.len() {
+            break;
+        }
+        let raw = body[pos..end].to_vec();
+        pos = end;
+        let mut data = body[start..end].to_vec();
+        if version >= 4 && frame_flags & 0x02 != 0 {
+            data = remove_unsynchronisation(&data);
+        }
+        // Compressed and encrypted frames can't be decoded, but are kept when rewriting
+        if version >= 4 && frame_flags & 0x0c != 0 || version == 3 && frame_flags & 0xc0 != 0 {
+            data.clear();
+        }
+        frames.push(Id3Frame { id, data, raw });
+    }
+    let footer = if version >= 4 && flags & 0x10 != 0 {
+        10
+    } else {
+        0
+    };
+    Ok(Id3Tag {
+        version,
+        frames,
+        len: 10 + size + footer,
+    })
+}
+
+fn read_id3v2(data: &[u8]) -> Result<AudioTags> {
+    let mut tags = AudioTags::default();
+    for Id3Frame {
+        id, data: frame, ..
+    } in parse_id3v2(data)?.frames
+    {
+        if frame.is_empty() {
+            continue;
+        }
+        match id.as_str() {
+            "APIC" => {
+                let encoding = frame[0];
+                let (mime, rest) = split_terminated(0, &frame[1..]);
+                let Some((&picture_type, rest)) = rest.split_first() else {
+                    continue;
+                };
+                let (_, data) = split_terminated(encoding, rest);
+                tags.set_picture(
+                    Picture {
+                        mime: String::from_utf8_lossy(mime).into_owned(),
+                        data: data.to_vec(),
+                    },
+                    picture_type == 3,
+                );
+            }
+            "PIC" if frame.len() > 5 => {
+                let encoding = frame[0];
+                let mime = match &frame[1..4] {
+                    b"PNG" => "image/png",
+                    _ => "image/jpeg",
+                };
+                let (_, data) = split_terminated(encoding, &frame[5..]);
+                tags.set_picture(
+                    Picture {
+                        mime: mime.to_string(),
+                        data: data.to_vec(),
+                    },
+                    frame[4] == 3,
+                );
+            }
+            _ if id.starts_with('T') => {
+                let value = id3_text(frame[0], &frame[1..]);
+                // ID3v2.4 separates multiple values with NUL, keep the first
+                let value = value.split('\0').next().unwrap_or_default().to_string();
+                tags.set(&id, value);
+            }
+            _ => {}
+        }
+    }
+    Ok(tags)
+}
+
+fn read_id3v1(data: &[u8]) -> Option<AudioTags> {
+    let tag = data.get(data.len().checked_sub(128)?..)?;
+    if !tag.starts_with(b"TAG") {
+        return None;
+    }
+    let text = |a: &[u8]| id3_text(0, a);
+    let mut tags = AudioTags::default();
+    tags.set("TITLE", text(&tag[3..33]));
+    tags.set("ARTIST", text(&tag[33..63]));
+    tags.set("YEAR", text(&tag[93..97]));
+    if let Some(genre) = ID3V1_GENRES.get(tag[127] as usize) {
+        tags.set("GENRE", genre.to_string());
+    }
+    Some(tags)
+}
+
+/// Resolves ID3v1 style numeric genres such as `(17)` or `17`
+fn id3_genre(value: &str) -> String {
+    let number = value
+        .strip_prefix('(')
+        .and_then(|a| a.split_once(')'))
+        .map(|a| a.0)
+        .unwrap_or(value);
+    match number
+        .parse::<usize>()
+        .ok()
+        .and_then(|a| ID3V1_GENRES.get(a))
+    {
+        Some(genre) => genre.to_string(),
+        None => value.to_string(),
+    }
+}
+
+const ID3V1_GENRES: [&str; 80] = [
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "Alternative Rock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native American",
+    "Cabaret",
+    "New Wave",
+    "Psychedelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+];
+
+/// Reassembles the first `count` packets of the first logical Ogg stream
+pub(crate) fn ogg_packets(data: &[u8], count: usize) -> Result<Vec<Vec<u8>>> {
+    let mut packets = vec![];
+    let mut current = vec![];
+    let mut pos = 0;
+    let mut serial = None;
+    while packets.len() < count {
+        if data.get(pos..pos + 4) != Some(b"OggS") || pos + 27 > data.len() {
+            bail!("Ogg stream ends before its header packets");
+        }
+        let page_serial = u32::from_le_bytes(data[pos + 14..pos + 18].try_into()?);
+        let segments = data[pos + 26] as usize;
+        let Some(table) = data.get(pos + 27..pos + 27 + segments) else {
+            bail!("Ogg page is truncated");
+        };
+        let mut body = pos + 27 + segments;
+        let next = body + table.iter().map(|a| *a as usize).sum::<usize>();
+        if next > data.len() {
+            bail!("Ogg page is truncated");
+        }
+        if *serial.get_or_insert(page_serial) == page_serial {
+            for len in table {
+                current.extend_from_slice(&data[body..body + *len as usize]);
+                body += *len as usize;
+                if *len < 255 {
+                    packets.push(std::mem::take(&mut current));
+                }
+            }
+        }
+        pos = next;
+    }
+    packets.truncate(count);
+    Ok(packets)
+}
+
+fn read_ogg(data: &[u8]) -> Result<AudioTags> {
+    let packets = ogg_packets(data, 2)?;
+    let comments = &packets[1];
+    let body = if let Some(a) = comments.strip_prefix(b"\x03vorbis") {
+        a
+    } else if let Some(a) = comments.strip_prefix(b"OpusTags") {
+        a
+    } else {
+        bail!("Ogg stream is neither Vorbis nor Opus");
+    };
+    read_vorbis_comments(body)
+}
+
+fn read_vorbis_comments(data: &[u8]) -> Result<AudioTags> {
+    let mut pos = 0;
+    let mut next = |len: usize| -> Result<&[u8]> {
+        if pos + len > data.len() {
+            bail!("Vorbis comment block is truncated");
+        }
+        pos += len;
+        Ok(&data[pos - len..pos])
+    };
+    let read_u32 = |a: &[u8]| u32::from_le_bytes([a[0], a[1], a[2], a[3]]) as usize;
+    let vendor_len = read_u32(next(4)?);
+    next(vendor_len)?;
+    let count = read_u32(next(4)?);
+    let mut tags = AudioTags::default();
+    for _ in 0..count {
+        let len = read_u32(next(4)?);
+        let comment = String::from_utf8_lossy(next(len)?).into_owned();
+        let Some((key, value)) = comment.split_once('=') else {
+            continue;
+        };
+        if key.eq_ignore_ascii_case("METADATA_BLOCK_PICTURE") {
+            if let Some((picture, front)) = base64_decode(value).and_then(|a| flac_picture(&a)) {
+                tags.set_picture(picture, front);
+            }
+        } else {
+            tags.set(key, value.to_string());
+        }
+    }
+    Ok(tags)
+}
+
+fn read_flac(data: &[u8]) -> Result<AudioTags> {
+    let mut tags = AudioTags::default();
+    let mut pos = 4;
+    loop {
+        if pos + 4 > data.len() {
+            bail!("FLAC metadata is truncated");
+        }
+        let header = data[pos];
+        let len = u32::from_be_bytes([0, data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let block = &data[pos + 4..(pos + 4 + len).min(data.len())];
+        match header & 0x7f {
+            4 => tags.merge(read_vorbis_comments(block)?),
+            6 => {
+                if let Some((picture, front)) = flac_picture(block) {
+                    tags.set_picture(picture, front);
+                }
+            }
+            _ => {}
+        }
+        pos += 4 + len;
+        if header & 0x80 != 0 {
+            return Ok(tags);
+        }
+    }
+}
+
+/// Parses a FLAC picture block, returning it and whether it is the front cover
+fn flac_picture(data: &[u8]) -> Option<(Picture, bool)> {
+    let read_u32 = |at: usize| -> Option<usize> {
+        Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as usize)
+    };
+    let picture_type = read_u32(0)?;
+    let mime_len = read_u32(4)?;
+    let mime = String::from_utf8_lossy(data.get(8..8 + mime_len)?).into_owned();
+    let desc_len = read_u32(8 + mime_len)?;
+    let data_at = 8 + mime_len + 4 + desc_len + 16;
+    let data_len = read_u32(data_at)?;
+    let bytes = data.get(data_at + 4..data_at + 4 + data_len)?.to_vec();
+    Some((Picture { mime, data: bytes }, picture_type == 3))
+}
+
+pub(crate) fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let mut ret = vec![];
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in input
+        .bytes()
+        .filter(|a| !a.is_ascii_whitespace() && *a != b'=')
+    {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            ret.push((buffer >> bits) as u8);
+        }
+    }
+    Some(ret)
+}
+
+/// Vorbis comment keys replaced when writing, in the order artist, title, year, genre
+const VORBIS_KEYS: [&str; 4] = ["ARTIST", "TITLE", "DATE", "GENRE"];
+
+impl AudioTags {
+    /// Write the set fields into the contents of an audio file, returning the new contents
+    ///
+    /// Fields that are `None` keep whatever value the file already has, and unrelated
+    /// tags are preserved. MP3 files get an ID3v2.3 tag unless they already have a
+    /// v2.4 one.
+    pub fn apply(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.starts_with(b"OggS") {
+            self.apply_ogg(data)
+        } else if data.starts_with(b"fLaC") {
+            self.apply_flac(data)
+        } else {
+            self.apply_id3v2(data)
+        }
+    }
+
+    /// Write the set fields into an audio file on disk
+    pub fn write_file(&self, path: &str) -> Result<()> {
+        let data = self.apply(&std::fs::read(path)?)?;
+        let tmp = format!("{}.tmp", path);
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    fn fields(&self) -> [(&'static str, Option<&String>); 4] {
+        [
+            (VORBIS_KEYS[0], self.artist.as_ref()),
+            (VORBIS_KEYS[1], self.title.as_ref()),
+            (VORBIS_KEYS[2], self.year.as_ref()),
+            (VORBIS_KEYS[3], self.genre.as_ref()),
+        ]
+    }
+
+    fn apply_id3v2(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let existing = if data.starts_with(b"ID3") {
+            Some(parse_id3v2(data)?)
+        } else {
+            None
+        };
+        let version = match existing.as_ref() {
+            Some(tag) if tag.version == 4 => 4,
+            _ => 3,
+        };
+        let ids = [
+            "TPE1",
+            "TIT2",
+            if version == 4 { "TDRC" } else { "TYER" },
+            "TCON",
+        ];
+        let mut replaced = vec![];
+        let mut frames = vec![];
+        for (id, (_, value)) in ids.iter().zip(self.fields()) {
+            if let Some(value) = value {
+                frames.extend(id3_frame(version, id, &id3_encode_text(version, value)));
+                replaced.push(*id);
+            }
+        }
+        if version == 3 && replaced.contains(&"TYER") {
+            replaced.push("TDRC");
+        }
+        if let Some(picture) = self.picture.as_ref() {
+            let mut body = vec![0];
+            body.extend_from_slice(picture.mime.as_bytes());
+            body.extend_from_slice(&[0, 3, 0]);
+            body.extend_from_slice(&picture.data);
+            frames.extend(id3_frame(version, "APIC", &body));
+            replaced.push("APIC");
+        }
+        let mut audio = data;
+        if let Some(tag) = existing.as_ref() {
+            // ID3v2.2 frames can't be mixed into a newer tag and are dropped
+            if tag.version >= 3 {
+                for frame in tag.frames.iter() {
+                    if !replaced.contains(&frame.id.as_str()) {
+                        frames.extend_from_slice(&frame.raw);
+                    }
+                }
+            }
+            audio = &data[tag.len.min(data.len())..];
+        }
+        let len = frames.len();
+        let mut ret = b"ID3".to_vec();
+        ret.extend_from_slice(&[version, 0, 0]);
+        ret.extend_from_slice(&to_syncsafe(len));
+        ret.extend(frames);
+        ret.extend_from_slice(audio);
+        Ok(ret)
+    }
+
+    fn apply_vorbis_comments(&self, vendor: &[u8], comments: Vec<String>) -> Vec<u8> {
+        let replaced = self
+            .fields()
+            .iter()
+            .filter(|a| a.1.is_some())
+            .map(|a| a.0)
+            // Some taggers write the year as `YEAR`, which would go stale next to `DATE`
+            .chain(self.year.iter().map(|_| "YEAR"))
+            .chain(self.picture.iter().map(|_| "METADATA_BLOCK_PICTURE"))
+            .collect::<Vec<_>>();
+        let mut comments = comments
+            .into_iter()
+            .filter(|c| {
+                let key = c.split('=').next().unwrap_or_default();
+                !replaced.iter().any(|r| r.eq_ignore_ascii_case(key))
+            })
+            .collect::<Vec<_>>();
+        for (key, value) in self.fields() {
+            if let Some(value) = value {
+                comments.push(format!("{}={}", key, value));
+            }
+        }
+        if let Some(picture) = self.picture.as_ref() {
+            comments.push(format!(
+                "METADATA_BLOCK_PICTURE={}",
+                base64_encode(&flac_picture_block(picture))
+            ));
+        }
+        let mut ret = (vendor.len() as u32).to_le_bytes().to_vec();
+        ret.extend_from_slice(vendor);
+        ret.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for c in comments {
+            ret.extend_from_slice(&(c.len() as u32).to_le_bytes());
+            ret.extend_from_slice(c.as_bytes());
+        }
+        ret
+    }
+
+    fn apply_flac(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let (blocks, audio_start) = flac_blocks(data)?;
+        let mut out_blocks = vec![];
+        let mut has_comments = false;
+        for (kind, block) in blocks {
+            match kind {
+                4 => {
+                    has_comments = true;
+                    let (vendor, comments) = vorbis_comment_list(&block)?;
+                    out_blocks.push((4, self.apply_vorbis_comments(&vendor, comments)));
+                }
+                6 if self.picture.is_some() && flac_picture(&block).is_some_and(|a| a.1) => {}
+                _ => out_blocks.push((kind, block)),
+            }
+        }
+        if !has_comments {
+            out_blocks.push((4, self.apply_vorbis_comments(b"usdx_parser", vec![])));
+        }
+        if let Some(picture) = self.picture.as_ref() {
+            out_blocks.push((6, flac_picture_block(picture)));
+        }
+        let mut ret = b"fLaC".to_vec();
+        let count = out_blocks.len();
+        for (i, (kind, block)) in out_blocks.into_iter().enumerate() {
+            let last = if i + 1 == count { 0x80 } else { 0 };
+            ret.push(kind | last);
+            ret.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+            ret.extend(block);
+        }
+        ret.extend_from_slice(&data[audio_start..]);
+        Ok(ret)
+    }
+
+    fn apply_ogg(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let pages = ogg_pages(data)?;
+        let Some(first) = pages.first() else {
+            bail!("Ogg stream has no pages");
+        };
+        let serial = first.serial;
+        let mut packets = vec![];
+        let mut current = vec![];
+        let mut header_pages = 0;
+        let mut header_count = 3;
+        for (i, page) in pages.iter().enumerate().filter(|a| a.1.serial == serial) {
+            let mut body = 0;
+            for len in page.segments.iter() {
+                current.extend_from_slice(&page.body[body..body + *len as usize]);
+                body += *len as usize;
+                if *len < 255 {
+                    if packets.is_empty() && current.starts_with(b"OpusHead") {
+                        header_count = 2;
+                    }
+                    packets.push(std::mem::take(&mut current));
+                }
+            }
+            if packets.len() >= header_count {
+                header_pages = i + 1;
+                break;
+            }
+        }
+        if packets.len() < header_count {
+            bail!("Ogg stream ends before its header packets");
+        }
+        let comments = &packets[1];
+        let (prefix, framing) = if comments.starts_with(b"\x03vorbis") {
+            (&comments[..7], true)
+        } else if comments.starts_with(b"OpusTags") {
+            (&comments[..8], false)
+        } else {
+            bail!("Ogg stream is neither Vorbis nor Opus");
+        };
+        let (vendor, list) = vorbis_comment_list(&comments[prefix.len()..])?;
+        let mut new_comments = prefix.to_vec();
+        new_comments.extend(self.apply_vorbis_comments(&vendor, list));
+        if framing {
+            new_comments.push(1);
+        }
+
+        let mut out = vec![OggPage {
+            header_type: 2,
+            granule: 0,
+            serial,
+            sequence: 0,
+            segments: lacing(packets[0].len()),
+            body: packets[0].clone(),
+        }];
+        let mut rest = vec![new_comments];
+        rest.extend(packets[2..header_count].iter().cloned());
+        let mut segments = vec![];
+        let mut body = vec![];
+        for packet in rest {
+            let lace = lacing(packet.len());
+            let mut offset = 0;
+            for len in lace {
+                if segments.len() == 255 {
+                    out.push(OggPage {
+                        header_type: 0,
+                        granule: 0,
+                        serial,
+                        sequence: 0,
+                        segments: std::mem::take(&mut segments),
+                        body: std::mem::take(&mut body),
+                    });
+                }
+                segments.push(len);
+                body.extend_from_slice(&packet[offset..offset + len as usize]);
+                offset += len as usize;
+            }
+        }
+        out.push(OggPage {
+            header_type: 0,
+            granule: 0,
+            serial,
+            sequence: 0,
+            segments,
+            body,
+        });
+        // Pages continuing a packet from the previous page need the continuation flag
+        for i in 1..out.len() {
+            if out[i - 1].segments.last() == Some(&255) {
+                out[i].header_type |= 1;
+            }
+        }
+        let mut sequence = 0;
+        let mut ret = vec![];
+        for mut page in out.into_iter().chain(pages.into_iter().skip(header_pages)) {
+            if page.serial == serial {
+                page.sequence = sequence;
+                sequence += 1;
+            }
+            ret.extend(page.to_bytes());
+        }
+        Ok(ret)
+    }
+}
+
+impl Song {
+    /// Tags describing this song's headers
+    pub fn to_audio_tags(&self) -> AudioTags {
+        AudioTags {
+            artist: self.artist.clone(),
+            title: Some(self.title.clone()),
+            year: self.year.clone(),
+            genre: self.genre.clone(),
+            picture: None,
+        }
+    }
+
+    /// Write `#ARTIST`, `#TITLE`, `#YEAR` and `#GENRE` into the tags of the `#MP3` file
+    ///
+    /// With `embed_cover` set, the `#COVER` image is embedded as the front cover as well.
+    pub fn write_audio_tags(&self, song_dir: &str, embed_cover: bool) -> Result<()> {
+        let Some(mp3) = self.mp3.as_ref() else {
+            bail!("No audio file specified!");
+        };
+        let mut tags = self.to_audio_tags();
+        if embed_cover {
+            let Some(cover) = self.cover.as_ref() else {
+                bail!("No cover specified!");
+            };
+            let mime = match Path::new(cover).extension().and_then(|a| a.to_str()) {
+                Some(a) if a.eq_ignore_ascii_case("png") => "image/png",
+                _ => "image/jpeg",
+            };
+            tags.picture = Some(Picture {
+                mime: mime.to_string(),
+                data: std::fs::read(Path::new(song_dir).join(cover))?,
+            });
+        }
+        let path = Path::new(song_dir).join(mp3);
+        let Some(path) = path.to_str() else {
+            bail!("Audio path is not valid UTF-8");
+        };
+        tags.write_file(path)
+    }
+}
+
+fn to_syncsafe(n: usize) -> [u8; 4] {
+    [
+        (n >> 21) as u8 & 0x7f,
+        (n >> 14) as u8 & 0x7f,
+        (n >> 7) as u8 & 0x7f,
+        n as u8 & 0x7f,
+    ]
+}
+
+fn id3_frame(version: u8, id: &str, body: &[u8]) -> Vec<u8> {
+    let mut ret = id.as_bytes().to_vec();
+    if version == 4 {
+        ret.extend_from_slice(&to_syncsafe(body.len()));
+    } else {
+        ret.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    }
+    ret.extend_from_slice(&[0, 0]);
+    ret.extend_from_slice(body);
+    ret
+}
+
+/// ID3v2 text frame body: UTF-8 for v2.4, otherwise Latin-1 or UTF-16 as needed
+fn id3_encode_text(version: u8, value: &str) -> Vec<u8> {
+    if version == 4 {
+        let mut ret = vec![3];
+        ret.extend_from_slice(value.as_bytes());
+        ret
+    } else if value.chars().all(|a| (a as u32) < 0x100) {
+        let mut ret = vec![0];
+        ret.extend(value.chars().map(|a| a as u8));
+        ret
+    } else {
+        let mut ret = vec![1, 0xff, 0xfe];
+        for unit in value.encode_utf16() {
+            ret.extend_from_slice(&unit.to_le_bytes());
+        }
+        ret
+    }
+}
+
+/// FLAC metadata blocks as `(type, body)` pairs
+type FlacBlocks = Vec<(u8, Vec<u8>)>;
+
+/// Metadata blocks of a FLAC file and the offset the audio frames start at
+fn flac_blocks(data: &[u8]) -> Result<(FlacBlocks, usize)> {
+    let mut blocks = vec![];
+    let mut pos = 4;
+    loop {
+        if pos + 4 > data.len() {
+            bail!("FLAC metadata is truncated");
+        }
+        let header = data[pos];
+        let len = u32::from_be_bytes([0, data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        if pos + 4 + len > data.len() {
+            bail!("FLAC metadata is truncated");
+        }
+        blocks.push((header & 0x7f, data[pos + 4..pos + 4 + len].to_vec()));
+        pos += 4 + len;
+        if header & 0x80 != 0 {
+            return Ok((blocks, pos));
+        }
+    }
+}
+
+/// Vendor string and raw `KEY=value` comments of a Vorbis comment block
+fn vorbis_comment_list(data: &[u8]) -> Result<(Vec<u8>, Vec<String>)> {
+    let mut pos = 0;
+    let mut next = |len: usize| -> Result<&[u8]> {
+        if pos + len > data.len() {
+            bail!("Vorbis comment block is truncated");
+        }
+        pos += len;
+        Ok(&data[pos - len..pos])
+    };
+    let read_u32 = |a: &[u8]| u32::from_le_bytes([a[0], a[1], a[2], a[3]]) as usize;
+    let vendor_len = read_u32(next(4)?);
+    let vendor = next(vendor_len)?.to_vec();
+    let count = read_u32(next(4)?);
+    let mut comments = vec![];
+    for _ in 0..count {
+        let len = read_u32(next(4)?);
+        comments.push(String::from_utf8_lossy(next(len)?).into_owned());
+    }
+    Ok((vendor, comments))
+}
+
+fn flac_picture_block(picture: &Picture) -> Vec<u8> {
+    let mut ret = 3u32.to_be_bytes().to_vec();
+    ret.extend_from_slice(&(picture.mime.len() as u32).to_be_bytes());
+    ret.extend_from_slice(picture.mime.as_bytes());
+    // Empty description, unknown dimensions, depth and palette size
+    ret.extend_from_slice(&[0; 20]);
+    ret.extend_from_slice(&(picture.data.len() as u32).to_be_bytes());
+    ret.extend_from_slice(&picture.data);
+    ret
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut ret = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, b)| acc | (*b as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(ALPHABET[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+    ret
+}
+
+struct OggPage {
+    header_type: u8,
+    granule: u64,
+    serial: u32,
+    sequence: u32,
+    segments: Vec<u8>,
+    body: Vec<u8>,
+}
+
+impl OggPage {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = b"OggS".to_vec();
+        ret.extend_from_slice(&[0, self.header_type]);
+        ret.extend_from_slice(&self.granule.to_le_bytes());
+        ret.extend_from_slice(&self.serial.to_le_bytes());
+        ret.extend_from_slice(&self.sequence.to_le_bytes());
+        ret.extend_from_slice(&[0; 4]);
+        ret.push(self.segments.len() as u8);
+        ret.extend_from_slice(&self.segments);
+        ret.extend_from_slice(&self.body);
+        let crc = ogg_crc(&ret);
+        ret[22..26].copy_from_slice(&crc.to_le_bytes());
+        ret
+    }
+}
+
+fn ogg_pages(data: &[u8]) -> Result<Vec<OggPage>> {
+    let mut pages = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        if data.get(pos..pos + 4) != Some(b"OggS") || pos + 27 > data.len() {
+            bail!("Invalid Ogg page at byte {}", pos);
+        }
+        let segments = data[pos + 26] as usize;
+        let Some(table) = data.get(pos + 27..pos + 27 + segments) else {
+            bail!("Ogg page is truncated");
+        };
+        let table = table.to_vec();
+        let body = pos + 27 + segments;
+        let next = body + table.iter().map(|a| *a as usize).sum::<usize>();
+        if next > data.len() {
+            bail!("Ogg page is truncated");
+        }
+        pages.push(OggPage {
+            header_type: data[pos + 5],
+            granule: u64::from_le_bytes(data[pos + 6..pos + 14].try_into()?),
+            serial: u32::from_le_bytes(data[pos + 14..pos + 18].try_into()?),
+            sequence: u32::from_le_bytes(data[pos + 18..pos + 22].try_into()?),
+            segments: table,
+            body: data[body..next].to_vec(),
+        });
+        pos = next;
+    }
+    Ok(pages)
+}
+
+/// Segment table entries for a packet of `len` bytes
+fn lacing(len: usize) -> Vec<u8> {
+    let mut ret = vec![255; len / 255];
+    ret.push((len % 255) as u8);
+    ret
+}
+
+/// CRC-32 used by Ogg (polynomial 0x04c11db7, no reflection)
+fn ogg_crc(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for b in data {
+        crc ^= (*b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[test]
+pub fn test_read_tags() {
+    let mp3 = AudioTags::from_file("tests/tags/tagged.mp3").unwrap();
+    assert_eq!(mp3.artist.as_deref(), Some("Tag Artist"));
+    assert_eq!(mp3.title.as_deref(), Some("Tag Title"));
+    assert_eq!(mp3.year.as_deref(), Some("1999"));
+    assert_eq!(mp3.genre.as_deref(), Some("Rock"));
+    assert_eq!(mp3.picture.unwrap().mime, "image/png");
+
+    let ogg = AudioTags::from_file("tests/tags/tagged.ogg").unwrap();
+    assert_eq!(ogg.artist.as_deref(), Some("Ogg Artist"));
+    assert_eq!(ogg.year.as_deref(), Some("2016"));
+    assert_eq!(ogg.picture.unwrap().data, b"\x89PNG fake");
+
+    let flac = AudioTags::from_file("tests/tags/tagged.flac").unwrap();
+    assert_eq!(flac.title.as_deref(), Some("Flac Title"));
+    assert_eq!(flac.genre.as_deref(), Some("Jazz"));
+    assert!(flac.picture.is_some());
+
+    let mut song = Song::new("", 300.0, 0);
+    song.mp3 = Some("tagged.mp3".to_string());
+    song.genre = Some("Pop".to_string());
+    let tags = song.fill_from_audio_tags("tests/tags").unwrap();
+    assert_eq!(song.title, "Tag Title");
+    assert_eq!(song.artist.as_deref(), Some("Tag Artist"));
+    assert_eq!(song.genre.as_deref(), Some("Pop"));
+    assert_eq!(song.cover, None);
+    assert_eq!(tags.picture.unwrap().mime, "image/png");
+}
+
+#[test]
+pub fn test_extract_cover() {
+    let dir = std::env::temp_dir().join(format!("usdx_extract_cover_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for file in ["tagged.mp3", "tagged.ogg"] {
+        std::fs::copy(format!("tests/tags/{}", file), dir.join(file)).unwrap();
+    }
+    let song_dir = dir.to_str().unwrap();
+    let mut song = Song::new("What?", 300.0, 0);
+    song.artist = Some("AC/DC".to_string());
+    song.mp3 = Some("tagged.ogg".to_string());
+    song.cover = Some("missing.jpg".to_string());
+    let cover = song.extract_cover(song_dir).unwrap();
+    assert_eq!(cover.as_deref(), Some("AC_DC - What_ [CO].png"));
+    assert_eq!(song.cover, cover);
+    assert_eq!(
+        std::fs::read(dir.join("AC_DC - What_ [CO].png")).unwrap(),
+        b"\x89PNG fake"
+    );
+    // An existing cover is kept
+    song.mp3 = Some("tagged.mp3".to_string());
+    assert_eq!(song.extract_cover(song_dir).unwrap(), None);
+    assert_eq!(song.cover, cover);
+    song.mp3 = Some("missing.mp3".to_string());
+    song.cover = None;
+    assert!(song.extract_cover(song_dir).is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+
+    let picture = Picture {
+        mime: "application/octet-stream".to_string(),
+        data: b"GIF89a".to_vec(),
+    };
+    assert_eq!(picture.extension(), "gif");
+}
+
+#[test]
+pub fn test_write_tags() {
+    let tags = AudioTags {
+        artist: Some("Ünïcode Artist".to_string()),
+        title: Some("New Title".to_string()),
+        year: Some("2001".to_string()),
+        genre: None,
+        picture: Some(Picture {
+            mime: "image/jpeg".to_string(),
+            data: b"\xff\xd8 new cover".to_vec(),
+        }),
+    };
+    for file in ["tagged.mp3", "tagged.ogg", "tagged.flac"] {
+        let data = std::fs::read(format!("tests/tags/{}", file)).unwrap();
+        let original = AudioTags::from_bytes(&data).unwrap();
+        let written = tags.apply(&data).unwrap();
+        let read = AudioTags::from_bytes(&written).unwrap();
+        assert_eq!(read.artist, tags.artist, "{}", file);
+        assert_eq!(read.title, tags.title, "{}", file);
+        assert_eq!(read.year, tags.year, "{}", file);
+        assert_eq!(read.genre, original.genre, "{}", file);
+        assert_eq!(read.picture, tags.picture, "{}", file);
+    }
+    let ogg = std::fs::read("tests/tags/tagged.ogg").unwrap();
+    let pages = ogg_pages(&tags.apply(&ogg).unwrap()).unwrap();
+    for (i, page) in pages.iter().enumerate() {
+        assert_eq!(page.sequence, i as u32);
+    }
+    let original = ogg_pages(&ogg).unwrap();
+    assert_eq!(original.last().unwrap().to_bytes(), ogg[ogg.len() - 128..]);
+    for len in [4, 26, 27, 40, 100] {
+        assert!(ogg_pages(&ogg[..len]).is_err());
+        assert!(ogg_packets(&ogg[..len], 2).is_err());
+    }
+
+    let comments = ["YEAR=1990", "DATE=1990", "ALBUM=Album"].map(String::from);
+    let block = tags.apply_vorbis_comments(b"vendor", comments.to_vec());
+    let (_, comments) = vorbis_comment_list(&block).unwrap();
+    assert!(comments.contains(&"DATE=2001".to_string()));
+    assert!(comments.contains(&"ALBUM=Album".to_string()));
+    assert!(!comments.iter().any(|a| a.starts_with("YEAR=")));
+}
@@ -0,0 +1,112 @@
+//! Async loading API
+//!
+//! File reading and parsing are blocking, so the async variants run them on a
+//! separate thread and complete once that thread is done. The futures only
+//! rely on the standard [`Waker`] protocol and work with any executor.
+use crate::library::SongLibrary;
+use crate::Song;
+use anyhow::Result;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+struct State<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// Future of a closure running on its own thread
+struct Blocking<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+impl<T: Send + 'static> Blocking<T> {
+    fn spawn<F>(f: F) -> Self
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let state = Arc::new(Mutex::new(State {
+            result: None,
+            waker: None,
+        }));
+        let shared = state.clone();
+        std::thread::spawn(move || {
+            let result = f();
+            let mut state = shared.lock().unwrap_or_else(|e| e.into_inner());
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        Blocking { state }
+    }
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Song {
+    /// Parse song from file without blocking the calling task
+    pub async fn from_file_async(path: &str) -> Result<Song> {
+        let path = path.to_string();
+        Blocking::spawn(move || Song::from_file(&path)).await
+    }
+}
+
+impl SongLibrary {
+    /// Scan `root` without blocking the calling task, parsing at most
+    /// `concurrency` files at once
+    pub async fn scan_async(root: &str, concurrency: usize) -> Result<SongLibrary> {
+        let root = root.to_string();
+        Blocking::spawn(move || SongLibrary::scan_with_threads(&root, concurrency)).await
+    }
+}
+
+/// Minimal executor driving a future on the current thread
+#[cfg(test)]
+fn block_on<F: Future>(future: F) -> F::Output {
+    use std::task::Wake;
+
+    struct Unpark(std::thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(a) => return a,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[test]
+pub fn test_async_loading() {
+    let song = block_on(Song::from_file_async("tests/duet.txt")).unwrap();
+    assert_eq!(song.title, "Duet Test");
+    assert!(block_on(Song::from_file_async("tests/missing.txt")).is_err());
+    let library = block_on(SongLibrary::scan_async("tests/library", 2)).unwrap();
+    assert_eq!(
+        library.stats(),
+        SongLibrary::scan("tests/library").unwrap().stats()
+    );
+}
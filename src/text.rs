@@ -0,0 +1,66 @@
+//! Text normalization shared by searching, sorting and duplicate detection
+
+/// Lowercase letters with diacritics and the ASCII letters they fold to
+const FOLDS: &[(&str, &str)] = &[
+    ("àáâãäåāăą", "a"),
+    ("æ", "ae"),
+    ("çćĉċč", "c"),
+    ("ďđð", "d"),
+    ("èéêëēĕėęě", "e"),
+    ("ĝğġģ", "g"),
+    ("ĥħ", "h"),
+    ("ìíîïĩīĭįı", "i"),
+    ("ĵ", "j"),
+    ("ķ", "k"),
+    ("ĺļľŀł", "l"),
+    ("ñńņňŉ", "n"),
+    ("òóôõöøōŏő", "o"),
+    ("œ", "oe"),
+    ("ŕŗř", "r"),
+    ("śŝşšș", "s"),
+    ("ß", "ss"),
+    ("ţťŧț", "t"),
+    ("þ", "th"),
+    ("ùúûüũūŭůűų", "u"),
+    ("ŵ", "w"),
+    ("ýÿŷ", "y"),
+    ("źżž", "z"),
+];
+
+/// Lowercases `text` and strips diacritics from Latin letters
+///
+/// Combining marks are dropped as well, so decomposed input folds the same way.
+/// ```rust
+/// # use usdx_parser::text::fold;
+/// assert_eq!(fold("Motörhead"), "motorhead");
+/// assert_eq!(fold("Straße"), "strasse");
+/// ```
+pub fn fold(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii() {
+            ret.push(c);
+        } else if ('\u{300}'..='\u{36f}').contains(&c) {
+            // Combining diacritical mark
+        } else if let Some((_, folded)) = FOLDS.iter().find(|a| a.0.contains(c)) {
+            ret.push_str(folded);
+        } else {
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+/// Folded words of `text`; apostrophes inside words are dropped (`don't` is `dont`)
+/// ```rust
+/// # use usdx_parser::text::tokens;
+/// assert_eq!(tokens("Don't Stop Me Now!"), ["dont", "stop", "me", "now"]);
+/// ```
+pub fn tokens(text: &str) -> Vec<String> {
+    fold(text)
+        .replace(['\'', '’'], "")
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|a| !a.is_empty())
+        .map(|a| a.to_string())
+        .collect()
+}
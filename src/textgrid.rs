@@ -0,0 +1,448 @@
+//! Praat TextGrid import and export
+//!
+//! A song is exported as two interval tiers per singer: `syllables` with one
+//! interval per note and `sentences` with one interval per line, each ending
+//! at its line break and starting where the break before says, if it does. Both the long and the short text format are read back,
+//! so charts can be aligned in Praat and the corrected timing imported again.
+//! Duets get `syllables P1`, `sentences P1`, `syllables P2` and so on.
+use crate::{Note, NoteType, Song, Voice};
+use anyhow::{bail, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Labelled time span of a tier, in seconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interval {
+    pub xmin: f64,
+    pub xmax: f64,
+    pub text: String,
+}
+
+/// Interval tier; point tiers are skipped when parsing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tier {
+    pub name: String,
+    pub intervals: Vec<Interval>,
+}
+
+/// Praat TextGrid holding interval tiers
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TextGrid {
+    pub xmin: f64,
+    pub xmax: f64,
+    pub tiers: Vec<Tier>,
+}
+
+impl TextGrid {
+    /// Parse TextGrid from file
+    pub fn from_file(path: &str) -> Result<TextGrid> {
+        TextGrid::from_str(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn tier(&self, name: &str) -> Option<&Tier> {
+        self.tiers.iter().find(|a| a.name == name)
+    }
+}
+
+impl Tier {
+    /// Adds an interval and fills any gap before it with an empty one
+    ///
+    /// Intervals starting before the end of the previous one are moved to start
+    /// at its end, so the tier stays contiguous as Praat requires.
+    fn push(&mut self, xmin: f64, xmax: f64, text: &str) {
+        let end = self.intervals.last().map(|a| a.xmax).unwrap_or(0.0);
+        let xmin = xmin.max(end);
+        if xmin > end {
+            self.intervals.push(Interval {
+                xmin: end,
+                xmax: xmin,
+                text: String::new(),
+            });
+        }
+        self.intervals.push(Interval {
+            xmin,
+            xmax: xmax.max(xmin),
+            text: text.to_string(),
+        });
+    }
+
+    /// Labelled intervals, skipping the empty filler between them
+    fn labelled(&self) -> impl Iterator<Item = &Interval> {
+        self.intervals.iter().filter(|a| !a.text.is_empty())
+    }
+}
+
+enum Token {
+    Number(f64),
+    Text(String),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) if chars.peek().map(|a| a.1) == Some('"') => {
+                            chars.next();
+                            text.push('"');
+                        }
+                        Some((_, '"')) => break,
+                        Some((_, c)) => text.push(c),
+                        None => bail!("Unterminated string in TextGrid"),
+                    }
+                }
+                tokens.push(Token::Text(text));
+            }
+            '!' => while chars.next_if(|a| a.1 != '\n').is_some() {},
+            '[' => {
+                while chars.next_if(|a| a.1 != ']').is_some() {}
+                chars.next();
+            }
+            '<' => {
+                while chars.next_if(|a| a.1 != '>').is_some() {}
+                chars.next();
+            }
+            '-' | '+' | '.' | '0'..='9' => {
+                let mut end = i + c.len_utf8();
+                while let Some((j, c)) = chars
+                    .next_if(|a| a.1.is_ascii_digit() || matches!(a.1, '.' | 'e' | 'E' | '-' | '+'))
+                {
+                    end = j + c.len_utf8();
+                }
+                match input[i..end].parse() {
+                    Ok(a) => tokens.push(Token::Number(a)),
+                    Err(_) => bail!("Invalid number in TextGrid: {}", &input[i..end]),
+                }
+            }
+            c if c.is_alphabetic() => {
+                while chars
+                    .next_if(|a| a.1.is_alphanumeric() || a.1 == '_')
+                    .is_some()
+                {}
+            }
+            _ => {}
+        }
+    }
+    Ok(tokens)
+}
+
+impl FromStr for TextGrid {
+    type Err = anyhow::Error;
+
+    /// ```rust
+    /// use usdx_parser::textgrid::TextGrid;
+    /// use std::str::FromStr;
+    ///
+    /// let short = "File type = \"ooTextFile\"\nObject class = \"TextGrid\"\n\n0\n2\n<exists>\n1\n\"IntervalTier\"\n\"words\"\n0\n2\n2\n0\n1\n\"hel\"\n1\n2\n\"lo\"\n";
+    /// let grid = TextGrid::from_str(short).unwrap();
+    /// assert_eq!(grid.tier("words").unwrap().intervals[1].text, "lo");
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = tokenize(s)?.into_iter();
+        if next_text(&mut tokens)? != "ooTextFile" || next_text(&mut tokens)? != "TextGrid" {
+            bail!("Not a TextGrid file");
+        }
+        let mut grid = TextGrid {
+            xmin: next_number(&mut tokens)?,
+            xmax: next_number(&mut tokens)?,
+            tiers: vec![],
+        };
+        let tier_count = next_number(&mut tokens)? as usize;
+        for _ in 0..tier_count {
+            let class = next_text(&mut tokens)?;
+            let name = next_text(&mut tokens)?;
+            next_number(&mut tokens)?;
+            next_number(&mut tokens)?;
+            let count = next_number(&mut tokens)? as usize;
+            let mut tier = Tier {
+                name,
+                intervals: vec![],
+            };
+            for _ in 0..count {
+                let xmin = next_number(&mut tokens)?;
+                let xmax = if class == "IntervalTier" {
+                    next_number(&mut tokens)?
+                } else {
+                    xmin
+                };
+                let text = next_text(&mut tokens)?;
+                tier.intervals.push(Interval { xmin, xmax, text });
+            }
+            if class == "IntervalTier" {
+                grid.tiers.push(tier);
+            }
+        }
+        Ok(grid)
+    }
+}
+
+fn next_text(tokens: &mut impl Iterator<Item = Token>) -> Result<String> {
+    match tokens.next() {
+        Some(Token::Text(a)) => Ok(a),
+        Some(Token::Number(a)) => bail!("Expected a string in TextGrid, found {}", a),
+        None => bail!("TextGrid ends early"),
+    }
+}
+
+fn next_number(tokens: &mut impl Iterator<Item = Token>) -> Result<f64> {
+    match tokens.next() {
+        Some(Token::Number(a)) => Ok(a),
+        Some(Token::Text(a)) => bail!("Expected a number in TextGrid, found '{}'", a),
+        None => bail!("TextGrid ends early"),
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+impl fmt::Display for TextGrid {
+    /// Writes the long text format
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "File type = \"ooTextFile\"")?;
+        writeln!(f, "Object class = \"TextGrid\"")?;
+        writeln!(f)?;
+        writeln!(f, "xmin = {} ", self.xmin)?;
+        writeln!(f, "xmax = {} ", self.xmax)?;
+        writeln!(f, "tiers? <exists> ")?;
+        writeln!(f, "size = {} ", self.tiers.len())?;
+        writeln!(f, "item []: ")?;
+        for (i, tier) in self.tiers.iter().enumerate() {
+            writeln!(f, "    item [{}]:", i + 1)?;
+            writeln!(f, "        class = \"IntervalTier\" ")?;
+            writeln!(f, "        name = {} ", quote(&tier.name))?;
+            writeln!(f, "        xmin = {} ", self.xmin)?;
+            writeln!(f, "        xmax = {} ", self.xmax)?;
+            writeln!(f, "        intervals: size = {} ", tier.intervals.len())?;
+            for (j, interval) in tier.intervals.iter().enumerate() {
+                writeln!(f, "        intervals [{}]:", j + 1)?;
+                writeln!(f, "            xmin = {} ", interval.xmin)?;
+                writeln!(f, "            xmax = {} ", interval.xmax)?;
+                writeln!(f, "            text = {} ", quote(&interval.text))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Interval text for `text`, as empty labels would be taken for filler when importing
+fn label(text: &str) -> &str {
+    if text.is_empty() {
+        " "
+    } else {
+        text
+    }
+}
+
+impl Song {
+    /// Singers of the song and the tier name suffix used for each
+    fn textgrid_voices(&self) -> Vec<(Option<Voice>, String)> {
+        let mut voices = vec![];
+        for note in self.notes.iter() {
+            if !voices
+                .iter()
+                .any(|a: &(Option<Voice>, String)| a.0 == note.voice)
+            {
+                let suffix = note.voice.map(|v| format!(" {}", v)).unwrap_or_default();
+                voices.push((note.voice, suffix));
+            }
+        }
+        voices
+    }
+
+    fn seconds(&self, beat: u32) -> f64 {
+        self.beat_to_ms(beat as f64) / 1000.0
+    }
+
+    /// Export syllable and sentence tiers, timed with `#BPM` and `#GAP`
+    pub fn to_textgrid(&self) -> TextGrid {
+        let mut grid = TextGrid::default();
+        for (voice, suffix) in self.textgrid_voices() {
+            let notes = self.notes.iter().filter(|a| a.voice == voice);
+            let mut syllables = Tier {
+                name: format!("syllables{}", suffix),
+                intervals: vec![],
+            };
+            let mut sentences = Tier {
+                name: format!("sentences{}", suffix),
+                intervals: vec![],
+            };
+            let mut sentence: Option<(f64, f64, String)> = None;
+            // Where a `- <end> <start>` line break says the next line starts
+            let mut line_start = None;
+            for note in notes {
+                let start = self.seconds(note.beat_number);
+                if note.note_type == NoteType::LineBreak {
+                    if let Some((xmin, _, text)) = sentence.take() {
+                        sentences.push(xmin, start, label(text.trim()));
+                    }
+                    line_start = note.line_start.map(|a| self.seconds(a));
+                    continue;
+                }
+                let end = self.seconds(note.beat_number + note.note_length.unwrap_or(0));
+                let lyric = note.lyric.as_deref().unwrap_or_default();
+                syllables.push(start, end, label(lyric));
+                let xmin = line_start.take().map_or(start, |a| a.min(start));
+                let current = sentence.get_or_insert((xmin, end, String::new()));
+                current.1 = end;
+                current.2.push_str(lyric);
+            }
+            if let Some((xmin, xmax, text)) = sentence {
+                sentences.push(xmin, xmax, label(text.trim()));
+            }
+            grid.tiers.push(syllables);
+            grid.tiers.push(sentences);
+        }
+        grid.xmax = grid
+            .tiers
+            .iter()
+            .filter_map(|a| a.intervals.last())
+            .map(|a| a.xmax)
+            .fold(0.0, f64::max);
+        for tier in grid.tiers.iter_mut() {
+            tier.push(grid.xmax, grid.xmax, "");
+            if tier.intervals.last().is_some_and(|a| a.xmin == a.xmax) {
+                tier.intervals.pop();
+            }
+        }
+        grid
+    }
+
+    /// Take note and line break timing from a TextGrid made by [`Song::to_textgrid`]
+    ///
+    /// Labelled intervals are matched to notes in order, so intervals may be moved
+    /// and resized but not added or removed. Lyrics are left untouched. Line breaks
+    /// that say where the next line starts take that from the next sentence.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let mut song = Song::from_file("tests/i_hate_everything_about_you.txt").unwrap();
+    /// let mut grid = song.to_textgrid();
+    /// let first = grid.tiers[0].intervals.iter_mut().find(|a| !a.text.is_empty());
+    /// first.unwrap().xmax += 0.5;
+    /// let length = song.notes[0].note_length.unwrap();
+    /// song.import_textgrid(&grid).unwrap();
+    /// assert!(song.notes[0].note_length.unwrap() > length);
+    /// ```
+    pub fn import_textgrid(&mut self, grid: &TextGrid) -> Result<()> {
+        let to_beat = |song: &Song, seconds: f64| -> Result<u32> {
+            let beat = song.ms_to_beat(seconds * 1000.0).round();
+            if beat < 0.0 {
+                bail!("Interval at {}s starts before #GAP", seconds);
+            }
+            Ok(beat as u32)
+        };
+        let mut timing = vec![];
+        for (voice, suffix) in self.textgrid_voices() {
+            let Some(syllables) = grid.tier(&format!("syllables{}", suffix)) else {
+                bail!("TextGrid has no syllables{} tier", suffix);
+            };
+            let sentences = grid.tier(&format!("sentences{}", suffix));
+            let mut syllables = syllables.labelled();
+            let mut sentences = sentences.map(|a| a.labelled().peekable());
+            for (i, note) in self.notes.iter().enumerate() {
+                if note.voice != voice {
+                    continue;
+                }
+                if note.note_type == NoteType::LineBreak {
+                    let Some(sentences) = sentences.as_mut() else {
+                        continue;
+                    };
+                    let Some(sentence) = sentences.next() else {
+                        bail!("TextGrid has fewer sentences than the song");
+                    };
+                    let line_start = match (note.line_start, sentences.peek()) {
+                        (Some(_), Some(next)) => Some(to_beat(self, next.xmin)?),
+                        _ => note.line_start,
+                    };
+                    timing.push((i, to_beat(self, sentence.xmax)?, None, line_start));
+                    continue;
+                }
+                let Some(syllable) = syllables.next() else {
+                    bail!("TextGrid has fewer syllables than the song");
+                };
+                let start = to_beat(self, syllable.xmin)?;
+                let end = to_beat(self, syllable.xmax)?;
+                let length = end.saturating_sub(start).max(1);
+                timing.push((i, start, Some(length), None));
+            }
+            if syllables.next().is_some() {
+                bail!("TextGrid has more syllables than the song");
+            }
+        }
+        for (i, beat, length, line_start) in timing {
+            let note: &mut Note = &mut self.notes[i];
+            note.beat_number = beat;
+            if length.is_some() {
+                note.note_length = length;
+            }
+            if line_start.is_some() {
+                note.line_start = line_start;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_textgrid_round_trip() {
+    for file in [
+        "tests/queen_bohemian_rhapsody.txt",
+        "tests/duet.txt",
+        "tests/i_hate_everything_about_you.txt",
+    ] {
+        let song = Song::from_file(file).unwrap();
+        let text = song.to_textgrid().to_string();
+        let grid = TextGrid::from_str(&text).unwrap();
+        assert_eq!(grid, song.to_textgrid(), "{}", file);
+        let mut imported = song.clone();
+        imported.import_textgrid(&grid).unwrap();
+        for (a, b) in imported.notes.iter().zip(song.notes.iter()) {
+            assert_eq!(a.beat_number, b.beat_number, "{}", file);
+            assert_eq!(a.note_length, b.note_length, "{}", file);
+        }
+    }
+    let duet = Song::from_file("tests/duet.txt").unwrap().to_textgrid();
+    assert!(duet.tier("sentences P2").is_some());
+
+    // Lines without lyrics keep their sentence interval
+    let text =
+        "#TITLE:T\n#BPM:100\n#GAP:0\n: 0 4 0 a\n- 6\n: 8 4 0  \n: 12 2 0 \n- 16\n: 20 4 0 b\nE\n";
+    let song: Song = text.parse().unwrap();
+    let grid = TextGrid::from_str(&song.to_textgrid().to_string()).unwrap();
+    assert_eq!(grid.tier("sentences").unwrap().labelled().count(), 3);
+    let mut moved = grid.clone();
+    moved.tiers[1].intervals[2].xmax += 0.3;
+    let mut imported = song.clone();
+    imported.import_textgrid(&moved).unwrap();
+    assert_eq!(imported.notes[4].beat_number, 18);
+    imported.import_textgrid(&grid).unwrap();
+    assert_eq!(imported.notes, song.notes);
+
+    // The next sentence starts where the line break says
+    let text = "#TITLE:T\n#BPM:100\n#GAP:0\n: 0 4 0 a\n- 6 10\n: 12 4 0 b\nE\n";
+    let song: Song = text.parse().unwrap();
+    let grid = TextGrid::from_str(&song.to_textgrid().to_string()).unwrap();
+    let starts = |grid: &TextGrid| {
+        let sentences = grid.tier("sentences").unwrap().labelled();
+        sentences
+            .map(|a| (a.xmin * 1000.0).round())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(starts(&grid), [0.0, 1500.0]);
+    let mut moved = grid.clone();
+    let next = moved.tiers[1]
+        .intervals
+        .iter_mut()
+        .rfind(|a| !a.text.is_empty());
+    next.unwrap().xmin = 1.2;
+    let mut imported = song.clone();
+    imported.import_textgrid(&moved).unwrap();
+    assert_eq!(imported.notes[1].line_start, Some(8));
+    imported.import_textgrid(&grid).unwrap();
+    assert_eq!(imported.notes, song.notes);
+}
@@ -0,0 +1,112 @@
+//! Chronological note and sentence events for game loops
+//!
+//! [`Song::timeline`] turns the beats of every voice into one stream of
+//! timestamped events, so highlighting and scoring can be driven by popping
+//! events whose time has come.
+use crate::{Note, NoteType, Song, Voice};
+use alloc::vec::Vec;
+
+/// What happens at a point of the timeline
+#[derive(Debug, Clone, Copy)]
+pub enum TimedEventKind<'a> {
+    SentenceStart,
+    NoteStart(&'a Note),
+    NoteEnd(&'a Note),
+    SentenceEnd,
+}
+
+/// Event at `ms` from the start of the audio
+#[derive(Debug, Clone, Copy)]
+pub struct TimedEvent<'a> {
+    pub ms: f64,
+    /// Duet singer the event belongs to, `None` for solo songs
+    pub voice: Option<Voice>,
+    pub kind: TimedEventKind<'a>,
+}
+
+impl TimedEventKind<'_> {
+    /// Order of events at the same time: whatever ends comes before whatever
+    /// starts, and sentences wrap their notes
+    fn rank(&self) -> u8 {
+        match self {
+            Self::NoteEnd(_) => 0,
+            Self::SentenceEnd => 1,
+            Self::SentenceStart => 2,
+            Self::NoteStart(_) => 3,
+        }
+    }
+}
+
+impl Song {
+    /// Events of every voice ordered by time, derived from `#GAP` and `#BPM`
+    /// ```rust
+    /// use usdx_parser::timeline::TimedEventKind;
+    /// use usdx_parser::Song;
+    ///
+    /// let song: Song = "#TITLE:T\n#BPM:150\n#GAP:0\n: 0 4 0 la\n: 4 4 0 la\n".parse().unwrap();
+    /// let events = song.timeline().collect::<Vec<_>>();
+    /// assert_eq!(events.len(), 6);
+    /// assert!(matches!(events[0].kind, TimedEventKind::SentenceStart));
+    /// assert_eq!(events[5].ms, 800.0);
+    /// ```
+    pub fn timeline(&self) -> impl Iterator<Item = TimedEvent<'_>> {
+        let mut events = Vec::new();
+        let mut open: Option<(Option<Voice>, f64)> = None;
+        let close = |events: &mut Vec<TimedEvent>, open: &mut Option<(Option<Voice>, f64)>| {
+            if let Some((voice, ms)) = open.take() {
+                events.push(TimedEvent {
+                    ms,
+                    voice,
+                    kind: TimedEventKind::SentenceEnd,
+                });
+            }
+        };
+        for note in &self.notes {
+            if open.is_some_and(|o| o.0 != note.voice) || note.note_type == NoteType::LineBreak {
+                close(&mut events, &mut open);
+            }
+            if note.note_type == NoteType::LineBreak {
+                continue;
+            }
+            let beat = note.beat_number as f64;
+            let start = self.beat_to_ms(beat);
+            let end = self.beat_to_ms(beat + note.note_length.unwrap_or_default() as f64);
+            let event = |ms, kind| TimedEvent {
+                ms,
+                voice: note.voice,
+                kind,
+            };
+            match &mut open {
+                Some((_, sentence_end)) => *sentence_end = sentence_end.max(end),
+                None => {
+                    events.push(event(start, TimedEventKind::SentenceStart));
+                    open = Some((note.voice, end));
+                }
+            }
+            events.push(event(start, TimedEventKind::NoteStart(note)));
+            events.push(event(end, TimedEventKind::NoteEnd(note)));
+        }
+        close(&mut events, &mut open);
+        events.sort_by(|a, b| {
+            a.ms.total_cmp(&b.ms)
+                .then(a.kind.rank().cmp(&b.kind.rank()))
+        });
+        events.into_iter()
+    }
+}
+
+#[test]
+pub fn test_timeline() {
+    let song = Song::from_file("tests/duet.txt").unwrap();
+    let events = song.timeline().collect::<Vec<_>>();
+    assert!(events.windows(2).all(|a| a[0].ms <= a[1].ms));
+    let count = |kind: fn(&TimedEventKind) -> bool| events.iter().filter(|e| kind(&e.kind)).count();
+    assert_eq!(count(|k| matches!(k, TimedEventKind::SentenceStart)), 4);
+    assert_eq!(count(|k| matches!(k, TimedEventKind::SentenceEnd)), 4);
+    assert_eq!(count(|k| matches!(k, TimedEventKind::NoteStart(_))), 6);
+    assert_eq!(events[0].ms, 1200.0);
+    assert_eq!(events[0].voice, Some(Voice::P1));
+    let last = events.last().unwrap();
+    assert!(matches!(last.kind, TimedEventKind::SentenceEnd));
+    assert_eq!(last.voice, Some(Voice::P2));
+}
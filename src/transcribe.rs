@@ -0,0 +1,223 @@
+//! Draft charts from a vocals-only track
+//!
+//! [`Song::draft_from_vocals`] follows the pitch of the singer with the YIN
+//! method and turns every steady run of one tone into a note. Runs whose
+//! pitch wobbles or was hard to make out become freestyle notes, and longer
+//! pauses start new lines. Lyrics are left as `~` to be filled in; the result
+//! is a starting point for charting, not a finished chart.
+use crate::audio::Audio;
+use crate::{Note, NoteType, Song};
+use anyhow::{bail, Result};
+
+/// Step between pitch estimates, in milliseconds
+pub const HOP_MS: f64 = 10.0;
+/// Lowest and highest frequency the tracker looks for, in Hz
+const RANGE: (f64, f64) = (70.0, 1000.0);
+/// Shortest run of frames that makes a note
+const MIN_FRAMES: usize = 6;
+/// Pause that starts a new line, in milliseconds
+const LINE_PAUSE_MS: f64 = 500.0;
+
+/// Pitch heard around one point of the audio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pitch {
+    /// MIDI note number, fractional between semitones
+    pub midi: f64,
+    /// From 0 for noise to 1 for a clean periodic tone
+    pub clarity: f32,
+}
+
+/// Pitch of every [`HOP_MS`] step of `audio`, `None` where nothing is sung
+pub fn track_pitch(audio: &Audio) -> Vec<Option<Pitch>> {
+    let rate = audio.sample_rate as f64;
+    let hop = ((rate * HOP_MS / 1000.0) as usize).max(1);
+    let min_lag = ((rate / RANGE.1) as usize).max(2);
+    let max_lag = (rate / RANGE.0) as usize;
+    // Integration window, at least one period of the lowest tone
+    let window = max_lag;
+    let samples = &audio.samples;
+    let mut ret = Vec::new();
+    let mut start = 0;
+    while start + window + max_lag <= samples.len() {
+        let frame = &samples[start..start + window + max_lag];
+        ret.push(
+            yin(frame, window, min_lag, max_lag).map(|(lag, clarity)| Pitch {
+                midi: 69.0 + 12.0 * (rate / lag / 440.0).log2(),
+                clarity,
+            }),
+        );
+        start += hop;
+    }
+    ret
+}
+
+/// Period of `frame` in samples and its clarity, by the YIN method
+fn yin(frame: &[f32], window: usize, min_lag: usize, max_lag: usize) -> Option<(f64, f32)> {
+    let energy: f64 = frame[..window].iter().map(|&s| (s as f64).powi(2)).sum();
+    // Quieter than -50 dBFS counts as silence
+    if energy / (window as f64) < 1e-5 {
+        return None;
+    }
+    let mut diff = vec![0.0; max_lag + 1];
+    for (lag, d) in diff.iter_mut().enumerate().skip(1) {
+        *d = (0..window)
+            .map(|j| (frame[j] as f64 - frame[j + lag] as f64).powi(2))
+            .sum();
+    }
+    // Cumulative mean normalized difference
+    let mut norm = vec![1.0; max_lag + 1];
+    let mut sum = 0.0;
+    for lag in 1..=max_lag {
+        sum += diff[lag];
+        norm[lag] = diff[lag] * lag as f64 / sum.max(f64::MIN_POSITIVE);
+    }
+    let mut lag = (min_lag..max_lag).find(|&l| norm[l] < 0.15)?;
+    while lag + 1 < max_lag && norm[lag + 1] < norm[lag] {
+        lag += 1;
+    }
+    // Parabola through the neighbours for a fractional period
+    let (a, b, c) = (norm[lag - 1], norm[lag], norm[lag + 1]);
+    let shift = match a + c - 2.0 * b {
+        d if d.abs() > f64::EPSILON => 0.5 * (a - c) / d,
+        _ => 0.0,
+    };
+    Some((
+        lag as f64 + shift.clamp(-0.5, 0.5),
+        (1.0 - b).clamp(0.0, 1.0) as f32,
+    ))
+}
+
+/// Run of frames sung on one tone
+struct Run {
+    start: usize,
+    pitches: Vec<Pitch>,
+}
+
+impl Run {
+    fn tone(&self) -> f64 {
+        let mut midis: Vec<f64> = self.pitches.iter().map(|p| p.midi).collect();
+        midis.sort_by(f64::total_cmp);
+        midis[midis.len() / 2]
+    }
+
+    /// Whether the pitch was clear and steady enough to score against
+    fn certain(&self) -> bool {
+        let tone = self.tone();
+        let clarity =
+            self.pitches.iter().map(|p| p.clarity).sum::<f32>() / self.pitches.len() as f32;
+        let spread = self
+            .pitches
+            .iter()
+            .filter(|p| (p.midi - tone).abs() > 0.5)
+            .count();
+        clarity >= 0.8 && spread * 4 <= self.pitches.len()
+    }
+}
+
+impl Song {
+    /// Draft a chart at `bpm` from the vocals of a song
+    ///
+    /// `#GAP` is put on the first note. Fails when no singing is found.
+    pub fn draft_from_vocals(audio: &Audio, title: &str, bpm: f32) -> Result<Song> {
+        if !(bpm.is_finite() && bpm > 0.0) {
+            bail!("BPM must be a positive number!");
+        }
+        let mut runs: Vec<Run> = Vec::new();
+        let mut current: Option<Run> = None;
+        for (i, pitch) in track_pitch(audio).into_iter().enumerate() {
+            let continues = match (&current, pitch) {
+                (Some(run), Some(p)) => (p.midi - run.tone()).abs() < 0.75,
+                _ => false,
+            };
+            if continues {
+                if let (Some(run), Some(p)) = (&mut current, pitch) {
+                    run.pitches.push(p);
+                }
+                continue;
+            }
+            runs.extend(current.take().filter(|r| r.pitches.len() >= MIN_FRAMES));
+            current = pitch.map(|p| Run {
+                start: i,
+                pitches: vec![p],
+            });
+        }
+        runs.extend(current.filter(|r| r.pitches.len() >= MIN_FRAMES));
+        let Some(first) = runs.first() else {
+            bail!("No singing found in the audio");
+        };
+
+        let gap = (first.start as f64 * HOP_MS).round();
+        let to_beat = |frame: usize| ((frame as f64 * HOP_MS - gap) * bpm as f64 / 15000.0).round();
+        let mut song = Song::new(title, bpm, gap as u32);
+        let mut last_end: Option<(usize, u32)> = None;
+        for run in &runs {
+            let beat = to_beat(run.start) as u32;
+            let end = (to_beat(run.start + run.pitches.len()) as u32).max(beat + 1);
+            // Rounding can pull a note onto the one before
+            let beat = last_end.map_or(beat, |(_, end)| beat.max(end));
+            let end = end.max(beat + 1);
+            if let Some((frame, last)) = last_end {
+                if (run.start - frame) as f64 * HOP_MS >= LINE_PAUSE_MS {
+                    song.notes.push(Note::line_break(last));
+                }
+            }
+            let note_type = match run.certain() {
+                true => NoteType::Normal,
+                false => NoteType::Freestyle,
+            };
+            let tone = run.tone().round() as i32 - 60;
+            song.notes
+                .push(Note::new(note_type, beat, end - beat, tone, "~"));
+            last_end = Some((run.start + run.pitches.len(), end));
+        }
+        Ok(song)
+    }
+}
+
+#[test]
+pub fn test_draft_from_vocals() {
+    // A4, C5 and after a pause E4, each held for 400 ms, then some noise
+    let rate = 8000;
+    let tones = [(0.5, 440.0), (0.9, 523.25), (2.0, 329.63)];
+    let mut noise = 1u32;
+    let samples = (0..rate * 3)
+        .map(|i| {
+            let t = i as f64 / rate as f64;
+            noise = noise.wrapping_mul(1103515245).wrapping_add(12345);
+            let hiss = ((noise >> 16) as f64 / 32768.0 - 1.0) * 0.3;
+            let sung = tones
+                .iter()
+                .find(|(start, _)| t >= *start && t < start + 0.4)
+                .map(|(_, f)| 0.5 * (2.0 * std::f64::consts::PI * f * t).sin());
+            match (sung, t >= 2.6) {
+                (Some(s), _) => s as f32,
+                (None, true) => hiss as f32,
+                (None, false) => 0.0,
+            }
+        })
+        .collect();
+    let audio = Audio {
+        sample_rate: rate as u32,
+        samples,
+    };
+    let song = Song::draft_from_vocals(&audio, "Draft", 300.0).unwrap();
+    assert!((song.gap as i32 - 500).abs() <= 20, "{}", song.gap);
+    let sung: Vec<_> = song
+        .notes
+        .iter()
+        .filter(|n| n.note_type != NoteType::LineBreak)
+        .collect();
+    let tones: Vec<_> = sung.iter().map(|n| n.note_tone).collect();
+    assert_eq!(tones, [Some(9), Some(12), Some(4)]);
+    assert!(sung.iter().all(|n| n.note_type == NoteType::Normal));
+    // 400 ms at 300 BPM are 8 beats
+    assert!((sung[0].note_length.unwrap() as i32 - 8).abs() <= 1);
+    assert_eq!(song.notes[2].note_type, NoteType::LineBreak);
+    assert!(song.to_string().parse::<Song>().is_ok());
+
+    let silence = Audio {
+        sample_rate: rate as u32,
+        samples: vec![0.0; rate],
+    };
+    assert!(Song::draft_from_vocals(&silence, "Draft", 300.0).is_err());
+}
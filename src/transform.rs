@@ -0,0 +1,257 @@
+//! Transformations of whole charts
+//!
+//! The timing ones move or rescale every note at once while keeping where
+//! the notes land in the audio, adjusting `#GAP` and `#BPM` to compensate.
+use crate::{NoteType, Song};
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
+
+/// Note whose scaled beat or length wasn't a whole number and was rounded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rounded {
+    /// Index of the note in [`Song::notes`]
+    pub note: usize,
+    /// Exact scaled beat
+    pub beat: f64,
+    /// Exact scaled length
+    pub length: Option<f64>,
+}
+
+/// `value * numerator / denominator` rounded to the nearest beat, and whether that was exact
+fn scale(value: u32, numerator: u32, denominator: u32) -> (u32, bool) {
+    let product = value as u64 * numerator as u64;
+    let rounded = (product + denominator as u64 / 2) / denominator as u64;
+    (
+        u32::try_from(rounded).unwrap_or(u32::MAX),
+        product.is_multiple_of(denominator as u64),
+    )
+}
+
+impl Song {
+    /// Shift the notes so the first one starts at beat 0, moving `#GAP` to match
+    ///
+    /// Returns the number of beats the notes moved. `#GAP` is whole
+    /// milliseconds, so the notes may land up to half a millisecond off.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:T\n#BPM:250\n#GAP:1000\n: 10 4 0 a\n- 16\n: 20 4 0 b\nE\n";
+    /// let mut song: Song = text.parse().unwrap();
+    /// assert_eq!(song.rebase_to_zero(), 10);
+    /// assert_eq!(song.gap, 1600);
+    /// assert_eq!(song.notes[2].beat_number, 10);
+    /// ```
+    pub fn rebase_to_zero(&mut self) -> u32 {
+        let Some(first) = self
+            .notes
+            .iter()
+            .filter(|n| n.note_type != NoteType::LineBreak)
+            .map(|n| n.beat_number)
+            .min()
+        else {
+            return 0;
+        };
+        if first == 0 {
+            return 0;
+        }
+        for note in &mut self.notes {
+            note.beat_number = note.beat_number.saturating_sub(first);
+            note.line_start = note.line_start.map(|a| a.saturating_sub(first));
+        }
+        let ms = self.beat_to_ms(first as f64) - self.gap as f64;
+        // Rounded by hand, `round` needs std
+        self.gap = self.gap.saturating_add((ms + 0.5) as u32);
+        first
+    }
+
+    /// Multiply `#BPM` and every beat and length by `numerator / denominator`
+    ///
+    /// Notes stay where they are in time, up to rounding to whole beats;
+    /// every note that had to be rounded is reported. Beats that no longer
+    /// fit a `u32` saturate.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let mut song = Song::from_file("tests/duet.txt").unwrap();
+    /// // Beat 10 of the first line break lands on 7.5
+    /// let rounded = song.scale_beats(3, 4).unwrap();
+    /// assert_eq!(rounded.len(), 3);
+    /// assert_eq!(rounded[0].beat, 7.5);
+    /// assert_eq!(song.notes[2].beat_number, 8);
+    /// ```
+    pub fn scale_beats(&mut self, numerator: u32, denominator: u32) -> Result<Vec<Rounded>> {
+        if numerator == 0 || denominator == 0 {
+            bail!("Can't scale beats by {}/{}", numerator, denominator);
+        }
+        let factor = numerator as f64 / denominator as f64;
+        let mut ret = Vec::new();
+        for (i, note) in self.notes.iter_mut().enumerate() {
+            let (beat, beat_exact) = scale(note.beat_number, numerator, denominator);
+            let length = note.note_length.map(|a| scale(a, numerator, denominator));
+            if !beat_exact || length.is_some_and(|(_, exact)| !exact) {
+                ret.push(Rounded {
+                    note: i,
+                    beat: note.beat_number as f64 * factor,
+                    length: note.note_length.map(|a| a as f64 * factor),
+                });
+            }
+            note.beat_number = beat;
+            note.note_length = length.map(|(a, _)| a);
+            note.line_start = note.line_start.map(|a| scale(a, numerator, denominator).0);
+        }
+        self.bpm = (self.bpm as f64 * factor) as f32;
+        Ok(ret)
+    }
+
+    /// Double `#BPM` and every beat and length, putting the chart on a finer grid
+    ///
+    /// Nothing moves in time. Beats that no longer fit a `u32` saturate.
+    pub fn double_bpm(&mut self) {
+        self.bpm *= 2.0;
+        for note in &mut self.notes {
+            note.beat_number = note.beat_number.saturating_mul(2);
+            note.note_length = note.note_length.map(|a| a.saturating_mul(2));
+            note.line_start = note.line_start.map(|a| a.saturating_mul(2));
+        }
+    }
+
+    /// Halve `#BPM` and every beat and length, putting the chart on a coarser grid
+    ///
+    /// Fails without changing anything when a beat or length is odd, as that
+    /// note would have to move.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let mut song = Song::from_file("tests/duet.txt").unwrap();
+    /// song.double_bpm();
+    /// assert_eq!(song.bpm, 625.0);
+    /// song.halve_bpm().unwrap();
+    /// assert_eq!(song.notes[1].beat_number, 4);
+    /// // The line break at beat 10 would move to 2.5 next
+    /// song.halve_bpm().unwrap();
+    /// assert!(song.halve_bpm().is_err());
+    /// ```
+    pub fn halve_bpm(&mut self) -> Result<()> {
+        for note in &self.notes {
+            let odd = |a: Option<u32>| a.is_some_and(|a| a % 2 != 0);
+            if note.beat_number % 2 != 0 || odd(note.note_length) || odd(note.line_start) {
+                bail!(
+                    "Note at beat {} doesn't fit a grid of half the BPM",
+                    note.beat_number
+                );
+            }
+        }
+        self.scale_beats(1, 2)?;
+        Ok(())
+    }
+
+    /// Turn every normal and golden note into freestyle, returning how many changed
+    ///
+    /// Gives a lyrics-only chart for practice, or for songs whose pitches
+    /// can't be trusted. Timing, tones and lyrics stay as they are.
+    /// ```rust
+    /// use usdx_parser::Song;
+    ///
+    /// let mut song = Song::from_file("tests/duet.txt").unwrap();
+    /// assert_eq!(song.to_freestyle(), 5);
+    /// assert!(song.is_freestyle_only());
+    /// ```
+    pub fn to_freestyle(&mut self) -> usize {
+        let mut count = 0;
+        for note in &mut self.notes {
+            if matches!(note.note_type, NoteType::Normal | NoteType::Golden) {
+                note.note_type = NoteType::Freestyle;
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+#[test]
+pub fn test_rebase_to_zero() {
+    let mut song = Song::from_file("tests/duet.txt").unwrap();
+    assert_eq!(song.rebase_to_zero(), 0);
+    // P2 starts at beat 20, but P1 at 0
+    assert_eq!(song.notes[4].beat_number, 20);
+
+    let text = std::fs::read_to_string("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let mut song: Song = text.replace(": 0 3 17 Is", ": 7 0 17 Is").parse().unwrap();
+    let before: Vec<f64> = song
+        .notes
+        .iter()
+        .map(|n| song.beat_to_ms(n.beat_number as f64))
+        .collect();
+    assert_eq!(song.rebase_to_zero(), 7);
+    assert_eq!(song.notes[0].beat_number, 0);
+    for (note, ms) in song.notes.iter().zip(before) {
+        assert!((song.beat_to_ms(note.beat_number as f64) - ms).abs() <= 0.5);
+    }
+    assert_eq!(Song::new("Empty", 100.0, 0).rebase_to_zero(), 0);
+}
+
+#[test]
+pub fn test_double_and_halve_bpm() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let mut doubled = song.clone();
+    doubled.double_bpm();
+    for (a, b) in doubled.notes.iter().zip(&song.notes) {
+        let ms = |s: &Song, beat: u32| s.beat_to_ms(beat as f64);
+        assert!((ms(&doubled, a.beat_number) - ms(&song, b.beat_number)).abs() < 1e-6);
+    }
+    doubled.halve_bpm().unwrap();
+    assert_eq!(doubled.to_string(), song.to_string());
+    // "Is" lasts 3 beats
+    let mut halved = song.clone();
+    let error = halved.halve_bpm().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Note at beat 0 doesn't fit a grid of half the BPM"
+    );
+    assert_eq!(halved.bpm, song.bpm);
+}
+
+#[test]
+pub fn test_scale_beats() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let mut scaled = song.clone();
+    // MIDI style 480 ticks per quarter note against USDX' 4 beats
+    assert_eq!(scaled.scale_beats(120, 1).unwrap(), []);
+    assert_eq!(scaled.notes[1].beat_number, 840);
+    assert_eq!(scaled.scale_beats(1, 120).unwrap(), []);
+    assert_eq!(scaled.to_string(), song.to_string());
+
+    let mut scaled = song.clone();
+    let rounded = scaled.scale_beats(2, 3).unwrap();
+    assert!(!rounded.is_empty());
+    for r in &rounded {
+        let note = &scaled.notes[r.note];
+        assert!((note.beat_number as f64 - r.beat).abs() <= 0.5);
+        if let (Some(a), Some(b)) = (note.note_length, r.length) {
+            assert!((a as f64 - b).abs() <= 0.5);
+        }
+    }
+    assert!(scaled.scale_beats(1, 0).is_err());
+    assert!(scaled.scale_beats(0, 1).is_err());
+}
+
+#[test]
+pub fn test_to_freestyle() {
+    let song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let mut free = song.clone();
+    assert!(free.to_freestyle() > 0);
+    assert!(free.is_freestyle_only());
+    assert_eq!(free.to_freestyle(), 0);
+    for (a, b) in free.notes.iter().zip(&song.notes) {
+        assert_eq!(
+            (a.beat_number, a.note_length),
+            (b.beat_number, b.note_length)
+        );
+        assert_eq!(a.lyric, b.lyric);
+        assert_eq!(
+            a.note_type == NoteType::LineBreak,
+            b.note_type == NoteType::LineBreak
+        );
+    }
+    assert!(free.to_string().contains("\nF 0 3 17 Is\n"));
+}
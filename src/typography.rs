@@ -0,0 +1,207 @@
+//! Consistent typography in lyrics and headers
+//!
+//! Community charts mix straight and curly quotes, hyphens standing in for
+//! dashes, three dots next to `…` and non-breaking spaces that break word
+//! splitting. [`Song::fix_typography`] rewrites all of them to one style,
+//! either plain ASCII or the conventions of a language.
+use crate::{NoteType, Song};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Characters to write for each kind of punctuation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Typography {
+    /// Opening and closing double quotes
+    pub double_quotes: (char, char),
+    /// Opening and closing single quotes
+    pub single_quotes: (char, char),
+    /// Apostrophe inside words, as in "don't"
+    pub apostrophe: char,
+    /// Dash standing alone between spaces
+    pub dash: char,
+    pub ellipsis: &'static str,
+}
+
+impl Default for Typography {
+    fn default() -> Self {
+        Self::ascii()
+    }
+}
+
+impl Typography {
+    /// Straight quotes, hyphens and three dots, which every font can show
+    pub fn ascii() -> Self {
+        Self {
+            double_quotes: ('"', '"'),
+            single_quotes: ('\'', '\''),
+            apostrophe: '\'',
+            dash: '-',
+            ellipsis: "...",
+        }
+    }
+
+    /// Curly quotes and dashes as used in a language
+    ///
+    /// Takes an ISO 639-1 code or an English name; unknown languages get
+    /// English conventions.
+    /// ```rust
+    /// use usdx_parser::typography::Typography;
+    ///
+    /// assert_eq!(Typography::for_language("German").double_quotes, ('„', '“'));
+    /// assert_eq!(Typography::for_language("fr").double_quotes, ('«', '»'));
+    /// ```
+    pub fn for_language(language: &str) -> Self {
+        let (double_quotes, single_quotes) = match language.trim().to_ascii_lowercase().as_str() {
+            "de" | "german" | "cs" | "czech" => (('„', '“'), ('‚', '‘')),
+            "fr" | "french" => (('«', '»'), ('‹', '›')),
+            "es" | "spanish" | "it" | "italian" | "pt" | "portuguese" => (('«', '»'), ('“', '”')),
+            "pl" | "polish" | "hu" | "hungarian" => (('„', '”'), ('«', '»')),
+            "sv" | "swedish" | "fi" | "finnish" => (('”', '”'), ('’', '’')),
+            _ => (('“', '”'), ('‘', '’')),
+        };
+        Self {
+            double_quotes,
+            single_quotes,
+            apostrophe: '’',
+            dash: '–',
+            ellipsis: "…",
+        }
+    }
+
+    /// Rewrite the punctuation of `text`
+    ///
+    /// `before` is the character preceding the text, or `None` at the start
+    /// of a line, and decides whether a quote at the very start opens or closes.
+    /// ```rust
+    /// use usdx_parser::typography::Typography;
+    ///
+    /// let english = Typography::for_language("en");
+    /// assert_eq!(english.fix("\"Don't\" - wait...", None), "“Don’t” – wait…");
+    /// assert_eq!(Typography::ascii().fix("„Ja“\u{a0}…", None), "\"Ja\" ...");
+    /// ```
+    pub fn fix(&self, text: &str, before: Option<char>) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut ret = String::with_capacity(text.len());
+        let mut prev = before;
+        let mut i = 0;
+        while i < chars.len() {
+            let next = chars.get(i + 1).copied();
+            let opens = prev.is_none_or(|a| a.is_whitespace() || "([{-–—".contains(a));
+            let c = chars[i];
+            match c {
+                '.' if chars[i..].starts_with(&['.', '.', '.']) => {
+                    ret.push_str(self.ellipsis);
+                    i += 3;
+                    prev = Some('.');
+                    continue;
+                }
+                '…' => ret.push_str(self.ellipsis),
+                '"' | '“' | '”' | '„' | '«' | '»' => match opens {
+                    true => ret.push(self.double_quotes.0),
+                    false => ret.push(self.double_quotes.1),
+                },
+                '\'' | '‘' | '’' | '‚' | '‹' | '›' => {
+                    let inside = prev.is_some_and(char::is_alphanumeric)
+                        && next.is_some_and(char::is_alphanumeric);
+                    match (inside, opens) {
+                        (true, _) => ret.push(self.apostrophe),
+                        (false, true) => ret.push(self.single_quotes.0),
+                        (false, false) => ret.push(self.single_quotes.1),
+                    }
+                }
+                '-' | '–' | '—'
+                    if prev.is_some_and(char::is_whitespace)
+                        && next.is_none_or(char::is_whitespace) =>
+                {
+                    ret.push(self.dash)
+                }
+                '\u{a0}' | '\u{2007}' | '\u{202f}' => ret.push(' '),
+                _ => ret.push(c),
+            }
+            prev = Some(c);
+            i += 1;
+        }
+        ret
+    }
+}
+
+impl Song {
+    /// Rewrite quotes, dashes, ellipses and non-breaking spaces in the lyrics
+    /// and text headers, returning how many lyrics and headers changed
+    ///
+    /// Quotes are paired across the syllables of a sentence. File names are
+    /// left alone.
+    /// ```rust
+    /// use usdx_parser::typography::Typography;
+    /// use usdx_parser::Song;
+    ///
+    /// let text = "#TITLE:Don't Stop\n#BPM:100\n#GAP:0\n: 0 2 0 \"Go\n: 2 2 0 \"\nE\n";
+    /// let mut song: Song = text.parse().unwrap();
+    /// assert_eq!(song.fix_typography(&Typography::for_language("en")), 3);
+    /// assert_eq!(song.title, "Don’t Stop");
+    /// assert_eq!(song.notes[1].lyric.as_deref(), Some("”"));
+    /// ```
+    pub fn fix_typography(&mut self, typography: &Typography) -> usize {
+        let mut count = 0;
+        let mut fix = |text: &mut String| {
+            let fixed = typography.fix(text, None);
+            if fixed != *text {
+                *text = fixed;
+                count += 1;
+            }
+        };
+        fix(&mut self.title);
+        for header in [
+            &mut self.artist,
+            &mut self.edition,
+            &mut self.genre,
+            &mut self.singer_p1,
+            &mut self.singer_p2,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            fix(header);
+        }
+        let mut prev = None;
+        let mut voice = None;
+        for note in &mut self.notes {
+            if note.note_type == NoteType::LineBreak || note.voice != voice {
+                prev = None;
+                voice = note.voice;
+            }
+            let Some(lyric) = &note.lyric else {
+                continue;
+            };
+            let fixed = typography.fix(lyric, prev);
+            prev = lyric.chars().last().or(prev);
+            if fixed != **lyric {
+                note.lyric = Some(fixed.into());
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+#[test]
+pub fn test_fix_typography() {
+    let german = Typography::for_language("de");
+    assert_eq!(german.fix("'Nein', sagt' er", None), "‚Nein‘, sagt‘ er");
+    assert_eq!(
+        german.fix("ein Ge-bet - oder—nicht", None),
+        "ein Ge-bet – oder—nicht"
+    );
+    assert_eq!(german.fix("..", None), "..");
+    assert_eq!(german.fix("\"", Some('a')), "“");
+
+    let mut song = Song::from_file("tests/queen_bohemian_rhapsody.txt").unwrap();
+    let original = song.clone();
+    let english = Typography::for_language("en");
+    let count = song.fix_typography(&english);
+    assert!(count > 0);
+    assert!(song.lyrics().contains("I’m just a poor boy"));
+    assert_eq!(song.fix_typography(&english), 0);
+    song.fix_typography(&Typography::ascii());
+    assert_eq!(song.to_string(), original.to_string());
+}
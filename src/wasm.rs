@@ -0,0 +1,139 @@
+//! Browser bindings
+//!
+//! Chart editors running in a browser get the same parser as desktop tools.
+//! Every function takes and returns strings, with songs encoded as JSON, so
+//! they cross the JavaScript boundary as they are. On `wasm32` targets they
+//! are also exported as raw functions working on the module's memory.
+use crate::json::Value;
+use crate::lint::{Lint, Severity};
+use crate::{ParseLimits, Song};
+
+/// Parse a chart into its JSON form, limited like an untrusted upload
+/// ```rust
+/// use usdx_parser::wasm;
+///
+/// let json = wasm::parse("#TITLE:Song\n#BPM:100\n#GAP:0\n: 0 4 2 la\n").unwrap();
+/// assert!(json.starts_with(r#"{"artist":null,"title":"Song""#));
+/// ```
+pub fn parse(text: &str) -> Result<String, String> {
+    let song = Song::from_str_limited(text, Default::default(), &ParseLimits::untrusted())
+        .map_err(|e| e.to_string())?;
+    Ok(song.to_json())
+}
+
+/// Write the JSON form of a song as chart text
+pub fn serialize(json: &str) -> Result<String, String> {
+    let song = Song::from_json(json).map_err(|e| e.to_string())?;
+    Ok(song.to_string())
+}
+
+/// Check a chart with [`Song::lint`], returning
+/// `{"valid":bool,"problems":[{"severity","message"}]}`
+///
+/// A chart is valid as long as none of its problems is an error.
+/// ```rust
+/// use usdx_parser::wasm;
+///
+/// let report = wasm::validate("#ARTIST:A\n#TITLE:Song\n#MP3:a.mp3\n#BPM:100\n#GAP:0\n: 8 4 2 la\n: 0 4 2 la\n");
+/// assert_eq!(
+///     report,
+///     r#"{"valid":false,"problems":[{"severity":"error","message":"Note at beat 0 starts before the one at beat 8"}]}"#
+/// );
+/// ```
+pub fn validate(text: &str) -> String {
+    let lints = match Song::from_str_limited(text, Default::default(), &ParseLimits::untrusted()) {
+        Ok(song) => song.lint(),
+        Err(e) => vec![Lint {
+            severity: Severity::Error,
+            line: None,
+            message: e.to_string(),
+        }],
+    };
+    let problems = lints
+        .iter()
+        .map(|l| {
+            Value::Object(vec![
+                (
+                    "severity".to_string(),
+                    Value::String(l.severity.to_string()),
+                ),
+                ("message".to_string(), Value::from(l.message.as_str())),
+            ])
+        })
+        .collect();
+    Value::Object(vec![
+        (
+            "valid".to_string(),
+            Value::Bool(lints.iter().all(|l| l.severity < Severity::Error)),
+        ),
+        ("problems".to_string(), Value::Array(problems)),
+    ])
+    .to_string()
+}
+
+#[cfg(target_arch = "wasm32")]
+mod exports {
+    //! Raw exports for callers without generated glue
+    //!
+    //! Strings are passed as pointer and length into memory from
+    //! `usdx_alloc`. Results come back as `pointer << 32 | length` of a buffer
+    //! whose first byte is 1 for success and 0 for an error message, to be
+    //! released with `usdx_free`.
+
+    fn input(ptr: *const u8, len: usize) -> String {
+        // SAFETY: the caller wrote `len` bytes at `ptr`
+        let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+
+    fn output(result: Result<String, String>) -> u64 {
+        let (ok, text) = match result {
+            Ok(a) => (1, a),
+            Err(a) => (0, a),
+        };
+        let mut data = Vec::with_capacity(text.len() + 1);
+        data.push(ok);
+        data.extend_from_slice(text.as_bytes());
+        let data = data.into_boxed_slice();
+        let len = data.len() as u64;
+        ((Box::into_raw(data) as *mut u8 as u64) << 32) | len
+    }
+
+    #[no_mangle]
+    pub extern "C" fn usdx_alloc(len: usize) -> *mut u8 {
+        Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+    }
+
+    /// # Safety
+    /// `ptr` and `len` must come from `usdx_alloc` or a returned result
+    #[no_mangle]
+    pub unsafe extern "C" fn usdx_free(ptr: *mut u8, len: usize) {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+    }
+
+    #[no_mangle]
+    pub extern "C" fn usdx_parse(ptr: *const u8, len: usize) -> u64 {
+        output(super::parse(&input(ptr, len)))
+    }
+
+    #[no_mangle]
+    pub extern "C" fn usdx_serialize(ptr: *const u8, len: usize) -> u64 {
+        output(super::serialize(&input(ptr, len)))
+    }
+
+    #[no_mangle]
+    pub extern "C" fn usdx_validate(ptr: *const u8, len: usize) -> u64 {
+        output(Ok(super::validate(&input(ptr, len))))
+    }
+}
+
+#[test]
+pub fn test_wasm_round_trip() {
+    let text = std::fs::read_to_string("tests/duet.txt").unwrap();
+    let json = parse(&text).unwrap();
+    let song = Song::try_from(text).unwrap();
+    assert_eq!(serialize(&json).unwrap(), song.to_string());
+    assert!(parse("#TITLE:No BPM\n").is_err());
+    assert!(serialize(r#"{"title":"No BPM"}"#).is_err());
+    assert!(validate(&song.to_string()).starts_with(r#"{"valid":true,"#));
+}
@@ -0,0 +1,169 @@
+//! Live library updates
+//!
+//! A [`Watcher`] owns a [`SongLibrary`] and rescans it on a background thread
+//! at a fixed interval, sending an event for every song that appears, changes
+//! or disappears. Polling relies on the same size, mtime and hash checks as
+//! [`SongLibrary::rescan`], so an idle library costs one directory walk per
+//! interval and works on every platform and network share.
+use crate::library::{SongEntry, SongLibrary};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Change to the songs of a watched library
+#[derive(Debug, Clone)]
+pub enum LibraryEvent {
+    Added(Box<SongEntry>),
+    Changed(Box<SongEntry>),
+    /// The chart was deleted or no longer is a chart
+    Removed(PathBuf),
+    /// A chart was added or changed but doesn't parse
+    Failed {
+        path: PathBuf,
+        error: String,
+    },
+    /// The library root could not be scanned
+    ScanFailed(String),
+}
+
+/// Background rescanning of a library
+pub struct Watcher {
+    events: Receiver<LibraryEvent>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<SongLibrary>,
+}
+
+impl SongLibrary {
+    /// Rescan and describe everything that changed as events
+    pub fn poll_events(&mut self) -> Vec<LibraryEvent> {
+        let songs_before = self
+            .songs
+            .iter()
+            .map(|e| e.path.clone())
+            .collect::<HashSet<_>>();
+        let changes = match self.rescan_changes() {
+            Ok(a) => a,
+            Err(e) => return vec![LibraryEvent::ScanFailed(format!("{:#}", e))],
+        };
+        let mut events = changes
+            .removed
+            .into_iter()
+            .filter(|a| songs_before.contains(a))
+            .map(LibraryEvent::Removed)
+            .collect::<Vec<_>>();
+        let reloaded = changes
+            .added
+            .into_iter()
+            .map(|a| (a, false))
+            .chain(changes.changed.into_iter().map(|a| (a, true)));
+        for (path, changed) in reloaded {
+            if let Some(entry) = self.songs.iter().find(|e| e.path == path) {
+                let entry = Box::new(entry.clone());
+                events.push(if changed && songs_before.contains(&path) {
+                    LibraryEvent::Changed(entry)
+                } else {
+                    LibraryEvent::Added(entry)
+                });
+            } else if let Some(error) = self.errors.iter().find(|e| e.path == path) {
+                events.push(LibraryEvent::Failed {
+                    path,
+                    error: format!("{:#}", error.error),
+                });
+            } else if songs_before.contains(&path) {
+                events.push(LibraryEvent::Removed(path));
+            }
+        }
+        events
+    }
+
+    /// Keep rescanning the library every `interval` on a background thread
+    /// ```rust
+    /// use usdx_parser::library::SongLibrary;
+    /// use std::time::Duration;
+    ///
+    /// let library = SongLibrary::scan("tests/library").unwrap();
+    /// let watcher = library.watch(Duration::from_millis(10));
+    /// assert!(watcher.try_events().is_empty());
+    /// let library = watcher.stop();
+    /// assert_eq!(library.songs.len(), 2);
+    /// ```
+    pub fn watch(mut self, interval: Duration) -> Watcher {
+        let (sender, events) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = std::thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                std::thread::park_timeout(interval);
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                for event in self.poll_events() {
+                    if sender.send(event).is_err() {
+                        return self;
+                    }
+                }
+            }
+            self
+        });
+        Watcher {
+            events,
+            stop,
+            thread,
+        }
+    }
+}
+
+impl Watcher {
+    /// Events that happened since the last call, without waiting
+    pub fn try_events(&self) -> Vec<LibraryEvent> {
+        self.events.try_iter().collect()
+    }
+
+    /// Wait up to `timeout` for the next event
+    pub fn next_event(&self, timeout: Duration) -> Option<LibraryEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Stop watching and get the up to date library back
+    pub fn stop(self) -> SongLibrary {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.thread().unpark();
+        match self.thread.join() {
+            Ok(library) => library,
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+}
+
+#[test]
+pub fn test_watch_library() {
+    let dir = std::env::temp_dir().join(format!("usdx_watch_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy("tests/duet.txt", dir.join("duet.txt")).unwrap();
+    let library = SongLibrary::scan(dir.to_str().unwrap()).unwrap();
+    let watcher = library.watch(Duration::from_millis(5));
+
+    std::fs::copy("tests/library/Broken/broken.txt", dir.join("broken.txt")).unwrap();
+    std::fs::write(dir.join("duet.txt"), "Not a chart anymore").unwrap();
+    let mut events = vec![];
+    while events.len() < 2 {
+        let Some(event) = watcher.next_event(Duration::from_secs(5)) else {
+            panic!("Timed out waiting for library events, got {:?}", events);
+        };
+        events.push(event);
+    }
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, LibraryEvent::Failed { path, .. } if path.ends_with("broken.txt"))));
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, LibraryEvent::Removed(path) if path.ends_with("duet.txt"))));
+    let library = watcher.stop();
+    assert!(library.songs.is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+}
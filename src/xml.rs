@@ -0,0 +1,174 @@
+//! Minimal XML reader for the converters that need one
+//!
+//! Only elements and attributes are kept; text content, comments, processing
+//! instructions and doctypes are skipped. Namespace prefixes are dropped from
+//! element and attribute names.
+use anyhow::{bail, Result};
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+}
+
+impl Element {
+    /// Value of an attribute, matched case-insensitively like the formats we read expect
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|a| a.0.eq_ignore_ascii_case(name))
+            .map(|a| a.1.as_str())
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children
+            .iter()
+            .filter(move |a| a.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Parse a document and return its root element
+pub(crate) fn parse(input: &str) -> Result<Element> {
+    let mut stack: Vec<Element> = vec![];
+    let mut rest = input;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if let Some(a) = rest.strip_prefix("<!--") {
+            let Some(end) = a.find("-->") else {
+                bail!("Unterminated XML comment");
+            };
+            rest = &a[end + 3..];
+            continue;
+        }
+        if rest.starts_with("<![CDATA[") {
+            let Some(end) = rest.find("]]>") else {
+                bail!("Unterminated CDATA section");
+            };
+            rest = &rest[end + 3..];
+            continue;
+        }
+        let Some(end) = tag_end(rest) else {
+            bail!("Unterminated XML tag");
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = local_name(name.trim());
+            let Some(element) = stack.pop() else {
+                bail!("Unexpected closing tag </{}>", name);
+            };
+            if !element.name.eq_ignore_ascii_case(name) {
+                bail!("Expected </{}>, found </{}>", element.name, name);
+            }
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(element),
+                None => return Ok(element),
+            }
+            continue;
+        }
+        let (tag, self_closing) = match tag.strip_suffix('/') {
+            Some(a) => (a, true),
+            None => (tag, false),
+        };
+        let element = parse_tag(tag)?;
+        if self_closing {
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(element),
+                None => return Ok(element),
+            }
+        } else {
+            stack.push(element);
+        }
+    }
+    match stack.first() {
+        Some(a) => bail!("Unclosed element <{}>", a.name),
+        None => bail!("Document has no root element"),
+    }
+}
+
+/// Position of the `>` closing the tag `rest` starts with, skipping quoted
+/// attribute values, which may contain one
+fn tag_end(rest: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in rest.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(i),
+            (Some(a), _) if a == c => quote = None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn parse_tag(tag: &str) -> Result<Element> {
+    let tag = tag.trim();
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let mut element = Element {
+        name: local_name(&tag[..name_end]).to_string(),
+        ..Default::default()
+    };
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let Some(eq) = rest.find('=') else {
+            bail!("Attribute without value in <{}>", element.name);
+        };
+        let name = local_name(rest[..eq].trim()).to_string();
+        rest = rest[eq + 1..].trim_start();
+        let Some(quote) = rest.chars().next().filter(|a| *a == '"' || *a == '\'') else {
+            bail!("Unquoted attribute {} in <{}>", name, element.name);
+        };
+        let Some(end) = rest[1..].find(quote) else {
+            bail!("Unterminated attribute {} in <{}>", name, element.name);
+        };
+        element
+            .attributes
+            .push((name, unescape(&rest[1..end + 1])?));
+        rest = rest[end + 2..].trim_start();
+    }
+    Ok(element)
+}
+
+fn unescape(value: &str) -> Result<String> {
+    let mut ret = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        ret.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find(';') else {
+            bail!("Unterminated entity in '{}'", value);
+        };
+        let entity = &rest[start + 1..start + end];
+        let c = match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(dec) = entity.strip_prefix('#') {
+                    dec.parse::<u32>().ok()
+                } else {
+                    None
+                };
+                match code.and_then(char::from_u32) {
+                    Some(a) => a,
+                    None => bail!("Unknown entity &{};", entity),
+                }
+            }
+        };
+        ret.push(c);
+        rest = &rest[start + end + 1..];
+    }
+    ret.push_str(rest);
+    Ok(ret)
+}